@@ -1,42 +1,103 @@
-use std::{collections::BTreeSet, error, fmt};
+use std::{
+    collections::BTreeSet, error, fmt, fs, io, num::NonZeroUsize, path::Path, time::Instant,
+};
 
 use anyhow::anyhow;
-use log::{debug, info};
-use semver::Version;
+use log::{debug, error, info, warn};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
     artifact::{Artifact, ConsumerError, ScriptProcessingError},
     backend::{Backend, SqiglState},
     manifest::{artifact::open_artifact, project::ProjectInfo},
-    migration::MigrationSet,
-    util::empty_database_version,
+    migration::{MigrationArtifact, MigrationSet, MigrationSetError},
+    util::{empty_database_version, format_duration, read_artifact},
 };
 
+/// The database's version is not compatible with the artifact being applied.
+#[derive(Debug, Error)]
+#[error("Cannot apply: The database is not compatible with this artifact.")]
+pub struct IncompatibleError {
+    /// The database's version.
+    pub version: Version,
+    /// The versions the artifact is compatible with.
+    pub from: VersionReq,
+}
+
+/// Apply an artifact to the database. If `force` is set, it is applied even if it is
+/// not compatible with the database's version. If `parallel` is set & the artifact is a
+/// migration marked as parallel, its statements are applied across that many connections.
 pub fn apply_artifact<Db: Backend, A: Artifact>(
-    mut database: Db,
+    database: &mut Db,
     artifact: A,
+    force: bool,
+    parallel: Option<NonZeroUsize>,
 ) -> anyhow::Result<SqiglState>
 where
     <Db as Backend>::Error: Send + Sync + 'static,
 {
     info!("Applying migration {}", artifact.print());
+    if let Some(description) = artifact.description() {
+        info!("{}", description);
+    }
 
     let state = database.open()?;
     if !artifact.compatible(&state.project_version) {
-        return Err(anyhow!(
-            "Cannot apply: The database is not compatible with this artifact."
-        ));
+        if !force {
+            return Err(IncompatibleError {
+                version: state.project_version,
+                from: artifact.spec().0,
+            }
+            .into());
+        }
+        warn!(
+            "The database's version {} is not compatible with this artifact; applying it \
+            anyway because --force was given",
+            state.project_version
+        );
+    }
+    let start = Instant::now();
+    let state = match parallel {
+        Some(connections) if artifact.parallel() => {
+            warn!(
+                "Applying {} in parallel: It is not atomic, & may be partially applied if it \
+                fails",
+                artifact.print()
+            );
+            database.apply_parallel(&artifact, force, connections)?
+        }
+        _ => database.apply(&artifact, force)?,
+    };
+    let elapsed = format_duration(start.elapsed());
+    match artifact.summary() {
+        Some(summary) => info!("Applied {} in {}: {}", artifact.print(), elapsed, summary),
+        None => info!("Applied {} in {}", artifact.print(), elapsed),
+    }
+
+    // The migration has already been committed, so failing maintenance doesn't fail it
+    if let Some(maintenance) = artifact.post() {
+        info!("Running {}", maintenance);
+        if let Err(e) = database.maintain(maintenance) {
+            error!(
+                "{} failed: {}. The migration was applied, & need not be retried",
+                maintenance, e
+            );
+        }
     }
-    let state = database.apply(&artifact)?;
-    info!("Migration complete");
     Ok(state)
 }
 
+/// Migrate to `version`. If `force` is set and no chain of migrations leads there,
+/// the single migration to `version` from a version other than the empty database is
+/// applied regardless of compatibility. `parallel` is passed on to `apply_artifact`.
 pub fn apply_version<Db: Backend>(
     version: Version,
     info: &ProjectInfo,
     mut database: Db,
+    force: bool,
+    parallel: Option<NonZeroUsize>,
 ) -> anyhow::Result<()>
 where
     <Db as Backend>::Error: Sync + Send + 'static,
@@ -47,22 +108,519 @@ where
     debug!("Current version: {}", &state.project_version);
 
     let migration_set = MigrationSet::open(info)?;
-    if let Some(migration) = migration_set.get(&state.project_version, &version) {
-        apply_artifact(database, migration)?;
-        Ok(())
-    } else {
-        Err(anyhow!(
-            "No saved migration for {} -> {}",
+    if let Some(steps) = migration_set.path(&state.project_version, &version) {
+        if steps.is_empty() {
+            info!("The database is already at {}", &version);
+        }
+        // Each step is applied in its own transaction; if one fails, the database is
+        // left at the version of the last step which succeeded.
+        for step in steps {
+            apply_artifact(&mut database, step, false, parallel)?;
+        }
+        return Ok(());
+    }
+    if !force {
+        return Err(anyhow!(
+            "No saved migration or chain of migrations for {} -> {}",
             &state.project_version,
             &version
-        ))
+        ));
+    }
+
+    let mut candidates: Vec<_> = migration_set
+        .migrations()
+        .filter(|m| *m.version() == version && !m.compatible(&empty_database_version()))
+        .collect();
+    match candidates.len() {
+        1 => {
+            apply_artifact(&mut database, candidates.pop().unwrap(), true, parallel)?;
+            Ok(())
+        }
+        0 => Err(anyhow!("No saved migration to {} to force", &version)),
+        _ => Err(anyhow!(
+            "Cannot force: there are multiple saved migrations to {} ({})",
+            &version,
+            candidates
+                .iter()
+                .map(|m| m.print().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// The migrations which would update a database from one version to another, as
+/// printed by `sqigl database plan`. Applying a plan applies exactly these migrations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    pub title: String,
+    /// The database's version when the plan was made
+    pub from: Version,
+    pub to: Version,
+    pub steps: Vec<PlannedStep>,
+}
+impl Plan {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow!("{:?} is not a valid plan: {}", path, e))
+    }
+}
+
+/// A migration of a plan, identified by its content id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedStep {
+    pub from: VersionReq,
+    pub to: Version,
+    pub content_id: String,
+}
+impl PlannedStep {
+    fn new(step: &MigrationArtifact) -> anyhow::Result<Self> {
+        let (from, to) = step.spec();
+        Ok(Self {
+            from,
+            to,
+            content_id: step.write_to(io::sink())?.to_string(),
+        })
+    }
+}
+
+/// Plan the migrations which would update the database to `version`, or to the latest
+/// released version if none is given. Nothing is applied.
+pub fn plan_version<Db: Backend>(
+    version: Option<Version>,
+    info: &ProjectInfo,
+    mut database: Db,
+) -> anyhow::Result<Plan>
+where
+    <Db as Backend>::Error: Sync + Send + 'static,
+{
+    let migration_set = MigrationSet::open(info)?;
+    let version = match version {
+        Some(version) => version,
+        None => match migration_set.latest_released_version() {
+            Some(version) => version.clone(),
+            None => return Err(anyhow!("The project has no released versions")),
+        },
+    };
+    let state = database.open()?;
+    let Some(steps) = migration_set.path(&state.project_version, &version) else {
+        return Err(anyhow!(
+            "No saved migration or chain of migrations for {} -> {}",
+            &state.project_version,
+            &version
+        ));
+    };
+    info!(
+        "Planned {} migration(s) for {} -> {}",
+        steps.len(),
+        &state.project_version,
+        &version
+    );
+    Ok(Plan {
+        title: info.project.title.clone(),
+        from: state.project_version,
+        to: version,
+        steps: steps
+            .iter()
+            .map(PlannedStep::new)
+            .collect::<anyhow::Result<_>>()?,
+    })
+}
+
+/// Apply exactly the migrations of a plan. Nothing is applied if the database is no
+/// longer at the version the plan was made at, or the saved migrations no longer match
+/// the plan's, such as when one was edited after planning. `parallel` is passed on to
+/// `apply_artifact`.
+pub fn apply_plan<Db: Backend>(
+    plan: &Plan,
+    info: &ProjectInfo,
+    mut database: Db,
+    parallel: Option<NonZeroUsize>,
+) -> anyhow::Result<()>
+where
+    <Db as Backend>::Error: Sync + Send + 'static,
+{
+    info!("Applying the plan for {} -> {}", &plan.from, &plan.to);
+    if plan.title != info.project.title {
+        return Err(anyhow!(
+            "This plan is for {}, not {}",
+            plan.title,
+            info.project.title
+        ));
+    }
+    let state = database.open()?;
+    if state.project_version != plan.from {
+        return Err(anyhow!(
+            "Stale plan: The database is at {}, but the plan was made at {}",
+            &state.project_version,
+            &plan.from
+        ));
+    }
+
+    let Some(steps) = MigrationSet::open(info)?.path(&plan.from, &plan.to) else {
+        return Err(anyhow!(
+            "Stale plan: There is no longer a saved chain of migrations for {} -> {}",
+            &plan.from,
+            &plan.to
+        ));
+    };
+    let planned: Vec<_> = steps
+        .iter()
+        .map(PlannedStep::new)
+        .collect::<anyhow::Result<_>>()?;
+    if planned.len() != plan.steps.len() {
+        return Err(anyhow!(
+            "Stale plan: The saved migrations for {} -> {} now take {} step(s), not {}",
+            &plan.from,
+            &plan.to,
+            planned.len(),
+            plan.steps.len()
+        ));
+    }
+    for (step, expected) in planned.iter().zip(plan.steps.iter()) {
+        if step != expected {
+            return Err(anyhow!(
+                "Stale plan: The saved migration {} -> {} has content id {}, but the plan \
+                expected {} -> {} with content id {}",
+                step.from,
+                step.to,
+                step.content_id,
+                expected.from,
+                expected.to,
+                expected.content_id
+            ));
+        }
+    }
+
+    for step in steps {
+        apply_artifact(&mut database, step, false, parallel)?;
     }
+    Ok(())
 }
 
-pub fn check_artifact<Db: Backend, A: Artifact>(artifact: A, mut database: Db) -> anyhow::Result<()>
+/// Migrate to the latest released version of the project.
+pub fn apply_latest<Db: Backend>(
+    info: &ProjectInfo,
+    database: Db,
+    force: bool,
+    parallel: Option<NonZeroUsize>,
+) -> anyhow::Result<()>
+where
+    <Db as Backend>::Error: Sync + Send + 'static,
+{
+    let Some(version) = MigrationSet::open(info)?.latest_released_version().cloned() else {
+        return Err(anyhow!("The project has no released versions"));
+    };
+    apply_version(version, info, database, force, parallel)
+}
+
+/// Execute a one-off script against the database, outside of any migration.
+pub fn exec_script<Db: Backend>(script: &Path, mut database: Db, record: bool) -> anyhow::Result<()>
 where
     <Db as Backend>::Error: Send + Sync + 'static,
 {
-    database.check(&artifact)?;
+    info!("Executing {:?}", script);
+    let code = read_artifact(script)?;
+    database.open()?;
+    database.exec(&code, record)?;
     Ok(())
 }
+
+/// Check that an artifact applies without errors, rolling it back afterwards. If
+/// `isolated` is set, it is checked against a temporary empty database instead, so that
+/// nothing is written to the database.
+pub fn check_artifact<Db: Backend, A: Artifact>(
+    artifact: A,
+    mut database: Db,
+    isolated: bool,
+) -> anyhow::Result<()>
+where
+    <Db as Backend>::Error: Send + Sync + 'static,
+{
+    if isolated {
+        database.check_isolated(&artifact)?;
+    } else {
+        database.check(&artifact)?;
+    }
+    Ok(())
+}
+
+/// The result of checking each saved schema of a project against an empty database.
+pub struct SchemaChecks {
+    /// Each version with a saved schema, in ascending order, & the error applying it
+    /// raised, if any.
+    pub results: Vec<(Version, Option<String>)>,
+}
+impl SchemaChecks {
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|(_, e)| e.is_some()).count()
+    }
+}
+impl fmt::Display for SchemaChecks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (version, error) in self.results.iter() {
+            match error {
+                None => writeln!(f, "{}: ok", version)?,
+                Some(e) => writeln!(f, "{}: failed: {}", version, e)?,
+            }
+        }
+        writeln!(
+            f,
+            "{} of {} version(s) passed",
+            self.results.len() - self.failed(),
+            self.results.len()
+        )
+    }
+}
+
+/// Check that the schema saved for every version still applies to an empty database,
+/// such as to catch saved schemas which the database no longer accepts. Each is checked
+/// against a temporary database, so nothing is written to `database`. A failure is
+/// recorded rather than returned, so that every version is checked.
+pub fn check_all_versions<Db: Backend>(
+    info: &ProjectInfo,
+    mut database: Db,
+) -> Result<SchemaChecks, MigrationSetError> {
+    info!("Checking every saved schema of {}", info.project.title);
+    let migrations = MigrationSet::open(info)?;
+    let mut results = Vec::new();
+    for version in migrations.versions() {
+        let Some(schema) = migrations.get_schema(version) else {
+            continue;
+        };
+        debug!("Checking the schema of {}", version);
+        let error = database
+            .check_isolated(&schema)
+            .err()
+            .map(|e| e.to_string());
+        results.push((version.clone(), error));
+    }
+    Ok(SchemaChecks { results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::save::save_project, backend::sqlite::SqliteBackend, testing::TestProject,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn reports_broken_schemas() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        save_project(&project.info(), false).unwrap();
+        let manifest = fs::read_to_string(project.path().join("sqigl.toml")).unwrap();
+        project
+            .write("sqigl.toml", &manifest.replace("0.1.0", "0.2.0"))
+            .write("src/b.sql", "create table b(x integer);");
+        save_project(&project.info(), false).unwrap();
+        // A saved schema which no longer applies
+        project.write("artifacts/0.2.0/schema.sql", "create tabel b(x integer);");
+
+        let checks = check_all_versions(&project.info(), SqliteBackend::local().unwrap()).unwrap();
+        assert_eq!(checks.failed(), 1);
+        assert_eq!(checks.results.len(), 2);
+        assert_eq!(checks.results[0], (Version::new(0, 1, 0), None));
+        assert_eq!(checks.results[1].0, Version::new(0, 2, 0));
+        assert!(checks.results[1].1.is_some());
+        let report = checks.to_string();
+        assert!(report.contains("0.1.0: ok\n0.2.0: failed: "), "{report}");
+        assert!(report.ends_with("1 of 2 version(s) passed\n"), "{report}");
+    }
+
+    /// Save each `(from, to, script, code)` as the only migration in the artifact for `to`.
+    fn write_migrations(project: &TestProject, migrations: &[(&str, &str, &str, &str)]) {
+        for (from, to, script, code) in migrations {
+            let manifest = format!(
+                "[[migrations]]\nscript = \"{script}\"\nfrom = \"={from}\"\nto = \"{to}\"\n"
+            );
+            project
+                .write(&format!("artifacts/{to}/{script}"), code)
+                .write(&format!("artifacts/{to}/sqigl.toml"), &manifest);
+        }
+    }
+
+    #[test]
+    fn applies_latest_through_intermediate_versions() {
+        let project = TestProject::new("0.3.0");
+        write_migrations(
+            &project,
+            &[
+                ("0.0.0", "0.1.0", "schema.sql", "create table a(x integer);"),
+                (
+                    "0.1.0",
+                    "0.2.0",
+                    "from_0.1.0.sql",
+                    "create table b(x integer);",
+                ),
+                (
+                    "0.2.0",
+                    "0.3.0",
+                    "from_0.2.0.sql",
+                    "create table c(x integer);",
+                ),
+            ],
+        );
+        let info = project.info();
+        let path = project.path().join("db.sqlite");
+        let open = || SqliteBackend::open_file(&path, Duration::ZERO).unwrap();
+
+        // There is no migration directly from 0.1.0
+        apply_version(Version::new(0, 1, 0), &info, open(), false, None).unwrap();
+        apply_latest(&info, open(), false, None).unwrap();
+        let mut db = open();
+        assert_eq!(db.open().unwrap().project_version, Version::new(0, 3, 0));
+        let versions: Vec<_> = db
+            .history(&Default::default())
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.version.to_string())
+            .collect();
+        assert_eq!(versions, ["0.3.0", "0.2.0", "0.1.0"]);
+    }
+
+    #[test]
+    fn forces_incompatible_artifacts() {
+        let project = TestProject::new("0.3.0");
+        write_migrations(
+            &project,
+            &[
+                ("0.0.0", "0.1.0", "schema.sql", "create table a(x integer);"),
+                (
+                    "0.1.0",
+                    "0.2.0",
+                    "from_0.1.0.sql",
+                    "create table b(x integer);",
+                ),
+                (
+                    "0.1.0",
+                    "0.3.0",
+                    "from_0.1.0.sql",
+                    "create table c(x integer);",
+                ),
+            ],
+        );
+        let info = project.info();
+        let mut db = SqliteBackend::local().unwrap();
+        let migrations = MigrationSet::open(&info).unwrap();
+        for (from, to) in [("0.0.0", "0.1.0"), ("0.1.0", "0.2.0")] {
+            let step = migrations
+                .get(&from.parse().unwrap(), &to.parse().unwrap())
+                .unwrap();
+            apply_artifact(&mut db, step, false, None).unwrap();
+        }
+        let skip = migrations
+            .get(&Version::new(0, 1, 0), &Version::new(0, 3, 0))
+            .unwrap();
+
+        let e = apply_artifact(&mut db, skip.clone(), false, None).unwrap_err();
+        let incompatible = e.downcast_ref::<IncompatibleError>().unwrap();
+        assert_eq!(incompatible.version, Version::new(0, 2, 0));
+        assert_eq!(db.open().unwrap().project_version, Version::new(0, 2, 0));
+
+        let state = apply_artifact(&mut db, skip, true, None).unwrap();
+        assert_eq!(state.project_version, Version::new(0, 3, 0));
+        assert_eq!(db.history(&Default::default()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn maintains_after_commit() {
+        let project = TestProject::new("0.2.0");
+        project
+            .write(
+                "artifacts/0.1.0/schema.sql",
+                "create table a(x);
+                with recursive n(i) as (select 1 union all select i + 1 from n where i < 1000)
+                insert into a select randomblob(1000) from n;
+                delete from a;",
+            )
+            .write(
+                "artifacts/0.1.0/sqigl.toml",
+                "[[migrations]]\nscript = \"schema.sql\"\nfrom = \"=0.0.0\"\nto = \"0.1.0\"\n\
+                post = \"vacuum\"\n",
+            )
+            .write(
+                "artifacts/0.2.0/from_0.1.0.sql",
+                "create table b(x); create index b_x on b(x); insert into b values (1), (2);",
+            )
+            .write(
+                "artifacts/0.2.0/sqigl.toml",
+                "[[migrations]]\nscript = \"from_0.1.0.sql\"\nfrom = \"=0.1.0\"\nto = \"0.2.0\"\n\
+                post = \"analyze\"\n",
+            );
+        let info = project.info();
+        let path = project.path().join("db.sqlite");
+        let migrations = MigrationSet::open(&info).unwrap();
+        let mut db = SqliteBackend::open_file(&path, Duration::ZERO).unwrap();
+        let check = rusqlite::Connection::open(&path).unwrap();
+        let query = |sql: &str| -> i64 { check.query_row(sql, [], |row| row.get(0)).unwrap() };
+
+        // `vacuum` fails within a transaction, & would leave the deleted rows' pages free
+        let schema = migrations.get_schema(&Version::new(0, 1, 0)).unwrap();
+        apply_artifact(&mut db, schema, false, None).unwrap();
+        assert_eq!(query("pragma freelist_count"), 0);
+
+        let migration = migrations
+            .get(&Version::new(0, 1, 0), &Version::new(0, 2, 0))
+            .unwrap();
+        apply_artifact(&mut db, migration, false, None).unwrap();
+        assert_eq!(
+            query("select count(*) from sqlite_stat1 where tbl = 'b'"),
+            1
+        );
+    }
+
+    #[test]
+    fn rejects_stale_plans() {
+        let project = TestProject::new("0.2.0");
+        write_migrations(
+            &project,
+            &[
+                ("0.0.0", "0.1.0", "schema.sql", "create table a(x integer);"),
+                (
+                    "0.1.0",
+                    "0.2.0",
+                    "from_0.1.0.sql",
+                    "create table b(x integer);",
+                ),
+            ],
+        );
+        let info = project.info();
+        let path = project.path().join("db.sqlite");
+        let open = || SqliteBackend::open_file(&path, Duration::ZERO).unwrap();
+        let plan = plan_version(Some(Version::new(0, 2, 0)), &info, open()).unwrap();
+        assert_eq!(plan.steps.len(), 2);
+        // As saved by `database plan` & read by `database apply --plan`
+        let plan: Plan = serde_json::from_str(&serde_json::to_string(&plan).unwrap()).unwrap();
+
+        project.write(
+            "artifacts/0.2.0/from_0.1.0.sql",
+            "create table c(x integer);",
+        );
+        let e = apply_plan(&plan, &info, open(), None)
+            .unwrap_err()
+            .to_string();
+        assert!(e.starts_with("Stale plan: The saved migration "), "{e}");
+        assert_eq!(
+            open().open().unwrap().project_version,
+            Version::new(0, 0, 0)
+        );
+
+        project.write(
+            "artifacts/0.2.0/from_0.1.0.sql",
+            "create table b(x integer);",
+        );
+        apply_plan(&plan, &info, open(), None).unwrap();
+        assert_eq!(
+            open().open().unwrap().project_version,
+            Version::new(0, 2, 0)
+        );
+        // The database has moved on since the plan was made
+        let e = apply_plan(&plan, &info, open(), None)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("The database is at 0.2.0"), "{e}");
+    }
+}
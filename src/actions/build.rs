@@ -1,28 +1,45 @@
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     error,
+    ffi::OsStr,
     fmt::{Debug, Display},
-    fs::File,
+    fs::{self, File},
     io::{self, Read, Write},
-    path::{Path, PathBuf},
+    num::NonZeroUsize,
+    path::{Path, PathBuf, StripPrefixError},
     str::from_utf8,
+    string::FromUtf8Error,
 };
 
 use log::{info, trace, warn};
-use semver::Version;
+use semver::{Version, VersionReq};
+use serde::Serialize;
 use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 use crate::{
-    artifact::{Artifact, ContentId, ScriptConsumer, ScriptProcessingError},
+    artifact::{
+        is_bundle, read_bundle, Artifact, BundleError, ContentId, NullConsumerError,
+        ScriptConsumer, ScriptName, ScriptProcessingError,
+    },
     manifest::{
-        module::{self, open_module, ModuleInfo},
-        project::ProjectInfo,
+        module::{self, open_module, ModuleInfo, Script},
+        project::{self, open_project, ProjectInfo},
+        SOURCE_DIRECTORY,
     },
-    util::from_empty_database,
+    util::{from_empty_database, is_blank_sql, read_artifact, read_script},
 };
 
 pub const SQL_EXTENSION: &str = "sql";
+/// Scripts named `*.sql.tera` are Tera templates, rendered with the project's build
+/// context before they are included in the build.
+pub const TEMPLATE_EXTENSION: &str = "tera";
+/// A line beginning with this directive is replaced by the contents of the file at the
+/// path which follows it, resolved in the same way as dependencies.
+pub const INCLUDE_DIRECTIVE: &str = "-- sqigl: include ";
+/// A line beginning with this directive is replaced by the contents of the fragment
+/// which follows it, as declared in the project manifest.
+pub const FRAGMENT_DIRECTIVE: &str = "-- sqigl: fragment ";
 
 #[derive(Clone)]
 enum Task {
@@ -38,7 +55,7 @@ impl Task {
     }
 }
 
-fn canonicalize_dep_path(
+pub fn canonicalize_dep_path(
     dep: &Path,
     module_dir: &Path,
     source_dir: &Path,
@@ -70,6 +87,50 @@ fn canonicalize_dep_path(
     }
 }
 
+/// Extensions are compared case-insensitively, so that eg `.SQL` files are scripts.
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+fn is_template(path: &Path) -> bool {
+    has_extension(path, TEMPLATE_EXTENSION)
+        && path
+            .file_stem()
+            .is_some_and(|stem| has_extension(Path::new(stem), SQL_EXTENSION))
+}
+
+/// Whether a file is a script, either SQL or a template rendering SQL.
+pub fn is_script(path: &Path) -> bool {
+    has_extension(path, SQL_EXTENSION) || is_template(path)
+}
+
+/// Resolve an entry of a module's directory to a canonical path. Symlinks are followed,
+/// but must not lead outside of the source directory.
+pub fn resolve_child(
+    child: PathBuf,
+    module_dir: &Path,
+    source_dir: &Path,
+) -> Result<PathBuf, BuildError> {
+    if !child.symlink_metadata()?.is_symlink() {
+        return Ok(child);
+    }
+    let target = child.canonicalize()?;
+    if target.starts_with(source_dir) {
+        trace!(
+            "Following symlink {} to {}",
+            child.to_str().unwrap(),
+            target.to_str().unwrap()
+        );
+        Ok(target)
+    } else {
+        Err(BuildError::DependencyOutsideRoot {
+            module: module_dir.to_path_buf(),
+            dep: target,
+        })
+    }
+}
+
 fn dep_module_path(dep: &Path) -> &Path {
     if dep.is_dir() {
         dep
@@ -116,7 +177,7 @@ fn push_module(
     }
 }
 
-fn get_script_deps<'a, 'b>(path: &'a Path, module: &'b ModuleInfo) -> Option<&'b Vec<PathBuf>> {
+fn get_script_deps<'b>(path: &Path, module: &'b ModuleInfo) -> Option<&'b Vec<PathBuf>> {
     let script_name = path.file_name();
     debug_assert!(script_name.is_some());
     for script in module.scripts.iter() {
@@ -177,98 +238,192 @@ fn defer_module(
     }
 }
 
-fn process_module_task(
-    module: ModuleInfo,
-    depend_stack: &mut Vec<Task>,
-    defer_stack: &mut Vec<PathBuf>,
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DependencyKind {
+    /// A module or script implicitly depends on the module containing it.
+    Parent,
+    /// A dependency declared by the module's manifest.
+    Module,
+    /// A dependency exported by an ancestor of the module, which it inherits.
+    Inherited { ancestor: PathBuf },
+    /// A dependency declared by one of the module's scripts.
+    Script { script: PathBuf },
+}
+
+/// A dependency of a module (or one of its scripts) on another module or script.
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    pub kind: DependencyKind,
+    /// The canonical path of the module or script depended upon.
+    pub path: PathBuf,
+}
+
+/// The nearest ancestor of a module within the source directory which exports
+/// dependencies, if there is one.
+fn exporting_ancestor(
+    module: &ModuleInfo,
     source_dir: &Path,
-    completed_tasks: &BTreeSet<PathBuf>,
-) -> Result<bool, BuildError> {
+) -> Result<Option<ModuleInfo>, BuildError> {
+    let mut path = module.path.parent().map(Path::to_path_buf);
+    while let Some(ancestor) = path.filter(|p| p.starts_with(source_dir)) {
+        let ancestor = open_module(ancestor)?;
+        if !ancestor.module.export_dependencies.is_empty() {
+            return Ok(Some(ancestor));
+        }
+        path = ancestor.path.parent().map(Path::to_path_buf);
+    }
+    Ok(None)
+}
+
+/// Discover the dependencies of a module & its scripts, in the order they must be
+/// resolved: the parent module, then module-level dependencies (including inherited
+/// ones), then script-level dependencies.
+pub fn module_dependencies(
+    module: &ModuleInfo,
+    source_dir: &Path,
+) -> Result<Vec<Dependency>, BuildError> {
+    let mut dependencies = Vec::new();
     if let Some(parent) = module.path.parent() {
-        // If our parent directory is a directory module, we depend on it implicitly. It
-        // may not have been processed if the project contains horizontal dependencies.
-        if parent.starts_with(&source_dir) && !completed_tasks.contains(parent) {
-            push_module(
-                parent.to_path_buf(),
-                depend_stack,
-                &completed_tasks,
-                source_dir,
-            )?;
-            return Ok(false);
+        if parent.starts_with(source_dir) {
+            dependencies.push(Dependency {
+                kind: DependencyKind::Parent,
+                path: parent.to_path_buf(),
+            });
         }
     }
     for dep in module.module.dependencies.iter() {
-        // Push module-level dependencies
-        let dep_path = canonicalize_dep_path(&dep, &module.path, &source_dir)?;
-        let dep_module = dep_module_path(&dep_path);
-        if completed_tasks.contains(dep_module) {
-            continue;
-        } else if dep_module == module.path {
-            // It is redundant for a module to depend on it's own script. A module is
-            // never complete until all of it's scripts are complete. Ignore.
-            warn!(
-                "Module {} depends on itself or one of its own scripts; this is ignored.",
-                dep_module.to_str().unwrap()
-            );
-        } else if !dep_module.starts_with(&source_dir) {
+        let path = canonicalize_dep_path(dep, &module.path, source_dir)?;
+        if !dep_module_path(&path).starts_with(source_dir) {
             return Err(BuildError::DependencyOutsideRoot {
                 module: module.path.clone(),
-                dep: dep_path,
+                dep: path,
             });
-        } else {
-            push_module(
-                dep_module.to_path_buf(),
-                depend_stack,
-                &completed_tasks,
-                source_dir,
-            )?;
-            return Ok(false);
+        }
+        dependencies.push(Dependency {
+            kind: DependencyKind::Module,
+            path,
+        });
+    }
+    if module.module.inherit_dependencies {
+        if let Some(ancestor) = exporting_ancestor(module, source_dir)? {
+            for dep in ancestor.module.export_dependencies.iter() {
+                let path = canonicalize_dep_path(dep, &ancestor.path, source_dir)?;
+                // A module can't inherit a dependency on itself or its own contents,
+                // such as when the exported module itself inherits dependencies
+                if dep_module_path(&path).starts_with(&module.path) {
+                    trace!(
+                        "Not inheriting dependency {} of {}",
+                        path.to_str().unwrap(),
+                        module.path.to_str().unwrap()
+                    );
+                    continue;
+                }
+                dependencies.push(Dependency {
+                    kind: DependencyKind::Inherited {
+                        ancestor: ancestor.path.clone(),
+                    },
+                    path,
+                });
+            }
         }
     }
     for script in module.scripts.iter() {
-        // Push script-level dependencies which are outside of the module
         for dep in script.dependencies.iter() {
-            let dep_path = canonicalize_dep_path(dep, &module.path, &source_dir)?;
-            let dep_module = dep_module_path(&dep_path);
-            if !dep_module.starts_with(&source_dir) {
+            let path = canonicalize_dep_path(dep, &module.path, source_dir)?;
+            if !dep_module_path(&path).starts_with(source_dir) {
                 return Err(BuildError::DependencyOutsideRoot {
                     module: module.path.clone(),
-                    dep: dep_path,
+                    dep: path,
                 });
-            } else if dep_module != module.path {
-                push_module(
-                    dep_module.to_path_buf(),
-                    depend_stack,
-                    &completed_tasks,
-                    source_dir,
-                )?;
-                return Ok(false);
             }
+            dependencies.push(Dependency {
+                kind: DependencyKind::Script {
+                    script: module.path.join(&script.script),
+                },
+                path,
+            });
         }
     }
 
+    Ok(dependencies)
+}
+
+fn process_module_task(
+    module: ModuleInfo,
+    depend_stack: &mut Vec<Task>,
+    defer_stack: &mut Vec<PathBuf>,
+    source_dir: &Path,
+    completed_tasks: &BTreeSet<PathBuf>,
+) -> Result<bool, BuildError> {
+    for dep in module_dependencies(&module, source_dir)? {
+        let dep_module = dep_module_path(&dep.path);
+        if completed_tasks.contains(dep_module) {
+            continue;
+        }
+        match dep.kind {
+            // If our parent directory is a directory module, we depend on it implicitly.
+            // It may not have been processed if the project contains horizontal
+            // dependencies.
+            DependencyKind::Parent => (),
+            DependencyKind::Module if dep_module == module.path => {
+                // It is redundant for a module to depend on its own script. A module is
+                // never complete until all of its scripts are complete. Ignore.
+                warn!(
+                    "Module {} depends on itself or one of its own scripts; this is ignored.",
+                    dep_module.to_str().unwrap()
+                );
+                continue;
+            }
+            DependencyKind::Module | DependencyKind::Inherited { .. } => (),
+            // Dependencies on scripts within this module are resolved when the script
+            // is processed.
+            DependencyKind::Script { .. } if dep_module == module.path => continue,
+            DependencyKind::Script { .. } => (),
+        }
+        push_module(
+            dep_module.to_path_buf(),
+            depend_stack,
+            completed_tasks,
+            source_dir,
+        )?;
+        return Ok(false);
+    }
+
+    // The order of `read_dir` is platform-dependent. Children are sorted by their order
+    // in the manifest & then by name so that scripts & submodules without dependencies
+    // between them are always built in the same order.
+    let mut children = Vec::new();
     for child_res in module.path.read_dir()? {
+        let child = resolve_child(child_res?.path(), &module.path, source_dir)?;
+        let priority = module
+            .scripts
+            .iter()
+            .find(|script| script.script.file_name() == child.file_name())
+            .map(Script::priority)
+            .unwrap_or(i32::MAX);
+        children.push((priority, child));
+    }
+    children.sort();
+
+    let mut submodules = Vec::new();
+    for (_, child) in children {
         // Push children which are .sql scripts, defer children which are submodules
-        let child = child_res?.path();
         if completed_tasks.contains(&child) {
             continue;
         }
 
         let md = child.metadata()?;
         if md.is_dir() {
-            defer_module(child, defer_stack, &completed_tasks);
-        } else if md.is_file() && child.extension().map(|s| s.to_str()) == Some(Some(SQL_EXTENSION))
-        {
-            push_script(
-                child,
-                depend_stack,
-                &source_dir,
-                &completed_tasks,
-                source_dir,
-            )?;
+            submodules.push(child);
+        } else if md.is_file() && is_script(&child) {
+            push_script(child, depend_stack, source_dir, completed_tasks, source_dir)?;
             return Ok(false);
         }
     }
+    // The defer stack is last-in first-out, so submodules are deferred in reverse
+    for child in submodules.into_iter().rev() {
+        defer_module(child, defer_stack, completed_tasks);
+    }
 
     Ok(true)
 }
@@ -283,13 +438,13 @@ fn process_script_task(
     let module = open_module(module_path.to_path_buf())?;
     if let Some(deps) = get_script_deps(&path, &module) {
         for dep in deps.iter() {
-            let dep_path = canonicalize_dep_path(&dep, &module_path, &source_dir)?;
+            let dep_path = canonicalize_dep_path(dep, module_path, source_dir)?;
             if !completed_tasks.contains(&dep_path) {
                 debug_assert!(
                     dep_module_path(&dep_path) == module_path,
                     "All dependencies outside of the current module were resolved previously"
                 );
-                if dep.extension().map(|s| s.to_str()) != Some(Some(SQL_EXTENSION)) {
+                if !is_script(dep) {
                     return Err(BuildError::DependencyIllegal {
                         module: module.path.clone(),
                         dep: dep.to_path_buf(),
@@ -298,8 +453,8 @@ fn process_script_task(
                 push_script(
                     dep_path,
                     depend_stack,
-                    &source_dir,
-                    &completed_tasks,
+                    source_dir,
+                    completed_tasks,
                     source_dir,
                 )?;
                 return Ok(false);
@@ -310,6 +465,121 @@ fn process_script_task(
     Ok(true)
 }
 
+/// Render a template script with the build context.
+fn render_template(path: &Path, code: &str, context: &tera::Context) -> Result<String, BuildError> {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let mut tera = tera::Tera::default();
+    tera.add_raw_template(name, code)
+        .and_then(|_| tera.render(name, context))
+        .map_err(|e| {
+            // The innermost of Tera's errors describes the problem
+            let mut cause: &dyn error::Error = &e;
+            while let Some(source) = cause.source() {
+                cause = source;
+            }
+            BuildError::Template {
+                script: path.to_path_buf(),
+                message: cause.to_string(),
+            }
+        })
+}
+
+/// Reads scripts, rendering templates & expanding their directives.
+struct Expander<'a> {
+    source_dir: &'a Path,
+    context: tera::Context,
+    /// The paths of the project's fragments, by name.
+    fragments: BTreeMap<String, PathBuf>,
+    max_bytes: u64,
+    /// Every file which has been included by another.
+    included: BTreeSet<PathBuf>,
+}
+impl<'a> Expander<'a> {
+    fn new(info: &ProjectInfo, source_dir: &'a Path) -> Self {
+        Self {
+            source_dir,
+            context: tera::Context::from_serialize(&info.build.context)
+                .expect("A TOML table is always a valid context"),
+            fragments: info
+                .fragments
+                .iter()
+                .map(|(name, path)| (name.clone(), info.resolve_path(path)))
+                .collect(),
+            max_bytes: info.build.max_script_bytes(),
+            included: BTreeSet::new(),
+        }
+    }
+
+    /// Read a script, rendering it if it is a template and replacing any include
+    /// directives with the contents of the files they name. Fragment directives are
+    /// replaced with the contents of the fragments they name, as they are. Files larger
+    /// than `max_bytes` are rejected. `stack` holds the files currently being included,
+    /// to detect cycles.
+    fn expand(
+        &mut self,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+        output: &mut Vec<u8>,
+    ) -> Result<(), BuildError> {
+        if let Some(start) = stack.iter().position(|p| p == path) {
+            return Err(BuildError::DependencyCycle(DependencyCycle {
+                cycle_path: stack[start..].to_vec(),
+                root: self.source_dir.to_path_buf(),
+            }));
+        }
+        stack.push(path.to_path_buf());
+
+        let mut code = read_script(path, self.max_bytes)?;
+        if is_template(path) {
+            code = render_template(path, &code, &self.context)?;
+        }
+        let directory = path
+            .parent()
+            .expect("Canonical paths to files should always have a parent");
+        for line in code.split_inclusive('\n') {
+            if let Some(include) = line.trim_start().strip_prefix(INCLUDE_DIRECTIVE) {
+                let include_path =
+                    canonicalize_dep_path(Path::new(include.trim()), directory, self.source_dir)?;
+                if !include_path.is_file() {
+                    return Err(BuildError::DependencyIllegal {
+                        module: path.to_path_buf(),
+                        dep: include_path,
+                    });
+                }
+                trace!("Including {}", include_path.to_str().unwrap());
+                let mut included = Vec::with_capacity(1024);
+                self.expand(&include_path, stack, &mut included)?;
+                self.included.insert(include_path);
+                output.extend_from_slice(included.trim_ascii_end());
+                output.push(b'\n');
+            } else if let Some(name) = line.trim_start().strip_prefix(FRAGMENT_DIRECTIVE) {
+                let name = name.trim();
+                let Some(fragment_path) = self.fragments.get(name) else {
+                    return Err(BuildError::UndefinedFragment {
+                        script: path.to_path_buf(),
+                        fragment: name.to_string(),
+                    });
+                };
+                trace!("Inlining fragment {}", name);
+                let fragment = read_script(fragment_path, self.max_bytes).map_err(|e| {
+                    BuildError::FragmentUnreadable {
+                        fragment: name.to_string(),
+                        path: fragment_path.clone(),
+                        source: e,
+                    }
+                })?;
+                output.extend_from_slice(fragment.trim_end().as_bytes());
+                output.push(b'\n');
+            } else {
+                output.extend_from_slice(line.as_bytes());
+            }
+        }
+
+        stack.pop();
+        Ok(())
+    }
+}
+
 /// Topographically sort the scripts in our project using Kahn's algorithm
 pub fn build_project(info: &ProjectInfo) -> Result<BuildArtifact, BuildError> {
     info!(
@@ -337,8 +607,11 @@ pub fn build_project(info: &ProjectInfo) -> Result<BuildArtifact, BuildError> {
     if !source_dir.exists() {
         warn!("No source directory found");
         info!("Build complete");
-        return Ok(BuildArtifact::new(scripts, info));
+        return Ok(BuildArtifact::new(scripts, BTreeMap::new(), info));
     };
+    if !source_dir.is_dir() {
+        return Err(BuildError::SourceNotDirectory(source_dir));
+    }
 
     push_module(
         source_dir.clone(),
@@ -392,8 +665,115 @@ pub fn build_project(info: &ProjectInfo) -> Result<BuildArtifact, BuildError> {
         debug_assert!(first_time, "A task is never processed twice");
     }
 
+    // Resolve includes & templates now, so that errors are reported before the build
+    // is used. Files which are included by another script are part of that script,
+    // rather than scripts of their own.
+    let mut expander = Expander::new(info, &source_dir);
+    let mut sources = BTreeMap::new();
+    for script in scripts.iter() {
+        let mut buffer = Vec::with_capacity(1024);
+        expander.expand(script, &mut Vec::new(), &mut buffer)?;
+        let code = String::from_utf8(buffer).expect("Scripts & fragments are read as UTF-8");
+        sources.insert(script.clone(), code);
+    }
+    let included = expander.included;
+    for script in included.iter() {
+        trace!(
+            "{} is included, so it won't be built alone",
+            script.display()
+        );
+    }
+    scripts.retain(|script| !included.contains(script));
+    sources.retain(|script, _| !included.contains(script));
+
     info!("Build complete");
-    Ok(BuildArtifact::new(scripts, info))
+    Ok(BuildArtifact::new(scripts, sources, info))
+}
+
+/// Resolve the name of a module, relative to the source directory.
+fn resolve_module_name(name: &Path, source_dir: &Path) -> Result<PathBuf, BuildError> {
+    match canonicalize_dep_path(name, source_dir, source_dir) {
+        Ok(path) if path.is_dir() => Ok(path),
+        Ok(_) | Err(BuildError::DependencyDoesNotExist { .. }) => {
+            Err(BuildError::NoSuchModule(name.to_path_buf()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// A module & all of the modules beneath it.
+fn module_subtree(module: PathBuf, source_dir: &Path) -> Result<Vec<PathBuf>, BuildError> {
+    let mut subtree = Vec::new();
+    let mut modules = vec![module];
+    while let Some(path) = modules.pop() {
+        for child_res in path.read_dir()? {
+            let child = resolve_child(child_res?.path(), &path, source_dir)?;
+            if child.is_dir() {
+                modules.push(child);
+            }
+        }
+        subtree.push(path);
+    }
+    Ok(subtree)
+}
+
+/// The modules which `modules` depend on, directly or transitively, including
+/// themselves. Each is mapped to the module which depends on it, or `None` for those
+/// in `modules`.
+fn module_closure(
+    modules: impl IntoIterator<Item = PathBuf>,
+    source_dir: &Path,
+) -> Result<BTreeMap<PathBuf, Option<PathBuf>>, BuildError> {
+    let mut closure = BTreeMap::new();
+    let mut stack = Vec::new();
+    for module in modules {
+        if closure.insert(module.clone(), None).is_none() {
+            stack.push(module);
+        }
+    }
+    while let Some(path) = stack.pop() {
+        let module = open_module(path)?;
+        for dep in module_dependencies(&module, source_dir)? {
+            let dep_module = dep_module_path(&dep.path).to_path_buf();
+            if !closure.contains_key(&dep_module) {
+                closure.insert(dep_module.clone(), Some(module.path.clone()));
+                stack.push(dep_module);
+            }
+        }
+    }
+    Ok(closure)
+}
+
+/// Build the project containing `project_dir` (which may be the project root, any of
+/// its subdirectories, or its manifest), returning the built SQL and its content ID.
+///
+/// ```
+/// let (sql, id) = sqigl::actions::build::build_sql("test_cases/happy/simple".as_ref())?;
+/// println!("-- {}\n{}", id, sql);
+/// # assert!(sql.contains("create table foo"));
+/// # Ok::<(), sqigl::actions::build::BuildSqlError>(())
+/// ```
+pub fn build_sql(project_dir: &Path) -> Result<(String, ContentId), BuildSqlError> {
+    let info = open_project(project_dir.canonicalize()?)?;
+    let artifact = build_project(&info)?;
+
+    let mut buffer = Vec::with_capacity(1024);
+    let id = artifact.write_to(&mut buffer)?;
+    Ok((String::from_utf8(buffer)?, id))
+}
+
+#[derive(Error, Debug)]
+pub enum BuildSqlError {
+    #[error("I/O Error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Could not open project: {0}")]
+    Project(#[from] project::OpenError),
+    #[error("{0}")]
+    Build(#[from] BuildError),
+    #[error("{0}")]
+    Render(#[from] ScriptProcessingError<NullConsumerError>),
+    #[error("Encoding error: {0}")]
+    Utf8(#[from] FromUtf8Error),
 }
 
 #[derive(Error, Debug)]
@@ -412,6 +792,26 @@ pub enum BuildError {
     Io(#[from] io::Error),
     #[error("Could not read module manifest: {0}")]
     ModuleManifest(#[from] module::OpenError),
+    #[error("Could not render template {script}: {message}")]
+    Template { script: PathBuf, message: String },
+    #[error("Script {0} contains no SQL statements")]
+    BlankScript(PathBuf),
+    #[error("Several scripts are named {name}: {scripts:?}")]
+    DuplicateScriptName { name: String, scripts: Vec<PathBuf> },
+    #[error("No module named {0} exists")]
+    NoSuchModule(PathBuf),
+    #[error("The source directory {0} is not a directory")]
+    SourceNotDirectory(PathBuf),
+    #[error("Module {module} depends on module {dep}, which was skipped")]
+    DependsOnSkipped { module: PathBuf, dep: PathBuf },
+    #[error("Script {script} uses fragment {fragment}, which is not declared")]
+    UndefinedFragment { script: PathBuf, fragment: String },
+    #[error("Could not read fragment {fragment} from {path}")]
+    FragmentUnreadable {
+        fragment: String,
+        path: PathBuf,
+        source: io::Error,
+    },
 }
 
 pub struct DependencyCycle {
@@ -474,72 +874,261 @@ impl Debug for DependencyCycle {
 }
 impl error::Error for DependencyCycle {}
 
+pub const SPLIT_INDEX_FILENAME: &str = "index.toml";
+
+/// Lists the scripts of a split build in the order they must be applied.
+#[derive(Serialize)]
+struct SplitBuildIndex {
+    title: String,
+    version: Version,
+    content_id: String,
+    scripts: Vec<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum SplitBuildError {
+    #[error("Output directory {0} is not empty")]
+    NotEmpty(PathBuf),
+    #[error("I/O Error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Could not process path: {0}")]
+    Prefix(#[from] StripPrefixError),
+    #[error("{0}")]
+    Render(#[from] ScriptProcessingError<NullConsumerError>),
+    #[error("Could not write index: {0}")]
+    Index(#[from] toml::ser::Error),
+}
+
+/// Write the header which begins a build, `-- [ title version ]`, followed by a blank
+/// line.
+pub fn write_artifact_header(
+    mut f: impl Write,
+    title: &str,
+    version: &Version,
+) -> Result<(), io::Error> {
+    write!(f, "-- [ {} {} ]\n\n", title.trim_ascii(), version)
+}
+
+/// Recover the title & version of a build from the header written by
+/// `write_artifact_header` on its first line.
+pub fn parse_artifact_header(code: &str) -> Option<(String, Version)> {
+    let header = code.lines().next()?.trim_end();
+    // Titles may contain spaces, but versions can't
+    let (title, version) = header
+        .strip_prefix("-- [ ")?
+        .strip_suffix(" ]")?
+        .trim_end()
+        .rsplit_once(' ')?;
+    Some((title.trim_ascii().to_string(), version.parse().ok()?))
+}
+
 #[derive(Debug, Clone)]
 pub struct BuildArtifact {
+    /// The scripts in build order, relative to the source directory.
     scripts: Vec<PathBuf>,
     version: Version,
     source_dir: PathBuf,
     title: String,
+    /// The code of each script, with templates rendered & directives expanded.
+    sources: BTreeMap<PathBuf, String>,
+    /// Pass scripts to consumers in batches of up to this many bytes.
+    batch_size: Option<NonZeroUsize>,
 }
 impl BuildArtifact {
-    pub fn new(scripts: Vec<PathBuf>, info: &ProjectInfo) -> Self {
+    fn new(scripts: Vec<PathBuf>, sources: BTreeMap<PathBuf, String>, info: &ProjectInfo) -> Self {
+        let source_dir = info.source_dir();
+        let relative = |script: PathBuf| {
+            script
+                .strip_prefix(&source_dir)
+                .expect("Scripts are always within the source directory")
+                .to_path_buf()
+        };
         Self {
-            scripts,
+            scripts: scripts.into_iter().map(relative).collect(),
+            sources: sources
+                .into_iter()
+                .map(|(script, code)| (relative(script), code))
+                .collect(),
             version: info.project.version.clone(),
-            source_dir: info.source_dir(),
+            source_dir,
             title: info.project.title.clone(),
+            batch_size: None,
         }
     }
+    /// Pass consecutive scripts to consumers together, in batches of up to `batch_size`
+    /// bytes, rather than one at a time. Scripts are never split, so a script larger
+    /// than the batch size is passed alone. Batches are passed without names, & the
+    /// scripts within a batch are separated by a `;`, so that a script without a
+    /// trailing semicolon doesn't run into the next. The separators aren't hashed, so
+    /// the content id is that of the unbatched build, even though the code applied
+    /// differs from the code hashed by those separators.
+    pub fn with_batch_size(mut self, batch_size: Option<NonZeroUsize>) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
     pub fn set_version(&mut self, version: &Version) {
         self.version = version.clone();
     }
-}
-impl Artifact for BuildArtifact {
-    fn compatible(&self, version: &Version) -> bool {
-        // A build is a migration from 0.0.0
-        let req = from_empty_database();
-        req.matches(version)
+    /// Restrict the build to the modules named in `only` & the modules they depend on,
+    /// if any are named, and remove the modules named in `skip`. Modules are named
+    /// relative to the source directory, and include their submodules. The build order
+    /// is unchanged. Fails if a module which is kept depends on one which is skipped.
+    pub fn filter_modules(
+        mut self,
+        only: &[PathBuf],
+        skip: &[PathBuf],
+    ) -> Result<Self, BuildError> {
+        let source_dir = self.source_dir.clone();
+        if !only.is_empty() {
+            let mut selected = Vec::new();
+            for name in only {
+                let module = resolve_module_name(name, &source_dir)?;
+                selected.extend(module_subtree(module, &source_dir)?);
+            }
+            let keep = module_closure(selected, &source_dir)?;
+            self.scripts
+                .retain(|script| keep.contains_key(dep_module_path(&source_dir.join(script))));
+        }
+        if !skip.is_empty() {
+            let mut skipped = BTreeSet::new();
+            for name in skip {
+                let module = resolve_module_name(name, &source_dir)?;
+                skipped.extend(module_subtree(module, &source_dir)?);
+            }
+            self.scripts
+                .retain(|script| !skipped.contains(dep_module_path(&source_dir.join(script))));
+
+            let kept = self
+                .scripts
+                .iter()
+                .map(|script| dep_module_path(&source_dir.join(script)).to_path_buf());
+            for (module, dependent) in module_closure(kept, &source_dir)? {
+                if let (true, Some(dependent)) = (skipped.contains(&module), dependent) {
+                    return Err(BuildError::DependsOnSkipped {
+                        module: dependent,
+                        dep: module,
+                    });
+                }
+            }
+        }
+        Ok(self)
     }
-    fn version(&self) -> &Version {
-        &self.version
+    /// The scripts of this build in build order, relative to the source directory. This
+    /// is the order in which they appear in the rendered build.
+    pub fn ordered_scripts(&self) -> &[PathBuf] {
+        &self.scripts
     }
-    fn spec(&self) -> (semver::VersionReq, Version) {
-        (from_empty_database(), self.version.clone())
+    /// The modules of this build in build order, along with their scripts, relative to
+    /// the source directory (which is the empty path). Each module appears once,
+    /// because all of a module's scripts are built consecutively.
+    pub fn modules(&self) -> Vec<(&Path, &[PathBuf])> {
+        self.scripts
+            .chunk_by(|a, b| a.parent() == b.parent())
+            .map(|scripts| {
+                let module = scripts[0]
+                    .parent()
+                    .expect("Paths to files should always have a parent");
+                (module, scripts)
+            })
+            .collect()
+    }
+    /// Fail if scripts in different modules share a file name, since logs & build headers
+    /// which name scripts only by file name can't tell them apart.
+    pub fn check_duplicate_names(&self) -> Result<(), BuildError> {
+        let mut by_name: BTreeMap<&OsStr, Vec<PathBuf>> = BTreeMap::new();
+        for script in self.scripts.iter() {
+            let name = script
+                .file_name()
+                .expect("Paths to files should always have a file name");
+            by_name.entry(name).or_default().push(script.clone());
+        }
+        match by_name.into_iter().find(|(_, scripts)| scripts.len() > 1) {
+            Some((name, scripts)) => Err(BuildError::DuplicateScriptName {
+                name: name.to_string_lossy().into_owned(),
+                scripts,
+            }),
+            None => Ok(()),
+        }
+    }
+    /// Fail if any of the build's scripts contain only whitespace & comments, such as
+    /// scripts which were created but never written.
+    pub fn check_strict(&self) -> Result<(), BuildError> {
+        for script in self.scripts.iter() {
+            if is_blank_sql(&self.sources[script]) {
+                return Err(BuildError::BlankScript(script.clone()));
+            }
+        }
+        Ok(())
     }
+    /// Write the build to `directory` as one script per module, prefixed by their
+    /// position in the build, along with an index listing them in order. Concatenating
+    /// the scripts in order reproduces the build.
+    pub fn write_split(&self, directory: &Path) -> Result<ContentId, SplitBuildError> {
+        fs::create_dir_all(directory)?;
+        if directory.read_dir()?.next().is_some() {
+            return Err(SplitBuildError::NotEmpty(directory.to_path_buf()));
+        }
 
-    fn scripts<Consumer: ScriptConsumer>(
+        let mut names = Vec::new();
+        let mut owners = Vec::with_capacity(self.scripts.len());
+        for (idx, (module, scripts)) in self.modules().into_iter().enumerate() {
+            let module_name = if module.as_os_str().is_empty() {
+                SOURCE_DIRECTORY.to_string()
+            } else {
+                module.to_str().unwrap().replace('/', ".")
+            };
+            names.push(format!("{:03}_{}.{}", idx, module_name, SQL_EXTENSION));
+            owners.extend(std::iter::repeat_n(idx, scripts.len()));
+        }
+        if names.is_empty() {
+            // The header must be written somewhere
+            names.push(format!("000_{}.{}", SOURCE_DIRECTORY, SQL_EXTENSION));
+        }
+
+        let mut files = Vec::with_capacity(names.len());
+        for name in names.iter() {
+            files.push(File::create_new(directory.join(name))?);
+        }
+        let id = self.render::<NullConsumerError>(|script, batch| {
+            let owner = script.map(|name| owners[name.index - 1]).unwrap_or(0);
+            files[owner].write_all(batch.as_bytes())?;
+            Ok(())
+        })?;
+
+        let index = SplitBuildIndex {
+            title: self.title.clone(),
+            version: self.version.clone(),
+            content_id: id.to_string(),
+            scripts: names,
+        };
+        fs::write(
+            directory.join(SPLIT_INDEX_FILENAME),
+            toml::to_string(&index)?,
+        )?;
+        Ok(id)
+    }
+
+    /// Renders the build into batches, passing each to `accept` along with the name
+    /// of the script it contains (or `None` for the header).
+    fn render<E: error::Error + Debug>(
         &self,
-        mut consumer: Consumer,
-    ) -> Result<ContentId, ScriptProcessingError<Consumer::Error>> {
+        mut accept: impl FnMut(Option<ScriptName>, &str) -> Result<(), ScriptProcessingError<E>>,
+    ) -> Result<ContentId, ScriptProcessingError<E>> {
         let mut hasher = Sha256::new();
         let mut batch_buffer = Vec::<u8>::with_capacity(1024);
-        let mut read_buffer = Vec::<u8>::with_capacity(1024);
 
-        write!(
-            batch_buffer,
-            "-- [ {} {} ]\n\n",
-            self.title.trim_ascii(),
-            self.version
-        )?;
+        write_artifact_header(&mut batch_buffer, &self.title, &self.version)?;
         let batch = from_utf8(&batch_buffer)?;
         hasher.update(batch);
-        consumer.accept(batch)?;
+        accept(None, batch)?;
 
         let last_idx = self.scripts.len().saturating_sub(1);
         for (idx, script) in self.scripts.iter().enumerate() {
             batch_buffer.clear();
-            read_buffer.clear();
-
-            write!(
-                batch_buffer,
-                "-- [ {} ]\n\n",
-                script.strip_prefix(&self.source_dir)?.to_str().unwrap()
-            )?;
-
-            let mut f = File::open(script)?;
-            f.read_to_end(&mut read_buffer)?;
 
-            batch_buffer.write_all(read_buffer.trim_ascii())?;
+            let name = script.to_str().unwrap();
+            write!(batch_buffer, "-- [ {} ]\n\n", name)?;
+            batch_buffer.write_all(self.sources[script].trim_ascii().as_bytes())?;
             if idx != last_idx {
                 batch_buffer.write_all(b"\n\n")?;
             } else {
@@ -548,11 +1137,692 @@ impl Artifact for BuildArtifact {
 
             let batch = from_utf8(&batch_buffer)?;
             hasher.update(batch);
-            consumer.accept(batch)?;
+            let name = ScriptName {
+                name,
+                index: idx + 1,
+                total: self.scripts.len(),
+            };
+            accept(Some(name), batch)?;
         }
 
-        let id = hasher.finalize().into();
+        Ok(hasher.finalize().into())
+    }
+}
+impl Artifact for BuildArtifact {
+    fn compatible(&self, version: &Version) -> bool {
+        // A build is a migration from 0.0.0
+        let req = from_empty_database();
+        req.matches(version)
+    }
+    fn version(&self) -> &Version {
+        &self.version
+    }
+    fn spec(&self) -> (semver::VersionReq, Version) {
+        (from_empty_database(), self.version.clone())
+    }
+
+    fn scripts<Consumer: ScriptConsumer>(
+        &self,
+        mut consumer: Consumer,
+    ) -> Result<ContentId, ScriptProcessingError<Consumer::Error>> {
+        let Some(batch_size) = self.batch_size else {
+            let id = self.render(|name, batch| match name {
+                Some(name) => consumer.accept_named(name, batch),
+                None => consumer.accept(batch),
+            })?;
+            consumer.commit(id)?;
+            return Ok(id);
+        };
+
+        let mut pending = String::new();
+        let id = self.render(|_, batch| {
+            if !pending.is_empty() && pending.len() + batch.len() > batch_size.get() {
+                consumer.accept(&pending)?;
+                pending.clear();
+            }
+            if !pending.is_empty() {
+                pending.push_str(";\n");
+            }
+            pending.push_str(batch);
+            Ok(())
+        })?;
+        if !pending.is_empty() {
+            consumer.accept(&pending)?;
+        }
         consumer.commit(id)?;
         Ok(id)
     }
 }
+
+/// A build which was written to a file, such as by `sqigl project build <output>`. The
+/// title & version are recovered from the header the build begins with.
+#[derive(Debug, Clone)]
+pub struct FileArtifact {
+    title: String,
+    from: VersionReq,
+    version: Version,
+    code: String,
+}
+impl FileArtifact {
+    /// Open a build, or a bundle containing any artifact.
+    pub fn open(path: &Path) -> Result<Self, FileArtifactError> {
+        let code = read_artifact(path)?;
+        if is_bundle(&code) {
+            let bundle = read_bundle(&code)?;
+            return Ok(Self {
+                title: bundle.title,
+                from: bundle.from,
+                version: bundle.to,
+                code: bundle.code,
+            });
+        }
+        let Some((title, version)) = parse_artifact_header(&code) else {
+            return Err(FileArtifactError::NoHeader(path.to_path_buf()));
+        };
+        Ok(Self {
+            title,
+            // A build is a migration from 0.0.0
+            from: from_empty_database(),
+            version,
+            code,
+        })
+    }
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+}
+impl Artifact for FileArtifact {
+    fn compatible(&self, version: &Version) -> bool {
+        self.from.matches(version)
+    }
+    fn version(&self) -> &Version {
+        &self.version
+    }
+    fn spec(&self) -> (VersionReq, Version) {
+        (self.from.clone(), self.version.clone())
+    }
+
+    fn scripts<Consumer: ScriptConsumer>(
+        &self,
+        mut consumer: Consumer,
+    ) -> Result<ContentId, ScriptProcessingError<Consumer::Error>> {
+        let id = Sha256::digest(&self.code).into();
+        consumer.accept(&self.code)?;
+        consumer.commit(id)?;
+        Ok(id)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FileArtifactError {
+    #[error("I/O Error: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Bundle(#[from] BundleError),
+    #[error("{0} is not a build: It does not begin with a `-- [ title version ]` header")]
+    NoHeader(PathBuf),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{sqlite::SqliteBackend, Backend},
+        testing::TestProject,
+    };
+
+    #[test]
+    fn unreadable_fragment() {
+        let project = TestProject::new("0.1.0");
+        let manifest = fs::read_to_string(project.path().join("sqigl.toml")).unwrap();
+        project.write(
+            "sqigl.toml",
+            &format!("{manifest}\n[fragments]\nmissing = \"missing.sql\"\n"),
+        );
+        project.write("src/a.sql", "-- sqigl: fragment missing\n");
+
+        let e = build_project(&project.info()).unwrap_err();
+        assert!(matches!(e, BuildError::FragmentUnreadable { .. }));
+        // The cause is reported as the error's source, not repeated in its message
+        let source = std::error::Error::source(&e).unwrap().to_string();
+        assert!(!e.to_string().contains(&source), "{e}");
+    }
+
+    #[test]
+    fn expands_fragments() {
+        let project = TestProject::new("0.1.0");
+        let manifest = fs::read_to_string(project.path().join("sqigl.toml")).unwrap();
+        project
+            .write(
+                "sqigl.toml",
+                &format!("{manifest}\n[fragments]\naudit = \"fragments/audit.sql\"\n"),
+            )
+            .write(
+                "fragments/audit.sql",
+                "create table if not exists audit(x integer);\n",
+            )
+            .write(
+                "src/a/a.sql",
+                "-- sqigl: fragment audit\ncreate table a(x integer);",
+            )
+            .write(
+                "src/b/b.sql",
+                "create table b(x integer);\n  -- sqigl: fragment audit\n",
+            );
+        let artifact = build_project(&project.info()).unwrap();
+        let code = artifact.to_string();
+        assert_eq!(code.matches("create table if not exists audit").count(), 2);
+        assert!(!code.contains("sqigl: fragment"), "{code}");
+
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.apply(&artifact, false).unwrap();
+
+        // Fragments contribute to the content id
+        project.write(
+            "fragments/audit.sql",
+            "create table if not exists audit(x integer, y integer);\n",
+        );
+        let changed = build_project(&project.info()).unwrap();
+        assert_ne!(changed.content_id(), artifact.content_id());
+    }
+
+    #[test]
+    fn undefined_fragment() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "-- sqigl: fragment missing\n");
+        let e = build_project(&project.info()).unwrap_err();
+        let BuildError::UndefinedFragment { script, fragment } = e else {
+            panic!("Expected an undefined fragment, got {e}");
+        };
+        assert_eq!(fragment, "missing");
+        assert_eq!(script.file_name(), Some(OsStr::new("a.sql")));
+    }
+
+    #[test]
+    fn batches_scripts_without_trailing_semicolons() {
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/a.sql", "create table a(x integer)")
+            .write("src/b.sql", "create table b(x integer)\n-- no semicolon")
+            .write("src/c.sql", "create table c(x integer);");
+        let info = project.info();
+        let unbatched = build_project(&info).unwrap();
+        let batched = unbatched
+            .clone()
+            .with_batch_size(NonZeroUsize::new(1 << 20));
+
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        let state = db.apply(&batched, false).unwrap();
+        assert_eq!(state.project_version, Version::new(0, 1, 0));
+        let schema = db.introspect(&[]).unwrap();
+        for table in ["a", "b", "c"] {
+            assert!(
+                schema.tables.keys().any(|(_, name)| name == table),
+                "{table} wasn't created"
+            );
+        }
+
+        // Batching passes fewer scripts to the consumer, without changing the content id
+        let (unbatched_accepts, id) = count_accepts(&unbatched);
+        let (batched_accepts, batched_id) = count_accepts(&batched);
+        assert_eq!(unbatched_accepts, 4);
+        assert!(batched_accepts < unbatched_accepts, "{batched_accepts}");
+        assert_eq!(batched_id, id);
+        assert_eq!(db.applied_ids().unwrap(), vec![id]);
+    }
+
+    /// The number of scripts an artifact passes to its consumer, & the content id it
+    /// commits.
+    fn count_accepts(artifact: &BuildArtifact) -> (usize, ContentId) {
+        struct Counter<'a> {
+            accepts: usize,
+            committed: &'a mut Option<(usize, ContentId)>,
+        }
+        impl ScriptConsumer for Counter<'_> {
+            type Error = NullConsumerError;
+
+            fn accept(&mut self, _script: &str) -> Result<(), ScriptProcessingError<Self::Error>> {
+                self.accepts += 1;
+                Ok(())
+            }
+
+            fn commit(self, id: ContentId) -> Result<(), ScriptProcessingError<Self::Error>> {
+                *self.committed = Some((self.accepts, id));
+                Ok(())
+            }
+        }
+
+        let mut committed = None;
+        artifact
+            .scripts(Counter {
+                accepts: 0,
+                committed: &mut committed,
+            })
+            .unwrap();
+        committed.expect("The artifact wasn't committed")
+    }
+
+    #[test]
+    fn included_scripts_are_built_once() {
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/audit.sql", "create table audit(x integer);")
+            .write(
+                "src/posts.sql",
+                "-- sqigl: include audit.sql\ncreate table posts(x integer);",
+            );
+        let artifact = build_project(&project.info()).unwrap();
+        assert_eq!(artifact.ordered_scripts(), [PathBuf::from("posts.sql")]);
+
+        let mut code = String::new();
+        artifact
+            .render::<NullConsumerError>(|_, batch| {
+                code.push_str(batch);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(code.matches("create table audit").count(), 1);
+
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.apply(&artifact, false).unwrap();
+    }
+
+    #[test]
+    fn artifact_header_round_trips() {
+        let version = Version::new(1, 2, 3);
+        let mut header = Vec::new();
+        write_artifact_header(&mut header, "my project", &version).unwrap();
+        let header = String::from_utf8(header).unwrap();
+        assert_eq!(
+            parse_artifact_header(&header),
+            Some(("my project".to_string(), version.clone()))
+        );
+        // Trailing whitespace is tolerated
+        assert_eq!(
+            parse_artifact_header("-- [ my project 1.2.3 ]  \ncreate table a(x integer);"),
+            Some(("my project".to_string(), version))
+        );
+
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+        assert_eq!(
+            parse_artifact_header(&artifact.to_string()),
+            Some(("test".to_string(), Version::new(0, 1, 0)))
+        );
+    }
+
+    #[test]
+    fn malformed_artifact_header() {
+        for code in [
+            "",
+            "create table a(x integer);",
+            "-- [ my project ]",
+            "-- [ my project 1.2 ]",
+            "-- my project 1.2.3",
+            "\n-- [ my project 1.2.3 ]",
+        ] {
+            assert_eq!(parse_artifact_header(code), None, "{code}");
+        }
+    }
+
+    /// `payments` depends on `accounts`, & `reporting` is independent of both.
+    fn modular_project() -> TestProject {
+        let project = TestProject::new("0.1.0");
+        project
+            .write(
+                "src/accounts/accounts.sql",
+                "create table accounts(x integer);",
+            )
+            .write(
+                "src/payments/payments.sql",
+                "create table payments(x integer);",
+            )
+            .write(
+                "src/payments/sqigl.toml",
+                "[module]\ndependencies = [\"/accounts\"]\n",
+            )
+            .write(
+                "src/reporting/reports.sql",
+                "create table reports(x integer);",
+            );
+        project
+    }
+
+    #[test]
+    fn only_builds_modules_and_dependencies() {
+        let project = modular_project();
+        let artifact = build_project(&project.info())
+            .unwrap()
+            .filter_modules(&[PathBuf::from("payments")], &[])
+            .unwrap();
+        assert_eq!(
+            artifact.ordered_scripts(),
+            [
+                PathBuf::from("accounts/accounts.sql"),
+                PathBuf::from("payments/payments.sql")
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_removes_modules() {
+        let project = modular_project();
+        let artifact = build_project(&project.info())
+            .unwrap()
+            .filter_modules(&[], &[PathBuf::from("reporting")])
+            .unwrap();
+        assert_eq!(
+            artifact.ordered_scripts(),
+            [
+                PathBuf::from("accounts/accounts.sql"),
+                PathBuf::from("payments/payments.sql")
+            ]
+        );
+
+        // `payments` is kept, but depends on `accounts`
+        let e = build_project(&project.info())
+            .unwrap()
+            .filter_modules(&[], &[PathBuf::from("accounts")])
+            .unwrap_err();
+        assert!(matches!(e, BuildError::DependsOnSkipped { .. }), "{e}");
+
+        let e = build_project(&project.info())
+            .unwrap()
+            .filter_modules(&[], &[PathBuf::from("missing")])
+            .unwrap_err();
+        assert!(matches!(e, BuildError::NoSuchModule(_)), "{e}");
+    }
+
+    #[test]
+    fn oversized_script() {
+        let project = TestProject::new("0.1.0");
+        let manifest = fs::read_to_string(project.path().join("sqigl.toml")).unwrap();
+        project
+            .write(
+                "sqigl.toml",
+                &format!("{manifest}\n[build]\nmax_script_bytes = 64\n"),
+            )
+            .write("src/small.sql", "create table a(x integer);")
+            .write("src/large.sql", &"create table b(x integer);\n".repeat(8));
+
+        let e = build_project(&project.info()).unwrap_err();
+        let BuildError::Io(e) = e else {
+            panic!("Expected an I/O error, got {e}");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::FileTooLarge);
+        assert!(e.to_string().contains("large.sql"), "{e}");
+    }
+
+    #[test]
+    fn duplicate_script_names() {
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/a/create.sql", "create table a(x integer);")
+            .write("src/b/create.sql", "create table b(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+
+        let e = artifact.check_duplicate_names().unwrap_err();
+        let BuildError::DuplicateScriptName { name, scripts } = e else {
+            panic!("Expected a duplicate script name, got {e}");
+        };
+        assert_eq!(name, "create.sql");
+        assert_eq!(
+            scripts,
+            vec![PathBuf::from("a/create.sql"), PathBuf::from("b/create.sql")]
+        );
+
+        fs::remove_file(project.path().join("src/b/create.sql")).unwrap();
+        project.write("src/b/create_b.sql", "create table b(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+        artifact.check_duplicate_names().unwrap();
+    }
+
+    #[test]
+    fn ordered_scripts_are_rendered_in_order() {
+        let project = modular_project();
+        project
+            .write(
+                "src/payments/refunds.sql",
+                "create table refunds(x integer);",
+            )
+            .write("src/alerts/alerts.sql", "create table alerts(x integer);")
+            // `alerts` must be built after `reporting`, despite its name
+            .write(
+                "src/alerts/sqigl.toml",
+                "[module]\ndependencies = [\"/reporting\"]\n",
+            );
+        let artifact = build_project(&project.info()).unwrap();
+
+        let rendered = artifact.to_string();
+        let rendered: Vec<_> = rendered
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.strip_prefix("-- [ ")?.strip_suffix(" ]"))
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(artifact.ordered_scripts(), rendered);
+        assert_eq!(
+            rendered,
+            [
+                "accounts/accounts.sql",
+                "reporting/reports.sql",
+                "alerts/alerts.sql",
+                "payments/payments.sql",
+                "payments/refunds.sql"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn split_builds_concatenate_to_the_build() {
+        let project = modular_project();
+        let artifact = build_project(&project.info()).unwrap();
+        let directory = project.path().join("split");
+        let id = artifact.write_split(&directory).unwrap();
+
+        let index: toml::Table =
+            toml::from_str(&fs::read_to_string(directory.join(SPLIT_INDEX_FILENAME)).unwrap())
+                .unwrap();
+        assert_eq!(index["content_id"].as_str(), Some(id.to_string().as_str()));
+        let scripts = index["scripts"].as_array().unwrap();
+        assert_eq!(scripts.len(), 3);
+        let mut concatenated = String::new();
+        for script in scripts {
+            concatenated
+                .push_str(&fs::read_to_string(directory.join(script.as_str().unwrap())).unwrap());
+        }
+
+        let mut built = Vec::new();
+        assert_eq!(artifact.write_to(&mut built).unwrap(), id);
+        assert_eq!(concatenated.as_bytes(), built);
+        let path = project.path().join("concatenated.sql");
+        fs::write(&path, concatenated).unwrap();
+        assert_eq!(FileArtifact::open(&path).unwrap().content_id(), id);
+    }
+
+    #[test]
+    fn builds_siblings_in_name_order() {
+        let project = TestProject::new("0.1.0");
+        for path in [
+            "src/z.sql",
+            "src/m/m.sql",
+            "src/a.sql",
+            "src/b/b.sql",
+            "src/k.sql",
+        ] {
+            project.write(path, "select 1;");
+        }
+        let info = project.info();
+        let first = build_project(&info).unwrap();
+        // Scripts precede submodules
+        assert_eq!(
+            first.ordered_scripts(),
+            ["a.sql", "k.sql", "z.sql", "b/b.sql", "m/m.sql"].map(PathBuf::from)
+        );
+        for _ in 0..4 {
+            let build = build_project(&info).unwrap();
+            assert_eq!(build.ordered_scripts(), first.ordered_scripts());
+            assert_eq!(build.content_id(), first.content_id());
+        }
+    }
+
+    #[test]
+    fn extensions_are_case_insensitive() {
+        assert!(is_script(Path::new("a.sql")));
+        assert!(is_script(Path::new("a.SQL")));
+        assert!(is_script(Path::new("a.Sql.TERA")));
+        assert!(!is_script(Path::new("a.tera")));
+        assert!(!is_script(Path::new("a.sqlite")));
+
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/a.SQL", "select 1;")
+            .write("src/b.sql", "select 2;");
+        let build = build_project(&project.info()).unwrap();
+        assert_eq!(
+            build.ordered_scripts(),
+            ["a.SQL", "b.sql"].map(PathBuf::from)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_symlinks_within_the_source_directory() {
+        use std::os::unix::fs::symlink;
+
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/shared/a.sql", "select 1;")
+            .write("outside/b.sql", "select 2;");
+        let source_dir = project.path().join("src").canonicalize().unwrap();
+        let module_dir = source_dir.join("module");
+        fs::create_dir(&module_dir).unwrap();
+        symlink(source_dir.join("shared/a.sql"), module_dir.join("a.sql")).unwrap();
+        symlink(
+            project.path().join("outside/b.sql"),
+            module_dir.join("b.sql"),
+        )
+        .unwrap();
+
+        let plain = source_dir.join("shared/a.sql");
+        assert_eq!(
+            resolve_child(plain.clone(), &module_dir, &source_dir).unwrap(),
+            plain
+        );
+        assert_eq!(
+            resolve_child(module_dir.join("a.sql"), &module_dir, &source_dir).unwrap(),
+            plain
+        );
+        assert!(matches!(
+            resolve_child(module_dir.join("b.sql"), &module_dir, &source_dir),
+            Err(BuildError::DependencyOutsideRoot { .. })
+        ));
+    }
+
+    #[test]
+    fn inherits_dependencies_from_grandparents() {
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/types/types.sql", "create table types(x integer);")
+            .write(
+                "src/app/sqigl.toml",
+                "[module]\nexport_dependencies = [\"/types\"]\n",
+            )
+            .write("src/app/app.sql", "create table app(x integer);")
+            .write("src/app/mid/mid.sql", "create table mid(x integer);")
+            .write(
+                "src/app/mid/leaf/sqigl.toml",
+                "[module]\ninherit_dependencies = true\n",
+            )
+            .write("src/app/mid/leaf/leaf.sql", "create table leaf(x integer);");
+        let info = project.info();
+        let source_dir = info.source_dir();
+        let leaf = open_module(source_dir.join("app/mid/leaf")).unwrap();
+        let inherited: Vec<_> = module_dependencies(&leaf, &source_dir)
+            .unwrap()
+            .into_iter()
+            .filter(|dep| matches!(dep.kind, DependencyKind::Inherited { .. }))
+            .collect();
+        assert_eq!(inherited.len(), 1);
+        assert_eq!(
+            inherited[0].kind,
+            DependencyKind::Inherited {
+                ancestor: source_dir.join("app")
+            }
+        );
+        assert_eq!(inherited[0].path, source_dir.join("types"));
+
+        // Only the grandchild inherits, so the types follow the rest of `app`
+        assert_eq!(
+            build_project(&info).unwrap().ordered_scripts(),
+            [
+                "app/app.sql",
+                "app/mid/mid.sql",
+                "types/types.sql",
+                "app/mid/leaf/leaf.sql"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn strict_rejects_blank_scripts() {
+        for blank in ["", " \n\t\n", "-- nothing yet\n/* to do */\n"] {
+            let project = TestProject::new("0.1.0");
+            project
+                .write("src/a.sql", "create table a(x integer); -- a comment")
+                .write("src/b.sql", blank);
+            // Without `strict`, blank scripts are allowed
+            let build = build_project(&project.info()).unwrap();
+            match build.check_strict() {
+                Err(BuildError::BlankScript(script)) => assert!(script.ends_with("b.sql")),
+                result => panic!("{blank:?} was accepted: {result:?}"),
+            }
+        }
+
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "-- a comment\ncreate table a(x integer);");
+        build_project(&project.info())
+            .unwrap()
+            .check_strict()
+            .unwrap();
+    }
+
+    #[test]
+    fn source_must_be_a_directory() {
+        let project = TestProject::new("0.1.0");
+        fs::remove_dir(project.path().join("src")).unwrap();
+        project.write("src", "create table a(x integer);");
+        let info = project.info();
+        match build_project(&info) {
+            Err(BuildError::SourceNotDirectory(path)) => assert_eq!(path, info.source_dir()),
+            result => panic!("Expected SourceNotDirectory, got {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn order_overrides_names() {
+        let project = TestProject::new("0.1.0");
+        for script in ["a", "b", "c", "y", "z"] {
+            project.write(&format!("src/m/{script}.sql"), "select 1;");
+        }
+        project.write(
+            "src/m/sqigl.toml",
+            "[[scripts]]\nscript = \"z.sql\"\norder = -1\n\n\
+            [[scripts]]\nscript = \"c.sql\"\norder = 5\n\n\
+            [[scripts]]\nscript = \"y.sql\"\norder = 5\n\n\
+            [[scripts]]\nscript = \"b.sql\"\n",
+        );
+        // Ordered scripts come first, lowest first & then by name, followed by the
+        // unordered scripts by name
+        assert_eq!(
+            build_project(&project.info()).unwrap().ordered_scripts(),
+            ["m/z.sql", "m/c.sql", "m/y.sql", "m/a.sql", "m/b.sql"].map(PathBuf::from)
+        );
+    }
+}
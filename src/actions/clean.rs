@@ -0,0 +1,94 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+
+use crate::{manifest::project::ProjectInfo, migration::MigrationSet, util::normalize_version};
+
+/// Remove the artifact directories of prerelease versions which were never released.
+/// Returns the directories which were (or, if `dry_run` is set, would be) removed.
+pub fn clean_project(info: &ProjectInfo, dry_run: bool) -> anyhow::Result<Vec<PathBuf>> {
+    info!("Cleaning artifacts");
+
+    let migrations = MigrationSet::open(info)?;
+    let working_dir = info
+        .artifacts_dir()
+        .join(normalize_version(&info.project.version).to_string());
+
+    // A directory may contain migrations to several versions; it is only stale if
+    // none of them need to be kept.
+    let mut protected: BTreeSet<&Path> = migrations.released_entries().map(|(_, p)| p).collect();
+    let mut stale = BTreeSet::new();
+    for (version, path) in migrations.prerelease_entries() {
+        if path == working_dir || *version == info.project.version {
+            debug!("Keeping {}: Working version", version);
+            protected.insert(path);
+        } else if migrations.released_from(version) {
+            debug!("Keeping {}: Referenced by a released migration", version);
+            protected.insert(path);
+        } else {
+            stale.insert(path);
+        }
+    }
+
+    let mut removed = Vec::new();
+    for path in stale.difference(&protected) {
+        if dry_run {
+            info!("Would remove {}", path.to_str().unwrap());
+        } else {
+            info!("Removing {}", path.to_str().unwrap());
+            fs::remove_dir_all(path)?;
+        }
+        removed.push(path.to_path_buf());
+    }
+
+    info!("Clean complete");
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestProject;
+
+    /// Save a migration to `to` from `from`, as `project save` would.
+    fn save(project: &TestProject, from: &str, to: &str) {
+        let script = format!("from_{}.sql", from);
+        project
+            .write(&format!("artifacts/{to}/{script}"), "select 1;")
+            .write(
+                &format!("artifacts/{to}/sqigl.toml"),
+                &format!(
+                    "[[migrations]]\nscript = \"{script}\"\nfrom = \"={from}\"\nto = \"{to}\"\n"
+                ),
+            );
+    }
+
+    #[test]
+    fn removes_dangling_prereleases() {
+        let project = TestProject::new("0.3.0-working");
+        save(&project, "0.0.0", "0.1.0");
+        save(&project, "0.1.0", "0.2.0-dangling");
+        save(&project, "0.1.0", "0.2.0-released");
+        save(&project, "0.2.0-released", "0.2.0");
+        save(&project, "0.2.0", "0.3.0-working");
+        let info = project.info();
+        let dangling = info.artifacts_dir().join("0.2.0-dangling");
+
+        assert_eq!(clean_project(&info, true).unwrap(), vec![dangling.clone()]);
+        assert!(dangling.exists());
+
+        assert_eq!(clean_project(&info, false).unwrap(), vec![dangling.clone()]);
+        assert!(!dangling.exists());
+        for kept in ["0.1.0", "0.2.0-released", "0.2.0", "0.3.0-working"] {
+            assert!(
+                info.artifacts_dir().join(kept).exists(),
+                "{kept} was removed"
+            );
+        }
+        assert!(clean_project(&info, false).unwrap().is_empty());
+    }
+}
@@ -1,27 +1,33 @@
 use crate::{
-    arguments::DatabaseKind,
-    backend::Backend,
+    arguments::{DatabaseKind, PlanFormat},
+    artifact::Artifact,
+    backend::{postgres::ConnectionParams, Backend, GenerateOptions, HistoryFilter},
     manifest::{
         self,
-        artifact::update_artifact_migration,
-        project::{update_project_version, ProjectInfo, ProjectManifest},
-        ARTIFACTS_DIRECTORY, SOURCE_DIRECTORY,
+        artifact::{find_artifact_migration, update_artifact_migration},
+        module::{Module, ModuleManifest},
+        project::{update_project_version, Database, ProjectInfo, ProjectManifest},
+        ARTIFACTS_DIRECTORY, MANIFEST_FILENAME, SOURCE_DIRECTORY,
     },
-    migration::{save_migration, MigrationSet},
-    util::normalize_version,
+    migration::{save_migration, MigrationSet, MigrationSetError, SaveMigrationError},
+    util::{confirm, normalize_version, ConfirmError},
 };
 use anyhow::anyhow;
-use log::info;
+use log::{info, warn};
 use semver::{Prerelease, Version};
 use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    error::Error,
+    fmt::{self, Debug, Display, Write as _},
     fs::{self, File},
     io::{self, Write},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 use tempfile::tempdir;
 use toml_edit::DocumentMut;
 
-use super::build::SQL_EXTENSION;
+use super::build::{canonicalize_dep_path, SQL_EXTENSION};
 
 pub const PATCH_FILENAME_PREFIX: &str = "patch_";
 
@@ -48,6 +54,74 @@ pub fn create_project(
     Ok(())
 }
 
+/// Create a module at `path` within the source directory, with a manifest declaring
+/// `dependencies`. Returns the module's directory.
+pub fn create_module(
+    info: &ProjectInfo,
+    path: &Path,
+    dependencies: Vec<PathBuf>,
+) -> anyhow::Result<PathBuf> {
+    info!("Creating new module");
+
+    if !path.components().all(|c| matches!(c, Component::Normal(_))) || path.as_os_str().is_empty()
+    {
+        return Err(anyhow!(
+            "Module path must be relative to the source directory, without `.` or `..`"
+        ));
+    }
+    let source_dir = info.source_dir();
+    fs::create_dir_all(&source_dir)?;
+    let source_dir = source_dir.canonicalize()?;
+    let module_dir = source_dir.join(path);
+    if module_dir.exists() {
+        return Err(anyhow!("Module {} already exists", path.display()));
+    }
+
+    // The outermost directory we create, so that it can be removed if the module turns
+    // out to be invalid
+    let created = module_dir
+        .ancestors()
+        .take_while(|p| !p.exists())
+        .last()
+        .unwrap()
+        .to_path_buf();
+    if !created
+        .parent()
+        .unwrap()
+        .canonicalize()?
+        .starts_with(&source_dir)
+    {
+        return Err(anyhow!(
+            "Module {} would be outside the source directory",
+            path.display()
+        ));
+    }
+    fs::create_dir_all(&module_dir)?;
+
+    let validate = || -> anyhow::Result<()> {
+        for dep in dependencies.iter() {
+            canonicalize_dep_path(dep, &module_dir, &source_dir)?;
+        }
+        let manifest = ModuleManifest {
+            module: Module {
+                dependencies: dependencies.clone(),
+                ..Default::default()
+            },
+            scripts: Vec::new(),
+        };
+        let mut f = File::create_new(module_dir.join(MANIFEST_FILENAME))?;
+        f.write_all(toml::to_string(&manifest)?.as_bytes())?;
+        Ok(())
+    };
+    if let Err(e) = validate() {
+        fs::remove_dir_all(&created)?;
+        return Err(e);
+    }
+
+    info!("Created module {}", path.display());
+    Ok(module_dir)
+}
+
 pub fn new_feature(title: String, info: ProjectInfo) -> anyhow::Result<Version> {
     info!("Creating new feature version");
 
@@ -56,41 +130,180 @@ pub fn new_feature(title: String, info: ProjectInfo) -> anyhow::Result<Version>
             "Cannot create new feature version: Already on a feature version"
         ));
     }
+    if title.is_empty() {
+        return Err(anyhow!(
+            "Cannot create new feature version: The title must not be empty"
+        ));
+    }
+    let mut illegal = String::new();
+    for c in title.chars() {
+        if !(c.is_ascii_alphanumeric() || c == '-' || illegal.contains(c)) {
+            illegal.push(c);
+        }
+    }
+    if !illegal.is_empty() {
+        return Err(anyhow!(
+            "Cannot create new feature version: The title {:?} contains illegal characters {:?} \
+            (must match `[0-9A-Za-z-]+`)",
+            title,
+            illegal
+        ));
+    }
+
     let mut new_version = info.project.version.clone();
     new_version.minor = info.project.version.minor + 1;
     new_version.pre = Prerelease::new(&title)?;
 
+    let version_dir = info
+        .artifacts_dir()
+        .join(normalize_version(&new_version).to_string());
+    if version_dir.exists() {
+        return Err(anyhow!(
+            "Cannot create new feature version: {} already exists",
+            &new_version
+        ));
+    }
+
     update_project_version(&new_version, &info)?;
 
     info!("Switched to feature version {}", &new_version);
     Ok(new_version)
 }
 
-pub fn create_migration(from: Version, to: Version, info: &ProjectInfo) -> anyhow::Result<()> {
+/// A feature version which has been saved, but not released.
+pub struct Feature {
+    /// The version the feature will be released as, without prerelease information
+    pub version: Version,
+    /// The feature's ID, from the version's prerelease information
+    pub id: String,
+    /// The artifact directory containing the feature, relative to the project root
+    pub directory: PathBuf,
+}
+impl Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}",
+            self.version,
+            self.id,
+            self.directory.display()
+        )
+    }
+}
+
+/// List the feature versions saved in the project's artifact directory, in order of
+/// their versions.
+pub fn list_features(info: &ProjectInfo) -> anyhow::Result<Vec<Feature>> {
+    Ok(MigrationSet::open(info)?
+        .prerelease_entries()
+        .map(|(version, directory)| Feature {
+            version: Version::new(version.major, version.minor, version.patch),
+            id: version.pre.to_string(),
+            directory: directory
+                .strip_prefix(&info.root)
+                .unwrap_or(directory)
+                .to_path_buf(),
+        })
+        .collect())
+}
+
+/// Create an empty migration. If `exact` is set, it is only compatible with the patch
+/// version of `from`, rather than its minor version. If `with_down` is set, an empty
+/// down script is created alongside it. No files are created if any already exist.
+pub fn create_migration(
+    from: Version,
+    to: Version,
+    info: &ProjectInfo,
+    exact: bool,
+    with_down: bool,
+) -> anyhow::Result<()> {
     info!("Creating new migration");
 
-    let script_name = format!("from_{}.sql", from);
+    let script_name = PathBuf::from(format!("from_{}.sql", from));
+    let down_name = with_down.then(|| PathBuf::from(format!("from_{}.down.sql", from)));
     let artifact_dir = info
         .artifacts_dir()
         .join(normalize_version(&to).to_string());
-    let path = artifact_dir.join(&script_name);
-    if path.exists() {
-        Err(anyhow!(
+    let paths: Vec<_> = [Some(&script_name), down_name.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(|name| artifact_dir.join(name))
+        .collect();
+    if let Some(path) = paths.iter().find(|path| path.exists()) {
+        return Err(anyhow!(
             "Cannot create migration: {} already exists.",
             path.to_str().unwrap()
-        ))
-    } else {
-        let _ = File::create_new(path)?;
-        update_artifact_migration(
-            crate::manifest::artifact::Migration {
-                script: Path::new(&script_name).to_path_buf(),
-                from: crate::util::from_minor_version(&from),
-                to,
-            },
-            artifact_dir,
-        )?;
-        Ok(())
+        ));
+    }
+
+    // A manifest entry left behind by a migration whose script was deleted keeps its
+    // settings
+    let existing = find_artifact_migration(&script_name, artifact_dir.clone())?;
+    let (parallel, post, description, down) = match existing {
+        Some(existing) => (
+            existing.parallel,
+            existing.post,
+            existing.description,
+            existing.down,
+        ),
+        None => Default::default(),
+    };
+    let mut created = Vec::new();
+    for path in paths.iter() {
+        if let Err(e) = File::create_new(path) {
+            // Don't leave behind a migration without its down script, or vice versa
+            for path in created {
+                let _ = fs::remove_file(path);
+            }
+            return Err(e.into());
+        }
+        created.push(path);
     }
+    update_artifact_migration(
+        crate::manifest::artifact::Migration {
+            script: script_name,
+            from: crate::util::from_version(&from, exact),
+            to,
+            parallel,
+            post,
+            description,
+            down: down_name.or(down),
+        },
+        artifact_dir,
+    )?;
+    Ok(())
+}
+
+/// An error generating a migration. Failures of the database are returned as
+/// `Database`, & other failures to generate or verify the migration as `Generate`.
+#[derive(thiserror::Error, Debug)]
+pub enum GenerateError<DatabaseError: Error + Debug = Infallible> {
+    #[error("{0}")]
+    MigrationSet(#[from] MigrationSetError),
+    #[error("Could not find schema for {0}")]
+    SchemaNotFound(Version),
+    #[error("{0}")]
+    Database(DatabaseError),
+    #[error("Failed to generate migration")]
+    Generate(#[source] anyhow::Error),
+    #[error("Could not serialize plan: {0}")]
+    Plan(#[from] serde_json::Error),
+    #[error("{0}")]
+    Confirm(#[from] ConfirmError),
+    #[error("Migration was not saved")]
+    NotSaved,
+    #[error("{0}")]
+    Save(#[from] SaveMigrationError),
+}
+
+/// What to do with a generated migration.
+#[derive(Debug, Clone, Copy)]
+pub enum GenerateOutput {
+    /// Print the migration's statements in this format, rather than saving it.
+    Plan(PlanFormat),
+    /// Save the migration, gzip-compressed if `compress` is set. Migrations which may
+    /// destroy data are saved only with confirmation, unless `yes` is set.
+    Save { compress: bool, yes: bool },
 }
 
 pub fn generate_migration<Db: Backend>(
@@ -98,24 +311,140 @@ pub fn generate_migration<Db: Backend>(
     to: Version,
     database: &mut Db,
     info: &ProjectInfo,
-) -> anyhow::Result<()> {
+    options: &GenerateOptions,
+    output: GenerateOutput,
+) -> Result<(), GenerateError<Db::Error>>
+where
+    <Db as Backend>::Error: Send + Sync + 'static,
+{
     info!("Generating migration");
 
     let migration_set = MigrationSet::open(info)?;
     let Some(from_schema) = migration_set.get_schema(&from) else {
-        return Err(anyhow!("Could not find schema for {}", from));
+        return Err(GenerateError::SchemaNotFound(from));
     };
     let Some(to_schema) = migration_set.get_schema(&to) else {
-        return Err(anyhow!("Could not find schema for {}", to));
+        return Err(GenerateError::SchemaNotFound(to));
     };
-    let artifact = database.generate_migration(&from_schema, &to_schema)?;
+    let artifact = database
+        .generate_migration(&from_schema, &to_schema, options)
+        .map_err(|e| match e.downcast::<Db::Error>() {
+            Ok(e) => GenerateError::Database(e),
+            Err(e) => GenerateError::Generate(e),
+        })?;
     let title = format!("from_{}.sql", &from);
+    if let Some(summary) = artifact.summary() {
+        info!("{} -> {}: {}", &from, &to, summary);
+    }
+    let (compress, yes) = match output {
+        GenerateOutput::Plan(format) => {
+            let statements = artifact.plan().unwrap_or_default();
+            match format {
+                PlanFormat::Sql => {
+                    for stmt in statements.iter() {
+                        println!("{}", stmt.sql);
+                    }
+                }
+                PlanFormat::Json => println!("{}", serde_json::to_string_pretty(&statements)?),
+            }
+            return Ok(());
+        }
+        GenerateOutput::Save { compress, yes } => (compress, yes),
+    };
 
-    let path = save_migration(&title, artifact, info)?;
+    let destructive = artifact.destructive_statements();
+    if !destructive.is_empty() {
+        warn!("The generated migration contains statements which may destroy data:");
+        for stmt in destructive.iter() {
+            warn!("    {}", stmt);
+        }
+        if !yes && !confirm("Save this migration?")? {
+            return Err(GenerateError::NotSaved);
+        }
+    }
+
+    let path = save_migration(&title, artifact, info, compress)?;
     info!("Generated migration at {:?}", path);
     Ok(())
 }
 
+pub fn ping_database<Db: Backend>(mut database: Db) -> anyhow::Result<()>
+where
+    <Db as Backend>::Error: Sync + Send + 'static,
+{
+    match database.ping()? {
+        Some(version) => println!("ok (server version {})", version),
+        None => println!("ok"),
+    }
+    Ok(())
+}
+
+/// Print the database's history. Entries matching a migration of the project which has
+/// a description are followed by it.
+pub fn show_history<Db: Backend>(
+    mut database: Db,
+    filter: &HistoryFilter,
+    info: &ProjectInfo,
+) -> anyhow::Result<()>
+where
+    <Db as Backend>::Error: Sync + Send + 'static,
+{
+    let mut descriptions = BTreeMap::new();
+    for migration in MigrationSet::open(info)?.migrations() {
+        if let Some(description) = migration.description() {
+            let id = migration.write_to(io::sink())?;
+            descriptions.insert(id, description.to_string());
+        }
+    }
+
+    for entry in database.history(filter)? {
+        match descriptions.get(&entry.content_id) {
+            Some(description) => println!("{}\t{}", entry, description),
+            None => println!("{}", entry),
+        }
+    }
+    Ok(())
+}
+
+/// Describe the database connection of a project without connecting to it.
+pub fn database_info(info: &ProjectInfo) -> anyhow::Result<String> {
+    let mut out = String::new();
+    match &info.database {
+        Database::Postgres(params) => {
+            let mut params = ConnectionParams::resolve(params)?;
+            // Describing the connection shouldn't fail just because it would
+            if let Err(e) = params.resolve_pgpass() {
+                warn!("{}", e);
+            }
+            writeln!(out, "database: postgres")?;
+            write!(out, "{}", params)?;
+        }
+        Database::Sqlite(params) => {
+            writeln!(out, "database: sqlite")?;
+            match &params.path {
+                Some(path) => writeln!(out, "path: {}", info.resolve_path(path).display())?,
+                None => writeln!(out, "path: in-memory")?,
+            }
+            for (name, path) in params.attach.iter() {
+                writeln!(
+                    out,
+                    "attach: {} = {}",
+                    name,
+                    info.resolve_path(path).display()
+                )?;
+            }
+            writeln!(out, "detect renames: {}", params.detect_renames)?;
+            if let Some(mode) = params.journal_mode {
+                writeln!(out, "journal mode: {}", mode)?;
+            }
+            if let Some(synchronous) = params.synchronous {
+                writeln!(out, "synchronous: {}", synchronous)?;
+            }
+        }
+    }
+    Ok(out)
+}
+
 pub fn install_sqigl<Db: Backend>(mut database: Db) -> anyhow::Result<()>
 where
     <Db as Backend>::Error: Sync + Send + 'static,
@@ -128,3 +457,238 @@ where
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::save::save_project, backend::sqlite::SqliteBackend, testing::TestProject,
+    };
+
+    #[test]
+    fn create_keeps_migration_settings() {
+        let project = TestProject::new("0.2.0");
+        project.write(
+            "artifacts/0.2.0/sqigl.toml",
+            "[[migrations]]\nscript = \"from_0.1.0.sql\"\nfrom = \"=0.1\"\nto = \"0.2.0\"\n\
+            description = \"Adds a\"\npost = \"vacuum\"\n",
+        );
+        let info = project.info();
+        create_migration(
+            Version::new(0, 1, 0),
+            Version::new(0, 2, 0),
+            &info,
+            false,
+            true,
+        )
+        .unwrap();
+
+        let version_dir = info.artifacts_dir().join("0.2.0");
+        assert!(version_dir.join("from_0.1.0.sql").is_file());
+        let migrations = crate::manifest::artifact::open_artifact(version_dir)
+            .unwrap()
+            .migrations;
+        assert_eq!(migrations.len(), 1);
+        assert_eq!(migrations[0].description.as_deref(), Some("Adds a"));
+        assert_eq!(
+            migrations[0].post,
+            Some(crate::manifest::artifact::Maintenance::Vacuum)
+        );
+        assert_eq!(
+            migrations[0].down.as_deref(),
+            Some(Path::new("from_0.1.0.down.sql"))
+        );
+    }
+
+    #[test]
+    fn creates_down_scripts() {
+        let project = TestProject::new("0.3.0");
+        let info = project.info();
+        let create = |from: Version, to: Version| create_migration(from, to, &info, false, true);
+        fs::create_dir(info.artifacts_dir().join("0.2.0")).unwrap();
+        create(Version::new(0, 1, 0), Version::new(0, 2, 0)).unwrap();
+
+        let version_dir = info.artifacts_dir().join("0.2.0");
+        for script in ["from_0.1.0.sql", "from_0.1.0.down.sql"] {
+            assert_eq!(fs::read_to_string(version_dir.join(script)).unwrap(), "");
+        }
+        let migrations = crate::manifest::artifact::open_artifact(version_dir)
+            .unwrap()
+            .migrations;
+        assert_eq!(migrations.len(), 1);
+        assert_eq!(migrations[0].script, Path::new("from_0.1.0.sql"));
+        assert_eq!(
+            migrations[0].down.as_deref(),
+            Some(Path::new("from_0.1.0.down.sql"))
+        );
+
+        // Neither script is created if the down script already exists
+        project.write("artifacts/0.3.0/from_0.2.0.down.sql", "drop table a;");
+        assert!(create(Version::new(0, 2, 0), Version::new(0, 3, 0)).is_err());
+        let version_dir = info.artifacts_dir().join("0.3.0");
+        assert!(!version_dir.join("from_0.2.0.sql").exists());
+        assert_eq!(
+            fs::read_to_string(version_dir.join("from_0.2.0.down.sql")).unwrap(),
+            "drop table a;"
+        );
+    }
+
+    #[test]
+    fn exact_migrations_match_one_patch() {
+        let project = TestProject::new("0.2.0");
+        let info = project.info();
+        for (from, exact) in [
+            (Version::new(0, 1, 1), true),
+            (Version::new(0, 3, 1), false),
+        ] {
+            let to = Version::new(from.major, from.minor + 1, 0);
+            fs::create_dir(info.artifacts_dir().join(to.to_string())).unwrap();
+            create_migration(from, to, &info, exact, false).unwrap();
+        }
+        let migrations = MigrationSet::open(&info).unwrap();
+        let get =
+            |from: &str, to: &str| migrations.get(&from.parse().unwrap(), &to.parse().unwrap());
+
+        assert!(get("0.1.1", "0.2.0").is_some());
+        assert!(get("0.1.0", "0.2.0").is_none());
+        assert!(get("0.1.2", "0.2.0").is_none());
+        assert!(migrations
+            .latest_compatible(&Version::new(0, 1, 2))
+            .is_none());
+        // Without `exact`, any patch of the minor version matches
+        assert!(get("0.3.0", "0.4.0").is_some());
+        assert!(get("0.3.2", "0.4.0").is_some());
+    }
+
+    #[test]
+    fn generate_without_schema() {
+        let project = TestProject::new("0.2.0");
+        let mut db = SqliteBackend::local().unwrap();
+        let result = generate_migration(
+            Version::new(0, 1, 0),
+            Version::new(0, 2, 0),
+            &mut db,
+            &project.info(),
+            &Default::default(),
+            GenerateOutput::Save {
+                compress: false,
+                yes: true,
+            },
+        );
+        assert!(
+            matches!(
+                result,
+                Err(GenerateError::SchemaNotFound(ref v)) if *v == Version::new(0, 1, 0)
+            ),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn feature_titles() {
+        let project = TestProject::new("0.1.0");
+        let version = new_feature("add-payments2".to_string(), project.info()).unwrap();
+        assert_eq!(version, "0.2.0-add-payments2".parse().unwrap());
+        assert_eq!(project.info().project.version, version);
+
+        let project = TestProject::new("0.1.0");
+        for title in ["add_payments", "add payments"] {
+            let e = new_feature(title.to_string(), project.info()).unwrap_err();
+            assert!(e.to_string().contains("illegal characters"), "{e}");
+        }
+        let e = new_feature("a_b c_".to_string(), project.info()).unwrap_err();
+        // Each illegal character is named once
+        assert!(e.to_string().contains("\"_ \""), "{e}");
+        let e = new_feature(String::new(), project.info()).unwrap_err();
+        assert!(e.to_string().contains("must not be empty"), "{e}");
+        project.write("artifacts/0.2.0-payments/sqigl.toml", "");
+        let e = new_feature("payments".to_string(), project.info()).unwrap_err();
+        assert!(e.to_string().contains("already exists"), "{e}");
+        assert_eq!(project.info().project.version, Version::new(0, 1, 0));
+    }
+
+    #[test]
+    fn lists_features() {
+        let project = TestProject::new("0.1.0");
+        let manifest = fs::read_to_string(project.path().join("sqigl.toml")).unwrap();
+        project.write("src/a.sql", "create table a(x integer);");
+        save_project(&project.info(), false).unwrap();
+        for title in ["reports", "payments"] {
+            new_feature(title.to_string(), project.info()).unwrap();
+            save_project(&project.info(), false).unwrap();
+            project.write("sqigl.toml", &manifest);
+        }
+
+        let features = list_features(&project.info()).unwrap();
+        let features: Vec<_> = features
+            .iter()
+            .map(|f| (f.version.to_string(), f.id.as_str(), f.directory.as_path()))
+            .collect();
+        assert_eq!(
+            features,
+            vec![
+                (
+                    "0.2.0".to_string(),
+                    "payments",
+                    Path::new("artifacts/0.2.0-payments")
+                ),
+                (
+                    "0.2.0".to_string(),
+                    "reports",
+                    Path::new("artifacts/0.2.0-reports")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn creates_nested_modules() {
+        let project = TestProject::new("0.1.0");
+        project.write(
+            "src/accounts/accounts.sql",
+            "create table accounts(x integer);",
+        );
+        let info = project.info();
+
+        let module = create_module(
+            &info,
+            Path::new("payments/refunds"),
+            vec![PathBuf::from("/accounts")],
+        )
+        .unwrap();
+        assert_eq!(
+            module,
+            info.source_dir()
+                .canonicalize()
+                .unwrap()
+                .join("payments/refunds")
+        );
+        let manifest: toml::Table =
+            toml::from_str(&fs::read_to_string(module.join(MANIFEST_FILENAME)).unwrap()).unwrap();
+        assert_eq!(
+            manifest["module"]["dependencies"].as_array().unwrap()[..],
+            [toml::Value::from("/accounts")]
+        );
+        // The intermediate module has no manifest of its own
+        assert!(!module.parent().unwrap().join(MANIFEST_FILENAME).exists());
+
+        let e = create_module(&info, Path::new("payments/refunds"), Vec::new()).unwrap_err();
+        assert!(e.to_string().ends_with("already exists"), "{e}");
+        for path in ["../outside", "./payments", "", "/payments/chargebacks"] {
+            assert!(
+                create_module(&info, Path::new(path), Vec::new()).is_err(),
+                "{path} was accepted"
+            );
+        }
+
+        // Only the directories created for an invalid module are removed
+        assert!(create_module(
+            &info,
+            Path::new("payments/chargebacks/disputes"),
+            vec![PathBuf::from("/missing")],
+        )
+        .is_err());
+        assert!(!info.source_dir().join("payments/chargebacks").exists());
+        assert!(module.exists());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::BTreeSet, io};
+
+use log::{debug, info};
+
+use crate::{
+    artifact::{Artifact, ContentId},
+    backend::Backend,
+    manifest::project::ProjectInfo,
+    migration::MigrationSet,
+};
+
+/// Find the artifacts recorded in the database's history which don't match any artifact
+/// in the project, such as a migration which was edited after it was applied. One-off
+/// scripts recorded by `database exec --record` are also reported.
+pub fn detect_drift<Db: Backend>(
+    info: &ProjectInfo,
+    mut database: Db,
+) -> anyhow::Result<Vec<ContentId>>
+where
+    <Db as Backend>::Error: Sync + Send + 'static,
+{
+    info!("Checking {} for drift", info.project.title);
+
+    let mut local = BTreeSet::new();
+    for migration in MigrationSet::open(info)?.migrations() {
+        let id = migration.write_to(io::sink())?;
+        debug!("{} has content id {}", migration.print(), id);
+        local.insert(id);
+    }
+
+    Ok(database
+        .applied_ids()?
+        .into_iter()
+        .filter(|id| !local.contains(id))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::save::save_project, backend::sqlite::SqliteBackend, testing::TestProject,
+    };
+    use semver::Version;
+    use std::{fs, time::Duration};
+
+    #[test]
+    fn detects_drift() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let info = project.info();
+        save_project(&info, false).unwrap();
+        let path = project.path().join("db.sqlite");
+        let open = || SqliteBackend::open_file(&path, Duration::ZERO).unwrap();
+
+        let schema = MigrationSet::open(&info)
+            .unwrap()
+            .get_schema(&Version::new(0, 1, 0))
+            .unwrap();
+        let applied = schema.content_id();
+        let mut db = open();
+        db.install().unwrap();
+        db.apply(&schema, false).unwrap();
+        drop(db);
+        assert!(detect_drift(&info, open()).unwrap().is_empty());
+
+        // A one-off script
+        let recorded = ContentId::try_from(vec![0; 32]).unwrap();
+        let mut db = open();
+        db.record(recorded, &Version::new(0, 1, 0)).unwrap();
+        drop(db);
+        assert_eq!(detect_drift(&info, open()).unwrap(), vec![recorded]);
+
+        // The migration was edited after it was applied
+        fs::write(
+            info.artifacts_dir().join("0.1.0/schema.sql"),
+            schema.to_string() + "\ncreate table b(x integer);\n",
+        )
+        .unwrap();
+        assert_eq!(
+            detect_drift(&info, open()).unwrap(),
+            vec![applied, recorded]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use log::info;
+
+use crate::{arguments::SchemaFormat, backend::Backend, manifest::project::ProjectInfo};
+
+/// Describe the structure of the database's schema, as migrations are generated from, in
+/// `format`. Schemas in the project's `exclude_schemas` are excluded.
+pub fn dump_schema<Db: Backend>(
+    info: &ProjectInfo,
+    mut database: Db,
+    format: SchemaFormat,
+) -> anyhow::Result<String> {
+    info!("Dumping the database schema of {}", info.project.title);
+    let schema = database.introspect(&info.migrate.exclude_schemas)?;
+    Ok(match format {
+        SchemaFormat::Json => serde_json::to_string_pretty(&schema)?,
+        SchemaFormat::Toml => toml::to_string(&schema)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::build::build_project, backend::sqlite::SqliteBackend, testing::TestProject,
+    };
+
+    #[test]
+    fn dumps_project_objects() {
+        let project = TestProject::new("0.1.0");
+        project.write(
+            "src/a.sql",
+            "create table a(x integer not null); create index a_x on a(x);",
+        );
+        let info = project.info();
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.apply(&build_project(&info).unwrap(), false).unwrap();
+
+        let dump = dump_schema(&info, db, SchemaFormat::Json).unwrap();
+        let schema: serde_json::Value = serde_json::from_str(&dump).unwrap();
+        // sqigl's own tables are not part of the project's schema
+        let tables: Vec<_> = schema["tables"].as_object().unwrap().keys().collect();
+        assert_eq!(tables, vec!["main.a"]);
+        assert_eq!(schema["tables"]["main.a"]["columns"][0]["name"], "x");
+        assert_eq!(schema["tables"]["main.a"]["columns"][0]["not_null"], true);
+        assert_eq!(
+            schema["indexes"]["main.a"],
+            serde_json::json!(["CREATE INDEX a_x on a(x)"])
+        );
+    }
+
+    #[test]
+    fn dumps_toml() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let info = project.info();
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.apply(&build_project(&info).unwrap(), false).unwrap();
+
+        let dump = dump_schema(&info, db, SchemaFormat::Toml).unwrap();
+        let schema: toml::Table = toml::from_str(&dump).unwrap();
+        assert!(schema["tables"].as_table().unwrap().contains_key("main.a"));
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::info;
+use toml_edit::{Decor, DocumentMut, Item, RawString, Table, Value};
+
+use crate::{
+    manifest::{project::ProjectInfo, MANIFEST_FILENAME},
+    util::replace_file,
+};
+
+/// The canonical order of keys in each table of a manifest, by the key of the table in
+/// the top level, or `""` for the top level itself. Keys which aren't listed, & the keys
+/// of tables nested further, are placed after those which are, in their original order.
+const KEY_ORDER: &[(&str, &[&str])] = &[
+    (
+        "",
+        &[
+            "project",
+            "module",
+            "database",
+            "migrate",
+            "build",
+            "fragments",
+            "scripts",
+            "migrations",
+        ],
+    ),
+    (
+        "project",
+        &[
+            "version",
+            "title",
+            "source_dir",
+            "artifacts_dir",
+            "internal_schema",
+        ],
+    ),
+    (
+        "database",
+        &[
+            "db",
+            "hostname",
+            "socket",
+            "port",
+            "username",
+            "database",
+            "password_command",
+            "password_file",
+            "certificate",
+            "sslmode",
+            "sslcert",
+            "sslkey",
+            "statement_timeout",
+            "transaction_timeout",
+            "connect_retries",
+            "connect_backoff",
+            "role",
+            "search_path",
+            "session_setup",
+            "path",
+            "detect_renames",
+            "lock_timeout",
+            "journal_mode",
+            "synchronous",
+            "attach",
+        ],
+    ),
+    (
+        "module",
+        &[
+            "dependencies",
+            "inherit_dependencies",
+            "export_dependencies",
+        ],
+    ),
+    ("migrate", &["protect", "exclude_schemas"]),
+    ("build", &["context", "max_script_bytes"]),
+    ("scripts", &["script", "dependencies", "order"]),
+    (
+        "migrations",
+        &[
+            "script",
+            "from",
+            "to",
+            "parallel",
+            "post",
+            "description",
+            "down",
+        ],
+    ),
+];
+
+/// The canonical order of the keys of a table, given the table's key in the top level.
+fn key_order(table: Option<&str>) -> &'static [&'static str] {
+    KEY_ORDER
+        .iter()
+        .find(|(name, _)| Some(*name) == table)
+        .map(|(_, keys)| *keys)
+        .unwrap_or_default()
+}
+
+fn rank(order: &[&str], key: &str) -> usize {
+    order.iter().position(|k| *k == key).unwrap_or(order.len())
+}
+
+/// The comment lines of a prefix, without indentation or blank lines.
+fn comments(raw: Option<&RawString>) -> String {
+    raw.and_then(RawString::as_str)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// A decor with the default whitespace, keeping the comments in its prefix, and a
+/// trailing comment in its suffix.
+fn normalize_decor(decor: &mut Decor, prefix: Option<&str>) {
+    let leading = comments(decor.prefix());
+    let trailing = decor
+        .suffix()
+        .and_then(RawString::as_str)
+        .and_then(|s| s.find('#').map(|idx| format!(" {}", s[idx..].trim_end())));
+    *decor = Decor::default();
+    match (prefix, leading.is_empty()) {
+        (Some(prefix), _) => decor.set_prefix(format!("{}{}", prefix, leading)),
+        (None, false) => decor.set_prefix(leading),
+        (None, true) => (),
+    }
+    if let Some(trailing) = trailing {
+        decor.set_suffix(trailing);
+    }
+}
+
+fn format_value(value: &mut Value) {
+    // Auto-formatting arrays & inline tables discards comments within them
+    if value.to_string().contains('#') {
+        return;
+    }
+    match value {
+        Value::Array(array) => array.fmt(),
+        Value::InlineTable(table) => table.fmt(),
+        _ => (),
+    }
+}
+
+/// Sort & reformat a table and the tables nested in it, assigning them positions in the
+/// document in the order they are visited. `name` is the table's key in the top level,
+/// or `""` for the top level itself.
+fn format_table(table: &mut Table, name: Option<&str>, position: &mut usize, first: &mut bool) {
+    let order = key_order(name);
+    table.sort_values_by(|k1, _, k2, _| rank(order, k1).cmp(&rank(order, k2)));
+    if !table.is_dotted() {
+        table.set_position(*position);
+        *position += 1;
+    }
+
+    for (mut key, item) in table.iter_mut() {
+        // Only the tables of the top level have a canonical order
+        let child = (name == Some("")).then(|| key.get().to_string());
+        let child = child.as_deref();
+        match item {
+            Item::Value(value) => {
+                normalize_decor(key.leaf_decor_mut(), None);
+                key.leaf_decor_mut().set_suffix(" ");
+                normalize_decor(value.decor_mut(), Some(" "));
+                format_value(value);
+            }
+            Item::Table(table) => format_header(table, child, position, first),
+            Item::ArrayOfTables(array) => {
+                for table in array.iter_mut() {
+                    format_header(table, child, position, first);
+                }
+            }
+            Item::None => (),
+        }
+    }
+}
+
+fn format_header(table: &mut Table, name: Option<&str>, position: &mut usize, first: &mut bool) {
+    // Implicit tables are only given a header if they contain values
+    let hidden = table.is_dotted() || (table.is_implicit() && table.get_values().is_empty());
+    if !hidden {
+        // Tables are separated by a blank line
+        let prefix = if *first { None } else { Some("\n") };
+        *first = false;
+        normalize_decor(table.decor_mut(), prefix);
+    }
+    format_table(table, name, position, first);
+}
+
+/// Rewrite a manifest in canonical form, returning it if it differs from the original.
+fn format_manifest(content: &str) -> anyhow::Result<Option<String>> {
+    let mut document: DocumentMut = content.parse()?;
+    let mut position = 0;
+    let mut first = document.get_values().is_empty();
+    format_table(document.as_table_mut(), Some(""), &mut position, &mut first);
+    let trailing = comments(Some(document.trailing()));
+    document.set_trailing(if trailing.is_empty() {
+        trailing
+    } else {
+        format!("\n{}", trailing)
+    });
+
+    let formatted = document.to_string();
+    Ok((formatted != content).then_some(formatted))
+}
+
+fn find_manifests(directory: &Path, manifests: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+    let manifest = directory.join(MANIFEST_FILENAME);
+    if manifest.is_file() {
+        manifests.push(manifest);
+    }
+    for child_res in directory.read_dir()? {
+        let child = child_res?.path();
+        if child.is_dir() {
+            find_manifests(&child, manifests)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the project, module & artifact manifests of a project in canonical form,
+/// with keys in a canonical order & consistent whitespace. Comments are preserved. If
+/// `check` is set, no files are modified. Returns the manifests which were (or would be)
+/// changed.
+pub fn format_project(info: &ProjectInfo, check: bool) -> anyhow::Result<Vec<PathBuf>> {
+    info!("Formatting the manifests of {}", info.project.title);
+    let mut manifests = vec![info.project_manifest()];
+    find_manifests(&info.source_dir(), &mut manifests)?;
+    find_manifests(&info.artifacts_dir(), &mut manifests)?;
+
+    let mut changed = Vec::new();
+    for manifest in manifests {
+        let content = fs::read_to_string(&manifest)?;
+        if let Some(formatted) = format_manifest(&content)? {
+            if !check {
+                info!("Formatting {:?}", manifest);
+                replace_file(&formatted, &manifest)?;
+            }
+            changed.push(manifest);
+        }
+    }
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_keys_per_table() {
+        let formatted = format_manifest(
+            "[database]\ndatabase = \"app\"\ndb = \"postgres\"\n\n\
+            [project]\ntitle = \"test\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            formatted,
+            "[project]\nversion = \"0.1.0\"\ntitle = \"test\"\n\n\
+            [database]\ndb = \"postgres\"\ndatabase = \"app\"\n"
+        );
+    }
+
+    #[test]
+    fn nested_tables_keep_their_order() {
+        let manifest = "[database]\ndb = \"sqlite\"\n\n\
+            [database.attach]\nproject = \"project.db\"\ndatabase = \"other.db\"\n";
+        assert_eq!(format_manifest(manifest).unwrap(), None);
+    }
+
+    #[test]
+    fn orders_migrations() {
+        let formatted = format_manifest(
+            "[[migrations]]\nto = \"0.2.0\"\ndescription = \"Adds a\"\n\
+            script = \"from_0.1.0.sql\"\nfrom = \"=0.1\"\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            formatted,
+            "[[migrations]]\nscript = \"from_0.1.0.sql\"\nfrom = \"=0.1\"\n\
+            to = \"0.2.0\"\ndescription = \"Adds a\"\n"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+use crate::{
+    actions::build::{
+        build_project, is_script, module_dependencies, resolve_child, BuildError, DependencyKind,
+    },
+    manifest::{module::open_module, project::ProjectInfo},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum NodeKind {
+    Module,
+    Script,
+}
+
+/// The dependency graph of a project's modules & scripts.
+pub struct DependencyGraph {
+    source_dir: PathBuf,
+    nodes: BTreeSet<(PathBuf, NodeKind)>,
+    /// Edges point from the dependent to the dependency.
+    edges: BTreeSet<(PathBuf, PathBuf, DependencyKind)>,
+    /// The nodes of a dependency cycle, if the project contains one.
+    cycle: Vec<PathBuf>,
+}
+impl DependencyGraph {
+    fn name(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.source_dir).unwrap_or(path);
+        if relative.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            relative
+                .to_str()
+                .expect("Couldn't process path string")
+                .to_string()
+        }
+    }
+    fn in_cycle(&self, from: &Path, to: &Path) -> bool {
+        let len = self.cycle.len();
+        (0..len).any(|idx| {
+            let (a, b) = (&self.cycle[idx], &self.cycle[(idx + 1) % len]);
+            (a == from && b == to) || (a == to && b == from)
+        })
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Display for DependencyGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "digraph dependencies {{")?;
+        writeln!(f, "    rankdir=LR;")?;
+        for (path, kind) in self.nodes.iter() {
+            let shape = match kind {
+                NodeKind::Module => "folder",
+                NodeKind::Script => "note",
+            };
+            write!(f, "    {} [shape={}", quote(&self.name(path)), shape)?;
+            if self.cycle.contains(path) {
+                write!(f, ", color=red, fontcolor=red")?;
+            }
+            writeln!(f, "];")?;
+        }
+        for (from, to, kind) in self.edges.iter() {
+            write!(
+                f,
+                "    {} -> {}",
+                quote(&self.name(from)),
+                quote(&self.name(to))
+            )?;
+            let mut attributes = Vec::new();
+            match kind {
+                DependencyKind::Parent => attributes.push("style=dashed"),
+                DependencyKind::Inherited { .. } => attributes.push("style=dotted"),
+                _ => (),
+            }
+            if self.in_cycle(from, to) {
+                attributes.push("color=red");
+            }
+            if !attributes.is_empty() {
+                write!(f, " [{}]", attributes.join(", "))?;
+            }
+            writeln!(f, ";")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Discover the dependency graph of a project. Submodules & scripts are connected to
+/// the modules containing them by `Parent` edges. If the project contains a dependency
+/// cycle, it is recorded so that it may be highlighted rather than reported as an error.
+pub fn dependency_graph(info: &ProjectInfo) -> Result<DependencyGraph, BuildError> {
+    info!("Graphing dependencies of {}", info.project.title);
+    let source_dir = info.source_dir();
+    let mut graph = DependencyGraph {
+        source_dir: source_dir.clone(),
+        nodes: Default::default(),
+        edges: Default::default(),
+        cycle: Default::default(),
+    };
+    if !source_dir.exists() {
+        warn!("No source directory found");
+        return Ok(graph);
+    }
+    if !source_dir.is_dir() {
+        return Err(BuildError::SourceNotDirectory(source_dir));
+    }
+
+    let mut modules = vec![source_dir.clone()];
+    while let Some(path) = modules.pop() {
+        let module = open_module(path)?;
+        graph.nodes.insert((module.path.clone(), NodeKind::Module));
+        for dep in module_dependencies(&module, &source_dir)? {
+            let from = match &dep.kind {
+                DependencyKind::Script { script } => script.clone(),
+                DependencyKind::Parent
+                | DependencyKind::Module
+                | DependencyKind::Inherited { .. } => module.path.clone(),
+            };
+            graph.edges.insert((from, dep.path, dep.kind));
+        }
+        for child_res in module.path.read_dir()? {
+            let child = resolve_child(child_res?.path(), &module.path, &source_dir)?;
+            let md = child.metadata()?;
+            if md.is_dir() {
+                modules.push(child);
+            } else if md.is_file() && is_script(&child) {
+                graph.nodes.insert((child.clone(), NodeKind::Script));
+                graph
+                    .edges
+                    .insert((child, module.path.clone(), DependencyKind::Parent));
+            }
+        }
+    }
+
+    match build_project(info) {
+        Ok(_) => (),
+        Err(BuildError::DependencyCycle(cycle)) => {
+            warn!("{}", cycle);
+            graph.cycle = cycle.cycle_path;
+        }
+        Err(e) => return Err(e),
+    }
+
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestProject;
+
+    #[test]
+    fn exports_edges() {
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/a/a.sql", "create table a(x integer);")
+            .write("src/b/b.sql", "create table b(x integer);")
+            .write("src/b/sqigl.toml", "[module]\ndependencies = [\"/a\"]\n");
+        assert_eq!(
+            dependency_graph(&project.info()).unwrap().to_string(),
+            "digraph dependencies {\n    \
+                rankdir=LR;\n    \
+                \".\" [shape=folder];\n    \
+                \"a\" [shape=folder];\n    \
+                \"a/a.sql\" [shape=note];\n    \
+                \"b\" [shape=folder];\n    \
+                \"b/b.sql\" [shape=note];\n    \
+                \"a\" -> \".\" [style=dashed];\n    \
+                \"a/a.sql\" -> \"a\" [style=dashed];\n    \
+                \"b\" -> \".\" [style=dashed];\n    \
+                \"b\" -> \"a\";\n    \
+                \"b/b.sql\" -> \"b\" [style=dashed];\n\
+            }\n"
+        );
+
+        // A cycle is highlighted rather than failing the export
+        project.write("src/a/sqigl.toml", "[module]\ndependencies = [\"/b\"]\n");
+        let dot = dependency_graph(&project.info()).unwrap().to_string();
+        assert!(
+            dot.contains("\"a\" [shape=folder, color=red, fontcolor=red];"),
+            "{dot}"
+        );
+        assert!(dot.contains("\"a\" -> \"b\" [color=red];"), "{dot}");
+        assert!(dot.contains("\"b\" -> \"a\" [color=red];"), "{dot}");
+        assert!(dot.contains("\"b\" -> \".\" [style=dashed];"), "{dot}");
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use log::info;
+
+use crate::{
+    actions::build::{is_script, SQL_EXTENSION},
+    backend::{introspection::index_name, Backend},
+    manifest::{
+        module::{ModuleManifest, Script},
+        project::ProjectInfo,
+        MANIFEST_FILENAME,
+    },
+};
+
+/// The module tables are imported into, relative to the source directory.
+pub const TABLES_MODULE: &str = "tables";
+/// The module indexes are imported into, relative to the source directory.
+pub const INDEXES_MODULE: &str = "indexes";
+
+/// Whether a directory contains any scripts, in it or its subdirectories.
+fn contains_scripts(directory: &Path) -> anyhow::Result<bool> {
+    for child_res in directory.read_dir()? {
+        let child = child_res?.path();
+        if (child.is_dir() && contains_scripts(&child)?) || (child.is_file() && is_script(&child)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// An object to import: its name, as it was created, & the code which created it.
+struct Object<'a> {
+    name: &'a str,
+    code: &'a str,
+}
+
+fn script_name(name: &str) -> anyhow::Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(anyhow!(
+            "Cannot import {}: Its name is not a valid file name",
+            name
+        ));
+    }
+    Ok(PathBuf::from(format!("{}.{}", name, SQL_EXTENSION)))
+}
+
+/// An identifier without the quotes around it, if it has them.
+fn unquote(name: &str) -> &str {
+    for (open, close) in [('"', '"'), ('`', '`'), ('[', ']')] {
+        if let Some(name) = name
+            .strip_prefix(open)
+            .and_then(|name| name.strip_suffix(close))
+        {
+            return name;
+        }
+    }
+    name
+}
+
+/// Write a module's scripts & its manifest, declaring the scripts in the order given.
+fn write_module(module_dir: &Path, scripts: Vec<(Script, Object)>) -> anyhow::Result<Vec<PathBuf>> {
+    if scripts.is_empty() {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(module_dir)?;
+    let mut written = Vec::new();
+    let mut manifest = ModuleManifest::default();
+    for (script, object) in scripts {
+        let path = module_dir.join(&script.script);
+        info!("Importing {} into {:?}", object.name, path);
+        let mut f = File::create_new(&path)?;
+        f.write_all(object.code.trim_end().as_bytes())?;
+        f.write_all(b";\n")?;
+        written.push(path);
+        manifest.scripts.push(script);
+    }
+    let path = module_dir.join(MANIFEST_FILENAME);
+    let mut f = File::create_new(&path)?;
+    f.write_all(toml::to_string(&manifest)?.as_bytes())?;
+    written.push(path);
+    Ok(written)
+}
+
+/// Write the schema of the database into the project's source directory, so that
+/// building the project reproduces it. Each table is written to a script in the `tables`
+/// module, & each index to a script in the `indexes` module which depends on its
+/// table's script. Tables depend on the tables their foreign keys refer to. Only
+/// databases which record the code of their tables (SQLite) can be imported. The source
+/// directory must not contain any scripts. Returns the files which were written.
+pub fn import_schema<Db: Backend>(
+    info: &ProjectInfo,
+    mut database: Db,
+) -> anyhow::Result<Vec<PathBuf>> {
+    info!("Importing the database schema into {}", info.project.title);
+    let source_dir = info.source_dir();
+    if source_dir.exists() && contains_scripts(&source_dir)? {
+        return Err(anyhow!(
+            "Cannot import into {}: The source directory already contains scripts",
+            info.project.title
+        ));
+    }
+
+    let schema = database.introspect(&info.migrate.exclude_schemas)?;
+    let mut tables = Vec::new();
+    let mut indexes = Vec::new();
+    for (name, table) in schema.tables.iter() {
+        let Some(code) = table.code.as_deref() else {
+            return Err(anyhow!(
+                "Cannot import {}.{}: The database doesn't record the code which created it",
+                name.0,
+                name.1
+            ));
+        };
+        let table_script = script_name(&name.1)?;
+        let mut dependencies = Vec::new();
+        for dep in table.references.iter() {
+            if schema.tables.contains_key(dep) {
+                dependencies.push(script_name(&dep.1)?);
+            }
+        }
+        for definition in schema.indexes_of(name) {
+            let index = index_name(definition)
+                .map(unquote)
+                .ok_or_else(|| anyhow!("Cannot import {:?}: Its name is not known", definition))?;
+            let script = Script {
+                script: script_name(index)?,
+                dependencies: vec![Path::new("..").join(TABLES_MODULE).join(&table_script)],
+                order: None,
+            };
+            indexes.push((
+                script,
+                Object {
+                    name: index,
+                    code: definition,
+                },
+            ));
+        }
+        let script = Script {
+            script: table_script,
+            dependencies,
+            order: None,
+        };
+        tables.push((
+            script,
+            Object {
+                name: &name.1,
+                code,
+            },
+        ));
+    }
+
+    let count = tables.len() + indexes.len();
+    let mut written = write_module(&source_dir.join(TABLES_MODULE), tables)?;
+    written.extend(write_module(&source_dir.join(INDEXES_MODULE), indexes)?);
+    info!("Imported {} object(s)", count);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::build::build_project, backend::sqlite::SqliteBackend, testing::TestProject,
+    };
+
+    #[test]
+    fn import_reproduces_schema() {
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        // Each table refers to the next, so they must be built in reverse order of name
+        db.exec(
+            "create table a(x integer primary key, b integer references b(x));
+            create table b(x integer primary key, c integer references c(x));
+            create table c(x integer primary key, y text not null default '');
+            create unique index \"a b\" on a(b);
+            create index c_y on c(y) where y != '';",
+            false,
+        )
+        .unwrap();
+        let expected = db.introspect(&[]).unwrap();
+        let project = TestProject::new("0.1.0");
+        let info = project.info();
+        import_schema(&info, db).unwrap();
+
+        let manifest = fs::read_to_string(info.source_dir().join("tables/sqigl.toml")).unwrap();
+        assert!(manifest.contains("dependencies = [\"b.sql\"]"));
+        assert!(info.source_dir().join("indexes/a b.sql").exists());
+        let build = build_project(&info).unwrap();
+        assert_eq!(
+            build.ordered_scripts(),
+            [
+                "tables/c.sql",
+                "tables/b.sql",
+                "tables/a.sql",
+                "indexes/a b.sql",
+                "indexes/c_y.sql"
+            ]
+            .map(PathBuf::from)
+        );
+        let mut built = SqliteBackend::local().unwrap();
+        built.install().unwrap();
+        built.apply(&build, false).unwrap();
+        assert_eq!(built.introspect(&[]).unwrap(), expected);
+    }
+
+    #[test]
+    fn import_requires_empty_source() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let db = SqliteBackend::local().unwrap();
+        assert!(import_schema(&project.info(), db).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use log::info;
+
+use crate::{
+    actions::build::{canonicalize_dep_path, is_script, resolve_child, BuildError},
+    manifest::{module::open_module, project::ProjectInfo},
+};
+
+/// A likely mistake in a project's module manifests.
+#[derive(Debug)]
+pub enum Finding {
+    /// A script that is neither declared in its module's manifest nor depended on by a
+    /// declared script, in a module with other scripts. Its order relative to its
+    /// siblings is unspecified.
+    UndeclaredScript { module: PathBuf, script: PathBuf },
+    /// A script declared in a module's manifest which does not exist.
+    MissingScript { module: PathBuf, script: PathBuf },
+    /// A dependency which does not exist.
+    MissingDependency { module: PathBuf, dep: PathBuf },
+    /// A dependency outside of the source directory.
+    DependencyOutsideRoot { module: PathBuf, dep: PathBuf },
+}
+
+pub struct Lint {
+    source_dir: PathBuf,
+    pub findings: Vec<Finding>,
+}
+impl Lint {
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.source_dir).unwrap_or(path)
+    }
+    fn module_name(&self, module: &Path) -> String {
+        let relative = self.relative(module);
+        if relative.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            relative.to_str().unwrap().to_string()
+        }
+    }
+}
+impl Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in self.findings.iter() {
+            match finding {
+                Finding::UndeclaredScript { module, script } => writeln!(
+                    f,
+                    "{}: script {} is not declared in the module manifest; its order is \
+                    unspecified",
+                    self.module_name(module),
+                    script.to_str().unwrap()
+                )?,
+                Finding::MissingScript { module, script } => writeln!(
+                    f,
+                    "{}: declared script {} does not exist",
+                    self.module_name(module),
+                    script.to_str().unwrap()
+                )?,
+                Finding::MissingDependency { module, dep } => writeln!(
+                    f,
+                    "{}: dependency {} does not exist",
+                    self.module_name(module),
+                    self.relative(dep).to_str().unwrap()
+                )?,
+                Finding::DependencyOutsideRoot { module, dep } => writeln!(
+                    f,
+                    "{}: dependency {} is outside the source directory",
+                    self.module_name(module),
+                    dep.to_str().unwrap()
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_dependency(
+    dep: &Path,
+    module: &Path,
+    source_dir: &Path,
+    findings: &mut Vec<Finding>,
+) -> Result<Option<PathBuf>, BuildError> {
+    match canonicalize_dep_path(dep, module, source_dir) {
+        Ok(path) => Ok(Some(path)),
+        Err(BuildError::DependencyDoesNotExist { module, dep }) => {
+            findings.push(Finding::MissingDependency { module, dep });
+            Ok(None)
+        }
+        Err(BuildError::DependencyOutsideRoot { module, dep }) => {
+            findings.push(Finding::DependencyOutsideRoot { module, dep });
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Check every module in a project for undeclared scripts, missing scripts, and
+/// dependencies which don't exist or are outside the source directory.
+pub fn lint_project(info: &ProjectInfo) -> Result<Lint, BuildError> {
+    info!("Linting {}", info.project.title);
+    let source_dir = info.source_dir();
+    let mut findings = Vec::new();
+
+    let mut modules = Vec::new();
+    if source_dir.exists() {
+        if !source_dir.is_dir() {
+            return Err(BuildError::SourceNotDirectory(source_dir));
+        }
+        modules.push(source_dir.clone());
+    }
+    while let Some(path) = modules.pop() {
+        let module = open_module(path)?;
+
+        let mut on_disk = BTreeSet::new();
+        for child_res in module.path.read_dir()? {
+            let child = child_res?.path();
+            // Scripts are matched against the manifest by the name of the link, rather than
+            // that of its target
+            let resolved = resolve_child(child.clone(), &module.path, &source_dir)?;
+            let md = resolved.metadata()?;
+            if md.is_dir() {
+                modules.push(resolved);
+            } else if md.is_file() && is_script(&child) {
+                on_disk.insert(child);
+            }
+        }
+
+        for dep in module
+            .module
+            .dependencies
+            .iter()
+            .chain(module.module.export_dependencies.iter())
+        {
+            check_dependency(dep, &module.path, &source_dir, &mut findings)?;
+        }
+        let mut ordered = BTreeSet::new();
+        for script in module.scripts.iter() {
+            let script_path = module.path.join(&script.script);
+            if !on_disk.contains(&script_path) {
+                findings.push(Finding::MissingScript {
+                    module: module.path.clone(),
+                    script: script.script.clone(),
+                });
+            }
+            ordered.insert(script_path);
+            for dep in script.dependencies.iter() {
+                if let Some(dep_path) =
+                    check_dependency(dep, &module.path, &source_dir, &mut findings)?
+                {
+                    ordered.insert(dep_path);
+                }
+            }
+        }
+
+        // A lone script can't be misordered relative to its siblings
+        if on_disk.len() > 1 {
+            for script in on_disk.difference(&ordered) {
+                findings.push(Finding::UndeclaredScript {
+                    module: module.path.clone(),
+                    script: PathBuf::from(script.file_name().unwrap()),
+                });
+            }
+        }
+    }
+
+    Ok(Lint {
+        source_dir,
+        findings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestProject;
+
+    /// Lint a project containing `files`, returning its findings & their report.
+    fn lint(files: &[(&str, &str)]) -> (Vec<Finding>, String) {
+        let project = TestProject::new("0.1.0");
+        for (path, content) in files {
+            project.write(path, content);
+        }
+        let lint = lint_project(&project.info()).unwrap();
+        let report = lint.to_string();
+        (lint.findings, report)
+    }
+
+    #[test]
+    fn clean_project() {
+        let (findings, _) = lint(&[
+            ("src/a.sql", ""),
+            ("src/b.sql", ""),
+            (
+                "src/sqigl.toml",
+                "[[scripts]]\nscript = \"b.sql\"\ndependencies = [\"a.sql\"]\n",
+            ),
+            // A lone script needn't be declared
+            ("src/c/c.sql", ""),
+        ]);
+        assert!(findings.is_empty(), "{findings:?}");
+    }
+
+    #[test]
+    fn undeclared_script() {
+        let (findings, report) = lint(&[
+            ("src/a.sql", ""),
+            ("src/b.sql", ""),
+            ("src/sqigl.toml", "[[scripts]]\nscript = \"a.sql\"\n"),
+        ]);
+        assert!(
+            matches!(
+                &findings[..],
+                [Finding::UndeclaredScript { script, .. }] if script == Path::new("b.sql")
+            ),
+            "{findings:?}"
+        );
+        assert_eq!(
+            report,
+            ".: script b.sql is not declared in the module manifest; its order is unspecified\n"
+        );
+    }
+
+    #[test]
+    fn missing_script() {
+        let (findings, report) = lint(&[
+            ("src/a/a.sql", ""),
+            ("src/a/sqigl.toml", "[[scripts]]\nscript = \"b.sql\"\n"),
+        ]);
+        assert!(
+            matches!(
+                &findings[..],
+                [Finding::MissingScript { script, .. }] if script == Path::new("b.sql")
+            ),
+            "{findings:?}"
+        );
+        assert_eq!(report, "a: declared script b.sql does not exist\n");
+    }
+
+    #[test]
+    fn missing_dependency() {
+        let (findings, report) = lint(&[
+            ("src/a/a.sql", ""),
+            ("src/a/sqigl.toml", "[module]\ndependencies = [\"/b\"]\n"),
+        ]);
+        assert!(
+            matches!(&findings[..], [Finding::MissingDependency { .. }]),
+            "{findings:?}"
+        );
+        assert_eq!(report, "a: dependency b does not exist\n");
+    }
+
+    #[test]
+    fn dependency_outside_root() {
+        let (findings, report) = lint(&[
+            ("src/a.sql", ""),
+            (
+                "src/sqigl.toml",
+                "[[scripts]]\nscript = \"a.sql\"\ndependencies = [\"../sqigl.toml\"]\n",
+            ),
+        ]);
+        assert!(
+            matches!(
+                &findings[..],
+                [Finding::DependencyOutsideRoot { dep, .. }] if dep.ends_with("sqigl.toml")
+            ),
+            "{findings:?}"
+        );
+        assert!(
+            report.starts_with(".: dependency /")
+                && report.ends_with(" is outside the source directory\n"),
+            "{report}"
+        );
+    }
+}
@@ -1,4 +1,14 @@
 pub mod apply;
 pub mod build;
+pub mod clean;
 pub mod create;
+pub mod drift;
+pub mod dump;
+pub mod fmt;
+pub mod graph;
+pub mod import;
+pub mod lint;
+pub mod repair;
 pub mod save;
+pub mod squash;
+pub mod watch;
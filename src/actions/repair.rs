@@ -0,0 +1,208 @@
+use std::io;
+
+use anyhow::anyhow;
+use log::{debug, info, warn};
+use semver::Version;
+
+use crate::{
+    artifact::{Artifact, ContentId},
+    backend::Backend,
+    manifest::project::ProjectInfo,
+    migration::MigrationSet,
+    util::{confirm, empty_database_version},
+};
+
+/// A correction to a database's history: the database's schema matches the schema saved
+/// for `version`, but its history records `recorded`.
+pub struct Repair {
+    pub recorded: Version,
+    pub version: Version,
+    /// The content id of the schema saved for `version`, which is recorded in the history
+    pub id: ContentId,
+}
+
+/// Find the saved version whose schema matches the database's schema, for a database
+/// which was migrated outside of sqigl. Each saved schema is loaded into a temporary
+/// database & introspected, then compared with the database. Returns `None` if the
+/// schema matches the version recorded in the history. Fails if it matches no saved
+/// version, or several versions other than the recorded one.
+pub fn plan_repair<Db: Backend>(
+    info: &ProjectInfo,
+    database: &mut Db,
+) -> anyhow::Result<Option<Repair>>
+where
+    <Db as Backend>::Error: Sync + Send + 'static,
+{
+    info!(
+        "Comparing the database with the saved versions of {}",
+        info.project.title
+    );
+    let recorded = database.open()?.project_version;
+    let exclude_schemas = &info.migrate.exclude_schemas;
+    let live = database.introspect(exclude_schemas)?;
+    // An empty database has no saved schema, but matches the empty version
+    if recorded == empty_database_version() && live == Default::default() {
+        return Ok(None);
+    }
+
+    let migrations = MigrationSet::open(info)?;
+    let mut matches = Vec::new();
+    for version in migrations.versions() {
+        let Some(schema) = migrations.get_schema(version) else {
+            continue;
+        };
+        if database.introspect_artifact(&schema, exclude_schemas)? == live {
+            debug!("The database matches version {}", version);
+            matches.push((version.clone(), schema.write_to(io::sink())?));
+        }
+    }
+
+    if matches.iter().any(|(version, _)| *version == recorded) {
+        return Ok(None);
+    }
+    match matches.len() {
+        0 => Err(anyhow!(
+            "The schema of the database matches no saved version of {}",
+            info.project.title
+        )),
+        1 => {
+            let (version, id) = matches.pop().unwrap();
+            Ok(Some(Repair {
+                recorded,
+                version,
+                id,
+            }))
+        }
+        _ => {
+            let versions: Vec<_> = matches.iter().map(|(v, _)| v.to_string()).collect();
+            Err(anyhow!(
+                "The schema of the database matches several saved versions ({}); cannot \
+                tell which it is at",
+                versions.join(", ")
+            ))
+        }
+    }
+}
+
+/// Rewrite the history of a database which was migrated outside of sqigl, so that it
+/// records the saved version matching its schema (see `plan_repair`). A migration which
+/// was interrupted is cleared, so that migrations may be applied again. Unless `yes` is
+/// set, the user is asked to confirm first. Returns the repair, if one was made.
+pub fn repair_history<Db: Backend>(
+    info: &ProjectInfo,
+    mut database: Db,
+    yes: bool,
+) -> anyhow::Result<Option<Repair>>
+where
+    <Db as Backend>::Error: Sync + Send + 'static,
+{
+    let plan = plan_repair(info, &mut database)?;
+    let pending = database.pending()?;
+    if let Some(id) = pending {
+        warn!(
+            "Migration {} was interrupted & may be partially applied",
+            id
+        );
+    }
+    let Some(repair) = plan else {
+        info!("The history matches the schema of the database; nothing to repair");
+        if pending.is_some() {
+            if !yes && !confirm("Clear the interrupted migration?")? {
+                return Err(anyhow!("The interrupted migration was not cleared"));
+            }
+            database.clear_pending()?;
+            info!("Cleared the interrupted migration");
+        }
+        return Ok(None);
+    };
+    warn!(
+        "The database's history records version {}, but its schema matches version {}",
+        repair.recorded, repair.version
+    );
+    if !yes
+        && !confirm(&format!(
+            "Record version {} in the history?",
+            repair.version
+        ))?
+    {
+        return Err(anyhow!("The history was not repaired"));
+    }
+    database.record(repair.id, &repair.version)?;
+    info!("Recorded version {} ({})", repair.version, repair.id);
+    Ok(Some(repair))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::{build::build_project, save::save_project},
+        backend::sqlite::SqliteBackend,
+        testing::TestProject,
+    };
+
+    /// Save the project's current source as `version`.
+    fn save_version(project: &TestProject, version: &str, source: &str) {
+        project
+            .write(
+                "sqigl.toml",
+                &format!(
+                    "[project]\ntitle = \"test\"\nversion = \"{version}\"\n\n\
+                    [database]\ndb = \"sqlite\"\n"
+                ),
+            )
+            .write("src/a.sql", source);
+        save_project(&project.info(), false).unwrap();
+    }
+
+    #[test]
+    fn plans_repair() {
+        let project = TestProject::new("0.1.0");
+        save_version(&project, "0.1.0", "create table a(x);");
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.apply(&build_project(&project.info()).unwrap(), false)
+            .unwrap();
+        assert!(plan_repair(&project.info(), &mut db).unwrap().is_none());
+
+        save_version(&project, "0.2.0", "create table a(x);\ncreate table b(y);");
+        db.exec("create table b(y);", false).unwrap();
+        let repair = plan_repair(&project.info(), &mut db).unwrap().unwrap();
+        assert_eq!(repair.recorded, Version::new(0, 1, 0));
+        assert_eq!(repair.version, Version::new(0, 2, 0));
+        let saved = MigrationSet::open(&project.info()).unwrap();
+        assert_eq!(
+            repair.id,
+            saved
+                .get_schema(&Version::new(0, 2, 0))
+                .unwrap()
+                .write_to(io::sink())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn refuses_ambiguous_repair() {
+        let project = TestProject::new("0.1.0");
+        save_version(&project, "0.1.0", "create table a(x);");
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.apply(&build_project(&project.info()).unwrap(), false)
+            .unwrap();
+        // Versions 0.2.0 & 0.3.0 differ, but have the same schema
+        save_version(&project, "0.2.0", "create table a(x);\ncreate table b(y);");
+        save_version(
+            &project,
+            "0.3.0",
+            "create table a(x);\n-- b is new\ncreate table b(y);",
+        );
+        db.exec("create table b(y);", false).unwrap();
+
+        let e = plan_repair(&project.info(), &mut db).err().unwrap();
+        assert!(e.to_string().contains("0.2.0, 0.3.0"), "{e}");
+
+        db.exec("create table c(z);", false).unwrap();
+        let e = plan_repair(&project.info(), &mut db).err().unwrap();
+        assert!(e.to_string().contains("matches no saved version"), "{e}");
+    }
+}
@@ -1,16 +1,23 @@
-use std::{fs, io};
+use std::{
+    convert::Infallible,
+    error::Error,
+    fmt::{self, Debug, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    actions::build::build_project,
+    actions::build::{build_project, BuildArtifact, BuildError, SQL_EXTENSION},
     arguments::ReleaseLevel,
+    artifact::{Artifact, ContentId},
     backend::Backend,
     manifest::{
-        artifact::update_migration_versions,
-        project::{update_project_version, ProjectInfo},
-        ARTIFACTS_DIRECTORY,
+        artifact::{update_migration_versions, UpdateVersionsError},
+        project::{update_project_version, ProjectInfo, UpdateVersionError},
+        ARTIFACTS_DIRECTORY, MANIFEST_FILENAME,
     },
-    migration::{save_migration, MigrationSet},
-    util::{empty_database_version, from_empty_database, normalize_version},
+    migration::{save_migration, MigrationSet, MigrationSetError, SaveMigrationError},
+    util::{empty_database_version, from_empty_database, normalize_version, COMPRESSED_EXTENSION},
 };
 
 use anyhow::anyhow;
@@ -19,7 +26,7 @@ use semver::{BuildMetadata, Version};
 
 pub const SCHEMA_ARTIFACT_TITLE: &str = "schema";
 
-pub fn save_project(info: &ProjectInfo) -> anyhow::Result<()> {
+pub fn save_project(info: &ProjectInfo, compress: bool) -> anyhow::Result<()> {
     info!(
         "Saving {} version {}",
         info.project.title, info.project.version
@@ -33,30 +40,130 @@ pub fn save_project(info: &ProjectInfo) -> anyhow::Result<()> {
     let version_dir = artifacts_dir.join(normalized.to_string());
     fs::create_dir_all(&version_dir)?;
 
-    save_migration(SCHEMA_ARTIFACT_TITLE, build, info)?;
+    save_migration(SCHEMA_ARTIFACT_TITLE, build, info, compress)?;
 
     info!("Save complete");
     Ok(())
 }
 
-pub fn release<Db: Backend>(
+/// The result of comparing a build of the project against the schema saved for its
+/// latest released version.
+pub struct BuildVerification {
+    pub version: Version,
+    pub build: ContentId,
+    pub saved: ContentId,
+}
+impl BuildVerification {
+    pub fn matches(&self) -> bool {
+        self.build == self.saved
+    }
+}
+impl Display for BuildVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.matches() {
+            writeln!(f, "build matches version {} ({})", self.version, self.build)
+        } else {
+            writeln!(
+                f,
+                "build ({}) differs from version {} ({})",
+                self.build, self.version, self.saved
+            )
+        }
+    }
+}
+
+/// Check that building the project reproduces the schema saved for its latest released
+/// version, such as to confirm in CI that a release was built from the sources it was
+/// saved from. The build is given the released version, because it appears in the
+/// build's header. Doesn't require a database.
+pub fn verify_build(info: &ProjectInfo) -> anyhow::Result<BuildVerification> {
+    info!("Verifying the build of {}", info.project.title);
+    let migrations = MigrationSet::open(info)?;
+    let version = migrations
+        .latest_released_version()
+        .ok_or_else(|| anyhow!("{} has no released versions", info.project.title))?
+        .clone();
+    let saved = migrations
+        .get_schema(&version)
+        .ok_or_else(|| anyhow!("No schema is saved for version {}", version))?;
+
+    let mut build = build_project(info)?;
+    build.set_version(&version);
+    Ok(BuildVerification {
+        build: build.write_to(io::sink())?,
+        saved: saved.write_to(io::sink())?,
+        version,
+    })
+}
+
+/// The changes a release will make to a project.
+pub struct ReleasePlan {
+    pub old_version: Version,
+    pub new_version: Version,
+    /// The artifact directory of the old version, if it exists. It is renamed to
+    /// `new_dir`; otherwise, `new_dir` is created.
+    pub old_dir: Option<PathBuf>,
+    pub new_dir: PathBuf,
+    /// The manifests which are edited.
+    pub manifests: Vec<PathBuf>,
+    /// The build which is saved, & where it is saved to.
+    pub schema: PathBuf,
+    build: BuildArtifact,
+    compress: bool,
+}
+impl Display for ReleasePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version: {} -> {}", self.old_version, self.new_version)?;
+        match &self.old_dir {
+            Some(old_dir) => writeln!(
+                f,
+                "rename: {} -> {}",
+                old_dir.display(),
+                self.new_dir.display()
+            )?,
+            None => writeln!(f, "create: {}", self.new_dir.display())?,
+        }
+        for manifest in self.manifests.iter() {
+            writeln!(f, "edit: {}", manifest.display())?;
+        }
+        writeln!(f, "write: {}", self.schema.display())
+    }
+}
+
+/// An error planning or carrying out a release. Only planning consults the database, so
+/// `release` never returns `Database`.
+#[derive(thiserror::Error, Debug)]
+pub enum ReleaseError<DatabaseError: Error + Debug = Infallible> {
+    #[error("{0}")]
+    Build(#[from] BuildError),
+    #[error("{0}")]
+    MigrationSet(#[from] MigrationSetError),
+    #[error("{0}")]
+    Database(DatabaseError),
+    #[error("Could not update project manifest: {0}")]
+    UpdateVersion(#[from] UpdateVersionError),
+    #[error("Could not update artifact manifest: {0}")]
+    UpdateVersions(#[from] UpdateVersionsError),
+    #[error("Could not save project: {0}")]
+    Save(#[from] SaveMigrationError),
+}
+
+/// Plan a release of the project, assigning it the next version after the latest
+/// released locally or applied to `database`. If `database` is `None` (offline), only
+/// local releases are considered, so the version assigned may be lower than one already
+/// applied to the database by a concurrent release. Nothing is modified.
+pub fn plan_release<Db: Backend>(
     level: ReleaseLevel,
     info: &ProjectInfo,
-    mut database: Db,
-) -> anyhow::Result<Version>
-where
-    <Db as Backend>::Error: Send + Sync + 'static,
-{
-    info!("Releasing project");
+    database: Option<Db>,
+    compress: bool,
+) -> Result<ReleasePlan, ReleaseError<Db::Error>> {
     if info.project.version.pre.is_empty() {
         warn!("Not on a a feature version");
     }
 
     // Validate the project is not broken before releasing.
-    let mut built = match build_project(info) {
-        Ok(x) => x,
-        Err(e) => return Err(e.into()),
-    };
+    let build = build_project(info)?;
 
     let old_version = info.project.version.clone();
     debug!("Current version: {}", &old_version);
@@ -69,11 +176,59 @@ where
     drop(migrations);
     debug!("Latest local version: {}", &latest_local);
 
-    let state = database.open()?;
-    debug!("Latest remote version: {}", &state.project_version);
-
-    let latest = latest_local.max(state.project_version);
+    let latest = if let Some(mut database) = database {
+        // Opening the database would install sqigl onto it; a database it isn't
+        // installed on has no version to consider
+        let remote = if database.installed().map_err(ReleaseError::Database)? {
+            database
+                .open()
+                .map_err(ReleaseError::Database)?
+                .project_version
+        } else {
+            empty_database_version()
+        };
+        debug!("Latest remote version: {}", &remote);
+        latest_local.max(remote)
+    } else {
+        warn!("Releasing offline; the database's version was not considered");
+        latest_local
+    };
     let new_version = level.release_version(&latest);
+
+    let artifacts_dir = info.artifacts_dir();
+    let old_dir = artifacts_dir.join(normalize_version(&old_version).to_string());
+    let new_dir = artifacts_dir.join(normalize_version(&new_version).to_string());
+    if new_dir.exists() {
+        return Err(UpdateVersionsError::AlreadyExists(new_version).into());
+    }
+    let schema = Path::new(SCHEMA_ARTIFACT_TITLE).with_extension(if compress {
+        format!("{}.{}", SQL_EXTENSION, COMPRESSED_EXTENSION)
+    } else {
+        SQL_EXTENSION.to_string()
+    });
+
+    Ok(ReleasePlan {
+        old_version,
+        manifests: vec![info.project_manifest(), new_dir.join(MANIFEST_FILENAME)],
+        schema: new_dir.join(schema),
+        old_dir: old_dir.exists().then_some(old_dir),
+        new_dir,
+        new_version,
+        build,
+        compress,
+    })
+}
+
+/// Carry out a planned release.
+pub fn release(plan: ReleasePlan, info: &ProjectInfo) -> Result<Version, ReleaseError> {
+    info!("Releasing project");
+    let ReleasePlan {
+        old_version,
+        new_version,
+        mut build,
+        compress,
+        ..
+    } = plan;
     info!("Assigned version {} to this release", &new_version);
 
     info!("Updating project manifest");
@@ -83,9 +238,157 @@ where
     update_migration_versions(&old_version, &new_version, info)?;
 
     info!("Saving project");
-    built.set_version(&new_version);
-    save_migration(SCHEMA_ARTIFACT_TITLE, built, info)?;
+    build.set_version(&new_version);
+    save_migration(SCHEMA_ARTIFACT_TITLE, build, info, compress)?;
 
     info!("Released version {}", &new_version);
     Ok(new_version)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::sqlite::SqliteBackend, testing::TestProject};
+
+    #[test]
+    fn plan_release_fails_to_build() {
+        let project = TestProject::new("0.1.0-feature");
+        project.write("src/a.sql", "-- sqigl: include missing.sql");
+        let result =
+            plan_release::<SqliteBackend>(ReleaseLevel::Minor, &project.info(), None, false);
+        assert!(
+            matches!(result, Err(ReleaseError::Build(_))),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
+    fn plan_release_considers_database() {
+        let project = TestProject::new("0.1.0-feature");
+        project.write("src/a.sql", "create table a(x integer);");
+        let info = project.info();
+        let plan = plan_release::<SqliteBackend>(ReleaseLevel::Minor, &info, None, false).unwrap();
+        assert_eq!(plan.new_version, Version::new(0, 1, 0));
+
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.record(
+            ContentId::try_from(vec![0; 32]).unwrap(),
+            &Version::new(0, 3, 0),
+        )
+        .unwrap();
+        let plan = plan_release(ReleaseLevel::Minor, &info, Some(db), false).unwrap();
+        assert_eq!(plan.new_version, Version::new(0, 4, 0));
+    }
+
+    #[test]
+    fn compressed_schemas_round_trip() {
+        use crate::{actions::apply::apply_artifact, artifact::Artifact, migration::MigrationSet};
+
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let info = project.info();
+        let id = build_project(&info).unwrap().content_id();
+        save_project(&info, true).unwrap();
+
+        let version_dir = info.artifacts_dir().join("0.1.0");
+        assert!(!version_dir.join("schema.sql").exists());
+        let compressed = fs::read(version_dir.join("schema.sql.gz")).unwrap();
+        assert_eq!(compressed[..2], [0x1f, 0x8b]);
+
+        let schema = MigrationSet::open(&info)
+            .unwrap()
+            .get_schema(&Version::new(0, 1, 0))
+            .unwrap();
+        assert_eq!(schema.content_id(), id);
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        apply_artifact(&mut db, schema, false, None).unwrap();
+        assert_eq!(db.open().unwrap().project_version, Version::new(0, 1, 0));
+        let history = db.history(&Default::default()).unwrap();
+        assert_eq!(history.last().unwrap().content_id, id);
+    }
+
+    #[test]
+    fn releases_offline() {
+        let project = TestProject::new("0.1.0-feature");
+        project.write("src/a.sql", "create table a(x integer);");
+        let info = project.info();
+        let plan = plan_release(ReleaseLevel::Minor, &info, None::<SqliteBackend>, false).unwrap();
+        assert_eq!(plan.new_version, Version::new(0, 1, 0));
+        // Planning modifies nothing
+        assert!(!plan.new_dir.exists());
+        assert_eq!(project.info().project.version, info.project.version);
+
+        let schema = plan.schema.clone();
+        assert_eq!(release(plan, &info).unwrap(), Version::new(0, 1, 0));
+        assert!(schema.exists());
+        assert_eq!(project.info().project.version, Version::new(0, 1, 0));
+
+        // The next release follows the local one
+        let mut info = project.info();
+        info.project.version = "0.2.0-next".parse().unwrap();
+        let plan = plan_release(ReleaseLevel::Minor, &info, None::<SqliteBackend>, false).unwrap();
+        assert_eq!(plan.new_version, Version::new(0, 2, 0));
+    }
+
+    #[test]
+    fn saves_to_configured_directories() {
+        use crate::{artifact::Artifact, migration::MigrationSet};
+
+        let project = TestProject::new("0.1.0");
+        project
+            .write(
+                "sqigl.toml",
+                "[project]\ntitle = \"test\"\nversion = \"0.1.0\"\nsource_dir = \"sql\"\n\
+                artifacts_dir = \"db/migrations\"\n\n[database]\ndb = \"sqlite\"\n",
+            )
+            .write("sql/a.sql", "create table a(x integer);")
+            // Ignored, as it isn't the source directory
+            .write("src/b.sql", "create table b(x integer);");
+        let info = project.info();
+        let build = build_project(&info).unwrap();
+        assert_eq!(build.ordered_scripts(), [PathBuf::from("a.sql")]);
+
+        save_project(&info, false).unwrap();
+        assert!(project
+            .path()
+            .join("db/migrations/0.1.0/schema.sql")
+            .is_file());
+        assert!(!project.path().join("artifacts/0.1.0").exists());
+        let schema = MigrationSet::open(&info)
+            .unwrap()
+            .get_schema(&Version::new(0, 1, 0))
+            .unwrap();
+        assert_eq!(schema.content_id(), build.content_id());
+    }
+
+    #[test]
+    fn verifies_builds_against_the_saved_schema() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let info = project.info();
+        assert!(verify_build(&info).is_err(), "No version has been saved");
+        save_project(&info, false).unwrap();
+
+        let verification = verify_build(&info).unwrap();
+        assert!(verification.matches());
+        assert_eq!(verification.version, Version::new(0, 1, 0));
+        assert_eq!(
+            verification.to_string(),
+            format!("build matches version 0.1.0 ({})\n", verification.saved)
+        );
+
+        project.write("src/a.sql", "create table a(x integer, y integer);");
+        let verification = verify_build(&info).unwrap();
+        assert!(!verification.matches());
+        assert_eq!(
+            verification.to_string(),
+            format!(
+                "build ({}) differs from version 0.1.0 ({})\n",
+                verification.build, verification.saved
+            )
+        );
+    }
+}
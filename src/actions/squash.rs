@@ -0,0 +1,192 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use semver::Version;
+
+use crate::{
+    artifact::Artifact,
+    backend::Backend,
+    manifest::{artifact::remove_artifact_migration, project::ProjectInfo},
+    migration::{save_migration, MigrationSet, SquashedMigration},
+};
+
+pub const ARCHIVE_DIRECTORY: &str = "archive";
+
+/// Replace the shortest chain of migrations leading from `from` to `to` with a single
+/// migration. The squashed migration is compatible with the same versions as the first
+/// migration in the chain. If `archive` is set, the original migrations are moved into
+/// the `archive/` directory of their artifact directories, and removed from their
+/// manifests.
+pub fn squash_migrations<Db: Backend>(
+    from: Version,
+    to: Version,
+    info: &ProjectInfo,
+    database: &mut Db,
+    archive: bool,
+    compress: bool,
+) -> anyhow::Result<PathBuf> {
+    info!("Squashing migrations from {} to {}", from, to);
+
+    let migration_set = MigrationSet::open(info)?;
+    let Some(steps) = migration_set.path(&from, &to) else {
+        return Err(anyhow!(
+            "No chain of migrations leads from {} to {}",
+            from,
+            to
+        ));
+    };
+    if steps.len() < 2 {
+        return Err(anyhow!(
+            "{} -> {} is already a single migration",
+            steps[0].print().to_string(),
+            to
+        ));
+    }
+    for step in steps.iter() {
+        info!("Squashing {}", step.print());
+    }
+
+    // Check that applying the chain to the schema of `from` reproduces the schema of
+    // `to`, by applying both to throwaway databases & comparing the results.
+    match (
+        migration_set.get_schema(&from),
+        migration_set.get_schema(&to),
+    ) {
+        (Some(from_schema), Some(to_schema)) => {
+            let applied = SquashedMigration::new(
+                std::iter::once(from_schema)
+                    .chain(steps.iter().cloned())
+                    .collect(),
+            );
+            let exclude_schemas = &info.migrate.exclude_schemas;
+            let applied = database.introspect_artifact(&applied, exclude_schemas)?;
+            let expected = database.introspect_artifact(&to_schema, exclude_schemas)?;
+            let differences = applied.differences(&expected);
+            if !differences.is_empty() {
+                return Err(anyhow!(
+                    "Applying the migrations to the schema of {} does not reproduce the \
+                    schema of {}. These objects differ:\n    {}",
+                    from,
+                    to,
+                    differences.join("\n    ")
+                ));
+            }
+        }
+        _ => warn!(
+            "The schemas of {} and {} are not both saved; the squashed migration cannot \
+            be verified",
+            from, to
+        ),
+    }
+
+    let originals: Vec<PathBuf> = steps.iter().map(|s| s.script().to_path_buf()).collect();
+    let title = format!("from_{}.sql", &from);
+    let path = save_migration(&title, SquashedMigration::new(steps), info, compress)?;
+    info!("Saved squashed migration at {:?}", path);
+
+    if archive {
+        warn!(
+            "Archiving the original migrations; databases between {} and {} will only be \
+            able to migrate using migrations saved elsewhere",
+            from, to
+        );
+        for script in originals {
+            let directory = script
+                .parent()
+                .expect("Canonical paths to files should always have a parent");
+            let name = script
+                .file_name()
+                .expect("Scripts should always have a file name");
+            let archive_dir = directory.join(ARCHIVE_DIRECTORY);
+            fs::create_dir_all(&archive_dir)?;
+            fs::rename(&script, archive_dir.join(name))?;
+            remove_artifact_migration(name.as_ref(), directory.to_path_buf())?;
+            info!("Archived {:?}", script);
+        }
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::sqlite::SqliteBackend, testing::TestProject};
+
+    /// A project with schemas for 0.1.0, 0.2.0 & 0.3.0 & a migration to each from the
+    /// last. The migration to 0.3.0 consists of `last_step`.
+    fn project(last_step: &str) -> TestProject {
+        let project = TestProject::new("0.3.0");
+        let tables = [
+            "create table a(x int);",
+            "create table b(y int);",
+            "create table c(z int);",
+        ];
+        for (idx, version) in ["0.1.0", "0.2.0", "0.3.0"].into_iter().enumerate() {
+            let mut manifest = format!(
+                "[[migrations]]\nscript = \"schema.sql\"\nfrom = \"=0.0.0\"\nto = \"{version}\"\n"
+            );
+            project.write(
+                &format!("artifacts/{version}/schema.sql"),
+                &tables[..=idx].join("\n"),
+            );
+            if idx > 0 {
+                let from = format!("0.{}.0", idx);
+                let step = if idx == 2 { last_step } else { tables[idx] };
+                project.write(&format!("artifacts/{version}/from_{from}.sql"), step);
+                manifest.push_str(&format!(
+                    "\n[[migrations]]\nscript = \"from_{from}.sql\"\n\
+                    from = \"=0.{idx}\"\nto = \"{version}\"\n"
+                ));
+            }
+            project.write(&format!("artifacts/{version}/sqigl.toml"), &manifest);
+        }
+        project
+    }
+
+    #[test]
+    fn squashes_chain() {
+        let project = project("create table c(z int);");
+        let info = project.info();
+        let mut db = SqliteBackend::local().unwrap();
+        let path = squash_migrations(
+            Version::new(0, 1, 0),
+            Version::new(0, 3, 0),
+            &info,
+            &mut db,
+            true,
+            false,
+        )
+        .unwrap();
+        let squashed = fs::read_to_string(path).unwrap();
+        assert!(squashed.contains("create table b(y int);\n"));
+        assert!(squashed.contains("create table c(z int);\n"));
+        let archived = info.artifacts_dir().join("0.2.0").join(ARCHIVE_DIRECTORY);
+        assert!(archived.join("from_0.1.0.sql").is_file());
+        let steps = MigrationSet::open(&info)
+            .unwrap()
+            .path(&Version::new(0, 1, 0), &Version::new(0, 3, 0))
+            .unwrap();
+        assert_eq!(steps.len(), 1);
+    }
+
+    #[test]
+    fn refuses_chain_which_differs() {
+        let project = project("create table d(z int);");
+        let info = project.info();
+        let mut db = SqliteBackend::local().unwrap();
+        let e = squash_migrations(
+            Version::new(0, 1, 0),
+            Version::new(0, 3, 0),
+            &info,
+            &mut db,
+            false,
+            false,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(e.contains("table main.c"), "{e}");
+        assert!(e.contains("table main.d"), "{e}");
+    }
+}
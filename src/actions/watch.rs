@@ -0,0 +1,195 @@
+use std::{
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use log::{error, info};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::{
+    actions::{
+        apply::apply_artifact,
+        build::{build_project, is_script, BuildArtifact},
+    },
+    backend::{Backend, GenerateOptions},
+    manifest::{
+        project::{open_project, ProjectInfo},
+        MANIFEST_FILENAME,
+    },
+};
+
+/// Changes are collected until none have been seen for this long, so that saving many
+/// files at once triggers a single rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Whether a filesystem event changes the project manifest, or a script or manifest in
+/// the source directory.
+fn is_relevant(event: &Event, info: &ProjectInfo) -> bool {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+    let source_dir = info.source_dir();
+    let project_manifest = info.project_manifest();
+    event.paths.iter().any(|path| {
+        *path == project_manifest
+            || (path.starts_with(&source_dir)
+                && (is_script(path) || path.file_name().is_some_and(|f| f == MANIFEST_FILENAME)))
+    })
+}
+
+/// Rebuild the project & apply it to the database, or check it against a temporary
+/// database if `isolated` is set. The project is reopened, so that changes to its
+/// manifest (and `context_file`, if given) are picked up. The first build is applied
+/// as it is, as by `project apply`; after that, the database is migrated from the
+/// `previous` build to the new one, unless `force` is set, in which case each build is
+/// applied on top of the last.
+fn rebuild<Db: Backend>(
+    root: &Path,
+    database: &mut Db,
+    force: bool,
+    isolated: bool,
+    context_file: Option<&Path>,
+    previous: &mut Option<BuildArtifact>,
+) -> anyhow::Result<()>
+where
+    <Db as Backend>::Error: Send + Sync + 'static,
+{
+    let mut info = open_project(root.to_path_buf())?;
+    if let Some(context_file) = context_file {
+        info.add_context_file(context_file)?;
+    }
+    let artifact = build_project(&info)?;
+    if isolated {
+        database.check_isolated(&artifact)?;
+        info!("Check complete");
+        return Ok(());
+    }
+    match previous.as_ref() {
+        Some(from) if !force => {
+            let options = GenerateOptions::new(&info.migrate);
+            let migration = database.generate_migration(from, &artifact, &options)?;
+            apply_artifact(database, migration, false, None)?;
+        }
+        _ => {
+            apply_artifact(database, artifact.clone(), force, None)?;
+        }
+    }
+    *previous = Some(artifact);
+    Ok(())
+}
+
+/// Rebuild & apply the project whenever a script or manifest in its source directory
+/// (or the project manifest) changes. If `isolated` is set, each build is checked
+/// against a temporary database instead. The variables of `context_file` are added to
+/// each build's context. Errors are logged, and watching continues.
+pub fn watch_project<Db: Backend>(
+    info: &ProjectInfo,
+    mut database: Db,
+    force: bool,
+    isolated: bool,
+    context_file: Option<&Path>,
+) -> anyhow::Result<()>
+where
+    <Db as Backend>::Error: Send + Sync + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&info.source_dir(), RecursiveMode::Recursive)?;
+    // Editors may replace the manifest rather than modifying it, so its directory is
+    // watched rather than the file itself
+    watcher.watch(&info.root, RecursiveMode::NonRecursive)?;
+
+    info!("Watching {} for changes", info.project.title);
+    let mut previous = None;
+    loop {
+        if let Err(e) = rebuild(
+            &info.root,
+            &mut database,
+            force,
+            isolated,
+            context_file,
+            &mut previous,
+        ) {
+            error!("{:#}", e);
+        }
+        info!("Waiting for changes");
+
+        // Wait for a relevant change, & then for changes to stop
+        let mut changed = false;
+        while !changed {
+            changed = is_relevant(&rx.recv()??, info);
+        }
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(event) => {
+                    event?;
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        info!("Change detected; rebuilding");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::sqlite::SqliteBackend, testing::TestProject};
+    use notify::event::{AccessKind, ModifyKind};
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn relevant_events() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "").write("notes.txt", "");
+        let info = project.info();
+        let modified =
+            |path: PathBuf| Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path);
+
+        assert!(is_relevant(
+            &modified(info.source_dir().join("a.sql")),
+            &info
+        ));
+        assert!(is_relevant(
+            &modified(info.source_dir().join(MANIFEST_FILENAME)),
+            &info
+        ));
+        assert!(is_relevant(&modified(info.project_manifest()), &info));
+        assert!(!is_relevant(&modified(info.root.join("notes.txt")), &info));
+        assert!(!is_relevant(
+            &modified(info.source_dir().join("a.txt")),
+            &info
+        ));
+        assert!(!is_relevant(
+            &Event::new(EventKind::Access(AccessKind::Any))
+                .add_path(info.source_dir().join("a.sql")),
+            &info
+        ));
+    }
+
+    #[test]
+    fn rebuilds_repeatedly() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        let mut previous = None;
+
+        rebuild(project.path(), &mut db, false, false, None, &mut previous).unwrap();
+        project.write("src/b.sql", "create table b(x integer);");
+        rebuild(project.path(), &mut db, false, false, None, &mut previous).unwrap();
+        fs::remove_file(project.path().join("src/b.sql")).unwrap();
+        project.write("src/c.sql", "create table c(x integer);");
+        rebuild(project.path(), &mut db, false, false, None, &mut previous).unwrap();
+
+        let schema = db.introspect(&[]).unwrap();
+        let tables: Vec<_> = schema
+            .tables
+            .keys()
+            .map(|(_, name)| name.as_str())
+            .collect();
+        assert_eq!(tables, vec!["a", "c"]);
+    }
+}
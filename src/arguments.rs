@@ -1,24 +1,71 @@
-use std::path::PathBuf;
+use std::{num::NonZeroUsize, path::PathBuf};
 
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
 use semver::{BuildMetadata, Prerelease, Version};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
+    artifact::ContentId,
     backend::{self, postgres::PostgresBackend, sqlite::SqliteBackend, Backend},
     manifest,
 };
 
 #[derive(Parser, Clone, Debug, Serialize, Deserialize)]
+#[command(after_long_help = "\
+Exit codes:
+  0  Success
+  1  Any other error
+  2  Invalid arguments
+  3  No project manifest was found
+  4  The project failed to build
+  5  Could not connect to the database
+  6  The database's version is not compatible with the artifact")]
 pub struct SqiglArguments {
     /// Level at which to output logs to stderr
     #[arg(long, default_value = "info", env = "SQIGL_LOG_LEVEL")]
     pub log_level: LogLevel,
+    /// Output more logs: each use raises the log level by one step from `--log-level`
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+    /// Output fewer logs: each use lowers the log level by one step from `--log-level`
+    #[arg(short, long, action = ArgAction::Count)]
+    pub quiet: u8,
+    /// Format in which to output logs
+    #[arg(long, default_value = "text", env = "SQIGL_LOG_FORMAT")]
+    pub log_format: LogFormat,
+    /// Format in which to report the error which caused sqigl to fail, on stderr
+    #[arg(long, default_value = "text", env = "SQIGL_ERROR_FORMAT")]
+    pub error_format: ErrorFormat,
+    /// Fail instead of installing sqigl onto databases it has not been installed on
+    #[arg(long, env = "SQIGL_NO_INSTALL")]
+    pub no_install: bool,
     #[command(subcommand)]
     pub command: SqiglCommands,
 }
 
+impl SqiglArguments {
+    /// The log level after applying `--verbose` & `--quiet` to `--log-level`. The
+    /// level can't be raised above `trace` or lowered below `off`.
+    pub fn effective_log_level(&self) -> log::LevelFilter {
+        // From least to most verbose
+        const LEVELS: [log::LevelFilter; 6] = [
+            log::LevelFilter::Off,
+            log::LevelFilter::Error,
+            log::LevelFilter::Warn,
+            log::LevelFilter::Info,
+            log::LevelFilter::Debug,
+            log::LevelFilter::Trace,
+        ];
+        let base = log::LevelFilter::from(self.log_level);
+        let idx = LEVELS.iter().position(|level| *level == base).unwrap();
+        let idx = (idx + self.verbose as usize)
+            .saturating_sub(self.quiet as usize)
+            .min(LEVELS.len() - 1);
+        LEVELS[idx]
+    }
+}
+
 #[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum LogLevel {
     Off,
@@ -41,14 +88,67 @@ impl From<LogLevel> for log::LevelFilter {
     }
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, with `timestamp`, `level`, `target` & `message` fields
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ErrorFormat {
+    /// A human-readable description, including the chain of causes
+    Text,
+    /// A JSON object with a stable `code`, a `message`, & fields specific to the error,
+    /// such as the path of a dependency cycle
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BuildFormat {
+    /// The scripts of the build, as they are applied
+    #[default]
+    Raw,
+    /// Wrapped in a transaction, stopping at the first error, for piping into `psql`
+    Psql,
+    /// Preceded by a header describing the build, which `database apply-file` checks
+    Bundle,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PagerMode {
+    /// Page output through `$PAGER` (or `less -R`) when stdout is a terminal
+    #[default]
+    Auto,
+    /// Always write output directly to stdout
+    Never,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchemaFormat {
+    #[default]
+    Json,
+    Toml,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlanFormat {
+    /// The statements of the migration, as they would be saved
+    #[default]
+    Sql,
+    /// A JSON array with an object per statement, with `operation`, `object`, `sql` &
+    /// `destructive` fields
+    Json,
+}
+
 #[derive(Subcommand, Clone, Debug, Serialize, Deserialize)]
 pub enum SqiglCommands {
     #[clap(subcommand)]
     Project(ProjectCommands),
     #[clap(subcommand)]
     Migration(MigrationCommands),
-    #[clap(subcommand)]
-    Database(DatabaseCommand),
+    Database(DatabaseArgs),
 }
 
 #[derive(Subcommand, Clone, Debug, Serialize, Deserialize)]
@@ -77,7 +177,14 @@ pub enum ProjectCommands {
         /// An ID for the change, such as a ticket number. Must be alphanumeric
         /// with hyphens, matching `[0-9A-Za-z-]+`.
         title: String,
-        /// The project root (or any of it's subdirectories).
+        /// The project root (or any of its subdirectories, or its manifest).
+        #[arg(default_value = ".")]
+        project: PathBuf,
+    },
+
+    /// List the feature versions which have been saved but not released, with the
+    /// version each will be released as, its ID, & its artifact directory.
+    Features {
         #[arg(default_value = ".")]
         project: PathBuf,
     },
@@ -90,8 +197,43 @@ pub enum ProjectCommands {
         /// Write output to a file instead of printing it to stdout.
         output: Option<PathBuf>,
         /// Do not print the build to stdout.
-        #[arg(short, long)]
-        quiet: bool,
+        #[arg(long, alias = "quiet")]
+        no_output: bool,
+        /// Write the build to this directory as one script per module, instead of
+        /// printing it to stdout.
+        #[arg(long, visible_alias = "output-dir", conflicts_with = "output")]
+        split: Option<PathBuf>,
+        /// Print the content ID of the build to stderr.
+        #[arg(long)]
+        show_id: bool,
+        /// Fail if the content ID of the build does not match this hex-encoded SHA256 digest.
+        #[arg(long)]
+        expect_id: Option<ContentId>,
+        /// The format of the output. The content ID is always that of the raw build.
+        #[arg(long, value_enum, default_value_t, conflicts_with = "split")]
+        format: BuildFormat,
+        /// Whether to page the build when printing it to a terminal.
+        #[arg(long, value_enum, default_value_t, env = "SQIGL_PAGER")]
+        pager: PagerMode,
+        /// A TOML file of variables for template scripts, which take precedence over
+        /// the `[build.context]` of the project manifest.
+        #[arg(long)]
+        context_file: Option<PathBuf>,
+        /// Fail if any script contains no SQL statements, only whitespace & comments.
+        #[arg(long)]
+        strict: bool,
+        /// Fail if scripts in different modules share a file name, such as two
+        /// `create.sql`s.
+        #[arg(long)]
+        deny_duplicate_names: bool,
+        /// Only build this module (relative to the source directory), its submodules,
+        /// & the modules they depend on. May be given more than once.
+        #[arg(long)]
+        only: Vec<PathBuf>,
+        /// Don't build this module or its submodules. Fails if another module depends
+        /// on it. May be given more than once.
+        #[arg(long)]
+        skip: Vec<PathBuf>,
     },
 
     /// Build & apply the current version of the project to an empty database
@@ -100,6 +242,20 @@ pub enum ProjectCommands {
     Check {
         #[arg(default_value = ".")]
         project: PathBuf,
+        /// Check against a temporary database (an in-memory database for SQLite)
+        /// rather than the configured database, which is never connected to. Postgres
+        /// roles still need privileges to create databases, from which the temporary
+        /// database is created by connecting to the `postgres` database.
+        #[arg(long)]
+        isolated: bool,
+        /// Rather than the current build, check that the schema saved for every version
+        /// applies to an empty database. Each is checked against a temporary database.
+        #[arg(long)]
+        all_versions: bool,
+        /// A TOML file of variables for template scripts, which take precedence over
+        /// the `[build.context]` of the project manifest.
+        #[arg(long)]
+        context_file: Option<PathBuf>,
     },
 
     /// Apply the current state of the project to the database. This is for
@@ -108,6 +264,18 @@ pub enum ProjectCommands {
     Apply {
         #[arg(default_value = ".")]
         project: PathBuf,
+        /// Apply the build even if it is not compatible with the database's version.
+        #[arg(long)]
+        force: bool,
+        /// Send consecutive scripts to the database together, in batches of up to this
+        /// many bytes, rather than one at a time. Scripts are never split, so a script
+        /// larger than this is sent alone. Scripts are no longer reported individually.
+        #[arg(long, value_name = "BYTES")]
+        batch_size: Option<NonZeroUsize>,
+        /// A TOML file of variables for template scripts, which take precedence over
+        /// the `[build.context]` of the project manifest.
+        #[arg(long)]
+        context_file: Option<PathBuf>,
     },
 
     /// Build the current version of the project and save it as a migration.
@@ -115,6 +283,101 @@ pub enum ProjectCommands {
     Save {
         #[arg(default_value = ".")]
         project: PathBuf,
+        /// Write the artifact gzip-compressed.
+        #[arg(long)]
+        compress: bool,
+        /// A TOML file of variables for template scripts, which take precedence over
+        /// the `[build.context]` of the project manifest.
+        #[arg(long)]
+        context_file: Option<PathBuf>,
+    },
+
+    /// Rebuild & apply the project to the database whenever a script or manifest
+    /// changes. The first build is applied to the database; after that, the database is
+    /// migrated from the previous build to the new one. This is for development use.
+    #[command()]
+    Watch {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// Apply each build even if it is not compatible with the database's version.
+        /// Each build is applied on top of the previous one, rather than migrated to, so
+        /// its scripts must be able to run again.
+        #[arg(long)]
+        force: bool,
+        /// Check each build against a temporary database (an in-memory database for
+        /// SQLite), rather than applying it to the configured database.
+        #[arg(long, conflicts_with = "force")]
+        isolated: bool,
+        /// A TOML file of variables for template scripts, which take precedence over
+        /// the `[build.context]` of the project manifest.
+        #[arg(long)]
+        context_file: Option<PathBuf>,
+    },
+
+    /// Output the dependency graph of the project's modules & scripts in Graphviz DOT
+    /// format.
+    #[command()]
+    Graph {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// A TOML file of variables for template scripts, which take precedence over
+        /// the `[build.context]` of the project manifest.
+        #[arg(long)]
+        context_file: Option<PathBuf>,
+    },
+
+    /// Create a new module, with a minimal manifest.
+    #[command()]
+    Module {
+        /// The path of the module, relative to the source directory.
+        path: PathBuf,
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// A module or script the new module depends on, relative to the new module
+        /// (such as `../common`) or to the source directory if it begins with `/`.
+        #[arg(long = "dependency", short)]
+        dependencies: Vec<PathBuf>,
+    },
+
+    /// Check module manifests for undeclared or missing scripts and invalid
+    /// dependencies.
+    #[command()]
+    Lint {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+    },
+
+    /// Rewrite the project's manifests with keys in a canonical order & consistent
+    /// whitespace, preserving comments.
+    #[command()]
+    Fmt {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// Don't modify any files; fail if any manifest is not already formatted.
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Check that building the project reproduces the schema saved for its latest
+    /// released version. Exits with an error if they differ.
+    #[command()]
+    VerifyBuild {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// A TOML file of variables for template scripts, which take precedence over
+        /// the `[build.context]` of the project manifest.
+        #[arg(long)]
+        context_file: Option<PathBuf>,
+    },
+
+    /// Remove the saved artifacts of prerelease versions which were never released.
+    #[command()]
+    Clean {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// List the directories which would be removed without removing them.
+        #[arg(long)]
+        dry_run: bool,
     },
 
     /// Assign a project a release number & save it under it's new version.
@@ -124,6 +387,29 @@ pub enum ProjectCommands {
         level: ReleaseLevel,
         #[arg(default_value = ".")]
         project: PathBuf,
+        /// Write the artifact gzip-compressed.
+        #[arg(long)]
+        compress: bool,
+        /// Don't connect to the database. The version is based only on local releases,
+        /// and may be lower than a version already applied to the database.
+        #[arg(long)]
+        offline: bool,
+        /// Print the version which would be assigned & the changes which would be made,
+        /// without making them.
+        #[arg(long)]
+        dry_run: bool,
+        /// A TOML file of variables for template scripts, which take precedence over
+        /// the `[build.context]` of the project manifest.
+        #[arg(long)]
+        context_file: Option<PathBuf>,
+    },
+
+    /// Write the schema of the project's database into its source directory, as a
+    /// module of tables & a module of indexes. Only SQLite databases are supported.
+    #[command()]
+    Import {
+        #[arg(default_value = ".")]
+        project: PathBuf,
     },
 }
 
@@ -141,18 +427,150 @@ impl From<DatabaseKind> for manifest::project::Database {
     }
 }
 
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+pub struct DatabaseArgs {
+    /// Target one tenant's copy of the schema, in this Postgres schema (which is created
+    /// when sqigl is installed for the tenant), with it as the search path. Each
+    /// tenant's version is tracked independently.
+    #[arg(long, value_name = "SCHEMA", global = true)]
+    pub schema: Option<String>,
+    #[command(subcommand)]
+    pub command: DatabaseCommand,
+}
+
 #[derive(Subcommand, Clone, Debug, Serialize, Deserialize)]
 pub enum DatabaseCommand {
     /// Install `sqigl` onto the database.
     Install {
         #[arg(default_value = ".")]
         project: PathBuf,
+        /// Create the database first if it doesn't exist. SQLite databases are always
+        /// created when they are opened.
+        #[arg(long)]
+        create_database: bool,
     },
 
     /// Apply the appropriate migration to update the database to the supplied
-    /// version.
+    /// version. If no single migration leads there, a chain of migrations is applied.
     Apply {
-        version: Version,
+        #[arg(required_unless_present_any = ["latest", "plan"])]
+        version: Option<Version>,
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// Update the database to the latest released version.
+        #[arg(long, conflicts_with = "version")]
+        latest: bool,
+        /// Apply exactly the migrations of a plan written by `sqigl database plan`,
+        /// failing if the database's version or the saved migrations no longer match it.
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["version", "latest", "force"])]
+        plan: Option<PathBuf>,
+        /// If no chain of migrations leads to the version, apply the migration to it
+        /// even though it is not compatible with the database's version.
+        #[arg(long)]
+        force: bool,
+        /// Apply migrations marked as parallel by running their statements across a pool
+        /// of connections (4 by default), each statement in its own transaction. This is
+        /// not atomic; a failed migration may be partially applied.
+        #[arg(
+            long,
+            value_name = "CONNECTIONS",
+            num_args = 0..=1,
+            default_missing_value = "4"
+        )]
+        parallel: Option<NonZeroUsize>,
+        /// Create the database first if it doesn't exist. SQLite databases are always
+        /// created when they are opened.
+        #[arg(long)]
+        create_database: bool,
+    },
+
+    /// Apply a build which was written to a file, such as by `sqigl project build
+    /// <output>`, rather than building the project. The file may also be a bundle.
+    ApplyFile {
+        artifact: PathBuf,
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// Apply the build even if it is not compatible with the database's version.
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Execute a one-off SQL script in a transaction. This is not a migration; the
+    /// project version is unchanged.
+    Exec {
+        script: PathBuf,
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// Record the script in the database's history.
+        #[arg(long)]
+        record: bool,
+    },
+
+    /// Check that the database is reachable, without installing or modifying
+    /// anything.
+    Ping {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+    },
+
+    /// Print the migrations which would be applied to update the database to a version,
+    /// as JSON, without applying them. The plan can be applied with `sqigl database
+    /// apply --plan`.
+    Plan {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// The version to update the database to. By default, the latest released
+        /// version.
+        #[arg(long)]
+        to: Option<Version>,
+    },
+
+    /// Print the structure of the database's schema (its tables, columns, indexes,
+    /// constraints, views & so on), as migrations are generated from. Objects are in a
+    /// consistent order, so that dumps may be compared.
+    DumpSchema {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        #[arg(long, value_enum, default_value_t)]
+        format: SchemaFormat,
+    },
+
+    /// List the artifacts applied to the database, most recent first.
+    History {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// Only list artifacts applied at this version or later.
+        #[arg(long)]
+        since: Option<Version>,
+        /// Only list artifacts applied at this version or earlier.
+        #[arg(long)]
+        until: Option<Version>,
+        /// List at most this many artifacts.
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+
+    /// Report artifacts applied to the database which no longer match any artifact in
+    /// the project, such as migrations edited after they were applied.
+    Drift {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+    },
+
+    /// Repair the history of a database which was migrated outside of sqigl, by
+    /// recording the saved version whose schema matches the database's. Fails if the
+    /// schema matches no saved version.
+    Repair {
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// Repair the history without confirmation.
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Print the connection parameters which would be used, and where each came from,
+    /// without connecting. Passwords are never printed.
+    Info {
         #[arg(default_value = ".")]
         project: PathBuf,
     },
@@ -193,6 +611,14 @@ pub enum MigrationCommands {
         /// The directory in which the new project's root directory will be created.
         #[arg(default_value = ".")]
         project: PathBuf,
+        /// Only allow the migration to be applied to the exact patch version of `from`,
+        /// rather than any patch version of its minor version.
+        #[arg(long)]
+        exact: bool,
+        /// Also create an empty down script reversing the migration, & record it in the
+        /// artifact manifest.
+        #[arg(long)]
+        with_down: bool,
     },
 
     /// Generate a new migration.
@@ -204,13 +630,142 @@ pub enum MigrationCommands {
         /// The directory in which the new project's root directory will be created.
         #[arg(default_value = ".")]
         project: PathBuf,
+        /// Write the migration gzip-compressed.
+        #[arg(long)]
+        compress: bool,
+        /// Save the migration without confirmation, even if it may destroy data.
+        #[arg(long, short)]
+        yes: bool,
+        /// Keep the temporary databases the schemas are loaded into, and print their
+        /// names, rather than removing them.
+        #[arg(long)]
+        keep_temp: bool,
+        /// Don't check that applying the migration to the schema of `from` reproduces
+        /// the schema of `to`.
+        #[arg(long)]
+        no_verify: bool,
+        /// Only allow the migration to be applied to the exact patch version of `from`,
+        /// rather than any patch version of its minor version.
+        #[arg(long)]
+        exact: bool,
+        /// Guard statements with `IF EXISTS` or `IF NOT EXISTS`, so that a partially
+        /// applied migration can be retried. Only supported for SQLite.
+        #[arg(long)]
+        if_exists: bool,
+        /// Print the statements of the migration on stdout, rather than saving it.
+        #[arg(long)]
+        plan_only: bool,
+        /// Ignore a schema & the objects in it, in addition to those in the
+        /// `exclude_schemas` of the project manifest. May be given more than once. Only
+        /// supported for Postgres.
+        #[arg(long = "exclude-schema", value_name = "SCHEMA")]
+        exclude_schemas: Vec<String>,
+        /// The format of the plan printed by `--plan-only`.
+        #[arg(long, value_enum, default_value_t, requires = "plan_only")]
+        format: PlanFormat,
     },
 
-    /// Run a migration against an empty database, and roll it back
-    Check {
+    /// Replace a chain of migrations with a single migration.
+    Squash {
+        /// The version the chain migrates from.
+        from: Version,
+        /// The version the chain migrates to.
+        to: Version,
+        #[arg(default_value = ".")]
+        project: PathBuf,
+        /// Move the original migrations into an `archive/` directory. Databases at
+        /// versions within the chain will no longer be able to migrate.
+        #[arg(long)]
+        archive: bool,
+        /// Write the migration gzip-compressed.
+        #[arg(long)]
+        compress: bool,
     },
 
+    /// Run a migration against an empty database, and roll it back
+    Check {},
+
     /// Create a new database, and apply the migrations
-    Apply {
+    Apply {},
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn arguments_are_consistent() {
+        SqiglArguments::command().debug_assert();
+    }
+
+    #[test]
+    fn build_output_is_separate_from_log_level() {
+        let args =
+            SqiglArguments::try_parse_from(["sqigl", "-qq", "project", "build", "--no-output"])
+                .unwrap();
+        assert_eq!(args.quiet, 2);
+        assert!(matches!(
+            args.command,
+            SqiglCommands::Project(ProjectCommands::Build {
+                no_output: true,
+                ..
+            })
+        ));
+        assert!(SqiglArguments::try_parse_from(["sqigl", "project", "build", "-q"]).is_err());
+
+        // `--quiet` is still accepted after `build`, for existing scripts
+        let args =
+            SqiglArguments::try_parse_from(["sqigl", "project", "build", "--quiet"]).unwrap();
+        assert_eq!(args.quiet, 0);
+        assert!(matches!(
+            args.command,
+            SqiglCommands::Project(ProjectCommands::Build {
+                no_output: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn effective_log_level() {
+        let level = |args: &[&str]| {
+            SqiglArguments::try_parse_from(
+                ["sqigl"].iter().chain(args).chain(&["project", "build"]),
+            )
+            .unwrap()
+            .effective_log_level()
+        };
+        assert_eq!(level(&[]), log::LevelFilter::Info);
+        assert_eq!(level(&["-vv"]), log::LevelFilter::Trace);
+        assert_eq!(level(&["-vvvv"]), log::LevelFilter::Trace);
+        assert_eq!(
+            level(&["--log-level", "warn", "-q"]),
+            log::LevelFilter::Error
+        );
+        assert_eq!(level(&["-qqqqq"]), log::LevelFilter::Off);
+        assert_eq!(level(&["-vq"]), log::LevelFilter::Info);
+    }
+
+    #[test]
+    fn building_commands_take_a_context_file() {
+        for command in [
+            "build",
+            "check",
+            "apply",
+            "save",
+            "watch",
+            "graph",
+            "verify-build",
+            "release patch",
+        ] {
+            let args = SqiglArguments::try_parse_from(
+                ["sqigl", "project"]
+                    .into_iter()
+                    .chain(command.split(' '))
+                    .chain(["--context-file", "context.toml"]),
+            );
+            assert!(args.is_ok(), "{command}: {}", args.unwrap_err());
+        }
     }
 }
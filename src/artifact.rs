@@ -1,19 +1,26 @@
+use log::debug;
 use semver::{BuildMetadata, Version, VersionReq};
 use serde::{Deserialize, Serialize};
-use sha2::digest::{
-    consts::{B0, B1},
-    generic_array::GenericArray,
-    typenum::{UInt, UTerm},
+use sha2::{
+    digest::{
+        consts::{B0, B1},
+        generic_array::GenericArray,
+        typenum::{UInt, UTerm},
+    },
+    Digest, Sha256,
 };
 use std::{
     error::Error,
     fmt::{Debug, Display},
-    io::{self, Read, Write},
+    io::{self, IsTerminal, Read, Write},
     path::StripPrefixError,
-    str::{self, Utf8Error},
+    str::{self, FromStr, Utf8Error},
+    time::Instant,
 };
 use thiserror::Error;
 
+use crate::{backend::PlannedStatement, manifest::artifact::Maintenance, util::format_duration};
+
 /// A SHA256 digest uniquely identifying a particular artifact's contents
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ContentId([u8; 32]);
@@ -26,7 +33,7 @@ impl Debug for ContentId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("sha256 [")?;
         for x in self.0 {
-            f.write_fmt(format_args!("{:x}", x))?;
+            f.write_fmt(format_args!("{:02x}", x))?;
         }
         f.write_str(" ]")?;
         Ok(())
@@ -35,7 +42,7 @@ impl Debug for ContentId {
 impl Display for ContentId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for x in self.0 {
-            f.write_fmt(format_args!("{:x}", x))?;
+            f.write_fmt(format_args!("{:02x}", x))?;
         }
         Ok(())
     }
@@ -77,10 +84,30 @@ impl TryFrom<Vec<u8>> for ContentId {
     }
 }
 
+/// Parses the hexadecimal form produced by `Display`, in either case.
+impl FromStr for ContentId {
+    type Err = ContentIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(c) = s.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(ContentIdError::InvalidDigit(c));
+        }
+        if s.len() != 64 {
+            return Err(ContentIdError::WrongLength(s.len()));
+        }
+        let bytes = hex::decode(s).expect("Hexadecimal digits of even length always decode");
+        Self::try_from(bytes)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Error)]
 pub enum ContentIdError {
     #[error("Must be exactly 32 bytes (found {0} bytes)")]
     WrongWidth(usize),
+    #[error("Must be 64 hexadecimal digits (found {0} digits)")]
+    WrongLength(usize),
+    #[error("Invalid hexadecimal digit {0:?}")]
+    InvalidDigit(char),
 }
 
 /// An artifact represents built code which can be applied to a database.
@@ -88,6 +115,32 @@ pub trait Artifact {
     fn compatible(&self, version: &Version) -> bool;
     fn version(&self) -> &Version;
     fn spec(&self) -> (VersionReq, Version);
+    /// Statements within the artifact which may destroy data, if they are known.
+    fn destructive_statements(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// A summary of the objects the artifact changes, such as "2 tables created, 1 index
+    /// dropped", if they are known.
+    fn summary(&self) -> Option<String> {
+        None
+    }
+    /// The statements of the artifact, if they are known.
+    fn plan(&self) -> Option<Vec<PlannedStatement>> {
+        None
+    }
+    /// Whether the artifact's statements are independent of each other, so that they may
+    /// be applied concurrently rather than in a single transaction.
+    fn parallel(&self) -> bool {
+        false
+    }
+    /// Maintenance to run after the artifact has been applied.
+    fn post(&self) -> Option<Maintenance> {
+        None
+    }
+    /// What the artifact does & why, if its author described it.
+    fn description(&self) -> Option<&str> {
+        None
+    }
     fn scripts<C: ScriptConsumer>(
         &self,
         consumer: C,
@@ -115,6 +168,42 @@ pub trait Artifact {
         let consumer: Consumer<F> = Consumer { f };
         self.scripts(consumer)
     }
+    /// Write the artifact as a script for `psql`, which stops at the first error and
+    /// applies the artifact in a single transaction. The content ID is that of the
+    /// artifact itself, not of the wrapped script.
+    fn write_psql_to<F: Write>(
+        &self,
+        mut f: F,
+    ) -> Result<ContentId, ScriptProcessingError<NullConsumerError>> {
+        f.write_all(b"\\set ON_ERROR_STOP on\nBEGIN;\n")?;
+        let id = self.write_to(&mut f)?;
+        // The last script needn't end with a semicolon, so its final statement is
+        // terminated (by an empty statement, if it was already) before committing
+        f.write_all(b"\n;\nCOMMIT;\n")?;
+        Ok(id)
+    }
+    /// Write the artifact as a bundle, which describes the artifact in a header
+    /// preceding its code. See `read_bundle`.
+    fn write_bundle<F: Write>(
+        &self,
+        title: &str,
+        mut f: F,
+    ) -> Result<ContentId, ScriptProcessingError<NullConsumerError>> {
+        let mut code = Vec::with_capacity(1024);
+        let id = self.write_to(&mut code)?;
+        let (from, to) = self.spec();
+        let header = BundleHeader {
+            title: title.trim_ascii().to_string(),
+            from,
+            to,
+            content_id: id.to_string(),
+        };
+        let header = toml::to_string(&header).map_err(anyhow::Error::from)?;
+        writeln!(f, "{} {} {}", BUNDLE_MAGIC, BUNDLE_VERSION, header.len())?;
+        f.write_all(header.as_bytes())?;
+        f.write_all(&code)?;
+        Ok(id)
+    }
     fn content_id(&self) -> ContentId {
         struct Consumer;
         impl ScriptConsumer for Consumer {
@@ -144,6 +233,100 @@ pub trait Artifact {
     }
 }
 
+/// Bundles begin with a line of the form `sqigl-bundle <format version> <header length>`,
+/// followed by a TOML header of that many bytes, followed by the artifact's code.
+pub const BUNDLE_MAGIC: &str = "sqigl-bundle";
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BundleHeader {
+    title: String,
+    from: VersionReq,
+    to: Version,
+    content_id: String,
+}
+
+/// An artifact read from a bundle, whose code has been checked against its content ID.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub title: String,
+    pub from: VersionReq,
+    pub to: Version,
+    pub content_id: ContentId,
+    pub code: String,
+}
+
+/// Whether `code` is a bundle, rather than a script.
+pub fn is_bundle(code: &str) -> bool {
+    code.starts_with(BUNDLE_MAGIC)
+}
+
+/// Read a bundle written by `Artifact::write_bundle`, checking that the content ID
+/// recorded in its header matches its code.
+pub fn read_bundle(bundle: &str) -> Result<Bundle, BundleError> {
+    let (first, rest) = bundle.split_once('\n').ok_or(BundleError::NotABundle)?;
+    let mut fields = first.split(' ');
+    if fields.next() != Some(BUNDLE_MAGIC) {
+        return Err(BundleError::NotABundle);
+    }
+    let (Some(Ok(version)), Some(Ok(len)), None) = (
+        fields.next().map(str::parse::<u32>),
+        fields.next().map(str::parse::<usize>),
+        fields.next(),
+    ) else {
+        return Err(BundleError::NotABundle);
+    };
+    if version != BUNDLE_VERSION {
+        return Err(BundleError::UnsupportedVersion(version));
+    }
+    let (Some(header), Some(code)) = (rest.get(..len), rest.get(len..)) else {
+        return Err(BundleError::Truncated);
+    };
+
+    let header: BundleHeader = toml::from_str(header)?;
+    let expected = hex::decode(&header.content_id)
+        .ok()
+        .and_then(|id| ContentId::try_from(id).ok())
+        .ok_or_else(|| BundleError::InvalidContentId(header.content_id.clone()))?;
+    let found = Sha256::digest(code).into();
+    if expected != found {
+        return Err(BundleError::ContentIdMismatch { expected, found });
+    }
+
+    Ok(Bundle {
+        title: header.title,
+        from: header.from,
+        to: header.to,
+        content_id: found,
+        code: code.to_string(),
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error(
+        "Not a bundle: It does not begin with a `{} <version> <length>` line",
+        BUNDLE_MAGIC
+    )]
+    NotABundle,
+    #[error("Unsupported bundle format version {0} (expected {})", BUNDLE_VERSION)]
+    UnsupportedVersion(u32),
+    #[error("The bundle is truncated")]
+    Truncated,
+    #[error("Invalid bundle header: {0}")]
+    InvalidHeader(#[from] toml::de::Error),
+    #[error("Invalid content ID in bundle header: {0}")]
+    InvalidContentId(String),
+    #[error(
+        "The bundle's code does not match its \
+        content ID:\n    expected {expected}\n    found    {found}"
+    )]
+    ContentIdMismatch {
+        expected: ContentId,
+        found: ContentId,
+    },
+}
+
 pub struct Spec {
     from: VersionReq,
     to: Version,
@@ -155,13 +338,89 @@ impl Display for Spec {
     }
 }
 
+/// Identifies a script within an artifact.
+#[derive(Clone, Copy, Debug)]
+pub struct ScriptName<'a> {
+    pub name: &'a str,
+    /// The position of the script within the artifact, starting from 1
+    pub index: usize,
+    pub total: usize,
+}
+
 /// Script consumers can access the code within an artifact.
 pub trait ScriptConsumer {
     type Error: ConsumerError + Error + Debug + Sync + Send + 'static;
     fn accept(&mut self, script: &str) -> Result<(), ScriptProcessingError<Self::Error>>;
+    /// Accept a script along with its name. Artifacts which know the names of their
+    /// scripts call this rather than `accept`.
+    fn accept_named(
+        &mut self,
+        _name: ScriptName,
+        script: &str,
+    ) -> Result<(), ScriptProcessingError<Self::Error>> {
+        self.accept(script)
+    }
     fn commit(self, id: ContentId) -> Result<(), ScriptProcessingError<Self::Error>>;
 }
 
+/// Reports each named script on stderr before passing it on to another consumer.
+/// Nothing is reported if stderr is not a terminal, unless another output is given with
+/// `with_output`. The time taken by each script is
+/// logged at debug level.
+pub struct Progress<C, W = io::Stderr> {
+    inner: C,
+    output: Option<W>,
+}
+impl<C: ScriptConsumer> Progress<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            output: io::stderr().is_terminal().then(io::stderr),
+        }
+    }
+}
+impl<C: ScriptConsumer, W: Write> Progress<C, W> {
+    /// Report each named script to `output`, whether or not it is a terminal.
+    pub fn with_output(inner: C, output: W) -> Self {
+        Self {
+            inner,
+            output: Some(output),
+        }
+    }
+}
+impl<C: ScriptConsumer, W: Write> ScriptConsumer for Progress<C, W> {
+    type Error = C::Error;
+
+    fn accept(&mut self, script: &str) -> Result<(), ScriptProcessingError<Self::Error>> {
+        self.inner.accept(script)
+    }
+    fn accept_named(
+        &mut self,
+        name: ScriptName,
+        script: &str,
+    ) -> Result<(), ScriptProcessingError<Self::Error>> {
+        if let Some(output) = self.output.as_mut() {
+            // Progress is best-effort; failing to report it doesn't fail the migration
+            let _ = writeln!(
+                output,
+                "applying script {} of {}: {}",
+                name.index, name.total, name.name
+            );
+        }
+        let start = Instant::now();
+        self.inner.accept_named(name, script)?;
+        debug!(
+            "Applied script {} in {}",
+            name.name,
+            format_duration(start.elapsed())
+        );
+        Ok(())
+    }
+    fn commit(self, id: ContentId) -> Result<(), ScriptProcessingError<Self::Error>> {
+        self.inner.commit(id)
+    }
+}
+
 pub trait ConsumerError {}
 
 #[derive(Error, Debug)]
@@ -187,6 +446,12 @@ pub enum ScriptProcessingError<DatabaseError: Error + Debug> {
     #[error("Could not process text: {0}")]
     Prefix(#[from] StripPrefixError),
 
+    #[error(
+        "Migration {0} was interrupted & may be partially applied; once the database's \
+        schema matches a saved version, run `sqigl database repair`"
+    )]
+    Pending(ContentId),
+
     #[error("Database error: {0}")]
     Database(DatabaseError),
 
@@ -198,3 +463,136 @@ impl<C: Error + Debug + ConsumerError> From<C> for ScriptProcessingError<C> {
         Self::Database(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actions::build::build_project, testing::TestProject};
+
+    fn bundle() -> (String, ContentId) {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+        let mut bundle = Vec::new();
+        let id = artifact.write_bundle("my project", &mut bundle).unwrap();
+        assert_eq!(id, artifact.content_id());
+        (String::from_utf8(bundle).unwrap(), id)
+    }
+
+    #[test]
+    fn content_id_round_trips() {
+        let id = ContentId::from(Sha256::digest("create table a(x integer);"));
+        let hex = id.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse::<ContentId>().unwrap(), id);
+        assert_eq!(hex.to_uppercase().parse::<ContentId>().unwrap(), id);
+    }
+
+    #[test]
+    fn invalid_content_ids() {
+        assert!(matches!(
+            "ab".repeat(31).parse::<ContentId>(),
+            Err(ContentIdError::WrongLength(62))
+        ));
+        assert!(matches!(
+            "a".repeat(65).parse::<ContentId>(),
+            Err(ContentIdError::WrongLength(65))
+        ));
+        assert!(matches!(
+            format!("{}g", "a".repeat(63)).parse::<ContentId>(),
+            Err(ContentIdError::InvalidDigit('g'))
+        ));
+        assert!(matches!(
+            "".parse::<ContentId>(),
+            Err(ContentIdError::WrongLength(0))
+        ));
+    }
+
+    #[test]
+    fn bundle_round_trips() {
+        let (bundle, id) = bundle();
+        assert!(is_bundle(&bundle));
+
+        let read = read_bundle(&bundle).unwrap();
+        assert_eq!(read.title, "my project");
+        assert_eq!(read.from, VersionReq::parse("=0.0.0").unwrap());
+        assert_eq!(read.to, Version::new(0, 1, 0));
+        assert_eq!(read.content_id, id);
+        assert!(read.code.contains("create table a(x integer);"));
+    }
+
+    #[test]
+    fn corrupt_bundle() {
+        let (bundle, _) = bundle();
+        // Change the last `x` in the bundle, which is in the artifact's code
+        let mut bytes = bundle.into_bytes();
+        let i = bytes.len() - bytes.iter().rev().position(|b| *b == b'x').unwrap() - 1;
+        bytes[i] = b'y';
+        let corrupt = String::from_utf8(bytes).unwrap();
+        assert!(matches!(
+            read_bundle(&corrupt),
+            Err(BundleError::ContentIdMismatch { .. })
+        ));
+
+        assert!(matches!(
+            read_bundle("create table a(x integer);\n"),
+            Err(BundleError::NotABundle)
+        ));
+    }
+
+    #[test]
+    fn reports_progress() {
+        struct Scripts<'a>(&'a mut Vec<String>);
+        impl ScriptConsumer for Scripts<'_> {
+            type Error = NullConsumerError;
+
+            fn accept(&mut self, script: &str) -> Result<(), ScriptProcessingError<Self::Error>> {
+                self.0.push(script.to_string());
+                Ok(())
+            }
+            fn commit(self, _id: ContentId) -> Result<(), ScriptProcessingError<Self::Error>> {
+                Ok(())
+            }
+        }
+
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/a.sql", "create table a(x integer);")
+            .write("src/b.sql", "create table b(x integer);")
+            .write("src/c/c.sql", "create table c(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+        let (mut scripts, mut output) = (Vec::new(), Vec::new());
+        let id = artifact
+            .scripts(Progress::with_output(Scripts(&mut scripts), &mut output))
+            .unwrap();
+        assert_eq!(id, artifact.content_id());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "applying script 1 of 3: a.sql\n\
+            applying script 2 of 3: b.sql\n\
+            applying script 3 of 3: c/c.sql\n"
+        );
+        // Scripts are still passed on
+        assert!(scripts.concat().contains("create table c(x integer);"));
+    }
+
+    #[test]
+    fn psql_scripts_are_transactional() {
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/a.sql", "create table a(x integer);")
+            .write("src/b.sql", "create table b(x integer)");
+        let artifact = build_project(&project.info()).unwrap();
+        let mut raw = Vec::new();
+        let id = artifact.write_to(&mut raw).unwrap();
+        let mut psql = Vec::new();
+        assert_eq!(artifact.write_psql_to(&mut psql).unwrap(), id);
+
+        let psql = String::from_utf8(psql).unwrap();
+        let code = psql
+            .strip_prefix("\\set ON_ERROR_STOP on\nBEGIN;\n")
+            .and_then(|code| code.strip_suffix("\n;\nCOMMIT;\n"))
+            .unwrap_or_else(|| panic!("Not wrapped in a transaction:\n{psql}"));
+        assert_eq!(code.as_bytes(), raw);
+    }
+}
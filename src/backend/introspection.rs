@@ -0,0 +1,310 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Serialize, Serializer};
+
+/// A schema-qualified name. Objects in SQLite's main database are in the `main` schema.
+pub type QualifiedName = (String, String);
+
+/// A backend-agnostic description of a database's schema. Migrations are generated by
+/// comparing two of them. Each backend introspects its databases into a `Schema`,
+/// filling in the kinds of objects it supports & leaving the rest empty. When serialized,
+/// objects are keyed by their names joined with `.`, & are in order of those names.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Schema {
+    pub schemas: BTreeSet<String>,
+    #[serde(serialize_with = "joined_keys")]
+    pub tables: BTreeMap<QualifiedName, Table>,
+    #[serde(serialize_with = "joined_keys")]
+    pub views: BTreeMap<QualifiedName, View>,
+    /// Maps each view to the views it selects from.
+    #[serde(serialize_with = "joined_dependencies")]
+    pub view_dependencies: BTreeMap<QualifiedName, BTreeSet<QualifiedName>>,
+    /// Maps each table & materialized view to the `CREATE INDEX` statements of its
+    /// indexes, excluding those created implicitly by a constraint.
+    #[serde(serialize_with = "joined_keys")]
+    pub indexes: BTreeMap<QualifiedName, Vec<String>>,
+    #[serde(serialize_with = "joined_keys")]
+    pub sequences: BTreeMap<QualifiedName, Sequence>,
+    /// Maps the schema, name, and identity arguments of each function (which distinguish
+    /// overloads) to its definition.
+    #[serde(serialize_with = "function_keys")]
+    pub functions: BTreeMap<(String, String, String), Function>,
+    /// Maps the schema, table, and name of each trigger to its `CREATE TRIGGER`
+    /// statement.
+    #[serde(serialize_with = "joined_keys")]
+    pub triggers: BTreeMap<(String, String, String), String>,
+}
+impl Schema {
+    /// The objects which differ between two schemas, such as `table public.a`, in order
+    /// of their kinds & names.
+    pub fn differences(&self, other: &Schema) -> Vec<String> {
+        let mut output: Vec<_> = self
+            .schemas
+            .symmetric_difference(&other.schemas)
+            .map(|name| format!("schema {}", name))
+            .collect();
+        differing("table", &self.tables, &other.tables, &mut output);
+        differing("view", &self.views, &other.views, &mut output);
+        differing(
+            "view dependencies of",
+            &self.view_dependencies,
+            &other.view_dependencies,
+            &mut output,
+        );
+        differing("indexes of", &self.indexes, &other.indexes, &mut output);
+        differing("sequence", &self.sequences, &other.sequences, &mut output);
+        differing("function", &self.functions, &other.functions, &mut output);
+        differing("trigger", &self.triggers, &other.triggers, &mut output);
+        output
+    }
+    /// The indexes of a table or materialized view.
+    pub fn indexes_of(&self, name: &QualifiedName) -> &[String] {
+        self.indexes
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+/// The name of the index created by a `CREATE INDEX` statement, including any quotes
+/// around it.
+pub fn index_name(definition: &str) -> Option<&str> {
+    let rest = strip_keyword(definition, "create")?;
+    let rest = strip_keyword(rest, "unique").unwrap_or(rest);
+    let rest = strip_keyword(rest, "index")?;
+    let rest = strip_keyword(rest, "if")
+        .and_then(|rest| strip_keyword(rest, "not"))
+        .and_then(|rest| strip_keyword(rest, "exists"))
+        .unwrap_or(rest);
+    let end = match rest.chars().next()? {
+        quote @ ('"' | '`' | '[') => {
+            let close = if quote == '[' { ']' } else { quote };
+            rest[1..].find(close)? + 2
+        }
+        _ => rest
+            .find(|c: char| c.is_whitespace() || c == '(')
+            .unwrap_or(rest.len()),
+    };
+    Some(&rest[..end])
+}
+
+/// The rest of `code` after its first word, if that word is `keyword`.
+pub fn strip_keyword<'a>(code: &'a str, keyword: &str) -> Option<&'a str> {
+    let code = code.trim_start();
+    let word = code.split(char::is_whitespace).next()?;
+    word.eq_ignore_ascii_case(keyword)
+        .then(|| code[word.len()..].trim_start())
+}
+
+/// The objects among `candidates` which any of `roots` depend on, either directly or
+/// through other candidates. Protected objects keep the deleted objects they depend on.
+pub fn dependencies_among<'a>(
+    roots: &BTreeSet<QualifiedName>,
+    candidates: &BTreeSet<QualifiedName>,
+    dependencies: impl Fn(&QualifiedName) -> Option<&'a BTreeSet<QualifiedName>>,
+) -> BTreeSet<QualifiedName> {
+    let mut output = BTreeSet::default();
+    let mut pending: Vec<_> = roots.iter().cloned().collect();
+    while let Some(name) = pending.pop() {
+        for dep in dependencies(&name).into_iter().flatten() {
+            if candidates.contains(dep) && !roots.contains(dep) && output.insert(dep.clone()) {
+                pending.push(dep.clone());
+            }
+        }
+    }
+    output
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Table {
+    pub columns: Vec<Column>,
+    pub constraints: Vec<Constraint>,
+    /// The tables this table's foreign keys refer to, other than itself.
+    #[serde(serialize_with = "joined_names")]
+    pub references: BTreeSet<QualifiedName>,
+    /// The statement which created the table, for backends which record it (SQLite).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Column {
+    pub name: String,
+    pub data_type: String,
+    pub not_null: bool,
+    pub default: Option<ColumnDefault>,
+    /// The column's position in the primary key, starting from 1, or 0 if the column
+    /// isn't part of the primary key.
+    pub primary_key: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnDefault {
+    /// A default value, as an expression
+    Expression(String),
+    Identity {
+        always: bool,
+    },
+    /// A stored generated column, as the expression generating it
+    Generated(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Constraint {
+    pub name: String,
+    pub definition: String,
+    pub foreign: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct View {
+    pub materialized: bool,
+    /// The view's query, without a trailing semicolon
+    pub definition: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Sequence {
+    pub data_type: String,
+    pub increment: i64,
+    pub min_value: i64,
+    pub max_value: i64,
+    pub start_value: i64,
+    pub cache: i64,
+    pub cycle: bool,
+    /// The column which owns this sequence, as `schema.table.column`
+    pub owned_by: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Function {
+    pub procedure: bool,
+    pub result: Option<String>,
+    /// A `CREATE OR REPLACE` statement, without a trailing semicolon
+    pub definition: String,
+}
+
+/// Names which are joined with `.` when used as keys, since JSON & TOML only support
+/// string keys.
+trait JoinedName {
+    fn joined(&self) -> String;
+}
+impl JoinedName for (String, String) {
+    fn joined(&self) -> String {
+        format!("{}.{}", self.0, self.1)
+    }
+}
+impl JoinedName for (String, String, String) {
+    fn joined(&self) -> String {
+        format!("{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// Describe the keys of two maps whose values differ, or which are only in one of them.
+fn differing<K: Ord + JoinedName, V: PartialEq>(
+    kind: &str,
+    a: &BTreeMap<K, V>,
+    b: &BTreeMap<K, V>,
+    output: &mut Vec<String>,
+) {
+    let keys: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+    for key in keys {
+        if a.get(key) != b.get(key) {
+            output.push(format!("{} {}", kind, key.joined()));
+        }
+    }
+}
+
+fn joined_keys<K: JoinedName, V: Serialize, S: Serializer>(
+    map: &BTreeMap<K, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(map.iter().map(|(k, v)| (k.joined(), v)))
+}
+
+fn joined_names<S: Serializer>(
+    names: &BTreeSet<QualifiedName>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(names.iter().map(JoinedName::joined))
+}
+
+fn joined_dependencies<S: Serializer>(
+    map: &BTreeMap<QualifiedName, BTreeSet<QualifiedName>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(map.iter().map(|(k, deps)| {
+        let deps: Vec<_> = deps.iter().map(JoinedName::joined).collect();
+        (k.joined(), deps)
+    }))
+}
+
+/// Functions are keyed by their signature, such as `public.add(integer, integer)`.
+fn function_keys<V: Serialize, S: Serializer>(
+    map: &BTreeMap<(String, String, String), V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        map.iter().map(|((schema, name, arguments), v)| {
+            (format!("{}.{}({})", schema, name, arguments), v)
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn differences() {
+        let mut a = Schema::default();
+        a.tables
+            .insert(("main".to_string(), "a".to_string()), Table::default());
+        a.tables
+            .insert(("main".to_string(), "b".to_string()), Table::default());
+        let mut b = a.clone();
+        b.tables.remove(&("main".to_string(), "a".to_string()));
+        b.indexes.insert(
+            ("main".to_string(), "b".to_string()),
+            vec!["CREATE INDEX b_x ON b(x)".to_string()],
+        );
+        b.schemas.insert("other".to_string());
+
+        assert!(a.differences(&a).is_empty());
+        assert_eq!(
+            a.differences(&b),
+            vec!["schema other", "table main.a", "indexes of main.b"]
+        );
+    }
+
+    #[test]
+    fn index_names() {
+        assert_eq!(index_name("CREATE INDEX a_x ON a(x)"), Some("a_x"));
+        assert_eq!(index_name("create unique index a_x on a(x)"), Some("a_x"));
+        assert_eq!(
+            index_name("CREATE INDEX IF NOT EXISTS \"a x\" ON a(x)"),
+            Some("\"a x\"")
+        );
+        assert_eq!(index_name("CREATE INDEX [a x]ON a(x)"), Some("[a x]"));
+        assert_eq!(index_name("CREATE TABLE a(x)"), None);
+    }
+
+    #[test]
+    fn dependencies_among_candidates() {
+        let name = |name: &str| ("main".to_string(), name.to_string());
+        let mut dependencies = BTreeMap::default();
+        dependencies.insert(name("a"), BTreeSet::from([name("b"), name("x")]));
+        dependencies.insert(name("b"), BTreeSet::from([name("c")]));
+        dependencies.insert(name("x"), BTreeSet::from([name("d")]));
+        let candidates = BTreeSet::from([name("b"), name("c"), name("d")]);
+
+        // `d` is only reachable through `x`, which isn't a candidate
+        assert_eq!(
+            dependencies_among(&BTreeSet::from([name("a")]), &candidates, |n| {
+                dependencies.get(n)
+            }),
+            BTreeSet::from([name("b"), name("c")])
+        );
+    }
+}
@@ -1,31 +1,126 @@
+pub mod introspection;
 pub mod postgres;
 pub mod sqlite;
 
+use anyhow::anyhow;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, num::NonZeroUsize};
 use thiserror::Error;
 
-use crate::artifact::{Artifact, ConsumerError, ScriptProcessingError};
+use crate::{
+    artifact::{Artifact, ConsumerError, ContentId, ScriptProcessingError},
+    manifest::{artifact::Maintenance, project::Migrate},
+};
+
+use self::introspection::Schema;
+
+/// The schema (Postgres) or table prefix (SQLite) of sqigl's own tables, unless the
+/// project configures another with `internal_schema`.
+pub const DEFAULT_INTERNAL_SCHEMA: &str = "sqigl_internal";
+
+/// The placeholder for the internal schema in queries on sqigl's own tables.
+const INTERNAL_PLACEHOLDER: &str = "{internal}";
+
+/// Queries on sqigl's own tables refer to the internal schema (or table prefix) with the
+/// placeholder `{internal}`; this replaces it with `internal`. Queries quote the names
+/// they make from it, so that it may be a keyword. `internal` must be a valid
+/// identifier, so that it needs no escaping.
+pub fn internal_sql(sql: &str, internal: &str) -> String {
+    debug_assert!(
+        sql.contains(INTERNAL_PLACEHOLDER),
+        "Queries passed to internal_sql should refer to the internal schema"
+    );
+    sql.replace(INTERNAL_PLACEHOLDER, internal)
+}
 
 pub trait Backend {
     type Error: Error;
     fn install(&mut self) -> Result<SqiglState, Self::Error>;
     fn open(&mut self) -> Result<SqiglState, Self::Error>;
+    /// Whether sqigl has been installed onto the database.
+    fn installed(&mut self) -> Result<bool, Self::Error>;
+    /// Check that the database is reachable and responding to queries, without
+    /// installing or modifying anything. Returns the server version, if available.
+    fn ping(&mut self) -> Result<Option<String>, Self::Error>;
+    /// Apply an artifact in a transaction. If `force` is set, it is applied even if it is
+    /// not compatible with the database's version.
     fn apply<A: Artifact>(
         &mut self,
         artifact: &A,
+        force: bool,
+    ) -> Result<SqiglState, ScriptProcessingError<Self::Error>>;
+    /// Apply an artifact whose statements are independent of each other by dispatching
+    /// them across `connections` connections, each statement in its own transaction.
+    /// This is not atomic: if a statement fails, those which completed remain applied.
+    /// The artifact is recorded in the history once every statement has completed.
+    fn apply_parallel<A: Artifact>(
+        &mut self,
+        artifact: &A,
+        force: bool,
+        connections: NonZeroUsize,
     ) -> Result<SqiglState, ScriptProcessingError<Self::Error>>;
+    /// Execute a one-off script in a transaction, outside of any migration. The project
+    /// version is unchanged; if `record` is set, the script is recorded in the history
+    /// under the current version. Scripts aren't recorded while a migration is pending,
+    /// though they may be executed to repair the database.
+    fn exec(
+        &mut self,
+        script: &str,
+        record: bool,
+    ) -> Result<(), ScriptProcessingError<Self::Error>>;
+    /// The artifacts which have been applied to reach the current state of the database,
+    /// most recent first.
+    fn history(&mut self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, Self::Error>;
+    /// The content ids of every artifact recorded in the database's history.
+    fn applied_ids(&mut self) -> Result<Vec<ContentId>, Self::Error>;
     fn check<A: Artifact>(
         &mut self,
         artifact: &A,
     ) -> Result<(), ScriptProcessingError<Self::Error>>;
+    /// Check an artifact against a temporary empty database rather than this one, so
+    /// that nothing is written to this database (including sqigl's own tables). The
+    /// artifact must be compatible with an empty database.
+    fn check_isolated<A: Artifact>(
+        &mut self,
+        artifact: &A,
+    ) -> Result<(), ScriptProcessingError<Self::Error>>;
+    /// The structure of the database's schema, as migrations are generated from.
+    /// sqigl's own tables, & schemas in `exclude_schemas`, are excluded.
+    fn introspect(&mut self, exclude_schemas: &[String]) -> anyhow::Result<Schema>;
+    /// Load an artifact into a temporary empty database & introspect it, as when
+    /// generating migrations. Nothing is written to this database. The artifact must be
+    /// compatible with an empty database.
+    fn introspect_artifact<A: Artifact>(
+        &mut self,
+        artifact: &A,
+        exclude_schemas: &[String],
+    ) -> anyhow::Result<Schema>;
+    /// Record an artifact in the history at `version` without applying it, making it the
+    /// head, as though it had been applied. Used to repair the history of a database
+    /// which was migrated outside of sqigl. Clears the pending migration, if any.
+    fn record(&mut self, id: ContentId, version: &Version) -> Result<(), Self::Error>;
+    /// The content id of a migration which was started outside of a transaction (such
+    /// as by `apply_parallel`) but never recorded, & so may be partially applied. No
+    /// migrations are applied while one is pending.
+    fn pending(&mut self) -> Result<Option<ContentId>, Self::Error>;
+    /// Forget the pending migration, once the database has been repaired.
+    fn clear_pending(&mut self) -> Result<(), Self::Error>;
+    /// Run a maintenance command, outside of any transaction. Refused while a migration
+    /// is pending.
+    fn maintain(
+        &mut self,
+        maintenance: Maintenance,
+    ) -> Result<(), ScriptProcessingError<Self::Error>>;
+    /// Generate a migration by loading both artifacts into temporary databases & comparing
+    /// them.
     fn generate_migration<A1: Artifact, A2: Artifact>(
         &mut self,
         from: &A1,
         to: &A2,
-    ) -> anyhow::Result<impl Artifact>;
+        options: &GenerateOptions,
+    ) -> anyhow::Result<impl Artifact + 'static>;
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -34,14 +129,145 @@ pub struct SqiglState {
     pub sqigl_version: Version,
 }
 
+/// An artifact or script recorded in a database's history.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    /// The project version after the artifact was applied
+    pub version: Version,
+    pub content_id: ContentId,
+    /// When the artifact was applied, as reported by the database
+    pub applied_at: String,
+    /// The version of sqigl which applied the artifact, if it was recorded
+    pub sqigl_version: Option<Version>,
+}
+impl Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t",
+            self.version, self.applied_at, self.content_id
+        )?;
+        match &self.sqigl_version {
+            Some(version) => write!(f, "sqigl {}", version),
+            None => write!(f, "sqigl unknown"),
+        }
+    }
+}
+
+/// Options for generating a migration.
+#[derive(Debug, Clone, Default)]
+pub struct GenerateOptions {
+    /// Objects which are never dropped, as `[schema.]name`.
+    pub protect: Vec<String>,
+    /// Schemas to ignore, where the backend supports them.
+    pub exclude_schemas: Vec<String>,
+    /// Keep the temporary databases for inspection, rather than removing them.
+    pub keep_temp: bool,
+    /// Apply the migration to a copy of `from` & check that the result matches `to`.
+    pub verify: bool,
+    /// Make the migration only compatible with the patch version of `from`, rather than
+    /// its minor version.
+    pub exact: bool,
+    /// Guard statements so that they succeed if they have already been applied, where
+    /// the backend supports it.
+    pub if_exists: bool,
+}
+impl GenerateOptions {
+    /// The options configured by the `[migrate]` table of the project manifest.
+    pub fn new(migrate: &Migrate) -> Self {
+        Self {
+            protect: migrate.protect.clone(),
+            exclude_schemas: migrate.exclude_schemas.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Constrains which entries of a database's history are returned.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    /// Only entries at this version or later
+    pub since: Option<Version>,
+    /// Only entries at this version or earlier
+    pub until: Option<Version>,
+    /// At most this many of the most recent entries
+    pub limit: Option<usize>,
+}
+impl HistoryFilter {
+    /// The bounds of the filter, as they are passed to the history query, which compares
+    /// them to the recorded versions by semver precedence.
+    pub fn bounds(&self) -> (Option<String>, Option<String>) {
+        (
+            self.since.as_ref().map(Version::to_string),
+            self.until.as_ref().map(Version::to_string),
+        )
+    }
+}
+
 pub trait SqlStatement {
     fn write_to(&self, buffer: &mut String);
+    /// Write the statement guarded with `IF EXISTS` or `IF NOT EXISTS`, so that it
+    /// succeeds if it has already been applied. Statements which can't be guarded are
+    /// written as they are.
+    fn write_guarded_to(&self, buffer: &mut String) {
+        self.write_to(buffer)
+    }
+    /// Whether executing this statement may destroy data.
+    fn destructive(&self) -> bool;
+    /// The operation this statement performs & the kind of object it performs it on,
+    /// such as `("created", "table")`, for summarizing a migration. `None` for statements
+    /// which don't change any object.
+    fn change(&self) -> Option<(&'static str, &'static str)>;
+    /// The operation this statement performs, such as `create_table`, for
+    /// machine-readable plans.
+    fn operation(&self) -> &'static str;
+    /// The name of the object this statement changes, if it is known.
+    fn object(&self) -> Option<String>;
+}
+
+/// A statement of a generated migration, for inspecting the migration before it's saved.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedStatement {
+    pub operation: &'static str,
+    pub object: Option<String>,
+    pub sql: String,
+    pub destructive: bool,
+}
+
+/// Check that applying a generated migration to the database it was generated from
+/// reproduces the target schema, given the statements which would migrate the result to
+/// that schema. Any such statements reveal a difference the migration missed.
+fn verify_migration<Stmt: SqlStatement>(residual: Vec<Stmt>, to: &Version) -> anyhow::Result<()> {
+    if residual.is_empty() {
+        return Ok(());
+    }
+    let mut differences = String::new();
+    for stmt in residual.iter() {
+        differences.push_str("\n    ");
+        stmt.write_to(&mut differences);
+    }
+    Err(anyhow!(
+        "The generated migration does not reproduce the schema of {}; it is missing:{}",
+        to,
+        differences
+    ))
 }
 
 pub struct GeneratedMigration<Stmt> {
     from: VersionReq,
     to: Version,
     statements: Vec<Stmt>,
+    /// Write statements with `write_guarded_to`.
+    guarded: bool,
+}
+impl<Stmt: SqlStatement> GeneratedMigration<Stmt> {
+    fn write_statement(&self, stmt: &Stmt, buffer: &mut String) {
+        if self.guarded {
+            stmt.write_guarded_to(buffer);
+        } else {
+            stmt.write_to(buffer);
+        }
+    }
 }
 
 impl<Stmt: SqlStatement> Artifact for GeneratedMigration<Stmt> {
@@ -54,6 +280,60 @@ impl<Stmt: SqlStatement> Artifact for GeneratedMigration<Stmt> {
     fn spec(&self) -> (VersionReq, Version) {
         (self.from.clone(), self.to.clone())
     }
+    fn destructive_statements(&self) -> Vec<String> {
+        self.statements
+            .iter()
+            .filter(|stmt| stmt.destructive())
+            .map(|stmt| {
+                let mut buffer = String::new();
+                self.write_statement(stmt, &mut buffer);
+                buffer
+            })
+            .collect()
+    }
+    fn summary(&self) -> Option<String> {
+        let mut counts: Vec<((&str, &str), usize)> = Vec::new();
+        for change in self.statements.iter().filter_map(|stmt| stmt.change()) {
+            match counts.iter_mut().find(|(c, _)| *c == change) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((change, 1)),
+            }
+        }
+        if counts.is_empty() {
+            return Some("no changes".to_string());
+        }
+        Some(
+            counts
+                .into_iter()
+                .map(|((operation, object), count)| {
+                    let plural = match (count, object.ends_with('x')) {
+                        (1, _) => "",
+                        (_, true) => "es",
+                        (_, false) => "s",
+                    };
+                    format!("{} {}{} {}", count, object, plural, operation)
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+    fn plan(&self) -> Option<Vec<PlannedStatement>> {
+        Some(
+            self.statements
+                .iter()
+                .map(|stmt| {
+                    let mut sql = String::new();
+                    self.write_statement(stmt, &mut sql);
+                    PlannedStatement {
+                        operation: stmt.operation(),
+                        object: stmt.object(),
+                        sql,
+                        destructive: stmt.destructive(),
+                    }
+                })
+                .collect(),
+        )
+    }
 
     fn scripts<C: crate::artifact::ScriptConsumer>(
         &self,
@@ -63,7 +343,7 @@ impl<Stmt: SqlStatement> Artifact for GeneratedMigration<Stmt> {
         let mut hasher = Sha256::new();
         for stmt in self.statements.iter() {
             buffer.clear();
-            stmt.write_to(&mut buffer);
+            self.write_statement(stmt, &mut buffer);
             buffer.push('\n');
             hasher.update(&buffer);
             consumer.accept(&buffer)?;
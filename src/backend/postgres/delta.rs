@@ -1,22 +1,1840 @@
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use log::info;
+use postgres::GenericClient;
 
 use crate::{
-    artifact::Artifact,
-    backend::{GeneratedMigration, SqlStatement},
+    backend::{
+        introspection::{
+            dependencies_among, index_name, Column, ColumnDefault, Constraint, Function,
+            QualifiedName, Schema, Sequence, Table, View,
+        },
+        SqlStatement,
+    },
+    util::is_protected,
 };
 
-pub fn delta(
-    mut from_db: impl postgres::GenericClient,
-    mut to_db: impl postgres::GenericClient,
-) -> anyhow::Result<Vec<Statement>> {
-    unimplemented!("Generated migrations for postgres are not yet implemented")
+/// Describe the schema of a database. Schemas in `exclude_schemas` & the objects in them
+/// are ignored entirely.
+pub fn introspect(
+    db: &mut impl GenericClient,
+    exclude_schemas: &[String],
+) -> anyhow::Result<Schema> {
+    let mut schema = Schema {
+        schemas: get_schemas(db, exclude_schemas)?,
+        views: get_views(db, exclude_schemas)?,
+        view_dependencies: get_view_dependencies(db)?,
+        sequences: get_sequences(db, exclude_schemas)?,
+        functions: get_functions(db, exclude_schemas)?,
+        triggers: get_triggers(db, exclude_schemas)?,
+        ..Default::default()
+    };
+    for name in get_tables(db, exclude_schemas)? {
+        let table = Table {
+            columns: get_columns(db, &name.0, &name.1)?,
+            constraints: get_constraints(db, &name.0, &name.1)?,
+            references: get_references(db, &name.0, &name.1)?,
+            code: None,
+        };
+        schema
+            .indexes
+            .insert(name.clone(), get_indexes(db, &name.0, &name.1)?);
+        schema.tables.insert(name, table);
+    }
+    // Views outside of the schema, such as those of the system catalogs, are ignored
+    let views = &schema.views;
+    schema
+        .view_dependencies
+        .retain(|view, _| views.contains_key(view));
+    for (name, view) in schema.views.iter() {
+        if view.materialized {
+            schema
+                .indexes
+                .insert(name.clone(), get_indexes(db, &name.0, &name.1)?);
+        }
+    }
+
+    Ok(schema)
+}
+
+/// Generate the statements to migrate a database with schema `from` to schema `to`. The
+/// columns, constraints & indexes of tables which exist in both are migrated in place.
+/// Objects matching `protect` are never dropped because they were deleted, though they
+/// may be dropped & recreated when they have changed. Neither are the triggers &
+/// sequences of protected tables, nor the deleted tables & views which protected ones
+/// refer to or select from.
+pub fn delta(from: &Schema, to: &Schema, protect: &[String]) -> Vec<Statement> {
+    let mut statements = Vec::default();
+    // Schemas containing protected objects, which must not be dropped either
+    let mut kept_schemas = BTreeSet::default();
+    let mut protected = |kind: &str, schema: &str, name: &str| {
+        if is_protected(protect, Some(schema), name) {
+            info!(
+                "{} {}.{} was deleted, but is protected; not dropping it",
+                kind, schema, name
+            );
+            kept_schemas.insert(schema.to_string());
+            true
+        } else {
+            false
+        }
+    };
+
+    // Schemas must be created before their objects, and dropped after them.
+    for schema in to.schemas.difference(&from.schemas) {
+        info!("Schema {} was created", schema);
+        statements.push(Statement::CreateSchema {
+            name: schema.clone(),
+        });
+    }
+
+    // Sequences are created before tables, because columns may default from them.
+    // Ownership is assigned once the owning tables exist.
+    let mut ownership = Vec::default();
+    for (name, sequence) in to.sequences.iter() {
+        match from.sequences.get(name) {
+            None => {
+                info!("Sequence {}.{} was created", name.0, name.1);
+                statements.push(Statement::CreateSequence {
+                    schema: name.0.clone(),
+                    name: name.1.clone(),
+                    sequence: sequence.clone(),
+                });
+                if sequence.owned_by.is_some() {
+                    ownership.push(Statement::AlterSequenceOwner {
+                        schema: name.0.clone(),
+                        name: name.1.clone(),
+                        owned_by: sequence.owned_by.clone(),
+                    });
+                }
+            }
+            Some(prev) if prev.owned_by != sequence.owned_by => {
+                // A sequence is dropped along with the table which owns it, so it must be
+                // disowned before that table is dropped.
+                if prev.owned_by.is_some() {
+                    statements.push(Statement::AlterSequenceOwner {
+                        schema: name.0.clone(),
+                        name: name.1.clone(),
+                        owned_by: None,
+                    });
+                }
+                if sequence.owned_by.is_some() {
+                    ownership.push(Statement::AlterSequenceOwner {
+                        schema: name.0.clone(),
+                        name: name.1.clone(),
+                        owned_by: sequence.owned_by.clone(),
+                    });
+                }
+            }
+            Some(_) => (),
+        }
+    }
+
+    // Deleted tables are kept if they are protected, or if a kept table's foreign keys
+    // refer to them.
+    let deleted_tables: BTreeSet<QualifiedName> = from
+        .tables
+        .keys()
+        .filter(|name| !to.tables.contains_key(*name))
+        .cloned()
+        .collect();
+    let mut kept_tables: BTreeSet<QualifiedName> = deleted_tables
+        .iter()
+        .filter(|(schema, name)| protected("Table", schema, name))
+        .cloned()
+        .collect();
+    for (schema, name) in dependencies_among(&kept_tables, &deleted_tables, |name| {
+        from.tables.get(name).map(|table| &table.references)
+    }) {
+        info!(
+            "Table {}.{} was deleted, but a protected table refers to it; not dropping it",
+            schema, name
+        );
+        kept_tables.insert((schema, name));
+    }
+
+    // Triggers are dropped before the functions they call & the tables they are
+    // attached to, and created after both. The triggers of protected tables are kept.
+    for (key, definition) in from.triggers.iter() {
+        let deleted = !to.triggers.contains_key(key);
+        if deleted && protected("Trigger", &key.0, &key.2) {
+            continue;
+        }
+        let table = (key.0.clone(), key.1.clone());
+        if deleted && (kept_tables.contains(&table) || is_protected(protect, Some(&key.0), &key.1))
+        {
+            info!(
+                "Trigger {} on {}.{} was deleted, but its table is protected; not dropping it",
+                key.2, key.0, key.1
+            );
+            continue;
+        }
+        if to.triggers.get(key) != Some(definition) {
+            let (schema, table, name) = key.clone();
+            info!(
+                "Trigger {} on {}.{} was deleted or changed",
+                name, schema, table
+            );
+            statements.push(Statement::DropTrigger {
+                schema,
+                table,
+                name,
+            });
+        }
+    }
+
+    // Views are dropped before any tables they might select from, and any view which
+    // is dropped must have its dependents dropped as well. Views which have changed
+    // are dropped and recreated. Deleted views are kept if they are protected, or if a
+    // kept view selects from them.
+    let deleted_views: BTreeSet<QualifiedName> = from
+        .views
+        .keys()
+        .filter(|name| !to.views.contains_key(*name))
+        .cloned()
+        .collect();
+    let mut kept_views: BTreeSet<QualifiedName> = deleted_views
+        .iter()
+        .filter(|(schema, name)| protected("View", schema, name))
+        .cloned()
+        .collect();
+    for (schema, name) in dependencies_among(&kept_views, &deleted_views, |name| {
+        from.view_dependencies.get(name)
+    }) {
+        info!(
+            "View {}.{} was deleted, but a protected view selects from it; not dropping it",
+            schema, name
+        );
+        kept_views.insert((schema, name));
+    }
+    let mut dropped_views: BTreeSet<QualifiedName> = from
+        .views
+        .iter()
+        .filter(|(name, view)| match to.views.get(*name) {
+            None => !kept_views.contains(*name),
+            Some(to_view) => to_view != *view,
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    loop {
+        let dependents: Vec<_> = from
+            .view_dependencies
+            .iter()
+            .filter(|(view, deps)| {
+                !dropped_views.contains(*view) && !deps.is_disjoint(&dropped_views)
+            })
+            .map(|(view, _)| view.clone())
+            .collect();
+        if dependents.is_empty() {
+            break;
+        }
+        dropped_views.extend(dependents);
+    }
+    // Kept views which select from a view which changed can't be kept as they are, so
+    // they are recreated from their previous definitions, along with their indexes.
+    let restored_views: BTreeSet<QualifiedName> =
+        dropped_views.intersection(&kept_views).cloned().collect();
+    for name in restored_views.iter() {
+        info!(
+            "View {}.{} is protected, but selects from a view which changed; recreating it",
+            name.0, name.1
+        );
+    }
+    for name in dependency_order(&dropped_views, &from.view_dependencies)
+        .into_iter()
+        .rev()
+    {
+        info!("View {}.{} was deleted or changed", name.0, name.1);
+        statements.push(Statement::DropView {
+            materialized: from.views[&name].materialized,
+            schema: name.0,
+            name: name.1,
+        });
+    }
+
+    // Foreign keys are dropped first, so that tables which refer to each other may
+    // be dropped in any order.
+    let dropped_tables: Vec<_> = from
+        .tables
+        .iter()
+        .filter(|(name, _)| deleted_tables.contains(*name) && !kept_tables.contains(*name))
+        .collect();
+    for ((schema, name), table) in &dropped_tables {
+        for constraint in table.constraints.iter().filter(|c| c.foreign) {
+            statements.push(Statement::DropConstraint {
+                schema: schema.clone(),
+                table: name.clone(),
+                name: constraint.name.clone(),
+            });
+        }
+    }
+
+    // The columns, constraints & indexes of tables which exist in both schemas are
+    // migrated individually; those which were deleted are kept if they or their table
+    // are protected. Constraints & indexes are dropped here, along with the foreign keys
+    // of dropped tables, and added once all tables exist. So are the indexes of
+    // materialized views which are kept.
+    let mut column_changes = Vec::default();
+    let mut constraints = Vec::default();
+    let mut foreign_keys = Vec::default();
+    let mut indexes = Vec::default();
+    let mut dropped_columns = BTreeSet::default();
+    let mut indexed: Vec<(&QualifiedName, bool)> = Vec::default();
+    for (name, new) in to.tables.iter() {
+        let Some(old) = from.tables.get(name) else {
+            continue;
+        };
+        let (schema, table) = name;
+        let table_protected = is_protected(protect, Some(schema), table);
+        indexed.push((name, table_protected));
+        for column in old.columns.iter() {
+            if new.columns.iter().any(|c| c.name == column.name) {
+                continue;
+            }
+            if table_protected {
+                info!(
+                    "Column {} of {}.{} was deleted, but its table is protected; not dropping it",
+                    column.name, schema, table
+                );
+                continue;
+            }
+            info!("Column {} of {}.{} was deleted", column.name, schema, table);
+            dropped_columns.insert(format!("{}.{}.{}", schema, table, column.name));
+            column_changes.push(Statement::DropColumn {
+                schema: schema.clone(),
+                table: table.clone(),
+                name: column.name.clone(),
+            });
+        }
+        for column in new.columns.iter() {
+            match old.columns.iter().find(|c| c.name == column.name) {
+                None => {
+                    info!("Column {} of {}.{} was created", column.name, schema, table);
+                    column_changes.push(Statement::AddColumn {
+                        schema: schema.clone(),
+                        table: table.clone(),
+                        definition: column_definition(column),
+                    });
+                }
+                Some(prev) if prev == column => (),
+                Some(prev) => {
+                    info!("Column {} of {}.{} was changed", column.name, schema, table);
+                    alter_column(schema, table, prev, column, &mut column_changes);
+                }
+            }
+        }
+
+        // Foreign keys are dropped first, as they may depend on other constraints.
+        let mut dropped_constraints: Vec<_> = old
+            .constraints
+            .iter()
+            .filter(|constraint| !new.constraints.contains(constraint))
+            .collect();
+        dropped_constraints.sort_by_key(|constraint| !constraint.foreign);
+        for constraint in dropped_constraints {
+            let deleted = !new.constraints.iter().any(|c| c.name == constraint.name);
+            if deleted && table_protected {
+                info!(
+                    "Constraint {} of {}.{} was deleted, but its table is protected; not \
+                    dropping it",
+                    constraint.name, schema, table
+                );
+                continue;
+            }
+            if deleted && protected("Constraint", schema, &constraint.name) {
+                continue;
+            }
+            info!(
+                "Constraint {} of {}.{} was deleted or changed",
+                constraint.name, schema, table
+            );
+            statements.push(Statement::DropConstraint {
+                schema: schema.clone(),
+                table: table.clone(),
+                name: constraint.name.clone(),
+            });
+        }
+        for constraint in new.constraints.iter() {
+            if old.constraints.contains(constraint) {
+                continue;
+            }
+            info!(
+                "Constraint {} of {}.{} was created or changed",
+                constraint.name, schema, table
+            );
+            let statement = Statement::AddConstraint {
+                schema: schema.clone(),
+                table: table.clone(),
+                name: constraint.name.clone(),
+                definition: constraint.definition.clone(),
+            };
+            if constraint.foreign {
+                foreign_keys.push(statement);
+            } else {
+                constraints.push(statement);
+            }
+        }
+    }
+    for (name, view) in to.views.iter() {
+        if view.materialized && from.views.contains_key(name) && !dropped_views.contains(name) {
+            indexed.push((name, is_protected(protect, Some(&name.0), &name.1)));
+        }
+    }
+    for (name, owner_protected) in indexed {
+        let (old, new) = (from.indexes_of(name), to.indexes_of(name));
+        for definition in old.iter().filter(|definition| !new.contains(definition)) {
+            let Some(index) = index_name(definition) else {
+                continue;
+            };
+            let deleted = !new
+                .iter()
+                .any(|definition| index_name(definition) == Some(index));
+            if deleted && owner_protected {
+                info!(
+                    "Index {} of {}.{} was deleted, but its table is protected; not dropping it",
+                    index, name.0, name.1
+                );
+                continue;
+            }
+            if deleted && protected("Index", &name.0, index) {
+                continue;
+            }
+            info!("Index {}.{} was deleted or changed", name.0, index);
+            statements.push(Statement::DropIndex {
+                schema: name.0.clone(),
+                name: index.to_string(),
+            });
+        }
+        for definition in new.iter().filter(|definition| !old.contains(definition)) {
+            info!("An index of {}.{} was created or changed", name.0, name.1);
+            indexes.push(Statement::CreateIndex {
+                definition: definition.clone(),
+            });
+        }
+    }
+
+    for ((schema, name), _) in &dropped_tables {
+        info!("Table {}.{} was deleted", schema, name);
+        statements.push(Statement::DropTable {
+            schema: schema.clone(),
+            name: name.clone(),
+        });
+    }
+
+    for (name, sequence) in from.sequences.iter() {
+        if to.sequences.contains_key(name) {
+            continue;
+        }
+        let owner_dropped = sequence.owned_by.as_ref().is_some_and(|owner| {
+            dropped_columns.contains(owner)
+                || dropped_tables.iter().any(|((schema, table), _)| {
+                    owner.starts_with(&format!("{}.{}.", schema, table))
+                })
+        });
+        if !owner_dropped && protected("Sequence", &name.0, &name.1) {
+            continue;
+        }
+        let owner_kept = sequence.owned_by.as_ref().is_some_and(|owner| {
+            kept_tables
+                .iter()
+                .any(|(schema, table)| owner.starts_with(&format!("{}.{}.", schema, table)))
+        });
+        if owner_kept {
+            info!(
+                "Sequence {}.{} was deleted, but its table is protected; not dropping it",
+                name.0, name.1
+            );
+            continue;
+        }
+        info!("Sequence {}.{} was deleted", name.0, name.1);
+        if !owner_dropped {
+            // Otherwise, it was dropped along with its table or column
+            statements.push(Statement::DropSequence {
+                schema: name.0.clone(),
+                name: name.1.clone(),
+            });
+        }
+    }
+
+    // Functions are created before tables, because defaults & constraints may call
+    // them. Those which can't be replaced in place are dropped & recreated.
+    for (key, function) in from.functions.iter() {
+        let replaceable = to
+            .functions
+            .get(key)
+            .is_some_and(|f| f.procedure == function.procedure && f.result == function.result);
+        if !to.functions.contains_key(key) && protected("Function", &key.0, &key.1) {
+            continue;
+        }
+        if !replaceable {
+            let (schema, name, arguments) = key.clone();
+            info!(
+                "Function {}.{}({}) was deleted or changed",
+                schema, name, arguments
+            );
+            statements.push(Statement::DropFunction {
+                schema,
+                name,
+                arguments,
+                procedure: function.procedure,
+            });
+        }
+    }
+    let mut body_checks_disabled = false;
+    for (key, function) in to.functions.iter() {
+        if from.functions.get(key) == Some(function) {
+            continue;
+        }
+        if !body_checks_disabled {
+            // Function bodies may refer to tables which have yet to be created.
+            statements.push(Statement::DisableFunctionBodyChecks);
+            body_checks_disabled = true;
+        }
+        info!("Function {}.{}({}) was created", key.0, key.1, key.2);
+        statements.push(Statement::CreateFunction {
+            schema: key.0.clone(),
+            name: key.1.clone(),
+            definition: function.definition.clone(),
+        });
+    }
+
+    // Likewise, foreign keys are added after all tables have been created.
+    statements.extend(column_changes);
+    statements.extend(constraints);
+    for (name, table) in to.tables.iter() {
+        if from.tables.contains_key(name) {
+            continue;
+        }
+        let (schema, name) = name;
+        info!("Table {}.{} was created", schema, name);
+        let mut definitions: Vec<_> = table.columns.iter().map(column_definition).collect();
+        for constraint in table.constraints.iter() {
+            if constraint.foreign {
+                foreign_keys.push(Statement::AddConstraint {
+                    schema: schema.clone(),
+                    table: name.clone(),
+                    name: constraint.name.clone(),
+                    definition: constraint.definition.clone(),
+                });
+            } else {
+                definitions.push(format!(
+                    "CONSTRAINT {} {}",
+                    constraint.name, constraint.definition
+                ));
+            }
+        }
+        statements.push(Statement::CreateTable {
+            schema: schema.clone(),
+            name: name.clone(),
+            definitions,
+        });
+        for definition in to.indexes_of(&(schema.clone(), name.clone())) {
+            indexes.push(Statement::CreateIndex {
+                definition: definition.clone(),
+            });
+        }
+    }
+    statements.extend(foreign_keys);
+    statements.extend(indexes);
+    statements.extend(ownership);
+
+    // Views are created once all tables exist, after the views they select from.
+    let mut created_views: BTreeSet<QualifiedName> = to
+        .views
+        .keys()
+        .filter(|name| !from.views.contains_key(*name) || dropped_views.contains(*name))
+        .cloned()
+        .collect();
+    created_views.extend(restored_views.iter().cloned());
+    let mut view_dependencies = to.view_dependencies.clone();
+    for name in restored_views.iter() {
+        if let Some(deps) = from.view_dependencies.get(name) {
+            view_dependencies.insert(name.clone(), deps.clone());
+        }
+    }
+    for name in dependency_order(&created_views, &view_dependencies) {
+        let (view, indexes) = if restored_views.contains(&name) {
+            (&from.views[&name], from.indexes_of(&name))
+        } else {
+            info!("View {}.{} was created", name.0, name.1);
+            (&to.views[&name], to.indexes_of(&name))
+        };
+        if view.materialized {
+            statements.push(Statement::CreateMatView {
+                schema: name.0.clone(),
+                name: name.1.clone(),
+                definition: view.definition.clone(),
+            });
+            for definition in indexes {
+                statements.push(Statement::CreateIndex {
+                    definition: definition.clone(),
+                });
+            }
+            statements.push(Statement::RefreshMatView {
+                schema: name.0,
+                name: name.1,
+            });
+        } else {
+            statements.push(Statement::CreateView {
+                schema: name.0,
+                name: name.1,
+                definition: view.definition.clone(),
+            });
+        }
+    }
+
+    for (key, definition) in to.triggers.iter() {
+        if from.triggers.get(key) != Some(definition) {
+            info!("Trigger {} on {}.{} was created", key.2, key.0, key.1);
+            statements.push(Statement::CreateTrigger {
+                schema: key.0.clone(),
+                table: key.1.clone(),
+                name: key.2.clone(),
+                definition: definition.clone(),
+            });
+        }
+    }
+
+    kept_schemas.extend(
+        kept_tables
+            .iter()
+            .chain(kept_views.iter())
+            .map(|n| n.0.clone()),
+    );
+    for schema in from.schemas.difference(&to.schemas) {
+        if kept_schemas.contains(schema) || is_protected(protect, None, schema) {
+            info!(
+                "Schema {} was deleted, but is or contains a protected object; not \
+                dropping it",
+                schema
+            );
+            continue;
+        }
+        info!("Schema {} was deleted", schema);
+        statements.push(Statement::DropSchema {
+            name: schema.clone(),
+        });
+    }
+
+    statements
 }
 
-pub enum Statement {}
+/// The statements which change column `old` of a table into `new`. Columns which become
+/// generated are dropped & added again, since their values are derived anyway.
+fn alter_column(
+    schema: &str,
+    table: &str,
+    old: &Column,
+    new: &Column,
+    statements: &mut Vec<Statement>,
+) {
+    let mut alter = |alteration| {
+        statements.push(Statement::AlterColumn {
+            schema: schema.to_string(),
+            table: table.to_string(),
+            name: new.name.clone(),
+            alteration,
+        })
+    };
+    if old.default != new.default {
+        match (&old.default, &new.default) {
+            (_, Some(ColumnDefault::Generated(_))) => {
+                statements.push(Statement::DropColumn {
+                    schema: schema.to_string(),
+                    table: table.to_string(),
+                    name: old.name.clone(),
+                });
+                statements.push(Statement::AddColumn {
+                    schema: schema.to_string(),
+                    table: table.to_string(),
+                    definition: column_definition(new),
+                });
+                return;
+            }
+            (Some(ColumnDefault::Generated(_)), _) => alter(ColumnAlteration::DropExpression),
+            (Some(ColumnDefault::Identity { .. }), Some(ColumnDefault::Identity { .. })) => (),
+            (Some(ColumnDefault::Identity { .. }), _) => alter(ColumnAlteration::DropIdentity),
+            (Some(ColumnDefault::Expression(_)), Some(ColumnDefault::Identity { .. })) => {
+                alter(ColumnAlteration::DropDefault)
+            }
+            _ => (),
+        }
+    }
+    if old.data_type != new.data_type {
+        alter(ColumnAlteration::Type(new.data_type.clone()));
+    }
+    // Identity columns must be declared `NOT NULL` first
+    if old.not_null != new.not_null {
+        alter(ColumnAlteration::NotNull(new.not_null));
+    }
+    if old.default != new.default {
+        match (&old.default, &new.default) {
+            (_, Some(ColumnDefault::Expression(expr))) => {
+                alter(ColumnAlteration::SetDefault(expr.clone()))
+            }
+            (Some(ColumnDefault::Identity { .. }), Some(ColumnDefault::Identity { always })) => {
+                alter(ColumnAlteration::SetGenerated { always: *always })
+            }
+            (_, Some(ColumnDefault::Identity { always })) => {
+                alter(ColumnAlteration::AddIdentity { always: *always })
+            }
+            (Some(ColumnDefault::Expression(_)), None) => alter(ColumnAlteration::DropDefault),
+            _ => (),
+        }
+    }
+}
+
+/// A column's definition, as it would appear in a `CREATE TABLE` statement.
+fn column_definition(column: &Column) -> String {
+    let mut definition = format!("{} {}", column.name, column.data_type);
+    match &column.default {
+        Some(ColumnDefault::Identity { always: true }) => {
+            definition.push_str(" GENERATED ALWAYS AS IDENTITY")
+        }
+        Some(ColumnDefault::Identity { always: false }) => {
+            definition.push_str(" GENERATED BY DEFAULT AS IDENTITY")
+        }
+        Some(ColumnDefault::Generated(expr)) => {
+            write!(definition, " GENERATED ALWAYS AS ({}) STORED", expr).unwrap()
+        }
+        Some(ColumnDefault::Expression(expr)) => write!(definition, " DEFAULT {}", expr).unwrap(),
+        None => (),
+    }
+    if column.not_null {
+        definition.push_str(" NOT NULL");
+    }
+    definition
+}
+
+fn get_schemas(
+    db: &mut impl GenericClient,
+    exclude: &[String],
+) -> anyhow::Result<BTreeSet<String>> {
+    Ok(db
+        .query(include_str!("sql/get_schemas.sql"), &[&exclude])?
+        .into_iter()
+        .map(|row| row.get("name"))
+        .collect())
+}
+
+fn get_tables(
+    db: &mut impl GenericClient,
+    exclude: &[String],
+) -> anyhow::Result<BTreeSet<QualifiedName>> {
+    Ok(db
+        .query(include_str!("sql/get_tables.sql"), &[&exclude])?
+        .into_iter()
+        .map(|row| (row.get("schema"), row.get("name")))
+        .collect())
+}
+
+fn get_sequences(
+    db: &mut impl GenericClient,
+    exclude: &[String],
+) -> anyhow::Result<BTreeMap<QualifiedName, Sequence>> {
+    Ok(db
+        .query(include_str!("sql/get_sequences.sql"), &[&exclude])?
+        .into_iter()
+        .map(|row| {
+            let sequence = Sequence {
+                data_type: row.get("data_type"),
+                increment: row.get("increment"),
+                min_value: row.get("min_value"),
+                max_value: row.get("max_value"),
+                start_value: row.get("start_value"),
+                cache: row.get("cache"),
+                cycle: row.get("cycle"),
+                owned_by: row.get("owned_by"),
+            };
+            ((row.get("schema"), row.get("name")), sequence)
+        })
+        .collect())
+}
+
+fn get_views(
+    db: &mut impl GenericClient,
+    exclude: &[String],
+) -> anyhow::Result<BTreeMap<QualifiedName, View>> {
+    Ok(db
+        .query(include_str!("sql/get_views.sql"), &[&exclude])?
+        .into_iter()
+        .map(|row| {
+            let definition: String = row.get("definition");
+            let view = View {
+                materialized: row.get("materialized"),
+                definition: definition.trim().trim_end_matches(';').to_string(),
+            };
+            ((row.get("schema"), row.get("name")), view)
+        })
+        .collect())
+}
+
+/// Maps each view to the views it selects from.
+fn get_view_dependencies(
+    db: &mut impl GenericClient,
+) -> anyhow::Result<BTreeMap<QualifiedName, BTreeSet<QualifiedName>>> {
+    let mut output: BTreeMap<_, BTreeSet<_>> = BTreeMap::default();
+    for row in db.query(include_str!("sql/get_view_dependencies.sql"), &[])? {
+        output
+            .entry((row.get("schema"), row.get("name")))
+            .or_default()
+            .insert((row.get("dependency_schema"), row.get("dependency_name")));
+    }
+
+    Ok(output)
+}
+
+/// Orders `names` such that every name follows all of its dependencies.
+fn dependency_order(
+    names: &BTreeSet<QualifiedName>,
+    dependencies: &BTreeMap<QualifiedName, BTreeSet<QualifiedName>>,
+) -> Vec<QualifiedName> {
+    fn visit(
+        name: &QualifiedName,
+        names: &BTreeSet<QualifiedName>,
+        dependencies: &BTreeMap<QualifiedName, BTreeSet<QualifiedName>>,
+        visited: &mut BTreeSet<QualifiedName>,
+        output: &mut Vec<QualifiedName>,
+    ) {
+        if !visited.insert(name.clone()) {
+            return;
+        }
+        for dep in dependencies.get(name).into_iter().flatten() {
+            visit(dep, names, dependencies, visited, output);
+        }
+        if names.contains(name) {
+            output.push(name.clone());
+        }
+    }
+
+    let mut visited = BTreeSet::default();
+    let mut output = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        visit(name, names, dependencies, &mut visited, &mut output);
+    }
+
+    output
+}
+
+fn get_functions(
+    db: &mut impl GenericClient,
+    exclude: &[String],
+) -> anyhow::Result<BTreeMap<(String, String, String), Function>> {
+    Ok(db
+        .query(include_str!("sql/get_functions.sql"), &[&exclude])?
+        .into_iter()
+        .map(|row| {
+            let definition: String = row.get("definition");
+            let function = Function {
+                procedure: row.get("procedure"),
+                result: row.get("result"),
+                definition: definition.trim().to_string(),
+            };
+            (
+                (row.get("schema"), row.get("name"), row.get("arguments")),
+                function,
+            )
+        })
+        .collect())
+}
+
+fn get_triggers(
+    db: &mut impl GenericClient,
+    exclude: &[String],
+) -> anyhow::Result<BTreeMap<(String, String, String), String>> {
+    Ok(db
+        .query(include_str!("sql/get_triggers.sql"), &[&exclude])?
+        .into_iter()
+        .map(|row| {
+            (
+                (row.get("schema"), row.get("table_name"), row.get("name")),
+                row.get("definition"),
+            )
+        })
+        .collect())
+}
+
+fn get_columns(
+    db: &mut impl GenericClient,
+    schema: &str,
+    table: &str,
+) -> anyhow::Result<Vec<Column>> {
+    Ok(db
+        .query(include_str!("sql/get_columns.sql"), &[&schema, &table])?
+        .into_iter()
+        .map(|row| {
+            let default: Option<String> = row.get("default");
+            let identity: String = row.get("identity");
+            let generated: String = row.get("generated");
+            let default = match (identity.as_str(), generated.as_str(), default) {
+                ("a", _, _) => Some(ColumnDefault::Identity { always: true }),
+                ("d", _, _) => Some(ColumnDefault::Identity { always: false }),
+                (_, "s", Some(expr)) => Some(ColumnDefault::Generated(expr)),
+                (_, _, Some(expr)) => Some(ColumnDefault::Expression(expr)),
+                _ => None,
+            };
+            Column {
+                name: row.get("name"),
+                data_type: row.get("type"),
+                not_null: row.get("not_null"),
+                default,
+                primary_key: row.get::<_, i32>("primary_key") as usize,
+            }
+        })
+        .collect())
+}
+
+fn get_constraints(
+    db: &mut impl GenericClient,
+    schema: &str,
+    table: &str,
+) -> anyhow::Result<Vec<Constraint>> {
+    Ok(db
+        .query(include_str!("sql/get_constraints.sql"), &[&schema, &table])?
+        .into_iter()
+        .map(|row| Constraint {
+            name: row.get("name"),
+            definition: row.get("definition"),
+            foreign: row.get("foreign"),
+        })
+        .collect())
+}
+
+fn get_references(
+    db: &mut impl GenericClient,
+    schema: &str,
+    table: &str,
+) -> anyhow::Result<BTreeSet<QualifiedName>> {
+    Ok(db
+        .query(include_str!("sql/get_references.sql"), &[&schema, &table])?
+        .into_iter()
+        .map(|row| (row.get("schema"), row.get("name")))
+        .collect())
+}
+
+/// `CREATE INDEX` statements for indexes not created implicitly by a constraint.
+fn get_indexes(
+    db: &mut impl GenericClient,
+    schema: &str,
+    table: &str,
+) -> anyhow::Result<Vec<String>> {
+    Ok(db
+        .query(include_str!("sql/get_indexes.sql"), &[&schema, &table])?
+        .into_iter()
+        .map(|row| row.get("definition"))
+        .collect())
+}
+
+/// A change to a column of a table which exists in both schemas.
+pub enum ColumnAlteration {
+    Type(String),
+    NotNull(bool),
+    SetDefault(String),
+    DropDefault,
+    AddIdentity {
+        always: bool,
+    },
+    SetGenerated {
+        always: bool,
+    },
+    DropIdentity,
+    /// Turn a generated column into an ordinary one, keeping its values
+    DropExpression,
+}
+impl ColumnAlteration {
+    fn write_to(&self, buffer: &mut String) {
+        let generated = |always| if always { "ALWAYS" } else { "BY DEFAULT" };
+        match self {
+            ColumnAlteration::Type(data_type) => write!(buffer, "TYPE {}", data_type).unwrap(),
+            ColumnAlteration::NotNull(true) => buffer.push_str("SET NOT NULL"),
+            ColumnAlteration::NotNull(false) => buffer.push_str("DROP NOT NULL"),
+            ColumnAlteration::SetDefault(expr) => write!(buffer, "SET DEFAULT {}", expr).unwrap(),
+            ColumnAlteration::DropDefault => buffer.push_str("DROP DEFAULT"),
+            ColumnAlteration::AddIdentity { always } => {
+                write!(buffer, "ADD GENERATED {} AS IDENTITY", generated(*always)).unwrap()
+            }
+            ColumnAlteration::SetGenerated { always } => {
+                write!(buffer, "SET GENERATED {}", generated(*always)).unwrap()
+            }
+            ColumnAlteration::DropIdentity => buffer.push_str("DROP IDENTITY"),
+            ColumnAlteration::DropExpression => buffer.push_str("DROP EXPRESSION"),
+        }
+    }
+}
+
+pub enum Statement {
+    CreateSchema {
+        name: String,
+    },
+    DropSchema {
+        name: String,
+    },
+    CreateTable {
+        schema: String,
+        name: String,
+        /// Column & constraint definitions
+        definitions: Vec<String>,
+    },
+    DropTable {
+        schema: String,
+        name: String,
+    },
+    AddColumn {
+        schema: String,
+        table: String,
+        /// The column's definition, as in a `CREATE TABLE` statement
+        definition: String,
+    },
+    DropColumn {
+        schema: String,
+        table: String,
+        name: String,
+    },
+    AlterColumn {
+        schema: String,
+        table: String,
+        name: String,
+        alteration: ColumnAlteration,
+    },
+    AddConstraint {
+        schema: String,
+        table: String,
+        name: String,
+        definition: String,
+    },
+    DropConstraint {
+        schema: String,
+        table: String,
+        name: String,
+    },
+    CreateIndex {
+        definition: String,
+    },
+    DropIndex {
+        schema: String,
+        name: String,
+    },
+    CreateSequence {
+        schema: String,
+        name: String,
+        sequence: Sequence,
+    },
+    DropSequence {
+        schema: String,
+        name: String,
+    },
+    AlterSequenceOwner {
+        schema: String,
+        name: String,
+        owned_by: Option<String>,
+    },
+    CreateView {
+        schema: String,
+        name: String,
+        definition: String,
+    },
+    DropView {
+        schema: String,
+        name: String,
+        materialized: bool,
+    },
+    CreateMatView {
+        schema: String,
+        name: String,
+        definition: String,
+    },
+    RefreshMatView {
+        schema: String,
+        name: String,
+    },
+    DisableFunctionBodyChecks,
+    CreateFunction {
+        schema: String,
+        name: String,
+        definition: String,
+    },
+    DropFunction {
+        schema: String,
+        name: String,
+        arguments: String,
+        procedure: bool,
+    },
+    CreateTrigger {
+        schema: String,
+        table: String,
+        name: String,
+        definition: String,
+    },
+    DropTrigger {
+        schema: String,
+        table: String,
+        name: String,
+    },
+}
 impl SqlStatement for Statement {
+    fn destructive(&self) -> bool {
+        // Sequences are included because their current value is lost.
+        matches!(
+            self,
+            Statement::DropTable { .. }
+                | Statement::DropColumn { .. }
+                | Statement::DropSequence { .. }
+        )
+    }
+    fn change(&self) -> Option<(&'static str, &'static str)> {
+        Some(match self {
+            Statement::CreateSchema { .. } => ("created", "schema"),
+            Statement::DropSchema { .. } => ("dropped", "schema"),
+            Statement::CreateTable { .. } => ("created", "table"),
+            Statement::DropTable { .. } => ("dropped", "table"),
+            Statement::AddColumn { .. } => ("added", "column"),
+            Statement::DropColumn { .. } => ("dropped", "column"),
+            Statement::AlterColumn { .. } => ("altered", "column"),
+            Statement::AddConstraint { .. } => ("added", "constraint"),
+            Statement::DropConstraint { .. } => ("dropped", "constraint"),
+            Statement::CreateIndex { .. } => ("created", "index"),
+            Statement::DropIndex { .. } => ("dropped", "index"),
+            Statement::CreateSequence { .. } => ("created", "sequence"),
+            Statement::DropSequence { .. } => ("dropped", "sequence"),
+            Statement::AlterSequenceOwner { .. } => ("altered", "sequence"),
+            Statement::CreateView { .. } => ("created", "view"),
+            Statement::DropView {
+                materialized: false,
+                ..
+            } => ("dropped", "view"),
+            Statement::DropView {
+                materialized: true, ..
+            } => ("dropped", "materialized view"),
+            Statement::CreateMatView { .. } => ("created", "materialized view"),
+            Statement::RefreshMatView { .. } => ("refreshed", "materialized view"),
+            Statement::DisableFunctionBodyChecks => return None,
+            Statement::CreateFunction { .. } => ("created", "function"),
+            Statement::DropFunction { .. } => ("dropped", "function"),
+            Statement::CreateTrigger { .. } => ("created", "trigger"),
+            Statement::DropTrigger { .. } => ("dropped", "trigger"),
+        })
+    }
+    fn operation(&self) -> &'static str {
+        match self {
+            Statement::CreateSchema { .. } => "create_schema",
+            Statement::DropSchema { .. } => "drop_schema",
+            Statement::CreateTable { .. } => "create_table",
+            Statement::DropTable { .. } => "drop_table",
+            Statement::AddColumn { .. } => "add_column",
+            Statement::DropColumn { .. } => "drop_column",
+            Statement::AlterColumn { .. } => "alter_column",
+            Statement::AddConstraint { .. } => "add_constraint",
+            Statement::DropConstraint { .. } => "drop_constraint",
+            Statement::CreateIndex { .. } => "create_index",
+            Statement::DropIndex { .. } => "drop_index",
+            Statement::CreateSequence { .. } => "create_sequence",
+            Statement::DropSequence { .. } => "drop_sequence",
+            Statement::AlterSequenceOwner { .. } => "alter_sequence_owner",
+            Statement::CreateView { .. } => "create_view",
+            Statement::DropView {
+                materialized: false,
+                ..
+            } => "drop_view",
+            Statement::DropView {
+                materialized: true, ..
+            } => "drop_materialized_view",
+            Statement::CreateMatView { .. } => "create_materialized_view",
+            Statement::RefreshMatView { .. } => "refresh_materialized_view",
+            Statement::DisableFunctionBodyChecks => "disable_function_body_checks",
+            Statement::CreateFunction { .. } => "create_function",
+            Statement::DropFunction { .. } => "drop_function",
+            Statement::CreateTrigger { .. } => "create_trigger",
+            Statement::DropTrigger { .. } => "drop_trigger",
+        }
+    }
+    fn object(&self) -> Option<String> {
+        Some(match self {
+            Statement::CreateSchema { name } | Statement::DropSchema { name } => name.clone(),
+            Statement::CreateTable { schema, name, .. }
+            | Statement::DropTable { schema, name }
+            | Statement::CreateSequence { schema, name, .. }
+            | Statement::DropSequence { schema, name }
+            | Statement::AlterSequenceOwner { schema, name, .. }
+            | Statement::CreateView { schema, name, .. }
+            | Statement::DropView { schema, name, .. }
+            | Statement::CreateMatView { schema, name, .. }
+            | Statement::RefreshMatView { schema, name }
+            | Statement::CreateFunction { schema, name, .. }
+            | Statement::DropIndex { schema, name }
+            | Statement::AddColumn {
+                schema,
+                table: name,
+                ..
+            } => format!("{}.{}", schema, name),
+            Statement::DropFunction {
+                schema,
+                name,
+                arguments,
+                ..
+            } => format!("{}.{}({})", schema, name, arguments),
+            // Columns, constraints & triggers are named within their table
+            Statement::DropColumn {
+                schema,
+                table,
+                name,
+            }
+            | Statement::AlterColumn {
+                schema,
+                table,
+                name,
+                ..
+            }
+            | Statement::AddConstraint {
+                schema,
+                table,
+                name,
+                ..
+            }
+            | Statement::DropConstraint {
+                schema,
+                table,
+                name,
+            }
+            | Statement::CreateTrigger {
+                schema,
+                table,
+                name,
+                ..
+            }
+            | Statement::DropTrigger {
+                schema,
+                table,
+                name,
+            } => format!("{}.{}.{}", schema, table, name),
+            // Index definitions are taken verbatim from the database
+            Statement::CreateIndex { .. } | Statement::DisableFunctionBodyChecks => return None,
+        })
+    }
     fn write_to(&self, buffer: &mut String) {
         match self {
-            _ => (),
+            Statement::CreateSchema { name } => {
+                write!(buffer, "CREATE SCHEMA {};", name).unwrap();
+            }
+            Statement::DropSchema { name } => {
+                write!(buffer, "DROP SCHEMA {};", name).unwrap();
+            }
+            Statement::CreateTable {
+                schema,
+                name,
+                definitions,
+            } => {
+                write!(buffer, "CREATE TABLE {}.{} (", schema, name).unwrap();
+                for (idx, definition) in definitions.iter().enumerate() {
+                    if idx != 0 {
+                        buffer.push(',');
+                    }
+                    write!(buffer, "\n    {}", definition).unwrap();
+                }
+                buffer.push_str("\n);");
+            }
+            Statement::DropTable { schema, name } => {
+                write!(buffer, "DROP TABLE {}.{};", schema, name).unwrap();
+            }
+            Statement::AddColumn {
+                schema,
+                table,
+                definition,
+            } => {
+                write!(
+                    buffer,
+                    "ALTER TABLE {}.{} ADD COLUMN {};",
+                    schema, table, definition
+                )
+                .unwrap();
+            }
+            Statement::DropColumn {
+                schema,
+                table,
+                name,
+            } => {
+                write!(
+                    buffer,
+                    "ALTER TABLE {}.{} DROP COLUMN {};",
+                    schema, table, name
+                )
+                .unwrap();
+            }
+            Statement::AlterColumn {
+                schema,
+                table,
+                name,
+                alteration,
+            } => {
+                write!(
+                    buffer,
+                    "ALTER TABLE {}.{} ALTER COLUMN {} ",
+                    schema, table, name
+                )
+                .unwrap();
+                alteration.write_to(buffer);
+                buffer.push(';');
+            }
+            Statement::AddConstraint {
+                schema,
+                table,
+                name,
+                definition,
+            } => {
+                write!(
+                    buffer,
+                    "ALTER TABLE {}.{} ADD CONSTRAINT {} {};",
+                    schema, table, name, definition
+                )
+                .unwrap();
+            }
+            Statement::DropConstraint {
+                schema,
+                table,
+                name,
+            } => {
+                write!(
+                    buffer,
+                    "ALTER TABLE {}.{} DROP CONSTRAINT {};",
+                    schema, table, name
+                )
+                .unwrap();
+            }
+            Statement::CreateIndex { definition } => {
+                buffer.push_str(definition);
+                buffer.push(';');
+            }
+            Statement::DropIndex { schema, name } => {
+                write!(buffer, "DROP INDEX {}.{};", schema, name).unwrap();
+            }
+            Statement::CreateSequence {
+                schema,
+                name,
+                sequence,
+            } => {
+                write!(
+                    buffer,
+                    "CREATE SEQUENCE {}.{} AS {} INCREMENT BY {} MINVALUE {} MAXVALUE {} \
+                    START WITH {} CACHE {} {};",
+                    schema,
+                    name,
+                    sequence.data_type,
+                    sequence.increment,
+                    sequence.min_value,
+                    sequence.max_value,
+                    sequence.start_value,
+                    sequence.cache,
+                    if sequence.cycle { "CYCLE" } else { "NO CYCLE" }
+                )
+                .unwrap();
+            }
+            Statement::DropSequence { schema, name } => {
+                write!(buffer, "DROP SEQUENCE {}.{};", schema, name).unwrap();
+            }
+            Statement::AlterSequenceOwner {
+                schema,
+                name,
+                owned_by,
+            } => {
+                write!(
+                    buffer,
+                    "ALTER SEQUENCE {}.{} OWNED BY {};",
+                    schema,
+                    name,
+                    owned_by.as_deref().unwrap_or("NONE")
+                )
+                .unwrap();
+            }
+            Statement::CreateView {
+                schema,
+                name,
+                definition,
+            } => {
+                write!(
+                    buffer,
+                    "CREATE VIEW {}.{} AS\n{};",
+                    schema, name, definition
+                )
+                .unwrap();
+            }
+            Statement::DropView {
+                schema,
+                name,
+                materialized,
+            } => {
+                if *materialized {
+                    write!(buffer, "DROP MATERIALIZED VIEW {}.{};", schema, name).unwrap();
+                } else {
+                    write!(buffer, "DROP VIEW {}.{};", schema, name).unwrap();
+                }
+            }
+            Statement::CreateMatView {
+                schema,
+                name,
+                definition,
+            } => {
+                write!(
+                    buffer,
+                    "CREATE MATERIALIZED VIEW {}.{} AS\n{}\nWITH NO DATA;",
+                    schema, name, definition
+                )
+                .unwrap();
+            }
+            Statement::RefreshMatView { schema, name } => {
+                write!(buffer, "REFRESH MATERIALIZED VIEW {}.{};", schema, name).unwrap();
+            }
+            // Only for the migration's transaction, not the rest of the session
+            Statement::DisableFunctionBodyChecks => {
+                buffer.push_str("SET LOCAL check_function_bodies = false;");
+            }
+            Statement::CreateFunction { definition, .. } => {
+                buffer.push_str(definition);
+                buffer.push(';');
+            }
+            Statement::DropFunction {
+                schema,
+                name,
+                arguments,
+                procedure,
+            } => {
+                let kind = if *procedure { "PROCEDURE" } else { "FUNCTION" };
+                write!(buffer, "DROP {} {}.{}({});", kind, schema, name, arguments).unwrap();
+            }
+            Statement::CreateTrigger { definition, .. } => {
+                buffer.push_str(definition);
+                buffer.push(';');
+            }
+            Statement::DropTrigger {
+                schema,
+                table,
+                name,
+            } => {
+                write!(buffer, "DROP TRIGGER {} ON {}.{};", name, schema, table).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::introspection::{Constraint, View};
+
+    fn name(name: &str) -> QualifiedName {
+        ("public".to_string(), name.to_string())
+    }
+
+    fn column(name: &str, data_type: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            not_null: false,
+            default: None,
+            primary_key: 0,
         }
     }
+
+    fn view(definition: &str) -> View {
+        View {
+            materialized: false,
+            definition: definition.to_string(),
+        }
+    }
+
+    fn sql(statements: &[Statement]) -> Vec<String> {
+        statements
+            .iter()
+            .map(|stmt| {
+                let mut buffer = String::new();
+                stmt.write_to(&mut buffer);
+                buffer
+            })
+            .collect()
+    }
+
+    /// A schema with table `a`, and table `b` whose foreign key refers to `a`, with an
+    /// index on `b`.
+    fn tables() -> Schema {
+        let mut schema = Schema::default();
+        schema.tables.insert(
+            name("a"),
+            Table {
+                columns: vec![column("id", "integer")],
+                constraints: vec![Constraint {
+                    name: "a_pkey".to_string(),
+                    definition: "PRIMARY KEY (id)".to_string(),
+                    foreign: false,
+                }],
+                ..Default::default()
+            },
+        );
+        schema.tables.insert(
+            name("b"),
+            Table {
+                columns: vec![column("a", "integer")],
+                constraints: vec![Constraint {
+                    name: "b_a_fkey".to_string(),
+                    definition: "FOREIGN KEY (a) REFERENCES a(id)".to_string(),
+                    foreign: true,
+                }],
+                references: BTreeSet::from([name("a")]),
+                code: None,
+            },
+        );
+        schema.indexes.insert(
+            name("b"),
+            vec!["CREATE INDEX b_a ON public.b USING btree (a)".to_string()],
+        );
+        schema
+    }
+
+    #[test]
+    fn identical_schemas() {
+        assert!(delta(&tables(), &tables(), &[]).is_empty());
+    }
+
+    #[test]
+    fn creates_tables_before_foreign_keys_and_indexes() {
+        assert_eq!(
+            sql(&delta(&Schema::default(), &tables(), &[])),
+            vec![
+                "CREATE TABLE public.a (\n    id integer,\n    CONSTRAINT a_pkey \
+                PRIMARY KEY (id)\n);",
+                "CREATE TABLE public.b (\n    a integer\n);",
+                "ALTER TABLE public.b ADD CONSTRAINT b_a_fkey FOREIGN KEY (a) REFERENCES a(id);",
+                "CREATE INDEX b_a ON public.b USING btree (a);",
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_foreign_keys_before_tables() {
+        assert_eq!(
+            sql(&delta(&tables(), &Schema::default(), &[])),
+            vec![
+                "ALTER TABLE public.b DROP CONSTRAINT b_a_fkey;",
+                "DROP TABLE public.a;",
+                "DROP TABLE public.b;",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_protected_tables() {
+        assert_eq!(
+            sql(&delta(
+                &tables(),
+                &Schema::default(),
+                &["public.a".to_string()]
+            )),
+            vec![
+                "ALTER TABLE public.b DROP CONSTRAINT b_a_fkey;",
+                "DROP TABLE public.b;",
+            ]
+        );
+    }
+
+    #[test]
+    fn disables_body_checks_for_the_transaction() {
+        let mut to = Schema::default();
+        to.functions.insert(
+            ("public".to_string(), "f".to_string(), String::new()),
+            Function {
+                procedure: false,
+                result: Some("bigint".to_string()),
+                definition: "CREATE OR REPLACE FUNCTION public.f() RETURNS bigint \
+                    LANGUAGE sql AS $$SELECT count(*) FROM a$$"
+                    .to_string(),
+            },
+        );
+        assert_eq!(
+            sql(&delta(&Schema::default(), &to, &[]))[0],
+            "SET LOCAL check_function_bodies = false;"
+        );
+    }
+
+    #[test]
+    fn recreates_dependent_views() {
+        let mut from = tables();
+        from.views.insert(name("v"), view("SELECT id FROM a"));
+        from.views.insert(name("w"), view("SELECT id FROM v"));
+        from.view_dependencies
+            .insert(name("w"), BTreeSet::from([name("v")]));
+        let mut to = from.clone();
+        to.views
+            .insert(name("v"), view("SELECT id + 1 AS id FROM a"));
+
+        assert_eq!(
+            sql(&delta(&from, &to, &[])),
+            vec![
+                "DROP VIEW public.w;",
+                "DROP VIEW public.v;",
+                "CREATE VIEW public.v AS\nSELECT id + 1 AS id FROM a;",
+                "CREATE VIEW public.w AS\nSELECT id FROM v;",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_tables_protected_tables_refer_to() {
+        assert!(delta(&tables(), &Schema::default(), &["public.b".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn keeps_triggers_of_protected_tables() {
+        let mut from = tables();
+        from.triggers.insert(
+            ("public".to_string(), "a".to_string(), "a_audit".to_string()),
+            "CREATE TRIGGER a_audit AFTER INSERT ON public.a FOR EACH ROW EXECUTE FUNCTION audit()"
+                .to_string(),
+        );
+        let mut to = tables();
+        to.tables.remove(&name("b"));
+
+        assert_eq!(
+            sql(&delta(&from, &to, &["a".to_string()])),
+            vec![
+                "ALTER TABLE public.b DROP CONSTRAINT b_a_fkey;",
+                "DROP TABLE public.b;",
+            ]
+        );
+    }
+
+    #[test]
+    fn restores_protected_views() {
+        let mut from = tables();
+        from.views.insert(name("v"), view("SELECT id FROM a"));
+        from.views.insert(
+            name("m"),
+            View {
+                materialized: true,
+                definition: "SELECT id FROM v".to_string(),
+            },
+        );
+        from.view_dependencies
+            .insert(name("m"), BTreeSet::from([name("v")]));
+        from.indexes.insert(
+            name("m"),
+            vec!["CREATE INDEX m_id ON public.m USING btree (id)".to_string()],
+        );
+        let mut to = tables();
+        to.views
+            .insert(name("v"), view("SELECT id + 1 AS id FROM a"));
+
+        assert_eq!(
+            sql(&delta(&from, &to, &["public.m".to_string()])),
+            vec![
+                "DROP MATERIALIZED VIEW public.m;",
+                "DROP VIEW public.v;",
+                "CREATE VIEW public.v AS\nSELECT id + 1 AS id FROM a;",
+                "CREATE MATERIALIZED VIEW public.m AS\nSELECT id FROM v\nWITH NO DATA;",
+                "CREATE INDEX m_id ON public.m USING btree (id);",
+                "REFRESH MATERIALIZED VIEW public.m;",
+            ]
+        );
+        // Nor is the view it selects from dropped, if it is deleted as well
+        to.views.clear();
+        assert!(delta(&from, &to, &["public.m".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn diffs_columns() {
+        let from = tables();
+        let mut to = tables();
+        let a = to.tables.get_mut(&name("a")).unwrap();
+        a.columns[0].data_type = "bigint".to_string();
+        a.columns.push(Column {
+            not_null: true,
+            default: Some(ColumnDefault::Expression("0".to_string())),
+            ..column("n", "integer")
+        });
+        to.tables.get_mut(&name("b")).unwrap().columns[0] = Column {
+            default: Some(ColumnDefault::Generated("1".to_string())),
+            ..column("a", "integer")
+        };
+        let mut expected = vec![
+            "ALTER TABLE public.a ALTER COLUMN id TYPE bigint;",
+            "ALTER TABLE public.a ADD COLUMN n integer DEFAULT 0 NOT NULL;",
+            "ALTER TABLE public.b DROP COLUMN a;",
+            "ALTER TABLE public.b ADD COLUMN a integer GENERATED ALWAYS AS (1) STORED;",
+        ];
+        assert_eq!(sql(&delta(&from, &to, &[])), expected);
+
+        // The columns of protected tables are kept, but may still change
+        let b = to.tables.get_mut(&name("b")).unwrap();
+        b.columns.clear();
+        expected.truncate(2);
+        assert_eq!(sql(&delta(&from, &to, &["b".to_string()])), expected);
+    }
+
+    #[test]
+    fn alters_defaults() {
+        let defaults = [
+            None,
+            Some(ColumnDefault::Expression("1".to_string())),
+            Some(ColumnDefault::Identity { always: false }),
+            Some(ColumnDefault::Identity { always: true }),
+            Some(ColumnDefault::Generated("a".to_string())),
+        ];
+        let alterations = |old: &Option<ColumnDefault>, new: &Option<ColumnDefault>| {
+            let mut statements = Vec::default();
+            alter_column(
+                "public",
+                "t",
+                &Column {
+                    default: old.clone(),
+                    ..column("x", "integer")
+                },
+                &Column {
+                    default: new.clone(),
+                    ..column("x", "integer")
+                },
+                &mut statements,
+            );
+            sql(&statements)
+                .into_iter()
+                .map(|sql| sql.replace("ALTER TABLE public.t ", ""))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            alterations(&defaults[1], &defaults[2]),
+            vec![
+                "ALTER COLUMN x DROP DEFAULT;",
+                "ALTER COLUMN x ADD GENERATED BY DEFAULT AS IDENTITY;",
+            ]
+        );
+        assert_eq!(
+            alterations(&defaults[2], &defaults[3]),
+            vec!["ALTER COLUMN x SET GENERATED ALWAYS;"]
+        );
+        assert_eq!(
+            alterations(&defaults[3], &defaults[1]),
+            vec![
+                "ALTER COLUMN x DROP IDENTITY;",
+                "ALTER COLUMN x SET DEFAULT 1;"
+            ]
+        );
+        assert_eq!(
+            alterations(&defaults[4], &defaults[0]),
+            vec!["ALTER COLUMN x DROP EXPRESSION;"]
+        );
+        assert_eq!(
+            alterations(&defaults[1], &defaults[0]),
+            vec!["ALTER COLUMN x DROP DEFAULT;"]
+        );
+        for old in defaults.iter() {
+            assert!(alterations(old, old).is_empty());
+        }
+    }
+
+    #[test]
+    fn diffs_constraints_and_indexes() {
+        let from = tables();
+        let mut to = tables();
+        let b = to.tables.get_mut(&name("b")).unwrap();
+        b.constraints = vec![
+            Constraint {
+                name: "b_a_fkey".to_string(),
+                definition: "FOREIGN KEY (a) REFERENCES a(id) ON DELETE CASCADE".to_string(),
+                foreign: true,
+            },
+            Constraint {
+                name: "b_a_check".to_string(),
+                definition: "CHECK ((a > 0))".to_string(),
+                foreign: false,
+            },
+        ];
+        to.indexes.insert(
+            name("b"),
+            vec!["CREATE UNIQUE INDEX b_a ON public.b USING btree (a)".to_string()],
+        );
+
+        assert_eq!(
+            sql(&delta(&from, &to, &[])),
+            vec![
+                "ALTER TABLE public.b DROP CONSTRAINT b_a_fkey;",
+                "DROP INDEX public.b_a;",
+                "ALTER TABLE public.b ADD CONSTRAINT b_a_check CHECK ((a > 0));",
+                "ALTER TABLE public.b ADD CONSTRAINT b_a_fkey FOREIGN KEY (a) REFERENCES a(id) \
+                ON DELETE CASCADE;",
+                "CREATE UNIQUE INDEX b_a ON public.b USING btree (a);",
+            ]
+        );
+
+        // Deleted constraints & indexes are kept if they or their table are protected
+        to.tables.get_mut(&name("a")).unwrap().constraints.clear();
+        to.tables.get_mut(&name("b")).unwrap().constraints.clear();
+        to.indexes.clear();
+        assert!(delta(&from, &to, &["public.b".to_string(), "a_pkey".to_string()]).is_empty());
+        assert!(delta(
+            &from,
+            &to,
+            &[
+                "public.a_pkey".to_string(),
+                "public.b_a_fkey".to_string(),
+                "public.b_a".to_string()
+            ]
+        )
+        .is_empty());
+    }
+
+    fn sequence(owned_by: Option<&str>) -> Sequence {
+        Sequence {
+            data_type: "bigint".to_string(),
+            increment: 1,
+            min_value: 1,
+            max_value: i64::MAX,
+            start_value: 1,
+            cache: 1,
+            cycle: false,
+            owned_by: owned_by.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn creates_standalone_sequences_before_tables() {
+        let mut to = tables();
+        to.sequences.insert(name("s"), sequence(None));
+
+        let created = sql(&delta(&Schema::default(), &to, &[]));
+        assert_eq!(
+            created[0],
+            "CREATE SEQUENCE public.s AS bigint INCREMENT BY 1 MINVALUE 1 \
+            MAXVALUE 9223372036854775807 START WITH 1 CACHE 1 NO CYCLE;"
+        );
+        assert!(created[1].starts_with("CREATE TABLE public.a"));
+        assert!(!created.iter().any(|stmt| stmt.contains("OWNED BY")));
+
+        assert_eq!(
+            sql(&delta(&to, &tables(), &[])),
+            vec!["DROP SEQUENCE public.s;"]
+        );
+    }
+
+    #[test]
+    fn orders_dependent_views() {
+        // `v_a` sorts first, but depends on `v_b`
+        let mut views = tables();
+        views.views.insert(name("v_a"), view("SELECT id FROM v_b"));
+        views.views.insert(name("v_b"), view("SELECT id FROM a"));
+        views
+            .view_dependencies
+            .insert(name("v_a"), BTreeSet::from([name("v_b")]));
+
+        let created = sql(&delta(&tables(), &views, &[]));
+        assert_eq!(
+            created,
+            vec![
+                "CREATE VIEW public.v_b AS\nSELECT id FROM a;",
+                "CREATE VIEW public.v_a AS\nSELECT id FROM v_b;",
+            ]
+        );
+        assert_eq!(
+            sql(&delta(&views, &tables(), &[])),
+            vec!["DROP VIEW public.v_a;", "DROP VIEW public.v_b;"]
+        );
+    }
 }
@@ -2,35 +2,57 @@ mod delta;
 
 use std::{
     env::{self, VarError},
-    fs,
-    num::NonZeroU16,
-    path::PathBuf,
+    error::Error as _,
+    fmt::{self, Display},
+    fs, io,
+    num::{NonZeroU16, NonZeroUsize},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::Duration,
 };
 
 use crate::{
-    artifact::{Artifact, ConsumerError, ContentId, ScriptConsumer, ScriptProcessingError},
-    manifest::{self, project::PostgresDatabase},
-    util::{empty_database_version, from_minor_version},
+    artifact::{
+        Artifact, ConsumerError, ContentId, Progress, ScriptConsumer, ScriptProcessingError,
+    },
+    manifest::{
+        self,
+        artifact::Maintenance,
+        project::{PostgresDatabase, SslMode},
+    },
+    util::{empty_database_version, from_version, split_statements},
     SQIGL_VERSION,
 };
 use anyhow::anyhow;
 use log::{debug, error, info, trace, warn};
-use native_tls::{Certificate, TlsConnector};
-use postgres::Client;
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres::{error::SqlState, Client};
 use postgres_native_tls::MakeTlsConnector;
 use postgres_secrets::{
     pgpass::{CredentialQuery, LoadError},
     PgPass,
 };
 use semver::Version;
+use sha2::{Digest, Sha256};
 
-use self::delta::delta;
+use self::delta::{delta, introspect};
 
-use super::{Backend, GeneratedMigration, SqiglState};
+use super::{
+    internal_sql, introspection::Schema, verify_migration, Backend, GenerateOptions,
+    GeneratedMigration, HistoryEntry, HistoryFilter, SqiglState, DEFAULT_INTERNAL_SCHEMA,
+};
 
 // Unofficial
 pub const STATEMENT_TIMEOUT_ENVVAR: &str = "PGSTATEMENT_TIMEOUT";
 pub const TRANSACTION_TIMEOUT_ENVVAR: &str = "PGTRANSACTION_TIMEOUT";
+pub const CONNECT_RETRIES_ENVVAR: &str = "PGCONNECT_RETRIES";
+pub const CONNECT_BACKOFF_ENVVAR: &str = "PGCONNECT_BACKOFF";
+/// The database connected to in order to create others.
+pub const MAINTENANCE_DATABASE: &str = "postgres";
+pub const PASSWORD_COMMAND_ENVVAR: &str = "PGPASSWORD_COMMAND";
+pub const PASSWORD_FILE_ENVVAR: &str = "PGPASSWORD_FILE";
 
 // https://www.postgresql.org/docs/current/libpq-envars.html
 pub const HOSTNAME_ENVVAR: &str = "PGHOST";
@@ -39,13 +61,78 @@ pub const DATABASE_ENVVAR: &str = "PGDATABASE";
 pub const USERNAME_ENVVAR: &str = "PGUSER";
 pub const PASSWORD_ENVVAR: &str = "PGPASSWORD";
 pub const CERTIFICATE_ENVVAR: &str = "PGROOTCERT";
+pub const SSLMODE_ENVVAR: &str = "PGSSLMODE";
+pub const SSLCERT_ENVVAR: &str = "PGSSLCERT";
+pub const SSLKEY_ENVVAR: &str = "PGSSLKEY";
 
 impl ConsumerError for postgres::Error {}
 
-fn get_state<Db: postgres::GenericClient>(db: &mut Db) -> Result<SqiglState, postgres::Error> {
-    Ok(db
-        .query_one(include_str!("sql/select_state.sql"), &[])?
-        .try_into()?)
+fn get_state<Db: postgres::GenericClient>(
+    db: &mut Db,
+    internal: &str,
+) -> Result<SqiglState, postgres::Error> {
+    db.query_one(
+        &*internal_sql(include_str!("sql/select_state.sql"), internal),
+        &[],
+    )?
+    .try_into()
+}
+
+/// Lock sqigl's state for the rest of the transaction, so that sqigl instances run
+/// serially.
+fn lock_state(tx: &mut postgres::Transaction, internal: &str) -> Result<(), postgres::Error> {
+    tx.execute(
+        &internal_sql(r#"select from "{internal}".state for update"#, internal),
+        &[],
+    )?;
+    Ok(())
+}
+
+/// The content id of the migration marked as pending, if any.
+fn get_pending<Db: postgres::GenericClient>(
+    db: &mut Db,
+    internal: &str,
+) -> Result<Option<ContentId>, postgres::Error> {
+    let pending: Option<Vec<u8>> = db
+        .query_one(
+            &internal_sql(
+                r#"select pending_artifact from "{internal}".state"#,
+                internal,
+            ),
+            &[],
+        )?
+        .get(0);
+    Ok(pending.map(|id| {
+        id.try_into()
+            .expect("Failed to parse content id in pending_artifact")
+    }))
+}
+
+/// Mark a migration as pending while it is applied outside of a transaction, or clear
+/// the mark.
+fn set_pending<Db: postgres::GenericClient>(
+    db: &mut Db,
+    internal: &str,
+    id: Option<ContentId>,
+) -> Result<(), postgres::Error> {
+    let id = id.map(|id| id.unwrap().to_vec());
+    db.execute(
+        &internal_sql(
+            r#"update "{internal}".state set pending_artifact = $1, updated_at = now()"#,
+            internal,
+        ),
+        &[&id],
+    )?;
+    Ok(())
+}
+
+/// Whether an error indicates that sqigl's tables do not exist, ie sqigl is
+/// not installed.
+fn is_not_installed(e: &postgres::Error) -> bool {
+    matches!(
+        e.code(),
+        Some(&SqlState::UNDEFINED_TABLE) | Some(&SqlState::INVALID_SCHEMA_NAME)
+    )
 }
 
 fn get_envvar(var: &str) -> anyhow::Result<Option<String>> {
@@ -94,6 +181,617 @@ fn get_timeout_envvar(envvar: &str) -> anyhow::Result<Option<f32>> {
     }
 }
 
+fn get_sslmode_envvar() -> anyhow::Result<Option<SslMode>> {
+    if let Some(mode) = get_envvar(SSLMODE_ENVVAR)? {
+        match mode.parse() {
+            Ok(x) => Ok(Some(x)),
+            Err(e) => Err(anyhow!("Could not parse {}: {}", SSLMODE_ENVVAR, e)),
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+fn read_pem(path: &Path, what: &str) -> anyhow::Result<Vec<u8>> {
+    match fs::read(path) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(anyhow!("Failed to read {}: {}", what, e)),
+    }
+}
+
+fn get_retries_envvar() -> anyhow::Result<Option<u32>> {
+    if let Some(digits) = get_envvar(CONNECT_RETRIES_ENVVAR)? {
+        match digits.parse() {
+            Ok(x) => Ok(Some(x)),
+            Err(e) => Err(anyhow!("Could not parse {}: {}", CONNECT_RETRIES_ENVVAR, e)),
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+/// How connecting to the database is retried when it fails for reasons which may be
+/// transient, such as the server not accepting connections yet.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// The number of times to retry after the first attempt fails
+    pub retries: u32,
+    /// The delay before the first retry, which doubles after each retry
+    pub backoff: Duration,
+}
+impl RetryPolicy {
+    pub const DEFAULT_RETRIES: u32 = 3;
+    pub const DEFAULT_BACKOFF: f32 = 0.5;
+
+    pub fn get(params: &PostgresDatabase) -> anyhow::Result<Self> {
+        let retries = get_retries_envvar()?
+            .or(params.connect_retries)
+            .unwrap_or(Self::DEFAULT_RETRIES);
+        let backoff = get_timeout_envvar(CONNECT_BACKOFF_ENVVAR)?
+            .or(params.connect_backoff)
+            .unwrap_or(Self::DEFAULT_BACKOFF);
+        let backoff = Duration::try_from_secs_f32(backoff)
+            .map_err(|e| anyhow!("Invalid connect_backoff of {} seconds: {}", backoff, e))?;
+        Ok(Self { retries, backoff })
+    }
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: Self::DEFAULT_RETRIES,
+            backoff: Duration::from_secs_f32(Self::DEFAULT_BACKOFF),
+        }
+    }
+}
+
+/// Whether a connection error may be resolved by trying again. Errors reported by the
+/// server (such as authentication failures) are not, unless the server is starting up.
+fn is_transient(e: &postgres::Error) -> bool {
+    if let Some(db_error) = e.as_db_error() {
+        *db_error.code() == SqlState::CANNOT_CONNECT_NOW
+    } else {
+        e.source().is_some_and(|source| source.is::<io::Error>())
+    }
+}
+
+fn connect(
+    config: &postgres::Config,
+    tls: &MakeTlsConnector,
+    retry: &RetryPolicy,
+    setup: Option<&str>,
+) -> Result<Client, postgres::Error> {
+    let mut backoff = retry.backoff;
+    let mut attempt = 0;
+    loop {
+        match config.connect(tls.clone()) {
+            Ok(mut db) => {
+                if let Some(setup) = setup {
+                    run_session_setup(&mut db, setup)?;
+                }
+                return Ok(db);
+            }
+            Err(e) if attempt < retry.retries && is_transient(&e) => {
+                attempt += 1;
+                warn!(
+                    "Failed to connect to database ({}); retrying in {:.2}s ({}/{})",
+                    e,
+                    backoff.as_secs_f32(),
+                    attempt,
+                    retry.retries
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Run the project's session setup on a new connection, outside of any transaction.
+fn run_session_setup(db: &mut Client, setup: &str) -> Result<(), postgres::Error> {
+    debug!("Running session setup");
+    db.batch_execute(setup)
+        .inspect_err(|e| error!("Session setup failed: {}", e))
+}
+
+/// Where the value of a connection parameter came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Environment(&'static str),
+    Manifest,
+    Pgpass,
+    PasswordCommand,
+    PasswordFile,
+}
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Environment(var) => write!(f, "environment variable {}", var),
+            Source::Manifest => write!(f, "manifest"),
+            Source::Pgpass => write!(f, "pgpass file"),
+            Source::PasswordCommand => write!(f, "password command"),
+            Source::PasswordFile => write!(f, "password file"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Resolved<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+/// Prefer the environment variable `var` over the manifest.
+fn resolve<T>(env: Option<T>, var: &'static str, manifest: Option<T>) -> Option<Resolved<T>> {
+    env.map(|value| Resolved {
+        value,
+        source: Source::Environment(var),
+    })
+    .or_else(|| {
+        manifest.map(|value| Resolved {
+            value,
+            source: Source::Manifest,
+        })
+    })
+}
+
+/// The parameters used to connect to a database, resolved from the environment, the
+/// manifest, & the pgpass file. The password is never displayed.
+#[derive(Clone)]
+pub struct ConnectionParams {
+    pub hostname: Option<Resolved<String>>,
+    pub port: Option<Resolved<NonZeroU16>>,
+    pub database: Option<Resolved<String>>,
+    pub username: Option<Resolved<String>>,
+    password: Option<Resolved<String>>,
+    pub password_command: Option<Resolved<String>>,
+    pub password_file: Option<Resolved<PathBuf>>,
+    pub certificate: Option<Resolved<PathBuf>>,
+    pub sslmode: Option<Resolved<SslMode>>,
+    pub sslcert: Option<Resolved<PathBuf>>,
+    pub sslkey: Option<Resolved<PathBuf>>,
+    /// In seconds
+    pub statement_timeout: Option<Resolved<f32>>,
+    /// In seconds
+    pub transaction_timeout: Option<Resolved<f32>>,
+    pub retry: RetryPolicy,
+}
+impl ConnectionParams {
+    /// Resolve parameters from the environment & the manifest, with the environment
+    /// taking priority. The pgpass file is not consulted.
+    pub fn resolve(params: &PostgresDatabase) -> anyhow::Result<Self> {
+        Ok(Self {
+            hostname: resolve(
+                get_envvar(HOSTNAME_ENVVAR)?,
+                HOSTNAME_ENVVAR,
+                params
+                    .socket
+                    .as_ref()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .or_else(|| params.hostname.clone()),
+            ),
+            port: resolve(get_port_envvar()?, PORT_ENVVAR, params.port),
+            database: resolve(
+                get_envvar(DATABASE_ENVVAR)?,
+                DATABASE_ENVVAR,
+                params.database.clone(),
+            ),
+            username: resolve(
+                get_envvar(USERNAME_ENVVAR)?,
+                USERNAME_ENVVAR,
+                params.username.clone(),
+            ),
+            password: resolve(get_envvar(PASSWORD_ENVVAR)?, PASSWORD_ENVVAR, None),
+            password_command: resolve(
+                get_envvar(PASSWORD_COMMAND_ENVVAR)?,
+                PASSWORD_COMMAND_ENVVAR,
+                params.password_command.clone(),
+            ),
+            password_file: resolve(
+                get_envvar(PASSWORD_FILE_ENVVAR)?.map(PathBuf::from),
+                PASSWORD_FILE_ENVVAR,
+                params.password_file.clone(),
+            ),
+            certificate: resolve(
+                get_envvar(CERTIFICATE_ENVVAR)?.map(PathBuf::from),
+                CERTIFICATE_ENVVAR,
+                params.certificate.clone(),
+            ),
+            sslmode: resolve(get_sslmode_envvar()?, SSLMODE_ENVVAR, params.sslmode),
+            sslcert: resolve(
+                get_envvar(SSLCERT_ENVVAR)?.map(PathBuf::from),
+                SSLCERT_ENVVAR,
+                params.sslcert.clone(),
+            ),
+            sslkey: resolve(
+                get_envvar(SSLKEY_ENVVAR)?.map(PathBuf::from),
+                SSLKEY_ENVVAR,
+                params.sslkey.clone(),
+            ),
+            statement_timeout: resolve(
+                get_timeout_envvar(STATEMENT_TIMEOUT_ENVVAR)?,
+                STATEMENT_TIMEOUT_ENVVAR,
+                params.statement_timeout,
+            ),
+            transaction_timeout: resolve(
+                get_timeout_envvar(TRANSACTION_TIMEOUT_ENVVAR)?,
+                TRANSACTION_TIMEOUT_ENVVAR,
+                params.transaction_timeout,
+            ),
+            retry: RetryPolicy::get(params)?,
+        })
+    }
+    /// If no password was supplied, obtain it from the password command or else the
+    /// password file, if either was given.
+    pub fn resolve_password(&mut self) -> anyhow::Result<()> {
+        if self.password.is_some() {
+            return Ok(());
+        }
+
+        if let Some(Resolved { value: command, .. }) = self.password_command.as_ref() {
+            debug!("Running password command");
+            let output = if cfg!(windows) {
+                process::Command::new("cmd").arg("/C").arg(command).output()
+            } else {
+                process::Command::new("sh").arg("-c").arg(command).output()
+            };
+            let output = match output {
+                Ok(x) => x,
+                Err(e) => return Err(anyhow!("Failed to run password command: {}", e)),
+            };
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Password command failed ({}): {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            let Ok(stdout) = String::from_utf8(output.stdout) else {
+                return Err(anyhow!("Password command did not print valid UTF-8"));
+            };
+            self.password = Some(Resolved {
+                value: stdout.trim().to_string(),
+                source: Source::PasswordCommand,
+            });
+        } else if let Some(Resolved { value: path, .. }) = self.password_file.as_ref() {
+            debug!("Reading password file {:?}", path);
+            let content = match fs::read_to_string(path) {
+                Ok(x) => x,
+                Err(e) => return Err(anyhow!("Failed to read password file: {}", e)),
+            };
+            self.password = Some(Resolved {
+                value: content.lines().next().unwrap_or_default().to_string(),
+                source: Source::PasswordFile,
+            });
+        }
+        Ok(())
+    }
+    /// If no password was supplied by any other means, look up the credentials in the
+    /// pgpass file, filling in any parameters which were not supplied.
+    pub fn resolve_pgpass(&mut self) -> anyhow::Result<()> {
+        if self.password.is_some()
+            || self.password_command.is_some()
+            || self.password_file.is_some()
+        {
+            return Ok(());
+        }
+
+        // Connections through a socket may be authenticated without a password, such as
+        // by peer authentication
+        let socket = self.socket_dir().is_some();
+        let pgpass = match PgPass::load() {
+            Ok(x) => x,
+            Err(LoadError::CouldNotLocate) if socket => return Ok(()),
+            Err(LoadError::CouldNotLocate) => {
+                return Err(anyhow!(
+                    "Could not connect to database: Credentials were not supplied."
+                ));
+            }
+            Err(e) => {
+                return Err(anyhow!("Failed to load pgpass file: {}", e));
+            }
+        };
+        // Like libpq, socket connections match pgpass entries for localhost
+        let hostname = match socket {
+            true => Some("localhost".to_string()),
+            false => self.hostname.as_ref().map(|x| x.value.clone()),
+        };
+        let query = CredentialQuery {
+            hostname,
+            port: self.port.as_ref().map(|x| x.value),
+            database: self.database.as_ref().map(|x| x.value.clone()),
+            username: self.username.as_ref().map(|x| x.value.clone()),
+        };
+        let Some(creds) = pgpass.find(&query)? else {
+            if socket {
+                return Ok(());
+            }
+            return Err(anyhow!(
+                "Could not connect to database: Credentials were not found in pgpass file."
+            ));
+        };
+        let from_pgpass = |value| {
+            Some(Resolved {
+                value,
+                source: Source::Pgpass,
+            })
+        };
+        self.hostname = self.hostname.take().or_else(|| from_pgpass(creds.hostname));
+        self.port = self.port.take().or(Some(Resolved {
+            value: creds.port,
+            source: Source::Pgpass,
+        }));
+        self.database = self.database.take().or_else(|| from_pgpass(creds.database));
+        self.username = self.username.take().or_else(|| from_pgpass(creds.username));
+        self.password = from_pgpass(creds.password);
+        Ok(())
+    }
+    pub fn ssl_mode(&self) -> SslMode {
+        self.sslmode.as_ref().map(|x| x.value).unwrap_or_default()
+    }
+    pub fn tls(&self) -> anyhow::Result<MakeTlsConnector> {
+        let mut builder = TlsConnector::builder();
+        if let Some(Resolved { value: path, .. }) = self.certificate.as_ref() {
+            let cert = Certificate::from_pem(&read_pem(path, "certificate")?)?;
+            builder.add_root_certificate(cert);
+        }
+        match self.ssl_mode() {
+            SslMode::Disable | SslMode::Prefer | SslMode::VerifyFull => (),
+            // Like libpq, require verifies the certificate authority if a root
+            // certificate was supplied
+            SslMode::Require if self.certificate.is_none() => {
+                builder.danger_accept_invalid_certs(true);
+            }
+            SslMode::Require | SslMode::VerifyCa => {
+                builder.danger_accept_invalid_hostnames(true);
+            }
+        }
+        match (self.sslcert.as_ref(), self.sslkey.as_ref()) {
+            (Some(cert), Some(key)) => {
+                let identity = Identity::from_pkcs8(
+                    &read_pem(&cert.value, "client certificate")?,
+                    &read_pem(&key.value, "client key")?,
+                )?;
+                builder.identity(identity);
+            }
+            (None, None) => (),
+            (Some(_), None) | (None, Some(_)) => {
+                return Err(anyhow!(
+                    "A client certificate & key must be supplied together ({} & {})",
+                    SSLCERT_ENVVAR,
+                    SSLKEY_ENVVAR
+                ));
+            }
+        }
+        Ok(MakeTlsConnector::new(builder.build()?))
+    }
+    /// The directory containing the server's Unix socket, if the hostname is one (ie, it
+    /// starts with `/`, as with libpq).
+    pub fn socket_dir(&self) -> Option<&Path> {
+        self.hostname
+            .as_ref()
+            .map(|x| Path::new(&x.value))
+            .filter(|path| path.starts_with("/"))
+    }
+    pub fn config(&self) -> anyhow::Result<postgres::Config> {
+        let mut config = postgres::Config::new();
+        if let Some(socket) = self.socket_dir() {
+            // The socket's name is derived from the port, so it is still used
+            config
+                .host_path(socket)
+                .port(self.port.as_ref().map(|x| x.value.get()).unwrap_or(5432));
+            if let Some(password) = self.password.as_ref() {
+                config.password(&password.value);
+            }
+            if !matches!(self.ssl_mode(), SslMode::Disable | SslMode::Prefer) {
+                warn!("TLS is not used when connecting through a Unix socket; ignoring sslmode");
+            }
+            config.ssl_mode(postgres::config::SslMode::Disable);
+        } else {
+            self.tcp_config(&mut config)?;
+        }
+        let Some(database) = self.database.as_ref() else {
+            return Err(anyhow!(
+                "Could not connect to database: Database was not supplied."
+            ));
+        };
+        let Some(username) = self.username.as_ref() else {
+            return Err(anyhow!(
+                "Could not connect to database: Username was not supplied."
+            ));
+        };
+        config.dbname(&database.value).user(&username.value);
+        Ok(config)
+    }
+    fn tcp_config(&self, config: &mut postgres::Config) -> anyhow::Result<()> {
+        let Some(hostname) = self.hostname.as_ref() else {
+            return Err(anyhow!(
+                "Could not connect to database: Hostname was not supplied."
+            ));
+        };
+        let Some(port) = self.port.as_ref() else {
+            return Err(anyhow!(
+                "Could not connect to database: Port was not supplied."
+            ));
+        };
+        let Some(password) = self.password.as_ref() else {
+            return Err(anyhow!(
+                "Could not connect to database: Credentials were not supplied."
+            ));
+        };
+
+        config
+            .host(&hostname.value)
+            .port(port.value.get())
+            .password(&password.value)
+            .ssl_mode(match self.ssl_mode() {
+                SslMode::Disable => postgres::config::SslMode::Disable,
+                SslMode::Prefer => postgres::config::SslMode::Prefer,
+                SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => {
+                    postgres::config::SslMode::Require
+                }
+            });
+        Ok(())
+    }
+    // Timeouts are specified as f32 of seconds but stored as milliseconds because
+    // a timeout w/o a unit is interpreted as milliseconds
+    // https://www.postgresql.org/docs/17/runtime-config-client.html#GUC-STATEMENT-TIMEOUT
+    pub fn stmt_timeout(&self) -> Option<usize> {
+        self.statement_timeout
+            .as_ref()
+            .map(|t| (t.value * 1000.) as usize)
+    }
+    pub fn tx_timeout(&self) -> Option<usize> {
+        self.transaction_timeout
+            .as_ref()
+            .map(|t| (t.value * 1000.) as usize)
+    }
+}
+impl Display for ConnectionParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn line<T: Display>(
+            f: &mut fmt::Formatter<'_>,
+            name: &str,
+            param: &Option<Resolved<T>>,
+        ) -> fmt::Result {
+            match param {
+                Some(Resolved { value, source }) => {
+                    writeln!(f, "{}: {} (from {})", name, value, source)
+                }
+                None => writeln!(f, "{}: not supplied", name),
+            }
+        }
+
+        line(f, "hostname", &self.hostname)?;
+        line(f, "port", &self.port)?;
+        line(f, "database", &self.database)?;
+        line(f, "username", &self.username)?;
+        match (&self.password, &self.password_command, &self.password_file) {
+            (Some(Resolved { source, .. }), _, _) => {
+                writeln!(f, "password: [ redacted ] (from {})", source)?
+            }
+            // The command may contain secrets of its own, such as tokens
+            (None, Some(Resolved { source, .. }), _) => writeln!(
+                f,
+                "password: output of password command [ redacted ] (from {})",
+                source
+            )?,
+            (None, None, Some(Resolved { value, source })) => writeln!(
+                f,
+                "password: password file {} (from {})",
+                value.display(),
+                source
+            )?,
+            (None, None, None) => writeln!(f, "password: not supplied")?,
+        }
+        match &self.sslmode {
+            Some(Resolved { value, source }) => {
+                writeln!(f, "sslmode: {} (from {})", value, source)?
+            }
+            None => writeln!(f, "sslmode: {} (default)", SslMode::default())?,
+        }
+        match &self.certificate {
+            Some(Resolved { value, source }) => {
+                writeln!(f, "root certificate: {} (from {})", value.display(), source)?
+            }
+            None => writeln!(f, "root certificate: system root certificates")?,
+        }
+        for (name, path) in [
+            ("client certificate", &self.sslcert),
+            ("client key", &self.sslkey),
+        ] {
+            match path {
+                Some(Resolved { value, source }) => {
+                    writeln!(f, "{}: {} (from {})", name, value.display(), source)?
+                }
+                None => writeln!(f, "{}: none", name)?,
+            }
+        }
+        for (name, timeout) in [
+            ("statement timeout", &self.statement_timeout),
+            ("transaction timeout", &self.transaction_timeout),
+        ] {
+            match timeout {
+                Some(Resolved { value, source }) => {
+                    writeln!(f, "{}: {:.2}s (from {})", name, value, source)?
+                }
+                None => writeln!(f, "{}: none", name)?,
+            }
+        }
+        writeln!(
+            f,
+            "connect retries: {}, backoff {:.2}s",
+            self.retry.retries,
+            self.retry.backoff.as_secs_f32()
+        )
+    }
+}
+
+/// Settings applied while running scripts. They are scoped to the migration transaction,
+/// or to the session on connections which are only used to run scripts.
+#[derive(Clone, Debug, Default)]
+pub struct SessionSettings {
+    pub role: Option<String>,
+    pub search_path: Option<Vec<String>>,
+}
+impl SessionSettings {
+    pub fn get(params: &PostgresDatabase) -> Self {
+        Self {
+            role: params.role.clone(),
+            search_path: params.search_path.clone(),
+        }
+    }
+    fn statements(&self, local: bool) -> Vec<String> {
+        let scope = if local { "set local" } else { "set" };
+        let mut statements = Vec::new();
+        if let Some(search_path) = &self.search_path {
+            debug!("Setting search_path to {}", search_path.join(", "));
+            let schemas: Vec<_> = search_path.iter().map(|s| quote_identifier(s)).collect();
+            // An empty search path is written as an empty string
+            let schemas = if schemas.is_empty() {
+                "''".to_string()
+            } else {
+                schemas.join(", ")
+            };
+            statements.push(format!("{} search_path to {}", scope, schemas));
+        }
+        // The role is set last, so that setting it can't deny us the privileges needed
+        // to apply the other settings
+        if let Some(role) = &self.role {
+            debug!("Setting role to {}", role);
+            statements.push(format!("{} role {}", scope, quote_identifier(role)));
+        }
+        statements
+    }
+    /// Apply the settings to a transaction, for the rest of it.
+    fn begin<C: postgres::GenericClient>(&self, tx: &mut C) -> Result<(), postgres::Error> {
+        for statement in self.statements(true) {
+            tx.batch_execute(&statement)?;
+        }
+        Ok(())
+    }
+    /// Apply the settings to a connection, for the rest of the session.
+    fn begin_session(&self, db: &mut Client) -> Result<(), postgres::Error> {
+        for statement in self.statements(false) {
+            db.batch_execute(&statement)?;
+        }
+        Ok(())
+    }
+    /// Return to the connecting user's role, for sqigl's bookkeeping at the end of a
+    /// transaction.
+    fn end<C: postgres::GenericClient>(&self, tx: &mut C) -> Result<(), postgres::Error> {
+        if self.role.is_some() {
+            debug!("Resetting role");
+            tx.batch_execute("set local role none")?;
+        }
+        Ok(())
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
 pub struct PostgresBackend {
     tls: MakeTlsConnector,
     config: postgres::Config,
@@ -104,6 +802,15 @@ pub struct PostgresBackend {
     // https://www.postgresql.org/docs/17/runtime-config-client.html#GUC-STATEMENT-TIMEOUT
     stmt_timeout: Option<usize>,
     tx_timeout: Option<usize>,
+
+    retry: RetryPolicy,
+    session: SessionSettings,
+    /// SQL run on each connection immediately after connecting.
+    setup: Option<String>,
+    /// The schema of sqigl's own tables
+    internal: String,
+    /// The schema of the tenant this backend targets, which is created on install.
+    tenant: Option<String>,
 }
 impl PostgresBackend {
     pub fn new(
@@ -112,28 +819,27 @@ impl PostgresBackend {
         tx_timeout: Option<usize>,
     ) -> Result<Self, postgres::Error> {
         let tls = MakeTlsConnector::new(TlsConnector::new().unwrap());
-        let db = config.clone().connect(tls.clone())?;
-        Ok(Self {
-            db,
-            config,
-            tls,
-            stmt_timeout,
-            tx_timeout,
-        })
+        Self::new_tls(config, tls, stmt_timeout, tx_timeout, Default::default())
     }
     pub fn new_tls(
         config: postgres::Config,
         tls: MakeTlsConnector,
         stmt_timeout: Option<usize>,
         tx_timeout: Option<usize>,
+        retry: RetryPolicy,
     ) -> Result<Self, postgres::Error> {
-        let db = config.clone().connect(tls.clone())?;
+        let db = connect(&config, &tls, &retry, None)?;
         Ok(Self {
             db,
             config,
             tls,
             stmt_timeout,
             tx_timeout,
+            retry,
+            session: Default::default(),
+            setup: None,
+            internal: DEFAULT_INTERNAL_SCHEMA.to_string(),
+            tenant: None,
         })
     }
     pub fn local() -> Result<Self, postgres::Error> {
@@ -151,103 +857,129 @@ impl PostgresBackend {
             tls,
             stmt_timeout: Default::default(),
             tx_timeout: Default::default(),
+            retry: Default::default(),
+            session: Default::default(),
+            setup: None,
+            internal: DEFAULT_INTERNAL_SCHEMA.to_string(),
+            tenant: None,
         })
     }
     pub fn get(params: &manifest::project::PostgresDatabase) -> anyhow::Result<Self> {
-        let tls = {
-            if let Some(path) = get_envvar(CERTIFICATE_ENVVAR)?
-                .map(PathBuf::from)
-                .as_ref()
-                .or(params.certificate.as_ref())
-            {
-                let content = match fs::read(path.as_path()) {
-                    Ok(x) => x,
-                    Err(e) => {
-                        return Err(anyhow!("Failed to read certificate: {}", e));
-                    }
-                };
-                let cert = Certificate::from_pem(&content)?;
-                let connector = TlsConnector::builder().add_root_certificate(cert).build()?;
-                MakeTlsConnector::new(connector)
-            } else {
-                let connector = TlsConnector::new()?;
-                MakeTlsConnector::new(connector)
-            }
-        };
-        let hostname = get_envvar(HOSTNAME_ENVVAR)?.or_else(|| params.hostname.clone());
-        let port = get_port_envvar()?.or(params.port);
-        let database = get_envvar(DATABASE_ENVVAR)?.or_else(|| params.database.clone());
-        let username = get_envvar(USERNAME_ENVVAR)?.or_else(|| params.username.clone());
-        let password = get_envvar(PASSWORD_ENVVAR)?;
-
-        // Timeouts are specified as f32 of seconds but stored as milliseconds because
-        // a timeout w/o a unit is interpreted as milliseconds
-        // https://www.postgresql.org/docs/17/runtime-config-client.html#GUC-STATEMENT-TIMEOUT
-        let stmt_timeout: Option<usize> = get_timeout_envvar(STATEMENT_TIMEOUT_ENVVAR)?
-            .or(params.statement_timeout)
-            .map(|t| (t * 1000.) as usize);
-        let tx_timeout: Option<usize> = get_timeout_envvar(TRANSACTION_TIMEOUT_ENVVAR)?
-            .or(params.transaction_timeout)
-            .map(|t| (t * 1000.) as usize);
-
-        if password.is_some() {
-            let Some(hostname) = hostname else {
-                return Err(anyhow!(
-                    "Could not connect to database: Hostname was not supplied."
-                ));
-            };
-            let Some(port) = port else {
-                return Err(anyhow!(
-                    "Could not connect to database: Port was not supplied."
-                ));
-            };
-            let Some(database) = database else {
-                return Err(anyhow!(
-                    "Could not connect to database: Database was not supplied."
-                ));
-            };
-            let Some(username) = username else {
-                return Err(anyhow!(
-                    "Could not connect to database: Username was not supplied."
-                ));
-            };
-
-            let mut config = postgres::Config::new();
-            config
-                .host(&hostname)
-                .port(port.get())
-                .dbname(&database)
-                .user(&username);
-            return Ok(Self::new_tls(config, tls, stmt_timeout, tx_timeout)?);
+        Self::get_database(params, false)
+    }
+    /// Connect like `get`, but to the maintenance database rather than the configured
+    /// one, for checking artifacts against temporary databases. The configured database
+    /// is never connected to, so it need not exist & the user needs no privileges on
+    /// it, but temporary databases are connected to with the configured parameters.
+    pub fn get_isolated(params: &manifest::project::PostgresDatabase) -> anyhow::Result<Self> {
+        Self::get_database(params, true)
+    }
+    fn get_database(
+        params: &manifest::project::PostgresDatabase,
+        maintenance: bool,
+    ) -> anyhow::Result<Self> {
+        let session = SessionSettings::get(params);
+        let mut params = ConnectionParams::resolve(params)?;
+        params.resolve_password()?;
+        params.resolve_pgpass()?;
+        let tls = params.tls()?;
+        let config = params.config()?;
+        let mut connect_to = config.clone();
+        if maintenance {
+            connect_to.dbname(MAINTENANCE_DATABASE);
         }
+        let mut backend = Self::new_tls(
+            connect_to,
+            tls,
+            params.stmt_timeout(),
+            params.tx_timeout(),
+            params.retry,
+        )?
+        .with_session(session);
+        backend.config = config;
+        Ok(backend)
+    }
+    /// Run scripts with a role & search path, rather than those of the connecting user.
+    pub fn with_session(mut self, session: SessionSettings) -> Self {
+        self.session = session;
+        self
+    }
+    /// Keep sqigl's own tables in the schema `internal`, rather than `sqigl_internal`.
+    pub fn with_internal_schema(mut self, internal: &str) -> Self {
+        self.internal = internal.to_string();
+        self
+    }
+    /// Target the tenant whose schema is `tenant`, creating it when sqigl is installed.
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+    /// Run a script on this connection & on each later connection, such as those to
+    /// temporary databases, immediately after connecting & outside of any transaction.
+    pub fn with_session_setup(mut self, setup: String) -> Result<Self, postgres::Error> {
+        run_session_setup(&mut self.db, &setup)?;
+        self.setup = Some(setup);
+        Ok(self)
+    }
+    /// Create the database described by `params` if it doesn't exist, by connecting to
+    /// the maintenance database with the same credentials. Returns whether it was
+    /// created.
+    pub fn create_database(params: &manifest::project::PostgresDatabase) -> anyhow::Result<bool> {
+        let mut params = ConnectionParams::resolve(params)?;
+        params.resolve_password()?;
+        params.resolve_pgpass()?;
+        let tls = params.tls()?;
+        let mut config = params.config()?;
+        let name = config
+            .get_dbname()
+            .expect("The database name is always configured")
+            .to_string();
+        config.dbname(MAINTENANCE_DATABASE);
+        let mut db = connect(&config, &tls, &params.retry, None)?;
 
-        let pgpass = match PgPass::load() {
-            Ok(x) => x,
-            Err(LoadError::CouldNotLocate) => {
-                return Err(anyhow!(
-                    "Could not connect to database: Credentials were not supplied."
-                ));
+        let exists: bool = db
+            .query_one(include_str!("sql/database_exists.sql"), &[&name])?
+            .get(0);
+        if exists {
+            info!("Database {} already exists", name);
+            return Ok(false);
+        }
+        match db.execute(&format!("create database {}", quote_identifier(&name)), &[]) {
+            Ok(_) => {
+                info!("Created database {}", name);
+                Ok(true)
             }
-            Err(e) => {
-                return Err(anyhow!("Failed to load pgpass file: {}", e));
+            // Another process may have created it since we checked
+            Err(e) if e.code() == Some(&SqlState::DUPLICATE_DATABASE) => {
+                info!("Database {} already exists", name);
+                Ok(false)
             }
-        };
-        let query = CredentialQuery {
-            hostname,
-            port,
-            database,
-            username,
-        };
-        if let Some(creds) = pgpass.find(&query)? {
-            Ok(Self::new_tls(creds.into(), tls, stmt_timeout, tx_timeout)?)
-        } else {
-            Err(anyhow!(
-                "Could not connect to database: Credentials were not found in pgpass file."
-            ))
+            Err(e) => Err(e.into()),
         }
     }
+    /// Create a schema if it doesn't exist. It is created as the role scripts are run as,
+    /// if one is configured, so that it owns the schema.
+    pub fn create_schema(&mut self, schema: &str) -> Result<(), postgres::Error> {
+        let session = self.session.clone();
+        let mut tx = self.open_transaction()?;
+        session.begin(&mut tx)?;
+        debug!("Creating schema {} if it doesn't exist", schema);
+        tx.batch_execute(&format!(
+            "create schema if not exists {}",
+            quote_identifier(schema)
+        ))?;
+        session.end(&mut tx)?;
+        tx.commit()
+    }
+    /// The schemas to exclude from introspection: `exclude_schemas`, and the schema of
+    /// sqigl's own tables.
+    fn excluded_schemas(&self, exclude_schemas: &[String]) -> Vec<String> {
+        let mut excluded = exclude_schemas.to_vec();
+        excluded.push(self.internal.clone());
+        excluded
+    }
     /// Open transaction & sets statement and transaction timeouts.
-    fn open_transaction(&mut self) -> Result<postgres::Transaction, postgres::Error> {
+    fn open_transaction(&mut self) -> Result<postgres::Transaction<'_>, postgres::Error> {
         let mut tx = self.db.transaction()?;
 
         if let Some(timeout) = self.stmt_timeout {
@@ -283,28 +1015,158 @@ impl PostgresBackend {
         Ok(tx)
     }
 }
+/// Record an artifact in the history at `version`, making it the head.
+fn append_history(
+    tx: &mut postgres::Transaction,
+    internal: &str,
+    id: ContentId,
+    version: &Version,
+) -> Result<(), postgres::Error> {
+    let prev_pk: Option<i64> = tx
+        .query_one(
+            &internal_sql(r#"select head from "{internal}".state"#, internal),
+            &[],
+        )?
+        .get("head");
+    // Retrying an apply which was recorded shouldn't record it again
+    let recorded: bool = tx
+        .query_one(
+            &*internal_sql(include_str!("sql/head_matches.sql"), internal),
+            &[&prev_pk, &&id.unwrap().as_slice(), &version.to_string()],
+        )?
+        .get(0);
+    if recorded {
+        debug!("Artifact {} is already at the head of the history", id);
+        return Ok(());
+    }
+    // NB: An artifact may be applied multiple times. Our artifact's row may
+    // already exist.
+    let artifact_pk: i64 = tx
+        .query_one(
+            &*internal_sql(include_str!("sql/get_artifact_by_id.sql"), internal),
+            &[&&id.unwrap().as_slice()],
+        )?
+        .get("pk");
+    let head_pk: i64 = tx
+        .query_one(
+            &*internal_sql(include_str!("sql/append_history.sql"), internal),
+            &[&prev_pk, &artifact_pk, &version.to_string(), &SQIGL_VERSION],
+        )?
+        .get("pk");
+    let updated = tx.execute(
+        &internal_sql(r#"update "{internal}".state set head = $1"#, internal),
+        &[&head_pk],
+    )?;
+    debug_assert!(updated == 1);
+    Ok(())
+}
+
+/// Bring the schema of sqigl's tables up to date, if they were installed by an earlier
+/// version of sqigl, & record that they are now this version's.
+fn upgrade_schema<Db: postgres::GenericClient>(
+    db: &mut Db,
+    internal: &str,
+    state: &mut SqiglState,
+) -> Result<(), postgres::Error> {
+    let running: Version = SQIGL_VERSION.parse().unwrap();
+    if state.sqigl_version >= running {
+        return Ok(());
+    }
+    info!(
+        "Upgrading sqigl's tables from version {}",
+        state.sqigl_version
+    );
+    db.batch_execute(&internal_sql(
+        include_str!("sql/upgrade_schema.sql"),
+        internal,
+    ))?;
+    db.execute(
+        &internal_sql(
+            r#"update "{internal}".state set sqigl_version = $1, updated_at = now()"#,
+            internal,
+        ),
+        &[&SQIGL_VERSION],
+    )?;
+    state.sqigl_version = running;
+    Ok(())
+}
+
+/// Temporary databases created on the server, which are dropped along with this guard
+/// (including when returning early with an error) unless they are to be kept.
+struct TempDatabases<'a> {
+    db: &'a mut Client,
+    names: Vec<(String, Version)>,
+    keep: bool,
+}
+impl<'a> TempDatabases<'a> {
+    fn new(db: &'a mut Client, keep: bool) -> Self {
+        Self {
+            db,
+            names: Vec::new(),
+            keep,
+        }
+    }
+    /// Create a temporary database to hold `version`, returning its name.
+    fn create(&mut self, version: &Version) -> Result<String, postgres::Error> {
+        let name = format!("sqigl_tmp_{}", rand::random::<u32>());
+        self.db.execute(&format!("create database {}", name), &[])?;
+        self.names.push((name.clone(), version.clone()));
+        Ok(name)
+    }
+}
+impl Drop for TempDatabases<'_> {
+    fn drop(&mut self) {
+        for (name, version) in self.names.iter() {
+            if self.keep {
+                warn!("Keeping temporary database {} ({})", name, version);
+            } else if let Err(e) = self.db.execute(&format!("drop database {}", name), &[]) {
+                error!("Failed to drop temporary database {}: {}", name, e);
+            }
+        }
+    }
+}
+
 impl Backend for PostgresBackend {
     type Error = postgres::Error;
 
     fn install(&mut self) -> Result<SqiglState, Self::Error> {
         info!("Installing sqigl onto database.");
+        if let Some(tenant) = self.tenant.clone() {
+            self.create_schema(&tenant)?;
+        }
+        let internal = &self.internal;
         let mut tx = self.db.transaction()?;
-        tx.batch_execute(include_str!("sql/schema.sql"))?;
-        tx.execute(include_str!("sql/initialize_state.sql"), &[&SQIGL_VERSION])?;
-        let state = get_state(&mut tx)?;
+        tx.batch_execute(&internal_sql(include_str!("sql/schema.sql"), internal))?;
+        tx.execute(
+            &*internal_sql(include_str!("sql/initialize_state.sql"), internal),
+            &[&SQIGL_VERSION],
+        )?;
+        // sqigl may already have been installed, by an earlier version
+        let mut state = get_state(&mut tx, internal)?;
+        upgrade_schema(&mut tx, internal, &mut state)?;
         tx.commit()?;
         Ok(state)
     }
     fn open(&mut self) -> Result<SqiglState, Self::Error> {
         info!("Opening database.");
-        let state = {
-            if let Ok(state) = get_state(&mut self.db) {
+        let state = match get_state(&mut self.db, &self.internal) {
+            Ok(mut state) => {
+                upgrade_schema(&mut self.db, &self.internal, &mut state)?;
                 state
-            } else {
+            }
+            Err(e) if is_not_installed(&e) => {
                 warn!("sqigl is not installed on this database; installing");
                 self.install()?
             }
+            Err(e) => return Err(e),
         };
+        if let Some(id) = get_pending(&mut self.db, &self.internal)? {
+            warn!(
+                "Migration {} was interrupted & may be partially applied; no migrations \
+                can be applied until the database is repaired",
+                id
+            );
+        }
 
         debug!(
             "Project Version: {} DB sqigl Version: {}",
@@ -313,51 +1175,109 @@ impl Backend for PostgresBackend {
         Ok(state)
     }
 
-    fn apply<A: Artifact>(
-        &mut self,
-        artifact: &A,
-    ) -> Result<SqiglState, ScriptProcessingError<Self::Error>> {
-        info!("Applying artifact.");
-        struct Consumer<'a> {
-            version: &'a Version,
-            tx: postgres::Transaction<'a>,
+    fn installed(&mut self) -> Result<bool, Self::Error> {
+        match get_state(&mut self.db, &self.internal) {
+            Ok(_) => Ok(true),
+            Err(e) if is_not_installed(&e) => Ok(false),
+            Err(e) => Err(e),
         }
-        impl ScriptConsumer for Consumer<'_> {
-            type Error = postgres::Error;
+    }
 
-            fn accept(&mut self, script: &str) -> Result<(), ScriptProcessingError<Self::Error>> {
-                trace!("Running a script.");
+    fn ping(&mut self) -> Result<Option<String>, Self::Error> {
+        self.db.execute("SELECT 1", &[])?;
+        let version = self
+            .db
+            .query_one("SELECT current_setting('server_version')", &[])?
+            .get(0);
+        Ok(Some(version))
+    }
+
+    fn introspect(&mut self, exclude_schemas: &[String]) -> anyhow::Result<Schema> {
+        let exclude_schemas = self.excluded_schemas(exclude_schemas);
+        introspect(&mut self.db, &exclude_schemas)
+    }
+
+    fn introspect_artifact<A: Artifact>(
+        &mut self,
+        artifact: &A,
+        exclude_schemas: &[String],
+    ) -> anyhow::Result<Schema> {
+        assert!(artifact.compatible(&empty_database_version()));
+        let exclude_schemas = &self.excluded_schemas(exclude_schemas);
+        let mut temp = TempDatabases::new(&mut self.db, false);
+        let name = temp.create(artifact.version())?;
+        // The connection to the temporary database must be closed before it is dropped;
+        // it is closed at the end of this block, before `temp` is dropped.
+        let schema = {
+            let mut db = connect(
+                self.config.clone().dbname(&name),
+                &self.tls,
+                &self.retry,
+                self.setup.as_deref(),
+            )?;
+            db.batch_execute(&artifact.to_string())?;
+            introspect(&mut db, exclude_schemas)?
+        };
+        Ok(schema)
+    }
+
+    fn record(&mut self, id: ContentId, version: &Version) -> Result<(), Self::Error> {
+        info!("Recording {} in the history at version {}.", id, version);
+        // Serialize with other instances, as when applying an artifact
+        let mut tx = self.db.transaction()?;
+        lock_state(&mut tx, &self.internal)?;
+        append_history(&mut tx, &self.internal, id, version)?;
+        set_pending(&mut tx, &self.internal, None)?;
+        tx.commit()
+    }
+    fn pending(&mut self) -> Result<Option<ContentId>, Self::Error> {
+        get_pending(&mut self.db, &self.internal)
+    }
+    fn clear_pending(&mut self) -> Result<(), Self::Error> {
+        info!("Clearing the interrupted migration.");
+        let mut tx = self.db.transaction()?;
+        lock_state(&mut tx, &self.internal)?;
+        set_pending(&mut tx, &self.internal, None)?;
+        tx.commit()
+    }
+
+    fn maintain(
+        &mut self,
+        maintenance: Maintenance,
+    ) -> Result<(), ScriptProcessingError<Self::Error>> {
+        if let Some(pending) = get_pending(&mut self.db, &self.internal)? {
+            error!("Maintenance aborted: A previous migration was interrupted");
+            return Err(ScriptProcessingError::Pending(pending));
+        }
+        // `vacuum` can't run inside a transaction block, so this isn't run in one
+        Ok(self.db.batch_execute(maintenance.as_str())?)
+    }
+
+    fn apply<A: Artifact>(
+        &mut self,
+        artifact: &A,
+        force: bool,
+    ) -> Result<SqiglState, ScriptProcessingError<Self::Error>> {
+        info!("Applying artifact.");
+        struct Consumer<'a> {
+            version: &'a Version,
+            internal: &'a str,
+            session: SessionSettings,
+            tx: postgres::Transaction<'a>,
+        }
+        impl ScriptConsumer for Consumer<'_> {
+            type Error = postgres::Error;
+
+            fn accept(&mut self, script: &str) -> Result<(), ScriptProcessingError<Self::Error>> {
+                trace!("Running a script.");
                 self.tx.batch_execute(script)?;
                 Ok(())
             }
 
             fn commit(mut self, id: ContentId) -> Result<(), ScriptProcessingError<Self::Error>> {
-                // NB: An artifact may be applied multiple times. Our artifact's row may
-                // already exist.
                 trace!("Committing artifact.");
-                let artifact_pk: i64 = self
-                    .tx
-                    .query_one(
-                        include_str!("sql/get_artifact_by_id.sql"),
-                        &[&&id.unwrap().as_slice()],
-                    )?
-                    .get("pk");
-                let prev_pk: Option<i64> = self
-                    .tx
-                    .query_one("select head from sqigl_internal.state", &[])?
-                    .get("head");
-                let head_pk: i64 = self
-                    .tx
-                    .query_one(
-                        include_str!("sql/append_history.sql"),
-                        &[&prev_pk, &artifact_pk, &self.version.to_string()],
-                    )?
-                    .get("pk");
-                let updated = self
-                    .tx
-                    .execute("update sqigl_internal.state set head = $1", &[&head_pk])?;
-                debug_assert!(updated == 1);
-
+                self.session.end(&mut self.tx)?;
+                append_history(&mut self.tx, self.internal, id, self.version)?;
                 self.tx.commit()?;
                 debug!("Artifact transaction committed.");
                 Ok(())
@@ -372,59 +1292,314 @@ impl Backend for PostgresBackend {
         // - All statements are executed in a single transaction
         // - The project version is compatible at the start of the transaction
         debug!("Opening artifact transaction.");
+        let session = self.session.clone();
+        let internal = self.internal.clone();
         let mut tx = self.open_transaction()?; // Sets timeouts
-        tx.execute("select from sqigl_internal.state for update", &[])?;
-        let state = get_state(&mut tx)?;
+        lock_state(&mut tx, &internal)?;
+        let state = get_state(&mut tx, &internal)?;
+        if let Some(pending) = get_pending(&mut tx, &internal)? {
+            error!("Migration aborted: A previous migration was interrupted");
+            return Err(ScriptProcessingError::Pending(pending));
+        }
         if !artifact.compatible(&state.project_version) {
-            error!("Migration aborted: Incompatible");
-            return Err(ScriptProcessingError::Incompatible);
+            if !force {
+                error!("Migration aborted: Incompatible");
+                return Err(ScriptProcessingError::Incompatible);
+            }
+            warn!(
+                "Forcing {} onto incompatible version {}",
+                artifact.print(),
+                state.project_version
+            );
         }
 
+        // The state is locked as the connecting user, who may have privileges the role
+        // doesn't
+        session.begin(&mut tx)?;
         let version = artifact.version();
-        let consumer = Consumer { version, tx };
-        artifact.scripts(consumer)?;
+        let consumer = Consumer {
+            version,
+            internal: &internal,
+            session,
+            tx,
+        };
+        artifact.scripts(Progress::new(consumer))?;
 
         info!("Migration applied.");
         Ok(state)
     }
 
+    fn apply_parallel<A: Artifact>(
+        &mut self,
+        artifact: &A,
+        force: bool,
+        connections: NonZeroUsize,
+    ) -> Result<SqiglState, ScriptProcessingError<Self::Error>> {
+        info!("Applying artifact in parallel.");
+        struct Consumer<'a> {
+            statements: &'a mut Vec<String>,
+        }
+        impl ScriptConsumer for Consumer<'_> {
+            type Error = postgres::Error;
+
+            fn accept(&mut self, script: &str) -> Result<(), ScriptProcessingError<Self::Error>> {
+                self.statements
+                    .extend(split_statements(script).into_iter().map(str::to_string));
+                Ok(())
+            }
+
+            fn commit(self, _id: ContentId) -> Result<(), ScriptProcessingError<Self::Error>> {
+                Ok(())
+            }
+        }
+
+        let mut statements = Vec::new();
+        let id = artifact.scripts(Consumer {
+            statements: &mut statements,
+        })?;
+
+        let internal = self.internal.clone();
+        // The state can't be locked while the statements run, because an open
+        // transaction would block statements such as `create index concurrently`.
+        // Instead, the artifact is marked as pending, & the version is checked again
+        // before recording the artifact. If sqigl is interrupted, the mark remains.
+        let mut tx = self.open_transaction()?; // Sets timeouts
+        lock_state(&mut tx, &internal)?;
+        let state = get_state(&mut tx, &internal)?;
+        if let Some(pending) = get_pending(&mut tx, &internal)? {
+            error!("Migration aborted: A previous migration was interrupted");
+            return Err(ScriptProcessingError::Pending(pending));
+        }
+        if !artifact.compatible(&state.project_version) {
+            if !force {
+                error!("Migration aborted: Incompatible");
+                return Err(ScriptProcessingError::Incompatible);
+            }
+            warn!(
+                "Forcing {} onto incompatible version {}",
+                artifact.print(),
+                state.project_version
+            );
+        }
+        debug!("Marking {} as pending", id);
+        set_pending(&mut tx, &internal, Some(id))?;
+        tx.commit()?;
+
+        let (config, tls, retry) = (self.config.clone(), self.tls.clone(), self.retry);
+        let setup = self.setup.as_deref();
+        let stmt_timeout = self.stmt_timeout;
+        let session = &self.session;
+
+        let workers = connections.get().min(statements.len());
+        info!(
+            "Applying {} statements across {} connections.",
+            statements.len(),
+            workers
+        );
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let work = || -> Result<(), postgres::Error> {
+            let mut db = connect(&config, &tls, &retry, setup)?;
+            if let Some(timeout) = stmt_timeout {
+                db.execute(&format!("set statement_timeout = {}", timeout), &[])?;
+            }
+            session.begin_session(&mut db)?;
+            // Once a statement fails, no more are started
+            while !failed.load(Ordering::Relaxed) {
+                let Some(statement) = statements.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                    break;
+                };
+                trace!("Running a statement.");
+                db.batch_execute(statement)?;
+            }
+            Ok(())
+        };
+        // Every statement must complete before the artifact is recorded in the history
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let result = work();
+                        if result.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        result
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Worker thread panicked"))
+                .collect()
+        });
+        if let Some(e) = results.into_iter().find_map(Result::err) {
+            error!(
+                "Migration failed: Statements which completed remain applied, but {} was \
+                not recorded in the history. It remains marked as pending until the \
+                database is repaired",
+                artifact.print()
+            );
+            return Err(e.into());
+        }
+
+        debug!("Opening artifact transaction.");
+        let mut tx = self.open_transaction()?; // Sets timeouts
+        lock_state(&mut tx, &internal)?;
+        let current = get_state(&mut tx, &internal)?;
+        if current != state {
+            error!(
+                "Migration aborted: The database's version changed from {} to {} while \
+                applying {}",
+                state.project_version,
+                current.project_version,
+                artifact.print()
+            );
+            return Err(ScriptProcessingError::Incompatible);
+        }
+        if get_pending(&mut tx, &internal)? != Some(id) {
+            error!(
+                "Migration aborted: {} stopped being marked as pending while it was applied, \
+                as when the database is repaired concurrently; it was not recorded",
+                artifact.print()
+            );
+            return Err(ScriptProcessingError::Incompatible);
+        }
+        trace!("Committing artifact.");
+        append_history(&mut tx, &internal, id, artifact.version())?;
+        set_pending(&mut tx, &internal, None)?;
+        tx.commit()?;
+        info!("Migration applied.");
+        Ok(state)
+    }
+
+    fn exec(
+        &mut self,
+        script: &str,
+        record: bool,
+    ) -> Result<(), ScriptProcessingError<Self::Error>> {
+        info!("Executing script.");
+        // Serialize with other instances, as when applying an artifact
+        let session = self.session.clone();
+        let internal = self.internal.clone();
+        let mut tx = self.open_transaction()?; // Sets timeouts
+        lock_state(&mut tx, &internal)?;
+        let state = get_state(&mut tx, &internal)?;
+        if record {
+            if let Some(pending) = get_pending(&mut tx, &internal)? {
+                error!(
+                    "Script aborted: A previous migration was interrupted, so it can't be recorded"
+                );
+                return Err(ScriptProcessingError::Pending(pending));
+            }
+        }
+        session.begin(&mut tx)?;
+        trace!("Running a script.");
+        tx.batch_execute(script)?;
+        session.end(&mut tx)?;
+        if record {
+            debug!("Recording script in history.");
+            let id = Sha256::digest(script).into();
+            append_history(&mut tx, &internal, id, &state.project_version)?;
+        }
+        tx.commit()?;
+        info!("Script executed.");
+        Ok(())
+    }
+
+    fn history(&mut self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, Self::Error> {
+        let mut state = get_state(&mut self.db, &self.internal)?;
+        upgrade_schema(&mut self.db, &self.internal, &mut state)?;
+        let (since, until) = filter.bounds();
+        let limit = filter.limit.map(|l| l as i64);
+        self.db
+            .query(
+                &*internal_sql(include_str!("sql/select_history.sql"), &self.internal),
+                &[&since, &until, &limit],
+            )?
+            .into_iter()
+            .map(HistoryEntry::try_from)
+            .collect()
+    }
+
+    fn applied_ids(&mut self) -> Result<Vec<ContentId>, Self::Error> {
+        Ok(self
+            .db
+            .query(
+                &*internal_sql(include_str!("sql/select_applied_ids.sql"), &self.internal),
+                &[],
+            )?
+            .iter()
+            .map(|row| {
+                row.get::<_, Vec<u8>>("id")
+                    .try_into()
+                    .expect("Failed to parse content id")
+            })
+            .collect())
+    }
+
     fn generate_migration<A1: Artifact, A2: Artifact>(
         &mut self,
         from: &A1,
         to: &A2,
-    ) -> anyhow::Result<impl Artifact> {
+        options: &GenerateOptions,
+    ) -> anyhow::Result<impl Artifact + 'static> {
         assert!(from.compatible(&empty_database_version()));
         assert!(to.compatible(&empty_database_version()));
+        if options.if_exists {
+            // DDL is transactional in Postgres, so migrations are never partially applied
+            warn!("Guarding statements with --if-exists is only supported for SQLite; ignoring");
+        }
 
-        let from_db_name = format!("sqigl_tmp_{}", rand::random::<u32>());
-        let to_db_name = format!("sqigl_tmp_{}", rand::random::<u32>());
-        for name in [&from_db_name, &to_db_name] {
-            self.db.execute(&format!("create database {}", name), &[])?;
-        }
-
-        let mut from_db = self
-            .config
-            .clone()
-            .dbname(&from_db_name)
-            .connect(self.tls.clone())?;
-        from_db.batch_execute(&from.to_string())?;
-        let mut to_db = self
-            .config
-            .clone()
-            .dbname(&to_db_name)
-            .connect(self.tls.clone())?;
-        to_db.batch_execute(&to.to_string())?;
-
-        let statements = delta(from_db, to_db)?;
-        for name in [&from_db_name, &to_db_name] {
-            self.db.execute(&format!("drop database {}", name), &[])?;
-        }
-
-        Ok(GeneratedMigration {
-            from: from_minor_version(from.version()),
-            to: to.version().clone(),
-            statements,
-        })
+        let exclude_schemas = &self.excluded_schemas(&options.exclude_schemas);
+        let mut temp = TempDatabases::new(&mut self.db, options.keep_temp);
+        let from_db_name = temp.create(from.version())?;
+        let to_db_name = temp.create(to.version())?;
+
+        // The connections to the temporary databases must be closed before they are
+        // dropped; they are closed at the end of this block, before `temp` is dropped.
+        let migration = {
+            let mut from_db = connect(
+                self.config.clone().dbname(&from_db_name),
+                &self.tls,
+                &self.retry,
+                self.setup.as_deref(),
+            )?;
+            from_db.batch_execute(&from.to_string())?;
+            let mut to_db = connect(
+                self.config.clone().dbname(&to_db_name),
+                &self.tls,
+                &self.retry,
+                self.setup.as_deref(),
+            )?;
+            to_db.batch_execute(&to.to_string())?;
+
+            let to_schema = introspect(&mut to_db, exclude_schemas)?;
+            let migration = GeneratedMigration {
+                from: from_version(from.version(), options.exact),
+                to: to.version().clone(),
+                statements: delta(
+                    &introspect(&mut from_db, exclude_schemas)?,
+                    &to_schema,
+                    &options.protect,
+                ),
+                guarded: false,
+            };
+            if options.verify {
+                debug!("Verifying the generated migration");
+                from_db.batch_execute(&migration.to_string())?;
+                verify_migration(
+                    delta(
+                        &introspect(&mut from_db, exclude_schemas)?,
+                        &to_schema,
+                        &options.protect,
+                    ),
+                    to.version(),
+                )?;
+            }
+            migration
+        };
+
+        Ok(migration)
     }
 
     fn check<A: Artifact>(
@@ -453,18 +1628,64 @@ impl Backend for PostgresBackend {
             }
         }
 
+        let session = self.session.clone();
+        let internal = self.internal.clone();
         let mut tx = self.open_transaction()?; // Sets timeouts
-        let state = get_state(&mut tx)?;
+        let state = get_state(&mut tx, &internal)?;
         if !artifact.compatible(&state.project_version) {
             error!("Migration aborted: Incompatible");
             return Err(ScriptProcessingError::Incompatible);
         }
+        session.begin(&mut tx)?;
 
         let consumer = Consumer { tx };
         artifact.scripts(consumer)?;
 
         Ok(())
     }
+
+    fn check_isolated<A: Artifact>(
+        &mut self,
+        artifact: &A,
+    ) -> Result<(), ScriptProcessingError<Self::Error>> {
+        info!("Checking artifact against a temporary database.");
+        struct Consumer<'a> {
+            db: &'a mut Client,
+        }
+        impl ScriptConsumer for Consumer<'_> {
+            type Error = postgres::Error;
+
+            fn accept(&mut self, script: &str) -> Result<(), ScriptProcessingError<Self::Error>> {
+                trace!("Running a script.");
+                self.db.batch_execute(script)?;
+                Ok(())
+            }
+
+            fn commit(self, _id: ContentId) -> Result<(), ScriptProcessingError<Self::Error>> {
+                trace!("Done checking.");
+                Ok(())
+            }
+        }
+
+        if !artifact.compatible(&empty_database_version()) {
+            error!("Migration aborted: Incompatible");
+            return Err(ScriptProcessingError::Incompatible);
+        }
+        let mut temp = TempDatabases::new(&mut self.db, false);
+        let name = temp.create(artifact.version())?;
+        // The connection to the temporary database must be closed before it is dropped
+        let mut db = connect(
+            self.config.clone().dbname(&name),
+            &self.tls,
+            &self.retry,
+            self.setup.as_deref(),
+        )?;
+        self.session.begin_session(&mut db)?;
+        artifact.scripts(Consumer { db: &mut db })?;
+        drop(db);
+
+        Ok(())
+    }
 }
 impl Default for PostgresBackend {
     fn default() -> Self {
@@ -472,6 +1693,27 @@ impl Default for PostgresBackend {
     }
 }
 
+impl TryFrom<postgres::Row> for HistoryEntry {
+    type Error = postgres::Error;
+
+    fn try_from(row: postgres::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            version: row
+                .try_get::<'_, _, String>("version")?
+                .parse()
+                .expect("Failed to parse semver in version"),
+            content_id: row
+                .try_get::<'_, _, Vec<u8>>("id")?
+                .try_into()
+                .expect("Failed to parse content id"),
+            applied_at: row.try_get("applied_at")?,
+            sqigl_version: row
+                .try_get::<'_, _, Option<String>>("sqigl_version")?
+                .map(|v| v.parse().expect("Failed to parse semver in sqigl_version")),
+        })
+    }
+}
+
 impl TryFrom<postgres::Row> for SqiglState {
     type Error = postgres::Error;
 
@@ -491,3 +1733,783 @@ impl TryFrom<postgres::Row> for SqiglState {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actions::build::build_project, testing::TestProject};
+    use std::collections::BTreeSet;
+
+    /// A connection to the local test database, targeting `tenant`.
+    fn tenant_backend(tenant: &str) -> PostgresBackend {
+        PostgresBackend::local()
+            .unwrap()
+            .with_internal_schema(&format!("{}_{}", DEFAULT_INTERNAL_SCHEMA, tenant))
+            .with_tenant(tenant)
+            .with_session(SessionSettings {
+                role: None,
+                search_path: Some(vec![tenant.to_string()]),
+            })
+    }
+
+    /// Held by tests which create temporary databases, so that they can tell which
+    /// temporary databases are their own.
+    static TEMP_DATABASES: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// The names of the temporary databases on the server.
+    fn temp_databases(db: &mut PostgresBackend) -> BTreeSet<String> {
+        db.db
+            .query(
+                "select datname from pg_database where datname like 'sqigl\\_tmp\\_%'",
+                &[],
+            )
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    fn drop_tenants(tenants: &[&str]) {
+        let mut db = PostgresBackend::local().unwrap();
+        for tenant in tenants {
+            db.db
+                .batch_execute(&format!(
+                    "drop schema if exists {0} cascade; \
+                    drop schema if exists {1}_{0} cascade;",
+                    tenant, DEFAULT_INTERNAL_SCHEMA
+                ))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn session_settings_statements() {
+        let settings = SessionSettings {
+            role: Some("app owner".to_string()),
+            search_path: Some(vec!["app".to_string(), "public".to_string()]),
+        };
+        assert_eq!(
+            settings.statements(true),
+            [
+                "set local search_path to \"app\", \"public\"",
+                "set local role \"app owner\"",
+            ]
+        );
+        assert_eq!(
+            settings.statements(false),
+            [
+                "set search_path to \"app\", \"public\"",
+                "set role \"app owner\""
+            ]
+        );
+        let empty = SessionSettings {
+            role: None,
+            search_path: Some(Vec::new()),
+        };
+        assert_eq!(empty.statements(true), ["set local search_path to ''"]);
+        assert!(SessionSettings::default().statements(true).is_empty());
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn session_settings_are_local() {
+        let mut db = PostgresBackend::local().unwrap();
+        let search_path = |db: &mut Client| -> String {
+            db.query_one("select current_setting('search_path')", &[])
+                .unwrap()
+                .get(0)
+        };
+        let before = search_path(&mut db.db);
+        let settings = SessionSettings {
+            role: Some("sqigl".to_string()),
+            search_path: Some(vec!["sqigl_test_path".to_string()]),
+        };
+        let mut tx = db.db.transaction().unwrap();
+        settings.begin(&mut tx).unwrap();
+        let row = tx
+            .query_one(
+                "select current_setting('search_path'), current_setting('role')",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(row.get::<_, String>(0), "sqigl_test_path");
+        assert_eq!(row.get::<_, String>(1), "sqigl");
+        tx.commit().unwrap();
+        assert_eq!(search_path(&mut db.db), before);
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn runs_session_setup() {
+        let mut db = PostgresBackend::local()
+            .unwrap()
+            .with_session_setup("set application_name = 'sqigl_setup'; set jit = off;".to_string())
+            .unwrap();
+        let row = db
+            .db
+            .query_one(
+                "select current_setting('application_name'), current_setting('jit')",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(row.get::<_, String>(0), "sqigl_setup");
+        assert_eq!(row.get::<_, String>(1), "off");
+
+        let e = PostgresBackend::local()
+            .unwrap()
+            .with_session_setup("set sqigl_no_such_setting = 1;".to_string())
+            .err()
+            .unwrap();
+        assert_eq!(e.code(), Some(&SqlState::UNDEFINED_OBJECT));
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn tenants_are_tracked_independently() {
+        let tenants = ["sqigl_test_tenant_a", "sqigl_test_tenant_b"];
+        drop_tenants(&tenants);
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+
+        let mut a = tenant_backend(tenants[0]);
+        a.install().unwrap();
+        a.apply(&artifact, false).unwrap();
+        assert_eq!(a.open().unwrap().project_version, Version::new(0, 1, 0));
+
+        let mut b = tenant_backend(tenants[1]);
+        assert_eq!(
+            b.install().unwrap().project_version,
+            empty_database_version()
+        );
+        assert_eq!(a.open().unwrap().project_version, Version::new(0, 1, 0));
+        b.apply(&artifact, false).unwrap();
+        for tenant in tenants {
+            let exists: bool =
+                b.db.query_one(
+                    "select to_regclass($1) is not null",
+                    &[&format!("{}.a", tenant)],
+                )
+                .unwrap()
+                .get(0);
+            assert!(exists, "{tenant}.a wasn't created");
+        }
+        drop_tenants(&tenants);
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn interrupted_parallel_apply() {
+        let tenant = "sqigl_test_interrupted";
+        drop_tenants(&[tenant]);
+        let mut db = tenant_backend(tenant);
+        db.install().unwrap();
+        let project = TestProject::new("0.1.0");
+        // The second statement fails, leaving the first applied
+        project.write(
+            "src/a.sql",
+            "create table a(x int);\ncreate table a(x int);",
+        );
+        let artifact = build_project(&project.info()).unwrap();
+        let connections = NonZeroUsize::new(1).unwrap();
+        assert!(db.apply_parallel(&artifact, false, connections).is_err());
+
+        let id = db.pending().unwrap().expect("The migration isn't pending");
+        let is_pending = |result: Result<(), ScriptProcessingError<_>>| match result {
+            Err(ScriptProcessingError::Pending(pending)) => pending == id,
+            _ => false,
+        };
+        assert!(is_pending(db.apply(&artifact, false).map(|_| ())));
+        assert!(is_pending(
+            db.apply_parallel(&artifact, false, connections).map(|_| ())
+        ));
+        assert!(is_pending(db.exec("select 1", true)));
+        assert!(is_pending(db.maintain(Maintenance::Analyze)));
+        // Scripts may still be executed to repair the database
+        db.exec("drop table a", false).unwrap();
+        assert_eq!(db.history(&Default::default()).unwrap().len(), 0);
+
+        db.clear_pending().unwrap();
+        project.write("src/a.sql", "create table a(x int);");
+        db.apply(&build_project(&project.info()).unwrap(), false)
+            .unwrap();
+        assert_eq!(db.open().unwrap().project_version, Version::new(0, 1, 0));
+        drop_tenants(&[tenant]);
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn filters_history() {
+        let tenant = "sqigl_test_history";
+        drop_tenants(&[tenant]);
+        let mut db = tenant_backend(tenant);
+        db.install().unwrap();
+        let versions = ["0.1.0", "0.2.0-payments", "0.2.0", "0.2.1+build", "0.10.0"];
+        for (i, version) in versions.iter().enumerate() {
+            db.record(
+                ContentId::try_from(vec![i as u8; 32]).unwrap(),
+                &version.parse().unwrap(),
+            )
+            .unwrap();
+        }
+        let mut history = |since: Option<&str>, until: Option<&str>, limit: Option<usize>| {
+            let filter = HistoryFilter {
+                since: since.map(|v| v.parse().unwrap()),
+                until: until.map(|v| v.parse().unwrap()),
+                limit,
+            };
+            db.history(&filter)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.version.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(history(None, None, None).len(), 5);
+        // Versions are compared by precedence rather than as strings, so 0.10.0 is the
+        // latest & a prerelease precedes its release
+        assert_eq!(
+            history(Some("0.2.0"), None, None),
+            ["0.10.0", "0.2.1+build", "0.2.0"]
+        );
+        assert_eq!(
+            history(None, Some("0.2.0"), None),
+            ["0.2.0", "0.2.0-payments", "0.1.0"]
+        );
+        assert_eq!(
+            history(Some("0.2.0-payments"), Some("0.2.1"), None),
+            ["0.2.1+build", "0.2.0", "0.2.0-payments"]
+        );
+        assert_eq!(history(Some("0.2.0"), None, Some(1)), ["0.10.0"]);
+        assert!(history(Some("1.0.0"), None, None).is_empty());
+        drop_tenants(&[tenant]);
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn keyword_internal_schema() {
+        let mut db = PostgresBackend::local()
+            .unwrap()
+            .with_internal_schema("select");
+        db.db
+            .batch_execute(r#"drop schema if exists "select" cascade"#)
+            .unwrap();
+        db.install().unwrap();
+        assert_eq!(db.open().unwrap().project_version, empty_database_version());
+        db.record(
+            ContentId::try_from(vec![0; 32]).unwrap(),
+            &Version::new(0, 1, 0),
+        )
+        .unwrap();
+        assert_eq!(db.history(&Default::default()).unwrap().len(), 1);
+        db.db
+            .batch_execute(r#"drop schema "select" cascade"#)
+            .unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn migrates_columns() {
+        use crate::actions::build::FileArtifact;
+
+        let project = TestProject::new("0.2.0");
+        project
+            .write(
+                "0.1.0.sql",
+                "-- [ test 0.1.0 ]\n\
+                create table t(a int primary key, b text, c int default 1, d int, \
+                    e int generated always as (a * 2) stored, g int);\n\
+                create index t_b on t(b);\n",
+            )
+            .write(
+                "0.2.0.sql",
+                "-- [ test 0.2.0 ]\n\
+                create table t(a int primary key, b varchar(10) not null, c int, \
+                    d int generated by default as identity, \
+                    e int generated always as (a * 3) stored, f text default 'x', \
+                    constraint t_c_check check (c > 0));\n\
+                create index t_b on t(b, a);\n",
+            );
+        let from = FileArtifact::open(&project.path().join("0.1.0.sql")).unwrap();
+        let to = FileArtifact::open(&project.path().join("0.2.0.sql")).unwrap();
+        let options = GenerateOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let _temp = TEMP_DATABASES.lock().unwrap();
+        let migration = PostgresBackend::local()
+            .unwrap()
+            .generate_migration(&from, &to, &options)
+            .unwrap()
+            .to_string();
+        assert!(
+            migration.contains("ALTER TABLE public.t DROP COLUMN g;"),
+            "{migration}"
+        );
+        assert!(
+            migration.contains("ALTER TABLE public.t ALTER COLUMN b TYPE character varying(10);"),
+            "{migration}"
+        );
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn excludes_schemas() {
+        use crate::actions::build::FileArtifact;
+
+        let project = TestProject::new("0.2.0");
+        project
+            .write("0.1.0.sql", "-- [ test 0.1.0 ]\ncreate table t(a int);\n")
+            .write(
+                "0.2.0.sql",
+                "-- [ test 0.2.0 ]\ncreate table t(a int);\n\
+                create schema audit;\ncreate table audit.log(a int);\n",
+            );
+        let from = FileArtifact::open(&project.path().join("0.1.0.sql")).unwrap();
+        let to = FileArtifact::open(&project.path().join("0.2.0.sql")).unwrap();
+        let _temp = TEMP_DATABASES.lock().unwrap();
+        let plan = |exclude_schemas: &[&str]| -> Vec<(&'static str, Option<String>)> {
+            let options = GenerateOptions {
+                exclude_schemas: exclude_schemas.iter().map(|s| s.to_string()).collect(),
+                verify: true,
+                ..Default::default()
+            };
+            PostgresBackend::local()
+                .unwrap()
+                .generate_migration(&from, &to, &options)
+                .unwrap()
+                .plan()
+                .unwrap()
+                .into_iter()
+                .map(|stmt| (stmt.operation, stmt.object))
+                .collect()
+        };
+
+        assert!(plan(&["audit"]).is_empty());
+        assert!(plan(&[]).contains(&("create_table", Some("audit.log".to_string()))));
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn introspects_keys() {
+        let mut db = PostgresBackend::local().unwrap();
+        db.db
+            .batch_execute(
+                "drop schema if exists sqigl_test_pk cascade; \
+                create schema sqigl_test_pk; \
+                create table sqigl_test_pk.s(x int primary key); \
+                create table sqigl_test_pk.t(a int, b int references sqigl_test_pk.s, c int, \
+                    primary key (c, a));",
+            )
+            .unwrap();
+        let schema = db.introspect(&[]).unwrap();
+        let table = &schema.tables[&("sqigl_test_pk".to_string(), "t".to_string())];
+        let positions: Vec<_> = table.columns.iter().map(|c| c.primary_key).collect();
+        assert_eq!(positions, vec![2, 0, 1]);
+        assert_eq!(
+            table.references,
+            std::collections::BTreeSet::from([("sqigl_test_pk".to_string(), "s".to_string())])
+        );
+        db.db
+            .batch_execute("drop schema sqigl_test_pk cascade")
+            .unwrap();
+    }
+
+    #[test]
+    fn invalid_backoff() {
+        for backoff in [f32::INFINITY, f32::NAN, -1., 1e30] {
+            let params = PostgresDatabase {
+                connect_backoff: Some(backoff),
+                ..Default::default()
+            };
+            assert!(RetryPolicy::get(&params).is_err(), "{backoff} was accepted");
+        }
+        let params = PostgresDatabase {
+            connect_backoff: Some(2.),
+            ..Default::default()
+        };
+        assert_eq!(
+            RetryPolicy::get(&params).unwrap().backoff,
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn info_redacts_password_command() {
+        let params = PostgresDatabase {
+            password_command: Some("vault read -field=password --token=secret db".to_string()),
+            ..Default::default()
+        };
+        let info = ConnectionParams::resolve(&params).unwrap().to_string();
+        assert!(!info.contains("secret"), "{info}");
+        assert!(
+            info.contains("password command [ redacted ] (from manifest)"),
+            "{info}"
+        );
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn not_installed_errors() {
+        let mut db = PostgresBackend::local().unwrap();
+        let missing = db
+            .db
+            .batch_execute("select * from sqigl_test_missing")
+            .unwrap_err();
+        assert!(is_not_installed(&missing), "{missing}");
+        let schema = db
+            .db
+            .batch_execute("create table sqigl_test_missing.a(x integer)")
+            .unwrap_err();
+        assert!(is_not_installed(&schema), "{schema}");
+
+        let mut tx = db.db.transaction().unwrap();
+        // Rolled back with the transaction, along with the role.
+        tx.batch_execute(
+            "create table sqigl_test_private(x integer); \
+            create role sqigl_test_unprivileged; \
+            set local role sqigl_test_unprivileged;",
+        )
+        .unwrap();
+        let denied = tx
+            .batch_execute("select * from sqigl_test_private")
+            .unwrap_err();
+        assert_eq!(denied.code(), Some(&SqlState::INSUFFICIENT_PRIVILEGE));
+        assert!(!is_not_installed(&denied), "{denied}");
+    }
+
+    #[test]
+    fn retries_transient_connection_failures() {
+        use std::io::{Read, Write};
+
+        // A "server" which is starting up, counting connection attempts
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let attempts = std::sync::Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut len = [0; 4];
+                stream.read_exact(&mut len).unwrap();
+                let mut startup = vec![0; u32::from_be_bytes(len) as usize - 4];
+                stream.read_exact(&mut startup).unwrap();
+
+                let fields = b"SFATAL\0C57P03\0Mthe database system is starting up\0\0";
+                let mut response = vec![b'E'];
+                response.extend((fields.len() as u32 + 4).to_be_bytes());
+                response.extend(fields);
+                stream.write_all(&response).unwrap();
+            }
+        });
+
+        let mut config = postgres::Config::new();
+        config
+            .host("127.0.0.1")
+            .port(port)
+            .user("sqigl")
+            .ssl_mode(postgres::config::SslMode::Disable);
+        let tls = MakeTlsConnector::new(TlsConnector::new().unwrap());
+        let retry = RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let Err(e) = connect(&config, &tls, &retry, None) else {
+            panic!("Connected to a server which hangs up");
+        };
+        assert!(is_transient(&e), "{e}");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn exec_commits_scripts() {
+        let tenant = "sqigl_test_exec";
+        drop_tenants(&[tenant]);
+        let mut db = tenant_backend(tenant);
+        // Installing creates the tenant's schema
+        db.install().unwrap();
+        let count = |db: &mut PostgresBackend| -> i64 {
+            db.db
+                .query_one(&format!("select count(*) from {tenant}.t"), &[])
+                .unwrap()
+                .get(0)
+        };
+
+        db.exec(
+            &format!(
+                "create temp table staging on commit drop as select 1 as x;\n\
+                create table {tenant}.t as select x from staging;"
+            ),
+            false,
+        )
+        .unwrap();
+        assert_eq!(count(&mut db), 1);
+        assert!(db.history(&Default::default()).unwrap().is_empty());
+
+        // A failing script is rolled back
+        let script = format!("insert into {tenant}.t values (2);");
+        assert!(db.exec(&format!("{script} selec"), true).is_err());
+        assert_eq!(count(&mut db), 1);
+
+        db.exec(&script, true).unwrap();
+        assert_eq!(count(&mut db), 2);
+        let history = db.history(&Default::default()).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].content_id, Sha256::digest(&script).into());
+        drop_tenants(&[tenant]);
+    }
+
+    #[test]
+    fn environment_overrides_manifest() {
+        // No other test resolves these variables
+        env::set_var(DATABASE_ENVVAR, "from_environment");
+        env::set_var(USERNAME_ENVVAR, "");
+        let params = PostgresDatabase {
+            database: Some("from_manifest".to_string()),
+            username: Some("sqigl".to_string()),
+            hostname: Some("db.example.com".to_string()),
+            ..Default::default()
+        };
+        let resolved = ConnectionParams::resolve(&params);
+        env::remove_var(DATABASE_ENVVAR);
+        env::remove_var(USERNAME_ENVVAR);
+        let resolved = resolved.unwrap();
+
+        let database = resolved.database.as_ref().unwrap();
+        assert_eq!(database.value, "from_environment");
+        assert!(matches!(
+            database.source,
+            Source::Environment(DATABASE_ENVVAR)
+        ));
+        // An empty variable is still set
+        let username = resolved.username.as_ref().unwrap();
+        assert_eq!(username.value, "");
+        assert!(matches!(
+            username.source,
+            Source::Environment(USERNAME_ENVVAR)
+        ));
+        let hostname = resolved.hostname.as_ref().unwrap();
+        assert_eq!(hostname.value, "db.example.com");
+        assert!(matches!(hostname.source, Source::Manifest));
+        assert!(resolved.port.is_none());
+
+        let info = resolved.to_string();
+        assert!(
+            info.contains("from_environment (from environment variable PGDATABASE)"),
+            "{info}"
+        );
+        assert!(info.contains("db.example.com (from manifest)"), "{info}");
+    }
+
+    #[test]
+    fn sslmodes() {
+        let modes = [
+            SslMode::Disable,
+            SslMode::Prefer,
+            SslMode::Require,
+            SslMode::VerifyCa,
+            SslMode::VerifyFull,
+        ];
+        for mode in modes {
+            assert_eq!(mode.as_str().parse::<SslMode>(), Ok(mode));
+        }
+        let e = "verify_full".parse::<SslMode>().unwrap_err();
+        assert!(e.starts_with("Unknown sslmode verify_full"), "{e}");
+
+        let params = |sslmode| PostgresDatabase {
+            hostname: Some("db.example.com".to_string()),
+            port: NonZeroU16::new(5432),
+            database: Some("sqigl".to_string()),
+            username: Some("sqigl".to_string()),
+            password_command: Some("echo password".to_string()),
+            sslmode,
+            ..Default::default()
+        };
+        let expected = [
+            postgres::config::SslMode::Disable,
+            postgres::config::SslMode::Prefer,
+            postgres::config::SslMode::Require,
+            postgres::config::SslMode::Require,
+            postgres::config::SslMode::Require,
+        ];
+        for (mode, expected) in modes.into_iter().zip(expected) {
+            let mut resolved = ConnectionParams::resolve(&params(Some(mode))).unwrap();
+            resolved.resolve_password().unwrap();
+            assert!(resolved.tls().is_ok(), "{mode:?}");
+            assert_eq!(resolved.config().unwrap().get_ssl_mode(), expected);
+        }
+        let resolved = ConnectionParams::resolve(&params(None)).unwrap();
+        assert_eq!(resolved.ssl_mode(), SslMode::Prefer);
+
+        // Connections through a socket never use TLS
+        let socket = PostgresDatabase {
+            socket: Some(PathBuf::from("/run/postgresql")),
+            ..params(Some(SslMode::VerifyFull))
+        };
+        let mut resolved = ConnectionParams::resolve(&socket).unwrap();
+        resolved.resolve_password().unwrap();
+        assert_eq!(
+            resolved.config().unwrap().get_ssl_mode(),
+            postgres::config::SslMode::Disable
+        );
+    }
+
+    #[test]
+    fn sockets_are_chosen_over_tcp() {
+        use postgres::config::Host;
+
+        let tcp = PostgresDatabase {
+            hostname: Some("db.example.com".to_string()),
+            port: NonZeroU16::new(5433),
+            database: Some("sqigl".to_string()),
+            username: Some("sqigl".to_string()),
+            password_command: Some("echo password".to_string()),
+            ..Default::default()
+        };
+        let config = |params: &PostgresDatabase| {
+            let mut resolved = ConnectionParams::resolve(params).unwrap();
+            resolved.resolve_password().unwrap();
+            resolved.config().unwrap()
+        };
+        assert_eq!(
+            config(&tcp).get_hosts(),
+            [Host::Tcp("db.example.com".to_string())]
+        );
+
+        // The socket takes the place of the hostname, & needs no password
+        let socket = PostgresDatabase {
+            socket: Some(PathBuf::from("/run/postgresql")),
+            password_command: None,
+            ..tcp.clone()
+        };
+        let socket = config(&socket);
+        assert_eq!(
+            socket.get_hosts(),
+            [Host::Unix(PathBuf::from("/run/postgresql"))]
+        );
+        assert_eq!(socket.get_ports(), [5433]);
+        assert_eq!(socket.get_password(), None);
+
+        // As with libpq, a hostname which is a path is a socket directory
+        let hostname = PostgresDatabase {
+            hostname: Some("/var/run/postgresql".to_string()),
+            ..tcp
+        };
+        assert_eq!(
+            config(&hostname).get_hosts(),
+            [Host::Unix(PathBuf::from("/var/run/postgresql"))]
+        );
+    }
+
+    #[test]
+    fn tls_certificates() {
+        let missing = PathBuf::from("/nonexistent/sqigl.pem");
+        let certificate = PostgresDatabase {
+            certificate: Some(missing.clone()),
+            sslmode: Some(SslMode::VerifyCa),
+            ..Default::default()
+        };
+        let e = ConnectionParams::resolve(&certificate)
+            .unwrap()
+            .tls()
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(e.starts_with("Failed to read certificate"), "{e}");
+
+        let client = PostgresDatabase {
+            sslcert: Some(missing),
+            ..Default::default()
+        };
+        let e = ConnectionParams::resolve(&client)
+            .unwrap()
+            .tls()
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            e.starts_with("A client certificate & key must be supplied together"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn keeps_temporary_databases() {
+        use crate::actions::build::FileArtifact;
+
+        let project = TestProject::new("0.2.0");
+        project
+            .write(
+                "0.1.0.sql",
+                "-- [ test 0.1.0 ]\ncreate table a(x integer);\n",
+            )
+            .write(
+                "0.2.0.sql",
+                "-- [ test 0.2.0 ]\ncreate table a(x integer);\nselec;\n",
+            );
+        let from = FileArtifact::open(&project.path().join("0.1.0.sql")).unwrap();
+        let to = FileArtifact::open(&project.path().join("0.2.0.sql")).unwrap();
+        let _temp = TEMP_DATABASES.lock().unwrap();
+        let mut db = PostgresBackend::local().unwrap();
+        let before = temp_databases(&mut db);
+
+        // By default, they're dropped even though generating the migration failed
+        assert!(db
+            .generate_migration(&from, &to, &Default::default())
+            .is_err());
+        assert_eq!(temp_databases(&mut db), before);
+
+        let options = GenerateOptions {
+            keep_temp: true,
+            ..Default::default()
+        };
+        assert!(db.generate_migration(&from, &to, &options).is_err());
+        let kept: Vec<_> = temp_databases(&mut db)
+            .difference(&before)
+            .cloned()
+            .collect();
+        assert_eq!(kept.len(), 2);
+        for name in kept {
+            db.db
+                .batch_execute(&format!("drop database {name}"))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn drops_temporary_databases() {
+        use crate::actions::build::FileArtifact;
+
+        let project = TestProject::new("0.2.0");
+        project
+            .write(
+                "0.1.0.sql",
+                "-- [ test 0.1.0 ]\ncreate table a(x integer);\n",
+            )
+            .write(
+                "0.2.0.sql",
+                "-- [ test 0.2.0 ]\ncreate table b(x integer);\n",
+            )
+            .write("broken.sql", "-- [ test 0.2.0 ]\nselec;\n");
+        let open = |name: &str| FileArtifact::open(&project.path().join(name)).unwrap();
+        let _temp = TEMP_DATABASES.lock().unwrap();
+        let mut db = PostgresBackend::local().unwrap();
+        let before = temp_databases(&mut db);
+
+        let options = GenerateOptions {
+            verify: true,
+            ..Default::default()
+        };
+        db.generate_migration(&open("0.1.0.sql"), &open("0.2.0.sql"), &options)
+            .unwrap();
+        assert_eq!(temp_databases(&mut db), before);
+        db.introspect_artifact(&open("0.2.0.sql"), &[]).unwrap();
+        assert_eq!(temp_databases(&mut db), before);
+        assert!(db.introspect_artifact(&open("broken.sql"), &[]).is_err());
+        assert_eq!(temp_databases(&mut db), before);
+    }
+}
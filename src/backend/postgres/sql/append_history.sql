@@ -1,3 +1,3 @@
-insert into sqigl_internal.history(prev, artifact, version)
-values ($1, $2, $3)
+insert into "{internal}".history(prev, artifact, version, sqigl_version)
+values ($1, $2, $3, $4)
 returning pk
@@ -1,3 +1,3 @@
-insert into sqigl_internal.artifacts(id) values ($1)
+insert into "{internal}".artifacts(id) values ($1)
 on conflict do nothing
 returning pk
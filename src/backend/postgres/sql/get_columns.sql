@@ -0,0 +1,20 @@
+select
+    quote_ident(a.attname) as name,
+    format_type(a.atttypid, a.atttypmod) as type,
+    a.attnotnull as not_null,
+    pg_get_expr(d.adbin, d.adrelid) as default,
+    a.attidentity::text as identity,
+    a.attgenerated::text as generated,
+    -- indkey is an int2vector, which is indexed from 0
+    coalesce((
+        select array_position(i.indkey::int2[], a.attnum) + 1
+        from pg_index as i
+        where i.indrelid = a.attrelid and i.indisprimary
+    ), 0) as primary_key
+from pg_attribute as a left join pg_attrdef as d
+on a.attrelid = d.adrelid and a.attnum = d.adnum
+where
+    a.attrelid = ($1::text || '.' || $2::text)::regclass and
+    a.attnum > 0 and
+    not a.attisdropped
+order by a.attnum;
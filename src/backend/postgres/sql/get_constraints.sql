@@ -0,0 +1,9 @@
+select
+    quote_ident(conname) as name,
+    pg_get_constraintdef(oid) as definition,
+    contype = 'f' as foreign
+from pg_constraint
+where
+    conrelid = ($1::text || '.' || $2::text)::regclass and
+    contype in ('p', 'u', 'c', 'f', 'x')
+order by contype = 'f', conname;
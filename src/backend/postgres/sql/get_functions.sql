@@ -0,0 +1,19 @@
+-- Functions & procedures, excluding those which belong to extensions
+select
+    quote_ident(n.nspname) as schema,
+    quote_ident(p.proname) as name,
+    pg_get_function_identity_arguments(p.oid) as arguments,
+    p.prokind = 'p' as procedure,
+    pg_get_function_result(p.oid) as result,
+    pg_get_functiondef(p.oid) as definition
+from pg_proc as p join pg_namespace as n on p.pronamespace = n.oid
+where
+    p.prokind in ('f', 'p') and
+    n.nspname not like 'pg\_%' and
+    n.nspname != 'information_schema' and
+    n.nspname != all($1::text[]) and
+    not exists (
+        select from pg_depend as d
+        where d.classid = 'pg_proc'::regclass and d.objid = p.oid and d.deptype = 'e'
+    )
+order by n.nspname, p.proname;
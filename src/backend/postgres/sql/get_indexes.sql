@@ -0,0 +1,10 @@
+-- Indexes which are not created implicitly by a constraint
+select pg_get_indexdef(i.indexrelid) as definition
+from pg_index as i
+where
+    i.indrelid = ($1::text || '.' || $2::text)::regclass and
+    not exists (
+        select from pg_constraint as c
+        where c.conindid = i.indexrelid and c.contype in ('p', 'u', 'x')
+    )
+order by i.indexrelid;
@@ -0,0 +1,8 @@
+select distinct quote_ident(n.nspname) as schema, quote_ident(c.relname) as name
+from pg_constraint as k
+join pg_class as c on k.confrelid = c.oid
+join pg_namespace as n on c.relnamespace = n.oid
+where
+    k.conrelid = ($1::text || '.' || $2::text)::regclass and
+    k.contype = 'f' and
+    k.confrelid != k.conrelid;
@@ -0,0 +1,7 @@
+select quote_ident(nspname) as name
+from pg_namespace
+where
+    nspname not like 'pg\_%' and
+    nspname != 'information_schema' and
+    nspname != all($1::text[])
+order by nspname;
@@ -0,0 +1,34 @@
+-- Sequences, excluding those created implicitly by identity columns
+select
+    quote_ident(n.nspname) as schema,
+    quote_ident(c.relname) as name,
+    format_type(s.seqtypid, null) as data_type,
+    s.seqincrement as increment,
+    s.seqmin as min_value,
+    s.seqmax as max_value,
+    s.seqstart as start_value,
+    s.seqcache as cache,
+    s.seqcycle as cycle,
+    quote_ident(tn.nspname) || '.' || quote_ident(t.relname) || '.' || quote_ident(a.attname)
+        as owned_by
+from pg_class as c
+join pg_namespace as n on c.relnamespace = n.oid
+join pg_sequence as s on s.seqrelid = c.oid
+left join pg_depend as d on
+    d.classid = 'pg_class'::regclass and
+    d.objid = c.oid and
+    d.refclassid = 'pg_class'::regclass and
+    d.deptype = 'a'
+left join pg_class as t on t.oid = d.refobjid
+left join pg_namespace as tn on tn.oid = t.relnamespace
+left join pg_attribute as a on a.attrelid = d.refobjid and a.attnum = d.refobjsubid
+where
+    c.relkind = 'S' and
+    n.nspname not like 'pg\_%' and
+    n.nspname != 'information_schema' and
+    n.nspname != all($1::text[]) and
+    not exists (
+        select from pg_depend as i
+        where i.classid = 'pg_class'::regclass and i.objid = c.oid and i.deptype = 'i'
+    )
+order by n.nspname, c.relname;
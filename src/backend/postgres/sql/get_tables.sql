@@ -1,3 +1,9 @@
-select table_schema, table_name
-from information_schema.tables 
-where table_schema not in ('information_schema', 'pg_catalog', 'pg_toast');
+select quote_ident(n.nspname) as schema, quote_ident(c.relname) as name
+from pg_class as c join pg_namespace as n on c.relnamespace = n.oid
+where
+    c.relkind in ('r', 'p') and
+    not c.relispartition and
+    n.nspname not like 'pg\_%' and
+    n.nspname != 'information_schema' and
+    n.nspname != all($1::text[])
+order by n.nspname, c.relname;
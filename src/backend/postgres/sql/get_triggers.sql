@@ -0,0 +1,14 @@
+select
+    quote_ident(n.nspname) as schema,
+    quote_ident(c.relname) as table_name,
+    quote_ident(t.tgname) as name,
+    pg_get_triggerdef(t.oid) as definition
+from pg_trigger as t
+join pg_class as c on t.tgrelid = c.oid
+join pg_namespace as n on c.relnamespace = n.oid
+where
+    not t.tgisinternal and
+    n.nspname not like 'pg\_%' and
+    n.nspname != 'information_schema' and
+    n.nspname != all($1::text[])
+order by n.nspname, c.relname, t.tgname;
@@ -0,0 +1,19 @@
+-- Pairs of views (or materialized views) where the first selects from the second
+select distinct
+    quote_ident(n.nspname) as schema,
+    quote_ident(c.relname) as name,
+    quote_ident(dn.nspname) as dependency_schema,
+    quote_ident(dc.relname) as dependency_name
+from pg_class as c
+join pg_namespace as n on c.relnamespace = n.oid
+join pg_rewrite as r on r.ev_class = c.oid
+join pg_depend as d on
+    d.classid = 'pg_rewrite'::regclass and
+    d.objid = r.oid and
+    d.refclassid = 'pg_class'::regclass
+join pg_class as dc on dc.oid = d.refobjid
+join pg_namespace as dn on dc.relnamespace = dn.oid
+where
+    c.relkind in ('v', 'm') and
+    dc.relkind in ('v', 'm') and
+    dc.oid != c.oid;
@@ -0,0 +1,12 @@
+select
+    quote_ident(n.nspname) as schema,
+    quote_ident(c.relname) as name,
+    c.relkind = 'm' as materialized,
+    pg_get_viewdef(c.oid) as definition
+from pg_class as c join pg_namespace as n on c.relnamespace = n.oid
+where
+    c.relkind in ('v', 'm') and
+    n.nspname not like 'pg\_%' and
+    n.nspname != 'information_schema' and
+    n.nspname != all($1::text[])
+order by n.nspname, c.relname;
@@ -1 +1 @@
-insert into sqigl_internal.state(sqigl_version) values ($1) on conflict do nothing
+insert into "{internal}".state(sqigl_version) values ($1) on conflict do nothing
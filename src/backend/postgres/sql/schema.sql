@@ -1,7 +1,7 @@
-create schema if not exists sqigl_internal;
+create schema if not exists "{internal}";
 
 -- Built artifacts which have been applied to the database
-create table if not exists sqigl_internal.artifacts (
+create table if not exists "{internal}".artifacts (
     pk bigint primary key generated always as identity,
     id bytea not null, -- SHA256(content)
     created_at timestamptz not null default now(),
@@ -10,25 +10,29 @@ create table if not exists sqigl_internal.artifacts (
 );
 
 -- Performs better than unique/btree index on uniformly random data
-create index if not exists id_idx on sqigl_internal.artifacts using hash (id);
+create index if not exists id_idx on "{internal}".artifacts using hash (id);
 
 -- Tree/persistent list of operations applied to this database
-create table if not exists sqigl_internal.history (
+create table if not exists "{internal}".history (
     pk bigint primary key generated always as identity,
-    prev bigint references sqigl_internal.history(pk), -- head before we applied change
-    artifact bigint not null references sqigl_internal.artifacts(pk),
+    prev bigint references "{internal}".history(pk), -- head before we applied change
+    artifact bigint not null references "{internal}".artifacts(pk),
     created_at timestamptz not null default now(),
     updated_at timestamptz,
     version text not null, -- semver of the sqigl project after artifact applied
-    remarks text
+    remarks text,
+    sqigl_version text -- semver of the sqigl binary which applied artifact
 );
 
 -- Current database state
-create table if not exists sqigl_internal.state (
+create table if not exists "{internal}".state (
     -- Ensure there is at most 1 row
     pk integer primary key generated always as (0) stored,
     created_at timestamptz not null default now(),
     updated_at timestamptz,
-    head bigint references sqigl_internal.history(pk), -- last change applied
-    sqigl_version text not null -- semver of the sqigl binary which installed db
+    head bigint references "{internal}".history(pk), -- last change applied
+    sqigl_version text not null, -- semver of the sqigl binary which installed db
+    -- SHA256 of a migration being applied outside of a transaction; if it is set while
+    -- no migration is running, the migration was interrupted
+    pending_artifact bytea
 );
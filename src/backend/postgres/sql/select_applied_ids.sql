@@ -0,0 +1,3 @@
+select distinct a.id
+from "{internal}".history as h
+join "{internal}".artifacts as a on a.pk = h.artifact
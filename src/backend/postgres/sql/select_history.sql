@@ -0,0 +1,37 @@
+-- Walk the history from the head, so that only the changes leading to the current
+-- state are included
+with recursive chain as (
+    select h.pk, h.prev, h.artifact, h.version, h.sqigl_version, h.created_at
+    from "{internal}".history as h
+    join "{internal}".state as s on s.head = h.pk
+    union all
+    select h.pk, h.prev, h.artifact, h.version, h.sqigl_version, h.created_at
+    from "{internal}".history as h
+    join chain as c on h.pk = c.prev
+),
+-- The precedence of each version & of the bounds ($1 & $2), in the order in which
+-- they are compared: A release follows its prereleases, & build metadata is ignored
+keys as (
+    select versions.version,
+        m[1]::numeric as major, m[2]::numeric as minor, m[3]::numeric as patch,
+        m[4] is null as released, coalesce(m[4], '') collate "C" as pre
+    from (
+        select version from chain union select $1::text union select $2::text
+    ) as versions,
+    regexp_match(versions.version, '^(\d+)\.(\d+)\.(\d+)(?:-([^+]+))?') as m
+)
+select c.version, c.sqigl_version, a.id,
+    to_char(c.created_at at time zone 'utc', 'YYYY-MM-DD HH24:MI:SS') as applied_at
+from chain as c
+join "{internal}".artifacts as a on a.pk = c.artifact
+join keys as k on k.version = c.version
+left join keys as since on since.version = $1::text
+left join keys as until on until.version = $2::text
+where ($1::text is null
+    or (k.major, k.minor, k.patch, k.released, k.pre)
+        >= (since.major, since.minor, since.patch, since.released, since.pre))
+and ($2::text is null
+    or (k.major, k.minor, k.patch, k.released, k.pre)
+        <= (until.major, until.minor, until.patch, until.released, until.pre))
+order by c.pk desc
+limit $3
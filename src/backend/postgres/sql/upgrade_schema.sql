@@ -0,0 +1,24 @@
+-- Bring the schema of databases installed by earlier versions of sqigl up to date.
+-- Columns are only added if they're missing, to avoid locking the tables otherwise.
+do $$
+begin
+    if not exists (
+        select from information_schema.columns
+        where
+            table_schema = '{internal}' and
+            table_name = 'history' and
+            column_name = 'sqigl_version'
+    ) then
+        alter table "{internal}".history add column sqigl_version text;
+    end if;
+    if not exists (
+        select from information_schema.columns
+        where
+            table_schema = '{internal}' and
+            table_name = 'state' and
+            column_name = 'pending_artifact'
+    ) then
+        alter table "{internal}".state add column pending_artifact bytea;
+    end if;
+end
+$$;
@@ -1,8 +1,15 @@
-use std::{collections::HashSet, fmt::Write};
+use std::{collections::BTreeSet, fmt::Write};
 
 use crate::{
-    backend::{Backend, GeneratedMigration, SqlStatement},
-    util::{empty_database_version, from_minor_version},
+    backend::{
+        internal_sql,
+        introspection::{
+            dependencies_among, index_name, strip_keyword, Column, ColumnDefault, QualifiedName,
+            Schema, Table,
+        },
+        Backend, GeneratedMigration, SqlStatement,
+    },
+    util::{empty_database_version, from_minor_version, is_protected},
     Artifact,
 };
 use log::info;
@@ -12,49 +19,336 @@ use sha2::{Digest as _, Sha256};
 
 use super::SqliteBackend;
 
+/// The schema that migrations are generated from: the tables of the main database, &
+/// their indexes. Indexes created implicitly by constraints are excluded.
+pub fn introspect(db: &mut Connection, internal: &str) -> anyhow::Result<Schema> {
+    let mut schema = Schema::default();
+    for name in get_table_names(db, internal)? {
+        let code = db
+            .prepare_cached(include_str!("sql/get_table_code.sql"))?
+            .query_row([&name], |row| row.get::<_, String>(0))?;
+        let indexes = db
+            .prepare_cached(include_str!("sql/get_indexes.sql"))?
+            .query_map([&name], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let references = db
+            .prepare_cached(include_str!("sql/get_referenced_tables.sql"))?
+            .query_map([&name], |row| Ok(("main".to_string(), row.get(0)?)))?
+            .collect::<Result<_, _>>()?;
+        let table = Table {
+            columns: get_columns(db, &name)?,
+            constraints: Vec::new(),
+            references,
+            code: Some(code),
+        };
+        let name = ("main".to_string(), name);
+        schema.indexes.insert(name.clone(), indexes);
+        schema.tables.insert(name, table);
+    }
+
+    Ok(schema)
+}
+
+/// Generate the statements to migrate a database with schema `from` to schema `to`. If
+/// `detect_renames` is set, a table which was dropped is renamed to a table which was
+/// created instead, when they are the only tables dropped & created and their columns
+/// are identical. Tables matching `protect` are never dropped, nor are the deleted tables
+/// which they refer to. Indexes are created along with their tables, & are dropped &
+/// recreated when they have changed on a table which exists in both schemas; deleted
+/// indexes are kept when they or their table match `protect`. Renamed tables keep their
+/// indexes. Tables which exist in both schemas but have changed are rebuilt.
 pub fn delta(
-    mut from_db: rusqlite::Connection,
-    mut to_db: rusqlite::Connection,
-) -> anyhow::Result<Vec<Statement>> {
+    from: &Schema,
+    to: &Schema,
+    detect_renames: bool,
+    protect: &[String],
+) -> Vec<Statement> {
     let mut statements = Vec::default();
 
-    let from_tables = get_table_names(&mut from_db)?;
-    let to_tables = get_table_names(&mut to_db)?;
-    for tbl in from_tables.iter() {
-        if !to_tables.contains(tbl) {
-            info!("Table {} was deleted", tbl);
-            statements.push(Statement::DropTable { name: tbl.clone() })
+    // Deleted tables are kept if they are protected, or if a kept table's foreign keys
+    // refer to them.
+    let deleted: BTreeSet<QualifiedName> = from
+        .tables
+        .keys()
+        .filter(|name| !to.tables.contains_key(*name))
+        .cloned()
+        .collect();
+    let mut kept: BTreeSet<QualifiedName> = deleted
+        .iter()
+        .filter(|(schema, tbl)| {
+            let protected = is_protected(protect, Some(schema), tbl);
+            if protected {
+                info!(
+                    "Table {} was deleted, but is protected; not dropping it",
+                    tbl
+                );
+            }
+            protected
+        })
+        .cloned()
+        .collect();
+    for name in dependencies_among(&kept, &deleted, |name| {
+        from.tables.get(name).map(|table| &table.references)
+    }) {
+        info!(
+            "Table {} was deleted, but a protected table refers to it; not dropping it",
+            name.1
+        );
+        kept.insert(name);
+    }
+    let mut dropped: Vec<_> = deleted.difference(&kept).collect();
+    let mut created: Vec<_> = to
+        .tables
+        .keys()
+        .filter(|name| !from.tables.contains_key(*name))
+        .collect();
+
+    if detect_renames {
+        if let ([old], [new]) = (dropped.as_slice(), created.as_slice()) {
+            if from.tables[*old].columns == to.tables[*new].columns {
+                info!("Table {} was renamed to {}", old.1, new.1);
+                statements.push(Statement::RenameTable {
+                    old: old.1.clone(),
+                    new: new.1.clone(),
+                });
+                dropped.clear();
+                created.clear();
+            } else {
+                info!(
+                    "Table {} was deleted and {} was created, but their columns differ; \
+                    not treating this as a rename",
+                    old.1, new.1
+                );
+            }
+        } else if !dropped.is_empty() && !created.is_empty() {
+            info!("Multiple tables were deleted or created; not attempting to detect renames");
         }
     }
-    for tbl in to_tables.iter() {
-        if !from_tables.contains(tbl) {
-            let code = to_db
-                .prepare_cached(include_str!("sql/get_table_code.sql"))?
-                .query_row([tbl], |row| Ok(row.get::<_, String>(0)?))?;
-            statements.push(Statement::CreateTable { code })
+
+    // SQLite can't alter most of a table's definition, so tables which have changed are
+    // rebuilt, keeping the values of the columns they still have. Protected tables are
+    // only rebuilt if they keep all of their columns.
+    // Dropping a table drops its indexes, so only the indexes of tables which are kept
+    // are dropped explicitly. Deleted indexes are kept if they or their table are
+    // protected.
+    let mut rebuilt = Vec::default();
+    let mut indexes = Vec::default();
+    for (name, table) in to.tables.iter() {
+        let Some(prev) = from.tables.get(name) else {
+            continue;
+        };
+        let (old, new) = (from.indexes_of(name), to.indexes_of(name));
+        let table_protected = is_protected(protect, Some(&name.0), &name.1);
+        if prev.code != table.code {
+            let (kept, dropped): (Vec<_>, Vec<_>) = prev
+                .columns
+                .iter()
+                .map(|column| column.name.clone())
+                .partition(|column| table.columns.iter().any(|c| &c.name == column));
+            if dropped.is_empty() || !table_protected {
+                info!("Table {} was changed; rebuilding it", name.1);
+                rebuilt.push(Statement::RebuildTable {
+                    name: name.1.clone(),
+                    code: table.code.clone().unwrap_or_default(),
+                    columns: kept,
+                    dropped,
+                });
+                for code in new {
+                    indexes.push(Statement::CreateIndex {
+                        table: name.1.clone(),
+                        code: code.clone(),
+                    });
+                }
+                continue;
+            }
+            info!(
+                "Table {} was changed, but is protected & would lose columns; not rebuilding it",
+                name.1
+            );
+        }
+        for code in old.iter().filter(|code| !new.contains(code)) {
+            if let Some(index) = index_name(code) {
+                let deleted = !new.iter().any(|code| index_name(code) == Some(index));
+                if deleted && (table_protected || is_protected(protect, Some(&name.0), index)) {
+                    info!(
+                        "Index {} of table {} was deleted, but is protected; not dropping it",
+                        index, name.1
+                    );
+                    continue;
+                }
+                info!("An index of table {} was deleted or changed", name.1);
+                statements.push(Statement::DropIndex {
+                    name: index.to_string(),
+                });
+            }
         }
+        for code in new.iter().filter(|code| !old.contains(code)) {
+            info!("An index of table {} was created or changed", name.1);
+            indexes.push(Statement::CreateIndex {
+                table: name.1.clone(),
+                code: code.clone(),
+            });
+        }
+    }
+
+    for (_, tbl) in dropped.iter() {
+        info!("Table {} was deleted", tbl);
+        statements.push(Statement::DropTable { name: tbl.clone() })
     }
+    statements.extend(rebuilt);
+    for name in created.iter() {
+        statements.push(Statement::CreateTable {
+            name: name.1.clone(),
+            code: to.tables[*name].code.clone().unwrap_or_default(),
+        });
+        for code in to.indexes_of(name) {
+            indexes.push(Statement::CreateIndex {
+                table: name.1.clone(),
+                code: code.clone(),
+            });
+        }
+    }
+    statements.extend(indexes);
 
-    Ok(statements)
+    statements
 }
 
-fn get_table_names(db: &mut Connection) -> anyhow::Result<HashSet<String>> {
-    let mut output = HashSet::default();
+fn get_table_names(db: &mut Connection, internal: &str) -> anyhow::Result<Vec<String>> {
+    let mut output = Vec::default();
     for name_res in db
-        .prepare(include_str!("sql/table_names.sql"))?
-        .query_map([], |row| Ok(row.get::<_, String>(0)?))?
+        .prepare(&internal_sql(include_str!("sql/table_names.sql"), internal))?
+        .query_map([], |row| row.get::<_, String>(0))?
+    {
+        output.push(name_res?);
+    }
+
+    Ok(output)
+}
+
+/// Columns as described by `PRAGMA table_info`.
+fn get_columns(db: &mut Connection, table: &str) -> anyhow::Result<Vec<Column>> {
+    let mut output = Vec::default();
+    for column_res in db
+        .prepare_cached(include_str!("sql/get_columns.sql"))?
+        .query_map([table], |row| {
+            Ok(Column {
+                name: row.get(0)?,
+                data_type: row.get(1)?,
+                not_null: row.get(2)?,
+                default: row
+                    .get::<_, Option<String>>(3)?
+                    .map(ColumnDefault::Expression),
+                primary_key: row.get(4)?,
+            })
+        })?
     {
-        output.insert(name_res?);
+        output.push(column_res?);
     }
 
     Ok(output)
 }
 
+/// Add `IF NOT EXISTS` to a `CREATE TABLE` or `CREATE INDEX` statement as stored in
+/// `sqlite_schema`, unless it already has it.
+fn guard_create(code: &str) -> String {
+    let Some(rest) = strip_keyword(code, "create") else {
+        return code.to_string();
+    };
+    let rest = strip_keyword(rest, "unique").unwrap_or(rest);
+    let Some(name) = strip_keyword(rest, "table").or_else(|| strip_keyword(rest, "index")) else {
+        return code.to_string();
+    };
+    if strip_keyword(name, "if").is_some() {
+        return code.to_string();
+    }
+    let split = code.len() - name.len();
+    format!("{}IF NOT EXISTS {}", &code[..split], name)
+}
+
+#[allow(clippy::enum_variant_names)]
 pub enum Statement {
-    DropTable { name: String },
-    CreateTable { code: String },
+    DropTable {
+        name: String,
+    },
+    CreateTable {
+        name: String,
+        code: String,
+    },
+    RenameTable {
+        old: String,
+        new: String,
+    },
+    CreateIndex {
+        table: String,
+        code: String,
+    },
+    DropIndex {
+        name: String,
+    },
+    /// Replace a table with one created by `code`, copying the values of `columns`.
+    /// `dropped` are the columns which are lost.
+    RebuildTable {
+        name: String,
+        code: String,
+        columns: Vec<String>,
+        dropped: Vec<String>,
+    },
 }
 impl SqlStatement for Statement {
+    fn destructive(&self) -> bool {
+        match self {
+            Statement::DropTable { .. } => true,
+            Statement::RebuildTable { dropped, .. } => !dropped.is_empty(),
+            _ => false,
+        }
+    }
+    fn change(&self) -> Option<(&'static str, &'static str)> {
+        Some(match self {
+            Statement::DropTable { .. } => ("dropped", "table"),
+            Statement::CreateTable { .. } => ("created", "table"),
+            Statement::RenameTable { .. } => ("renamed", "table"),
+            Statement::CreateIndex { .. } => ("created", "index"),
+            Statement::DropIndex { .. } => ("dropped", "index"),
+            Statement::RebuildTable { .. } => ("rebuilt", "table"),
+        })
+    }
+    fn operation(&self) -> &'static str {
+        match self {
+            Statement::DropTable { .. } => "drop_table",
+            Statement::CreateTable { .. } => "create_table",
+            Statement::RenameTable { .. } => "rename_table",
+            Statement::CreateIndex { .. } => "create_index",
+            Statement::DropIndex { .. } => "drop_index",
+            Statement::RebuildTable { .. } => "rebuild_table",
+        }
+    }
+    fn object(&self) -> Option<String> {
+        Some(match self {
+            Statement::DropTable { name }
+            | Statement::CreateTable { name, .. }
+            | Statement::DropIndex { name }
+            | Statement::RebuildTable { name, .. } => name.clone(),
+            Statement::RenameTable { old, .. } => old.clone(),
+            // The table the index is created on, since its name is part of its code
+            Statement::CreateIndex { table, .. } => table.clone(),
+        })
+    }
+    fn write_guarded_to(&self, buffer: &mut String) {
+        match self {
+            Statement::DropTable { name } => {
+                write!(buffer, "DROP TABLE IF EXISTS {};", name).unwrap();
+            }
+            Statement::CreateTable { code, .. } | Statement::CreateIndex { code, .. } => {
+                buffer.push_str(&guard_create(code));
+                buffer.push(';');
+            }
+            Statement::DropIndex { name } => {
+                write!(buffer, "DROP INDEX IF EXISTS {};", name).unwrap();
+            }
+            // SQLite has no guarded form of renames
+            Statement::RenameTable { .. } | Statement::RebuildTable { .. } => self.write_to(buffer),
+        }
+    }
     fn write_to(&self, buffer: &mut String) {
         match self {
             Statement::DropTable { name } => {
@@ -62,10 +356,363 @@ impl SqlStatement for Statement {
                     .write_fmt(format_args!("DROP TABLE {};", name))
                     .unwrap();
             }
-            Statement::CreateTable { code } => {
-                buffer.push_str(&code);
+            Statement::CreateTable { code, .. } | Statement::CreateIndex { code, .. } => {
+                buffer.push_str(code);
                 buffer.push(';');
             }
+            Statement::DropIndex { name } => {
+                write!(buffer, "DROP INDEX {};", name).unwrap();
+            }
+            Statement::RenameTable { old, new } => {
+                buffer
+                    .write_fmt(format_args!("ALTER TABLE {} RENAME TO {};", old, new))
+                    .unwrap();
+            }
+            Statement::RebuildTable {
+                name,
+                code,
+                columns,
+                ..
+            } => {
+                // The legacy behavior leaves references to the table from other tables,
+                // views & triggers alone, so that they refer to the new table instead.
+                let old = format!("{}_sqigl_old", name);
+                write!(
+                    buffer,
+                    "PRAGMA legacy_alter_table = ON;\n\
+                    ALTER TABLE {} RENAME TO {};\n\
+                    PRAGMA legacy_alter_table = OFF;\n\
+                    {};\n",
+                    name, old, code
+                )
+                .unwrap();
+                if !columns.is_empty() {
+                    let columns: Vec<_> = columns
+                        .iter()
+                        .map(|column| format!("\"{}\"", column.replace('"', "\"\"")))
+                        .collect();
+                    let columns = columns.join(", ");
+                    writeln!(
+                        buffer,
+                        "INSERT INTO {} ({}) SELECT {} FROM {};",
+                        name, columns, columns, old
+                    )
+                    .unwrap();
+                }
+                write!(buffer, "DROP TABLE {};", old).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(code: &str) -> Table {
+        Table {
+            code: Some(code.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn name(table: &str) -> QualifiedName {
+        ("main".to_string(), table.to_string())
+    }
+
+    fn sql(statements: &[Statement]) -> Vec<String> {
+        statements
+            .iter()
+            .map(|stmt| {
+                let mut buffer = String::new();
+                stmt.write_to(&mut buffer);
+                buffer
+            })
+            .collect()
+    }
+
+    #[test]
+    fn guards_creates() {
+        assert_eq!(
+            guard_create("CREATE TABLE a(x)"),
+            "CREATE TABLE IF NOT EXISTS a(x)"
+        );
+        assert_eq!(
+            guard_create("CREATE UNIQUE INDEX a_x ON a(x)"),
+            "CREATE UNIQUE INDEX IF NOT EXISTS a_x ON a(x)"
+        );
+        assert_eq!(
+            guard_create("CREATE INDEX if not exists a_x ON a(x)"),
+            "CREATE INDEX if not exists a_x ON a(x)"
+        );
+
+        // The guarded statements still parse, & may be run again
+        let db = Connection::open_in_memory().unwrap();
+        for code in [
+            "CREATE TABLE a(x)",
+            "CREATE UNIQUE INDEX a_x ON a(x)",
+            "create table \"b c\" (x)",
+            "CREATE INDEX if not exists a_x ON a(x)",
+        ] {
+            for _ in 0..2 {
+                db.execute_batch(&guard_create(code)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn diffs_indexes() {
+        let mut from = Schema::default();
+        from.tables.insert(name("a"), table("CREATE TABLE a(x, y)"));
+        from.indexes.insert(
+            name("a"),
+            vec![
+                "CREATE INDEX a_x ON a(x)".to_string(),
+                "CREATE INDEX a_y ON a(y)".to_string(),
+            ],
+        );
+        let mut to = Schema::default();
+        to.tables.insert(name("a"), table("CREATE TABLE a(x, y)"));
+        to.tables.insert(name("b"), table("CREATE TABLE b(z)"));
+        to.indexes.insert(
+            name("a"),
+            vec![
+                "CREATE INDEX a_x ON a(x)".to_string(),
+                "CREATE INDEX a_y ON a(x, y)".to_string(),
+            ],
+        );
+        to.indexes
+            .insert(name("b"), vec!["CREATE INDEX b_z ON b(z)".to_string()]);
+
+        assert_eq!(
+            sql(&delta(&from, &to, false, &[])),
+            vec![
+                "DROP INDEX a_y;",
+                "CREATE TABLE b(z);",
+                "CREATE INDEX a_y ON a(x, y);",
+                "CREATE INDEX b_z ON b(z);",
+            ]
+        );
+        assert!(delta(&to, &to, false, &[]).is_empty());
+    }
+
+    #[test]
+    fn dropped_tables_drop_their_indexes() {
+        let mut from = Schema::default();
+        from.tables.insert(name("a"), table("CREATE TABLE a(x)"));
+        from.indexes
+            .insert(name("a"), vec!["CREATE INDEX a_x ON a(x)".to_string()]);
+
+        assert_eq!(
+            sql(&delta(&from, &Schema::default(), false, &[])),
+            vec!["DROP TABLE a;"]
+        );
+    }
+
+    #[test]
+    fn keeps_protected_indexes() {
+        let mut from = Schema::default();
+        from.tables.insert(name("a"), table("CREATE TABLE a(x, y)"));
+        from.tables.insert(name("b"), table("CREATE TABLE b(z)"));
+        from.indexes.insert(
+            name("a"),
+            vec![
+                "CREATE INDEX a_x ON a(x)".to_string(),
+                "CREATE INDEX a_y ON a(y)".to_string(),
+            ],
+        );
+        from.indexes
+            .insert(name("b"), vec!["CREATE INDEX b_z ON b(z)".to_string()]);
+        let mut to = from.clone();
+        to.indexes.clear();
+        to.indexes
+            .insert(name("a"), vec!["CREATE INDEX a_y ON a(x, y)".to_string()]);
+
+        // Changed indexes are still recreated
+        assert_eq!(
+            sql(&delta(
+                &from,
+                &to,
+                false,
+                &["a".to_string(), "b_z".to_string()]
+            )),
+            vec!["DROP INDEX a_y;", "CREATE INDEX a_y ON a(x, y);"]
+        );
+    }
+
+    #[test]
+    fn keeps_tables_protected_tables_refer_to() {
+        let mut from = Schema::default();
+        from.tables.insert(name("a"), table("CREATE TABLE a(x)"));
+        from.tables.insert(
+            name("b"),
+            Table {
+                references: BTreeSet::from([name("a")]),
+                ..table("CREATE TABLE b(a REFERENCES a(x))")
+            },
+        );
+        from.tables.insert(name("c"), table("CREATE TABLE c(y)"));
+
+        assert_eq!(
+            sql(&delta(&from, &Schema::default(), false, &["b".to_string()])),
+            vec!["DROP TABLE c;"]
+        );
+    }
+
+    #[test]
+    fn rebuilds_changed_tables() {
+        let column = |name: &str| Column {
+            name: name.to_string(),
+            data_type: "".to_string(),
+            not_null: false,
+            default: None,
+            primary_key: 0,
+        };
+        let mut from = Schema::default();
+        from.tables.insert(
+            name("a"),
+            Table {
+                columns: vec![column("x"), column("y")],
+                ..table("CREATE TABLE a(x, y)")
+            },
+        );
+        from.indexes
+            .insert(name("a"), vec!["CREATE INDEX a_x ON a(x)".to_string()]);
+        let mut to = from.clone();
+        to.tables.insert(
+            name("a"),
+            Table {
+                columns: vec![column("x"), column("z")],
+                ..table("CREATE TABLE a(x NOT NULL, z)")
+            },
+        );
+
+        let statements = delta(&from, &to, false, &[]);
+        assert!(statements[0].destructive());
+        assert_eq!(
+            sql(&statements),
+            vec![
+                "PRAGMA legacy_alter_table = ON;\n\
+                ALTER TABLE a RENAME TO a_sqigl_old;\n\
+                PRAGMA legacy_alter_table = OFF;\n\
+                CREATE TABLE a(x NOT NULL, z);\n\
+                INSERT INTO a (\"x\") SELECT \"x\" FROM a_sqigl_old;\n\
+                DROP TABLE a_sqigl_old;",
+                "CREATE INDEX a_x ON a(x);",
+            ]
+        );
+        // Protected tables aren't rebuilt if that would lose columns
+        assert!(delta(&from, &to, false, &["a".to_string()]).is_empty());
+    }
+
+    /// A table with integer columns `columns`.
+    fn table_of(tbl: &str, columns: &[&str]) -> Table {
+        Table {
+            columns: columns
+                .iter()
+                .map(|column| Column {
+                    name: column.to_string(),
+                    data_type: "INTEGER".to_string(),
+                    not_null: false,
+                    default: None,
+                    primary_key: 0,
+                })
+                .collect(),
+            ..table(&format!("CREATE TABLE {}({})", tbl, columns.join(", ")))
         }
     }
+
+    #[test]
+    fn detects_renamed_tables() {
+        let mut from = Schema::default();
+        from.tables.insert(name("a"), table_of("a", &["x"]));
+        let mut to = Schema::default();
+        to.tables.insert(name("b"), table_of("b", &["x"]));
+        assert_eq!(
+            sql(&delta(&from, &to, true, &[])),
+            vec!["ALTER TABLE a RENAME TO b;"]
+        );
+        assert_eq!(
+            sql(&delta(&from, &to, false, &[])),
+            vec!["DROP TABLE a;", "CREATE TABLE b(x);"]
+        );
+
+        // The columns differ, so this isn't a rename
+        let mut changed = Schema::default();
+        changed.tables.insert(name("b"), table_of("b", &["x", "y"]));
+        assert_eq!(
+            sql(&delta(&from, &changed, true, &[])),
+            vec!["DROP TABLE a;", "CREATE TABLE b(x, y);"]
+        );
+
+        // Which table was renamed to which is ambiguous
+        from.tables.insert(name("c"), table_of("c", &["x"]));
+        to.tables.insert(name("d"), table_of("d", &["x"]));
+        assert_eq!(
+            sql(&delta(&from, &to, true, &[])),
+            vec![
+                "DROP TABLE a;",
+                "DROP TABLE c;",
+                "CREATE TABLE b(x);",
+                "CREATE TABLE d(x);",
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_destructive_statements() {
+        let mut from = Schema::default();
+        from.tables.insert(name("a"), table_of("a", &["x", "y"]));
+        from.tables.insert(name("b"), table_of("b", &["x"]));
+        let destructive = |to: &Schema| -> Vec<(&str, String, bool)> {
+            delta(&from, to, false, &[])
+                .iter()
+                .map(|stmt| (stmt.operation(), stmt.object().unwrap(), stmt.destructive()))
+                .collect()
+        };
+
+        // Dropping a table or a column is destructive
+        let mut to = Schema::default();
+        to.tables.insert(name("a"), table_of("a", &["x"]));
+        assert_eq!(
+            destructive(&to),
+            vec![
+                ("drop_table", "b".to_string(), true),
+                ("rebuild_table", "a".to_string(), true),
+            ]
+        );
+
+        // Adding a table or a column isn't
+        let mut to = from.clone();
+        to.tables.insert(name("a"), table_of("a", &["x", "y", "z"]));
+        to.tables.insert(name("c"), table_of("c", &["x"]));
+        assert_eq!(
+            destructive(&to),
+            vec![
+                ("rebuild_table", "a".to_string(), false),
+                ("create_table", "c".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn summarizes_changes() {
+        let summary = |from: &Schema, to: &Schema| {
+            GeneratedMigration {
+                from: VersionReq::STAR,
+                to: Version::new(0, 1, 0),
+                statements: delta(from, to, false, &[]),
+                guarded: false,
+            }
+            .summary()
+            .unwrap()
+        };
+        let mut from = Schema::default();
+        from.tables.insert(name("a"), table_of("a", &["x"]));
+        let mut to = Schema::default();
+        to.tables.insert(name("b"), table_of("b", &["x"]));
+        to.tables.insert(name("c"), table_of("c", &["x"]));
+        assert_eq!(summary(&from, &to), "1 table dropped, 2 tables created");
+        assert_eq!(summary(&to, &to), "no changes");
+    }
 }
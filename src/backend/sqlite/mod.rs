@@ -1,45 +1,281 @@
 mod delta;
 
 use std::{
+    env,
     error::{self, Error},
+    fs::{File, OpenOptions, TryLockError},
+    io,
+    num::NonZeroUsize,
     ops::Deref,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    artifact::{Artifact, ConsumerError, ContentId, ScriptConsumer, ScriptProcessingError},
+    artifact::{
+        Artifact, ConsumerError, ContentId, Progress, ScriptConsumer, ScriptProcessingError,
+    },
+    manifest::{
+        artifact::Maintenance,
+        project::{JournalMode, Synchronous},
+    },
     util::empty_database_version,
     SQIGL_VERSION,
 };
 use log::{debug, error, info, trace, warn};
 use rusqlite::{Connection, Transaction, TransactionBehavior};
 use semver::Version;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
-use self::delta::delta;
+use self::delta::{delta, introspect};
 
-use super::{Backend, GeneratedMigration, SqiglState};
+use super::{
+    internal_sql, introspection::Schema, verify_migration, Backend, GenerateOptions,
+    GeneratedMigration, HistoryEntry, HistoryFilter, SqiglState, DEFAULT_INTERNAL_SCHEMA,
+};
 
 impl ConsumerError for rusqlite::Error {}
 
 fn get_state<Db: Deref<Target = rusqlite::Connection>>(
     db: &Db,
+    internal: &str,
 ) -> Result<SqiglState, rusqlite::Error> {
-    Ok(db
-        .prepare_cached(include_str!("sql/select_state.sql"))?
-        .query_row([], |r| r.try_into())?)
+    db.prepare_cached(&internal_sql(
+        include_str!("sql/select_state.sql"),
+        internal,
+    ))?
+    .query_row([], |r| r.try_into())
+}
+
+/// Whether an error indicates that sqigl's tables do not exist, ie sqigl is
+/// not installed.
+fn is_not_installed(e: &rusqlite::Error) -> bool {
+    match e {
+        rusqlite::Error::SqliteFailure(_, Some(msg)) => msg.starts_with("no such table"),
+        _ => false,
+    }
+}
+
+fn attach(db: &Connection, name: &str, path: &str) -> Result<(), rusqlite::Error> {
+    db.execute(
+        &format!("ATTACH DATABASE ?1 AS \"{}\"", name.replace('"', "\"\"")),
+        [path],
+    )?;
+    Ok(())
+}
+
+/// Bring the schema of sqigl's tables up to date, if they were installed by an earlier
+/// version of sqigl, & record that they are now this version's.
+fn upgrade_schema(
+    db: &Connection,
+    internal: &str,
+    state: &mut SqiglState,
+) -> Result<(), rusqlite::Error> {
+    let running: Version = SQIGL_VERSION.parse().unwrap();
+    if state.sqigl_version >= running {
+        return Ok(());
+    }
+    info!(
+        "Upgrading sqigl's tables from version {}",
+        state.sqigl_version
+    );
+    let current: bool = db.query_row(
+        &internal_sql(include_str!("sql/history_has_sqigl_version.sql"), internal),
+        [],
+        |r| r.get(0),
+    )?;
+    if !current {
+        db.execute_batch(&internal_sql(
+            include_str!("sql/upgrade_schema.sql"),
+            internal,
+        ))?;
+    }
+    db.execute(
+        &internal_sql(
+            r#"update main."{internal}_state" set sqigl_version = ?1, updated_at = unixepoch()"#,
+            internal,
+        ),
+        [SQIGL_VERSION],
+    )?;
+    state.sqigl_version = running;
+    Ok(())
+}
+
+/// Record an artifact in the history at `version`, making it the head. The running
+/// version of sqigl is recorded alongside it.
+fn append_history(
+    tx: &rusqlite::Transaction,
+    internal: &str,
+    id: ContentId,
+    version: &Version,
+) -> Result<(), rusqlite::Error> {
+    let prev_pk: Option<i64> = tx.query_row(
+        &internal_sql(r#"select head from main."{internal}_state""#, internal),
+        [],
+        |r| r.get("head"),
+    )?;
+    // Retrying an apply which was recorded shouldn't record it again
+    let recorded: bool = tx
+        .prepare(&internal_sql(
+            include_str!("sql/head_matches.sql"),
+            internal,
+        ))?
+        .query_row((prev_pk, id.unwrap(), version.to_string()), |r| r.get(0))?;
+    if recorded {
+        debug!("Artifact {} is already at the head of the history", id);
+        return Ok(());
+    }
+    // NB: An artifact may be applied multiple times. Our artifact's row may
+    // already exist.
+    let artifact_pk: i64 = tx
+        .prepare(&internal_sql(
+            include_str!("sql/get_artifact_by_id.sql"),
+            internal,
+        ))?
+        .query_row([id.unwrap()], |r| r.get("pk"))?;
+    let head_pk: i64 = tx
+        .prepare(&internal_sql(
+            include_str!("sql/append_history.sql"),
+            internal,
+        ))?
+        .query_row(
+            (prev_pk, artifact_pk, version.to_string(), SQIGL_VERSION),
+            |r| r.get::<_, i64>("pk"),
+        )?;
+    tx.prepare(&internal_sql(
+        r#"update main."{internal}_state" set head = ?1"#,
+        internal,
+    ))?
+    .execute([head_pk])?;
+    Ok(())
+}
+
+/// How often to try to take the lock on a database while waiting for it.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Take an advisory lock on a file beside a database, waiting up to `timeout` for
+/// another process to release it. The lock is released when the file is closed.
+fn lock_database(path: &Path, timeout: Duration) -> Result<File, LockError> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    let error = |e| LockError::Io(lock_path.clone(), e);
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(error)?;
+    let start = Instant::now();
+    loop {
+        match file.try_lock() {
+            Ok(()) => {
+                debug!("Locked {:?}", lock_path);
+                return Ok(file);
+            }
+            Err(TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                trace!("Waiting for the lock on {:?}", lock_path);
+                thread::sleep(LOCK_POLL_INTERVAL);
+            }
+            Err(TryLockError::WouldBlock) => return Err(LockError::Locked(lock_path)),
+            Err(TryLockError::Error(e)) => return Err(error(e)),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LockError {
+    #[error("Another sqigl process holds the lock on the database ({0:?})")]
+    Locked(PathBuf),
+    #[error("Failed to lock the database ({0:?}): {1}")]
+    Io(PathBuf, io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum OpenError {
+    #[error(transparent)]
+    Lock(#[from] LockError),
+    #[error("Failed to open the database: {0}")]
+    Database(#[from] rusqlite::Error),
 }
 
 pub struct SqliteBackend {
     db: rusqlite::Connection,
+    detect_renames: bool,
+    /// The schema names of attached databases
+    attached: Vec<String>,
+    /// Held while the backend exists, so that commands spanning multiple transactions
+    /// aren't interleaved with those of other sqigl processes. `None` for in-memory
+    /// databases.
+    lock: Option<File>,
+    /// The prefix of sqigl's own tables
+    internal: String,
 }
 impl SqliteBackend {
     pub fn new(db: rusqlite::Connection) -> Self {
-        Self { db }
+        Self {
+            db,
+            detect_renames: false,
+            attached: Vec::new(),
+            lock: None,
+            internal: DEFAULT_INTERNAL_SCHEMA.to_string(),
+        }
+    }
+    /// Open a database file, first locking it, waiting up to `lock_timeout` for another
+    /// process to release it.
+    pub fn open_file(path: &Path, lock_timeout: Duration) -> Result<Self, OpenError> {
+        let lock = lock_database(path, lock_timeout)?;
+        let mut backend = Self::new(Connection::open(path)?);
+        backend.lock = Some(lock);
+        Ok(backend)
+    }
+    /// Attach another database file under the schema `name`, so that scripts may refer
+    /// to its tables as `name.table`. sqigl's own tables always remain in the main
+    /// database.
+    pub fn attach(&mut self, name: &str, path: &Path) -> Result<(), rusqlite::Error> {
+        debug!("Attaching {:?} as {}", path, name);
+        attach(&self.db, name, path.to_str().unwrap())?;
+        self.attached.push(name.to_string());
+        Ok(())
+    }
+    /// Set the journal mode of the database. The mode actually in effect is returned by
+    /// SQLite; in-memory databases, for instance, don't support `wal`.
+    pub fn set_journal_mode(&mut self, mode: JournalMode) -> Result<(), rusqlite::Error> {
+        let actual: String =
+            self.db
+                .pragma_update_and_check(None, "journal_mode", mode.as_str(), |row| row.get(0))?;
+        if actual.eq_ignore_ascii_case(mode.as_str()) {
+            debug!("Set journal mode to {}", mode);
+        } else {
+            warn!(
+                "Could not set journal mode to {}; the database is using {}",
+                mode, actual
+            );
+        }
+        Ok(())
+    }
+    pub fn set_synchronous(&mut self, synchronous: Synchronous) -> Result<(), rusqlite::Error> {
+        debug!("Setting synchronous to {}", synchronous);
+        self.db
+            .pragma_update(None, "synchronous", synchronous.as_str())
     }
     pub fn local() -> Result<Self, rusqlite::Error> {
-        Ok(Self {
-            db: rusqlite::Connection::open_in_memory()?,
-        })
+        Ok(Self::new(rusqlite::Connection::open_in_memory()?))
+    }
+    /// Detect renamed tables when generating migrations, rather than dropping & creating
+    /// them.
+    pub fn with_rename_detection(mut self, detect_renames: bool) -> Self {
+        self.detect_renames = detect_renames;
+        self
+    }
+    /// Prefix the names of sqigl's own tables with `internal`, rather than
+    /// `sqigl_internal`.
+    pub fn with_internal_schema(mut self, internal: &str) -> Self {
+        self.internal = internal.to_string();
+        self
     }
 }
 impl Backend for SqliteBackend {
@@ -47,32 +283,110 @@ impl Backend for SqliteBackend {
 
     fn install(&mut self) -> Result<SqiglState, Self::Error> {
         info!("Installing sqigl onto databse");
+        let internal = &self.internal;
         let mut tx = self.db.transaction()?;
-        tx.execute_batch(include_str!("sql/schema.sql"))?;
-        tx.prepare(include_str!("sql/initialize_state.sql"))?
-            .execute([SQIGL_VERSION])?;
-        let state = get_state(&tx)?;
+        tx.execute_batch(&internal_sql(include_str!("sql/schema.sql"), internal))?;
+        tx.prepare(&internal_sql(
+            include_str!("sql/initialize_state.sql"),
+            internal,
+        ))?
+        .execute([SQIGL_VERSION])?;
+        // sqigl may already have been installed, by an earlier version
+        let mut state = get_state(&tx, internal)?;
+        upgrade_schema(&tx, internal, &mut state)?;
         tx.commit()?;
 
         Ok(state)
     }
     fn open(&mut self) -> Result<SqiglState, Self::Error> {
-        if let Ok(state) = get_state(&&self.db) {
-            Ok(state)
-        } else {
-            warn!("sqigl is not installed on this database; installing");
-            let state = self.install()?;
-            Ok(state)
+        match get_state(&&self.db, &self.internal) {
+            Ok(mut state) => {
+                upgrade_schema(&self.db, &self.internal, &mut state)?;
+                Ok(state)
+            }
+            Err(e) if is_not_installed(&e) => {
+                warn!("sqigl is not installed on this database; installing");
+                let state = self.install()?;
+                Ok(state)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn installed(&mut self) -> Result<bool, Self::Error> {
+        match get_state(&&self.db, &self.internal) {
+            Ok(_) => Ok(true),
+            Err(e) if is_not_installed(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn ping(&mut self) -> Result<Option<String>, Self::Error> {
+        let version = self
+            .db
+            .query_row("SELECT sqlite_version()", [], |row| row.get(0))?;
+        Ok(Some(version))
+    }
+
+    fn introspect(&mut self, exclude_schemas: &[String]) -> anyhow::Result<Schema> {
+        if !exclude_schemas.is_empty() {
+            warn!("Excluding schemas is only supported for Postgres; ignoring");
+        }
+        introspect(&mut self.db, &self.internal)
+    }
+
+    fn introspect_artifact<A: Artifact>(
+        &mut self,
+        artifact: &A,
+        exclude_schemas: &[String],
+    ) -> anyhow::Result<Schema> {
+        assert!(artifact.compatible(&empty_database_version()));
+        if !exclude_schemas.is_empty() {
+            warn!("Excluding schemas is only supported for Postgres; ignoring");
+        }
+        // As when generating migrations, attached databases are replaced by empty
+        // in-memory databases
+        let mut db = Connection::open_in_memory()?;
+        for name in self.attached.iter() {
+            attach(&db, name, ":memory:")?;
         }
+        db.execute_batch(&artifact.to_string())?;
+        introspect(&mut db, &self.internal)
+    }
+
+    fn record(&mut self, id: ContentId, version: &Version) -> Result<(), Self::Error> {
+        info!("Recording {} in the history at version {}.", id, version);
+        // Serialize with other instances, as when applying an artifact
+        let tx = Transaction::new(&mut self.db, TransactionBehavior::Exclusive)?;
+        append_history(&tx, &self.internal, id, version)?;
+        tx.commit()
+    }
+    // Every artifact, even one marked as parallel, is applied in a single transaction, so
+    // a migration is never left partially applied
+    fn pending(&mut self) -> Result<Option<ContentId>, Self::Error> {
+        Ok(None)
+    }
+    fn clear_pending(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn maintain(
+        &mut self,
+        maintenance: Maintenance,
+    ) -> Result<(), ScriptProcessingError<Self::Error>> {
+        // `vacuum` can't run inside a transaction, so this isn't run in one
+        Ok(self.db.execute_batch(maintenance.as_str())?)
     }
 
     fn apply<A: Artifact>(
         &mut self,
         artifact: &A,
+        force: bool,
     ) -> Result<SqiglState, ScriptProcessingError<Self::Error>> {
         info!("Applying artifact.");
         struct Consumer<'a> {
             version: &'a Version,
+            internal: &'a str,
             tx: rusqlite::Transaction<'a>,
         }
         impl ScriptConsumer for Consumer<'_> {
@@ -89,26 +403,7 @@ impl Backend for SqliteBackend {
 
             fn commit(self, id: ContentId) -> Result<(), ScriptProcessingError<rusqlite::Error>> {
                 debug!("Committing migration.");
-                // NB: An artifact may be applied multiple times. Our artifact's row may
-                // already exist.
-                let artifact_pk: i64 = self
-                    .tx
-                    .prepare(include_str!("sql/get_artifact_by_id.sql"))?
-                    .query_row([id.unwrap()], |r| r.get("pk"))?;
-                let prev_pk: Option<i64> =
-                    self.tx
-                        .query_row("select head from sqigl_internal_state", [], |r| {
-                            r.get("head")
-                        })?;
-                let head_pk: i64 = self
-                    .tx
-                    .prepare(include_str!("sql/append_history.sql"))?
-                    .query_row((prev_pk, artifact_pk, self.version.to_string()), |r| {
-                        r.get::<_, i64>("pk")
-                    })?;
-                self.tx
-                    .prepare("update sqigl_internal_state set head = ?1")?
-                    .execute([head_pk])?;
+                append_history(&self.tx, self.internal, id, self.version)?;
                 self.tx.commit()?;
                 debug!("Migration committed.");
                 Ok(())
@@ -121,20 +416,94 @@ impl Backend for SqliteBackend {
         // - The version is compatible at the start of our transaction
         debug!("Opening artifact transaction.");
         let tx = Transaction::new(&mut self.db, TransactionBehavior::Exclusive)?;
-        let state = get_state(&tx)?;
+        let state = get_state(&tx, &self.internal)?;
         if !artifact.compatible(&state.project_version) {
-            error!("Migration aborted: Incompatible");
-            return Err(ScriptProcessingError::Incompatible);
+            if !force {
+                error!("Migration aborted: Incompatible");
+                return Err(ScriptProcessingError::Incompatible);
+            }
+            warn!(
+                "Forcing {} onto incompatible version {}",
+                artifact.print(),
+                state.project_version
+            );
         }
 
         let version = artifact.version();
-        let consumer = Consumer { version, tx };
-        artifact.scripts(consumer)?;
+        let consumer = Consumer {
+            version,
+            internal: &self.internal,
+            tx,
+        };
+        artifact.scripts(Progress::new(consumer))?;
 
-        let state = get_state(&&self.db)?;
+        let state = get_state(&&self.db, &self.internal)?;
         Ok(state)
     }
 
+    fn apply_parallel<A: Artifact>(
+        &mut self,
+        artifact: &A,
+        force: bool,
+        _connections: NonZeroUsize,
+    ) -> Result<SqiglState, ScriptProcessingError<Self::Error>> {
+        // SQLite only permits one writer at a time
+        warn!(
+            "SQLite databases can't apply statements in parallel; applying them in a single \
+            transaction"
+        );
+        self.apply(artifact, force)
+    }
+
+    fn exec(
+        &mut self,
+        script: &str,
+        record: bool,
+    ) -> Result<(), ScriptProcessingError<Self::Error>> {
+        info!("Executing script.");
+        // Serialize with other instances, as when applying an artifact
+        let tx = Transaction::new(&mut self.db, TransactionBehavior::Exclusive)?;
+        let state = get_state(&tx, &self.internal)?;
+        trace!("Running a script.");
+        tx.execute_batch(script)?;
+        if record {
+            debug!("Recording script in history.");
+            let id = Sha256::digest(script).into();
+            append_history(&tx, &self.internal, id, &state.project_version)?;
+        }
+        tx.commit()?;
+        info!("Script executed.");
+        Ok(())
+    }
+
+    fn history(&mut self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, Self::Error> {
+        let mut state = get_state(&&self.db, &self.internal)?;
+        upgrade_schema(&self.db, &self.internal, &mut state)?;
+        let (since, until) = filter.bounds();
+        let limit = filter.limit.map(|l| l as i64);
+        self.db
+            .prepare(&internal_sql(
+                include_str!("sql/select_history.sql"),
+                &self.internal,
+            ))?
+            .query_map((since, until, limit), |r| r.try_into())?
+            .collect()
+    }
+
+    fn applied_ids(&mut self) -> Result<Vec<ContentId>, Self::Error> {
+        self.db
+            .prepare(&internal_sql(
+                include_str!("sql/select_applied_ids.sql"),
+                &self.internal,
+            ))?
+            .query_map([], |r| {
+                Ok(r.get::<_, Vec<u8>>("id")?
+                    .try_into()
+                    .expect("Failed to parse content id"))
+            })?
+            .collect()
+    }
+
     fn check<A: Artifact>(
         &mut self,
         artifact: &A,
@@ -165,7 +534,7 @@ impl Backend for SqliteBackend {
         }
 
         let tx = self.db.transaction()?;
-        let state = get_state(&tx)?;
+        let state = get_state(&tx, &self.internal)?;
         if !artifact.compatible(&state.project_version) {
             error!("Migration aborted: Incompatible");
             return Err(ScriptProcessingError::Incompatible);
@@ -178,31 +547,135 @@ impl Backend for SqliteBackend {
         Ok(())
     }
 
+    fn check_isolated<A: Artifact>(
+        &mut self,
+        artifact: &A,
+    ) -> Result<(), ScriptProcessingError<Self::Error>> {
+        info!("Checking artifact against an in-memory database.");
+        struct Consumer {
+            db: Connection,
+        }
+        impl ScriptConsumer for Consumer {
+            type Error = rusqlite::Error;
+
+            fn accept(
+                &mut self,
+                script: &str,
+            ) -> Result<(), ScriptProcessingError<rusqlite::Error>> {
+                trace!("Running a script.");
+                self.db.execute_batch(script)?;
+                Ok(())
+            }
+
+            fn commit(self, _id: ContentId) -> Result<(), ScriptProcessingError<rusqlite::Error>> {
+                trace!("Done checking");
+                Ok(())
+            }
+        }
+
+        if !artifact.compatible(&empty_database_version()) {
+            error!("Migration aborted: Incompatible");
+            return Err(ScriptProcessingError::Incompatible);
+        }
+        // As when generating migrations, attached databases are replaced by empty
+        // in-memory databases
+        let db = Connection::open_in_memory()?;
+        for name in self.attached.iter() {
+            attach(&db, name, ":memory:")?;
+        }
+        artifact.scripts(Consumer { db })?;
+
+        Ok(())
+    }
+
     fn generate_migration<A1: Artifact, A2: Artifact>(
         &mut self,
         from_schema: &A1,
         to_schema: &A2,
-    ) -> anyhow::Result<impl Artifact> {
-        let from_db = Connection::open_in_memory()?;
-        from_db.execute_batch(&from_schema.to_string())?;
-        let to_db = Connection::open_in_memory()?;
-        to_db.execute_batch(&to_schema.to_string())?;
-        let statements = delta(from_db, to_db)?;
-
-        let from = crate::util::from_minor_version(from_schema.version());
+        options: &GenerateOptions,
+    ) -> anyhow::Result<impl Artifact + 'static> {
+        if !options.exclude_schemas.is_empty() {
+            // Only the main database is compared, so there are no other schemas to exclude
+            warn!("Excluding schemas is only supported for Postgres; ignoring");
+        }
+        // Attached databases are replaced by empty in-memory databases, so that scripts
+        // referring to them can run. Only the main database is compared.
+        let mut from_db = Connection::open_in_memory()?;
+        let mut to_db = Connection::open_in_memory()?;
+        for name in self.attached.iter() {
+            attach(&from_db, name, ":memory:")?;
+            attach(&to_db, name, ":memory:")?;
+        }
+        let result = from_db
+            .execute_batch(&from_schema.to_string())
+            .and_then(|_| to_db.execute_batch(&to_schema.to_string()));
+
+        if options.keep_temp {
+            for (db, version) in [
+                (&from_db, from_schema.version()),
+                (&to_db, to_schema.version()),
+            ] {
+                let path =
+                    env::temp_dir().join(format!("sqigl_tmp_{}.sqlite", rand::random::<u32>()));
+                db.execute("VACUUM INTO ?1", [path.to_str().unwrap()])?;
+                warn!("Keeping temporary database {:?} ({})", path, version);
+            }
+        }
+        result?;
+        let to_db_schema = introspect(&mut to_db, &self.internal)?;
+        let statements = delta(
+            &introspect(&mut from_db, &self.internal)?,
+            &to_db_schema,
+            self.detect_renames,
+            &options.protect,
+        );
+
+        let from = crate::util::from_version(from_schema.version(), options.exact);
         let to = to_schema.version().clone();
-        Ok(GeneratedMigration {
+        let migration = GeneratedMigration {
             from,
             to,
             statements,
-        })
+            guarded: options.if_exists,
+        };
+        if options.verify {
+            debug!("Verifying the generated migration");
+            from_db.execute_batch(&migration.to_string())?;
+            let residual = delta(
+                &introspect(&mut from_db, &self.internal)?,
+                &to_db_schema,
+                self.detect_renames,
+                &options.protect,
+            );
+            verify_migration(residual, to_schema.version())?;
+        }
+        Ok(migration)
     }
 }
 impl Default for SqliteBackend {
     fn default() -> Self {
-        Self {
-            db: rusqlite::Connection::open_in_memory().unwrap(),
-        }
+        Self::new(rusqlite::Connection::open_in_memory().unwrap())
+    }
+}
+
+impl TryFrom<&rusqlite::Row<'_>> for HistoryEntry {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &rusqlite::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            version: row
+                .get::<_, String>("version")?
+                .parse()
+                .expect("Failed to parse semver in version"),
+            content_id: row
+                .get::<_, Vec<u8>>("id")?
+                .try_into()
+                .expect("Failed to parse content id"),
+            applied_at: row.get("applied_at")?,
+            sqigl_version: row
+                .get::<_, Option<String>>("sqigl_version")?
+                .map(|v| v.parse().expect("Failed to parse semver in sqigl_version")),
+        })
     }
 }
 
@@ -225,3 +698,453 @@ impl TryFrom<&rusqlite::Row<'_>> for SqiglState {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actions::build::build_project, backend::SqlStatement as _, testing::TestProject};
+    use std::{collections::BTreeSet, fs};
+
+    #[test]
+    fn migrates_changed_tables() {
+        use crate::actions::build::FileArtifact;
+
+        let project = TestProject::new("0.2.0");
+        project
+            .write(
+                "0.1.0.sql",
+                "-- [ test 0.1.0 ]\n\
+                create table a(id integer primary key, x text);\n\
+                create table b(a integer references a(id), y);\n\
+                create index b_y on b(y);\n",
+            )
+            .write(
+                "0.2.0.sql",
+                "-- [ test 0.2.0 ]\n\
+                create table a(id integer primary key, x text not null);\n\
+                create table b(a integer references a(id), y not null, z default 1);\n\
+                create index b_y on b(y);\n",
+            );
+        let from = FileArtifact::open(&project.path().join("0.1.0.sql")).unwrap();
+        let to = FileArtifact::open(&project.path().join("0.2.0.sql")).unwrap();
+        let options = GenerateOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let migration = SqliteBackend::local()
+            .unwrap()
+            .generate_migration(&from, &to, &options)
+            .unwrap()
+            .to_string();
+        assert!(
+            migration.contains("ALTER TABLE b RENAME TO b_sqigl_old;"),
+            "{migration}"
+        );
+    }
+
+    #[test]
+    fn plans_added_tables_as_json() {
+        use crate::actions::build::FileArtifact;
+
+        let project = TestProject::new("0.2.0");
+        project
+            .write(
+                "0.1.0.sql",
+                "-- [ test 0.1.0 ]\ncreate table a(x integer);\n",
+            )
+            .write(
+                "0.2.0.sql",
+                "-- [ test 0.2.0 ]\ncreate table a(x integer);\ncreate table b(y text);\n",
+            );
+        let from = FileArtifact::open(&project.path().join("0.1.0.sql")).unwrap();
+        let to = FileArtifact::open(&project.path().join("0.2.0.sql")).unwrap();
+        let plan = SqliteBackend::local()
+            .unwrap()
+            .generate_migration(&from, &to, &Default::default())
+            .unwrap()
+            .plan()
+            .unwrap();
+        // As printed by `migration generate --plan-only --format json`
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string_pretty(&plan).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "operation": "create_table",
+                "object": "b",
+                "sql": "CREATE TABLE b(y text);",
+                "destructive": false,
+            }])
+        );
+    }
+
+    #[test]
+    fn verification_flags_partial_migrations() {
+        let mut from_db = Connection::open_in_memory().unwrap();
+        let mut to_db = Connection::open_in_memory().unwrap();
+        from_db.execute_batch("create table a(x);").unwrap();
+        to_db
+            .execute_batch("create table a(x); create table b(y); create table c(z);")
+            .unwrap();
+        let mut diff = |from_db: &mut Connection| {
+            delta(
+                &introspect(from_db, DEFAULT_INTERNAL_SCHEMA).unwrap(),
+                &introspect(&mut to_db, DEFAULT_INTERNAL_SCHEMA).unwrap(),
+                false,
+                &[],
+            )
+        };
+
+        // A differ which only covers some of the changes
+        let mut partial = diff(&mut from_db);
+        partial.truncate(1);
+        for stmt in partial {
+            let mut sql = String::new();
+            stmt.write_to(&mut sql);
+            from_db.execute_batch(&sql).unwrap();
+        }
+        let e = verify_migration(diff(&mut from_db), &Version::new(0, 2, 0))
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("schema of 0.2.0"), "{e}");
+        assert!(e.ends_with("missing:\n    CREATE TABLE c(z);"), "{e}");
+    }
+
+    #[test]
+    fn sets_journal_mode() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.sqlite");
+        let journal_mode = |db: &SqliteBackend| -> String {
+            db.db
+                .pragma_query_value(None, "journal_mode", |row| row.get(0))
+                .unwrap()
+        };
+        let mut db = SqliteBackend::open_file(&path, Duration::ZERO).unwrap();
+        db.set_journal_mode(JournalMode::Wal).unwrap();
+        assert_eq!(journal_mode(&db), "wal");
+        drop(db);
+        // `wal` persists in the database file
+        let db = SqliteBackend::open_file(&path, Duration::ZERO).unwrap();
+        assert_eq!(journal_mode(&db), "wal");
+
+        // In-memory databases keep their mode
+        let mut db = SqliteBackend::local().unwrap();
+        db.set_journal_mode(JournalMode::Wal).unwrap();
+        assert_eq!(journal_mode(&db), "memory");
+    }
+
+    #[test]
+    fn records_an_artifact_again() {
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        let (a, b) = (
+            ContentId::try_from(vec![0; 32]).unwrap(),
+            ContentId::try_from(vec![1; 32]).unwrap(),
+        );
+        db.record(a, &Version::new(0, 1, 0)).unwrap();
+        db.record(b, &Version::new(0, 2, 0)).unwrap();
+        db.record(a, &Version::new(0, 3, 0)).unwrap();
+        assert_eq!(db.history(&Default::default()).unwrap().len(), 3);
+        // Already at the head of the history
+        db.record(a, &Version::new(0, 3, 0)).unwrap();
+        assert_eq!(db.history(&Default::default()).unwrap().len(), 3);
+        assert_eq!(db.open().unwrap().project_version, Version::new(0, 3, 0));
+    }
+
+    #[test]
+    fn filters_history() {
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        let versions = ["0.1.0", "0.2.0-payments", "0.2.0", "0.2.1+build", "0.10.0"];
+        for (i, version) in versions.iter().enumerate() {
+            db.record(
+                ContentId::try_from(vec![i as u8; 32]).unwrap(),
+                &version.parse().unwrap(),
+            )
+            .unwrap();
+        }
+        let mut history = |since: Option<&str>, until: Option<&str>, limit: Option<usize>| {
+            let filter = HistoryFilter {
+                since: since.map(|v| v.parse().unwrap()),
+                until: until.map(|v| v.parse().unwrap()),
+                limit,
+            };
+            db.history(&filter)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.version.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(history(None, None, None).len(), 5);
+        // Versions are compared by precedence rather than as strings, so 0.10.0 is the
+        // latest & a prerelease precedes its release
+        assert_eq!(
+            history(Some("0.2.0"), None, None),
+            ["0.10.0", "0.2.1+build", "0.2.0"]
+        );
+        assert_eq!(
+            history(None, Some("0.2.0"), None),
+            ["0.2.0", "0.2.0-payments", "0.1.0"]
+        );
+        assert_eq!(
+            history(Some("0.2.0-payments"), Some("0.2.1"), None),
+            ["0.2.1+build", "0.2.0", "0.2.0-payments"]
+        );
+        assert_eq!(history(Some("0.2.0"), None, Some(1)), ["0.10.0"]);
+        assert!(history(Some("1.0.0"), None, None).is_empty());
+    }
+
+    #[test]
+    fn lock_excludes_other_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let held = SqliteBackend::open_file(&path, Duration::ZERO).unwrap();
+
+        let timeout = Duration::from_millis(200);
+        let start = Instant::now();
+        let result = SqliteBackend::open_file(&path, timeout);
+        assert!(
+            matches!(result, Err(OpenError::Lock(LockError::Locked(_)))),
+            "The database was opened while locked"
+        );
+        assert!(start.elapsed() >= timeout);
+
+        drop(held);
+        SqliteBackend::open_file(&path, Duration::ZERO).unwrap();
+    }
+
+    #[test]
+    fn custom_internal_prefix() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+
+        // A keyword, which must be quoted
+        let mut db = SqliteBackend::local().unwrap().with_internal_schema("user");
+        db.install().unwrap();
+        db.apply(&artifact, false).unwrap();
+        assert_eq!(db.open().unwrap().project_version, Version::new(0, 1, 0));
+        assert_eq!(db.history(&Default::default()).unwrap().len(), 1);
+        let tables: Vec<_> = db
+            .introspect(&[])
+            .unwrap()
+            .tables
+            .into_keys()
+            .map(|(_, name)| name)
+            .collect();
+        assert_eq!(tables, vec!["a"]);
+        assert!(db
+            .db
+            .query_row("select count(*) from user_history", [], |r| r
+                .get::<_, i64>(0))
+            .is_ok());
+    }
+
+    #[test]
+    fn underscores_in_prefix_are_not_wildcards() {
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.exec("create table sqiglxinternalxa(x integer);", false)
+            .unwrap();
+        let tables: Vec<_> = db
+            .introspect(&[])
+            .unwrap()
+            .tables
+            .into_keys()
+            .map(|(_, name)| name)
+            .collect();
+        assert_eq!(tables, vec!["sqiglxinternalxa"]);
+    }
+
+    #[test]
+    fn upgrades_outdated_tables() {
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.record(
+            ContentId::try_from(vec![0; 32]).unwrap(),
+            &Version::new(0, 1, 0),
+        )
+        .unwrap();
+        // As installed by a version of sqigl which didn't record itself in the history
+        db.db
+            .execute_batch(
+                "alter table sqigl_internal_history drop column sqigl_version; \
+                update sqigl_internal_state set sqigl_version = '0.0.1';",
+            )
+            .unwrap();
+
+        let state = db.open().unwrap();
+        assert_eq!(state.sqigl_version, SQIGL_VERSION.parse().unwrap());
+        assert_eq!(db.open().unwrap(), state);
+        assert_eq!(db.history(&Default::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn not_installed_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("create table a(x integer);")
+            .unwrap();
+
+        let db = Connection::open(&path).unwrap();
+        let missing = db.execute("select * from missing", []).unwrap_err();
+        assert!(is_not_installed(&missing), "{missing}");
+        let syntax = db.execute("selec 1", []).unwrap_err();
+        assert!(!is_not_installed(&syntax), "{syntax}");
+
+        let readonly =
+            Connection::open_with_flags(&path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+        let denied = readonly
+            .execute("insert into a values (1)", [])
+            .unwrap_err();
+        assert!(!is_not_installed(&denied), "{denied}");
+    }
+
+    #[test]
+    fn migrates_with_attached_databases() {
+        use crate::actions::build::FileArtifact;
+
+        let project = TestProject::new("0.2.0");
+        project
+            .write(
+                "0.1.0.sql",
+                "-- [ test 0.1.0 ]\n\
+                create table a(x integer);\n\
+                create table aux.audit(x integer);\n",
+            )
+            .write(
+                "0.2.0.sql",
+                "-- [ test 0.2.0 ]\n\
+                create table a(x integer);\n\
+                create table b(y integer);\n\
+                create table aux.audit(x integer);\n\
+                insert into aux.audit values (1);\n",
+            );
+        let from = FileArtifact::open(&project.path().join("0.1.0.sql")).unwrap();
+        let to = FileArtifact::open(&project.path().join("0.2.0.sql")).unwrap();
+
+        let (main, aux) = (
+            project.path().join("db.sqlite"),
+            project.path().join("aux.sqlite"),
+        );
+        let mut db = SqliteBackend::open_file(&main, Duration::ZERO).unwrap();
+        db.attach("aux", &aux).unwrap();
+        db.install().unwrap();
+        db.apply(&from, false).unwrap();
+        let options = GenerateOptions {
+            verify: true,
+            ..Default::default()
+        };
+        // Only the main database is compared
+        let migration = db.generate_migration(&from, &to, &options).unwrap();
+        assert_eq!(migration.to_string().trim(), "CREATE TABLE b(y integer);");
+        db.apply(&migration, false).unwrap();
+        assert_eq!(db.open().unwrap().project_version, Version::new(0, 2, 0));
+        drop(db);
+
+        let tables = |path: &Path| -> Vec<String> {
+            Connection::open(path)
+                .unwrap()
+                .prepare("select name from sqlite_schema where type = 'table' order by name")
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        // sqigl's tables stay in the main database
+        assert_eq!(tables(&aux), vec!["audit"]);
+        let main_tables = tables(&main);
+        assert!(main_tables.contains(&"a".to_string()), "{main_tables:?}");
+        assert!(main_tables.contains(&"b".to_string()), "{main_tables:?}");
+    }
+
+    #[test]
+    fn exec_commits_scripts() {
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.record(
+            ContentId::try_from(vec![0; 32]).unwrap(),
+            &Version::new(0, 1, 0),
+        )
+        .unwrap();
+        let count = |db: &SqliteBackend| -> i64 {
+            db.db
+                .query_row("select count(*) from t", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        db.exec(
+            "create temp table staging as select 1 as x;\n\
+            create table t as select x from staging;",
+            false,
+        )
+        .unwrap();
+        assert_eq!(count(&db), 1);
+        assert_eq!(db.history(&Default::default()).unwrap().len(), 1);
+
+        // A failing script is rolled back
+        assert!(db.exec("insert into t values (2); selec", true).is_err());
+        assert_eq!(count(&db), 1);
+
+        let script = "insert into t values (2);";
+        db.exec(script, true).unwrap();
+        assert_eq!(count(&db), 2);
+        let history = db.history(&Default::default()).unwrap();
+        assert_eq!(history.len(), 2);
+        // Newest first
+        assert_eq!(history[0].content_id, Sha256::digest(script).into());
+        assert_eq!(db.open().unwrap().project_version, Version::new(0, 1, 0));
+    }
+
+    #[test]
+    fn keeps_temporary_databases() {
+        use crate::actions::build::FileArtifact;
+
+        let project = TestProject::new("0.2.0");
+        project
+            .write(
+                "0.1.0.sql",
+                "-- [ test 0.1.0 ]\ncreate table a(x integer);\n",
+            )
+            .write(
+                "0.2.0.sql",
+                "-- [ test 0.2.0 ]\ncreate table a(x integer);\nselec;\n",
+            );
+        let from = FileArtifact::open(&project.path().join("0.1.0.sql")).unwrap();
+        let to = FileArtifact::open(&project.path().join("0.2.0.sql")).unwrap();
+        // No other test keeps temporary databases
+        let temp_databases = || -> BTreeSet<PathBuf> {
+            fs::read_dir(env::temp_dir())
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| {
+                    let name = path.file_name().unwrap().to_string_lossy();
+                    name.starts_with("sqigl_tmp_") && name.ends_with(".sqlite")
+                })
+                .collect()
+        };
+        let before = temp_databases();
+        let mut db = SqliteBackend::local().unwrap();
+
+        // By default, nothing is kept, even though generating the migration failed
+        assert!(db
+            .generate_migration(&from, &to, &Default::default())
+            .is_err());
+        assert_eq!(temp_databases(), before);
+
+        let options = GenerateOptions {
+            keep_temp: true,
+            ..Default::default()
+        };
+        assert!(db.generate_migration(&from, &to, &options).is_err());
+        let kept: Vec<_> = temp_databases().difference(&before).cloned().collect();
+        assert_eq!(kept.len(), 2);
+        for path in kept {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
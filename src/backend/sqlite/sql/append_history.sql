@@ -1,3 +1,3 @@
-insert into sqigl_internal_history(prev, artifact, version)
-values (?1, ?2, ?3)
+insert into main."{internal}_history"(prev, artifact, version, sqigl_version)
+values (?1, ?2, ?3, ?4)
 returning pk
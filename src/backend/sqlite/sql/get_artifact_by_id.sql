@@ -1 +1,5 @@
-insert or ignore into sqigl_internal_artifacts(id) values (?1) returning pk
+-- A no-op update rather than `insert or ignore`, which returns no row when the artifact
+-- has already been recorded
+insert into main."{internal}_artifacts"(id) values (?1)
+on conflict (id) do update set id = excluded.id
+returning pk
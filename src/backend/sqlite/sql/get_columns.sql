@@ -0,0 +1,2 @@
+select name, type, "notnull", dflt_value, pk from pragma_table_info(?1)
+order by cid;
@@ -0,0 +1,6 @@
+select sql from main.sqlite_schema
+where
+    type = 'index' and
+    tbl_name = ?1 and
+    sql is not null
+order by rowid;
@@ -0,0 +1,2 @@
+select distinct "table" from pragma_foreign_key_list(?1)
+where "table" != ?1;
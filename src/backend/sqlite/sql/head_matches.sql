@@ -0,0 +1,5 @@
+select exists(
+    select 1 from main."{internal}_history" as h
+    join main."{internal}_artifacts" as a on a.pk = h.artifact
+    where h.pk = ?1 and a.id = ?2 and h.version = ?3
+)
@@ -0,0 +1,4 @@
+select exists(
+    select 1 from pragma_table_info('{internal}_history', 'main')
+    where name = 'sqigl_version'
+)
@@ -1,3 +1,3 @@
 insert or ignore
-into sqigl_internal_state(pk, sqigl_version)
+into main."{internal}_state"(pk, sqigl_version)
 values (0, ?1)
@@ -0,0 +1,3 @@
+select distinct a.id
+from main."{internal}_history" as h
+join main."{internal}_artifacts" as a on a.pk = h.artifact
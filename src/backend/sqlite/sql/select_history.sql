@@ -0,0 +1,51 @@
+-- Walk the history from the head, so that only the changes leading to the current
+-- state are included
+with recursive chain as (
+    select h.pk, h.prev, h.artifact, h.version, h.sqigl_version, h.created_at
+    from main."{internal}_history" as h
+    join main."{internal}_state" as s on s.head = h.pk
+    union all
+    select h.pk, h.prev, h.artifact, h.version, h.sqigl_version, h.created_at
+    from main."{internal}_history" as h
+    join chain as c on h.pk = c.prev
+),
+-- Each version & bound (?1 & ?2) without build metadata, which doesn't affect precedence
+cores as (
+    select version,
+        case when instr(version, '+') > 0
+            then substr(version, 1, instr(version, '+') - 1)
+            else version
+        end as core
+    from (select version from chain union select ?1 union select ?2)
+    where version is not null
+),
+-- The precedence of each version, in the order in which they are compared: A release
+-- follows its prereleases. Casting text to an integer reads the leading digits.
+keys as (
+    select version,
+        cast(core as integer) as major,
+        cast(substr(core, instr(core, '.') + 1) as integer) as minor,
+        cast(
+            substr(
+                substr(core, instr(core, '.') + 1),
+                instr(substr(core, instr(core, '.') + 1), '.') + 1
+            ) as integer
+        ) as patch,
+        instr(core, '-') = 0 as released,
+        case when instr(core, '-') > 0 then substr(core, instr(core, '-') + 1) else '' end as pre
+    from cores
+)
+select c.version, c.sqigl_version, a.id, datetime(c.created_at, 'unixepoch') as applied_at
+from chain as c
+join main."{internal}_artifacts" as a on a.pk = c.artifact
+join keys as k on k.version = c.version
+left join keys as since on since.version = ?1
+left join keys as until on until.version = ?2
+where (?1 is null
+    or (k.major, k.minor, k.patch, k.released, k.pre)
+        >= (since.major, since.minor, since.patch, since.released, since.pre))
+and (?2 is null
+    or (k.major, k.minor, k.patch, k.released, k.pre)
+        <= (until.major, until.minor, until.patch, until.released, until.pre))
+order by c.pk desc
+limit coalesce(?3, -1)
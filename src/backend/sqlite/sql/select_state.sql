@@ -1,3 +1,3 @@
 select s.sqigl_version, h.version as project_version
-from sqigl_internal_state as s left join sqigl_internal_history as h
+from main."{internal}_state" as s left join main."{internal}_history" as h
 on s.head = h.pk
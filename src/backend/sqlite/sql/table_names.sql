@@ -1,5 +1,6 @@
 select tbl_name from sqlite_schema
 where
     type = 'table' and
-    tbl_name not like 'sqlite_%'
+    substr(tbl_name, 1, length('sqlite_')) <> 'sqlite_' and
+    substr(tbl_name, 1, length('{internal}_')) <> '{internal}_'
 group by tbl_name;
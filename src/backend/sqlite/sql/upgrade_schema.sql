@@ -0,0 +1,2 @@
+-- Bring the schema of databases installed by earlier versions of sqigl up to date
+alter table main."{internal}_history" add column sqigl_version text;
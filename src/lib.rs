@@ -0,0 +1,25 @@
+#![allow(unused, clippy::large_enum_variant, clippy::result_large_err)]
+#![deny(unused_must_use, clippy::dbg_macro)]
+//! sqigl builds SQL projects & migrates databases to them. The `sqigl` binary is a thin
+//! CLI over this library; `actions::build::build_sql` builds a project into a string.
+
+pub mod actions;
+pub mod arguments;
+pub mod artifact;
+pub mod backend;
+pub mod logging;
+pub mod manifest;
+pub mod migration;
+pub mod report;
+#[cfg(test)]
+mod testing;
+pub mod util;
+
+use artifact::Artifact;
+
+pub const SQIGL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A failure to connect to the database.
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct ConnectionError(pub anyhow::Error);
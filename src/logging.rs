@@ -0,0 +1,96 @@
+use std::io::{stderr, Write};
+
+use chrono::{SecondsFormat, Utc};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde::Serialize;
+
+/// Logs to stderr as newline-delimited JSON objects.
+pub struct JsonLogger {
+    level: LevelFilter,
+}
+impl JsonLogger {
+    pub fn new(level: LevelFilter) -> Self {
+        Self { level }
+    }
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_boxed_logger(Box::new(self))
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+}
+
+/// Render a record as a single newline-terminated JSON object.
+fn render(record: &Record) -> Option<String> {
+    let line = JsonRecord {
+        timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        level: record.level().as_str(),
+        target: record.target(),
+        message: record.args().to_string(),
+    };
+    let mut s = serde_json::to_string(&line).ok()?;
+    s.push('\n');
+    Some(s)
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if let Some(line) = render(record) {
+            // Logging is best-effort; there is nowhere to report a failure to log.
+            let _ = stderr().lock().write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = stderr().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn renders_json_lines() {
+        let line = render(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("sqigl::test")
+                .args(format_args!("multi\nline \"message\""))
+                .build(),
+        )
+        .unwrap();
+        let (json, rest) = line.split_once('\n').unwrap();
+        assert!(rest.is_empty());
+
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["target"], "sqigl::test");
+        assert_eq!(value["message"], "multi\nline \"message\"");
+        assert!(value["timestamp"].is_string());
+    }
+
+    #[test]
+    fn filters_by_level() {
+        let logger = JsonLogger::new(LevelFilter::Info);
+        let metadata = |level| Metadata::builder().level(level).build();
+        assert!(logger.enabled(&metadata(Level::Warn)));
+        assert!(logger.enabled(&metadata(Level::Info)));
+        assert!(!logger.enabled(&metadata(Level::Debug)));
+    }
+}
@@ -1,46 +1,65 @@
-#![allow(unused)]
+#![allow(unused, clippy::large_enum_variant, clippy::result_large_err)]
 #![deny(unused_must_use, clippy::dbg_macro)]
 
-use crate::{
-    actions::{apply::check_artifact, create::create_migration},
-    arguments::{
-        DatabaseCommand as DbCmd, MigrationCommands, ProjectCommands as ProjCmd, SqiglArguments,
-        SqiglCommands as Cmd,
-    },
-    backend::Backend,
-};
 use actions::{
-    apply::{apply_artifact, apply_version},
-    build::build_project,
-    create::{create_project, generate_migration, install_sqigl, new_feature},
-    save::{release, save_project},
+    apply::{
+        apply_artifact, apply_latest, apply_plan, apply_version, exec_script, plan_version,
+        IncompatibleError, Plan,
+    },
+    build::{build_project, BuildError, FileArtifact},
+    clean::clean_project,
+    create::{
+        create_module, create_project, database_info, generate_migration, install_sqigl,
+        list_features, new_feature, ping_database, show_history, GenerateOutput,
+    },
+    drift::detect_drift,
+    dump::dump_schema,
+    fmt::format_project,
+    graph::dependency_graph,
+    import::import_schema,
+    lint::lint_project,
+    repair::repair_history,
+    save::{plan_release, release, save_project, verify_build},
+    squash::squash_migrations,
+    watch::watch_project,
 };
 use anyhow::anyhow;
-use artifact::Artifact;
-use backend::{postgres::PostgresBackend, sqlite::SqliteBackend};
+use artifact::{Artifact, ScriptProcessingError};
+use backend::{postgres::PostgresBackend, sqlite::SqliteBackend, GenerateOptions, HistoryFilter};
 use clap::Parser;
-use log::debug;
+use log::{debug, info, warn};
+use logging::JsonLogger;
 use manifest::{
-    project::{open_project, Database, ProjectInfo},
-    MANIFEST_FILENAME,
+    project::{open_project, Database, OpenError, ProjectInfo},
+    read_toml, MANIFEST_FILENAME,
 };
 use migration::MigrationSet;
+use report::{error_json, ErrorKind};
+use sqigl::{
+    actions::{
+        apply::{check_all_versions, check_artifact},
+        create::create_migration,
+    },
+    arguments::{
+        BuildFormat, DatabaseArgs, DatabaseCommand as DbCmd, ErrorFormat, LogFormat,
+        MigrationCommands, ProjectCommands as ProjCmd, SqiglArguments, SqiglCommands as Cmd,
+    },
+    backend::Backend,
+};
 use std::{
     env,
-    fs::File,
+    fs::{self, File},
     io::{self, stdout},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
 };
+use util::Output;
 
-mod actions;
-pub mod arguments;
-mod artifact;
-mod backend;
-mod manifest;
-mod migration;
-mod util;
-
-pub const SQIGL_VERSION: &str = env!("CARGO_PKG_VERSION");
+use sqigl::{
+    actions, artifact, backend, logging, manifest, migration, report, util, ConnectionError,
+    SQIGL_VERSION,
+};
 
 fn get_directory(directory: Option<PathBuf>) -> io::Result<PathBuf> {
     Ok(directory
@@ -50,34 +69,125 @@ fn get_directory(directory: Option<PathBuf>) -> io::Result<PathBuf> {
         .to_path_buf())
 }
 
+/// Open a project, adding the variables of `context_file` to its build context if one
+/// was given.
+fn open_with_context(
+    project: PathBuf,
+    context_file: Option<PathBuf>,
+) -> anyhow::Result<ProjectInfo> {
+    let mut info = open_project(project.canonicalize()?)?;
+    if let Some(context_file) = context_file {
+        info.add_context_file(&context_file)?;
+    }
+    Ok(info)
+}
+
 enum DatabaseBackend {
     Postgres(PostgresBackend),
     Sqlite(SqliteBackend),
 }
 impl DatabaseBackend {
     pub fn get(info: &ProjectInfo) -> anyhow::Result<Self> {
+        Self::open(info, false).map_err(|e| ConnectionError(e).into())
+    }
+    /// Connect to the database, or for Postgres to the maintenance database if
+    /// `isolated` is set (see `PostgresBackend::get_isolated`).
+    fn open(info: &ProjectInfo, isolated: bool) -> anyhow::Result<Self> {
         match &info.database {
-            Database::Postgres(params) => Ok(Self::Postgres(PostgresBackend::get(params)?)),
+            Database::Postgres(params) => {
+                let backend = if isolated {
+                    PostgresBackend::get_isolated(params)?
+                } else {
+                    PostgresBackend::get(params)?
+                };
+                let mut backend = backend.with_internal_schema(info.internal_schema());
+                if let Some(tenant) = &info.tenant {
+                    backend = backend.with_tenant(tenant);
+                }
+                let Some(path) = &params.session_setup else {
+                    return Ok(Self::Postgres(backend));
+                };
+                let path = info.resolve_path(path);
+                let setup = fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Failed to read session setup {:?}: {}", path, e))?;
+                Ok(Self::Postgres(backend.with_session_setup(setup).map_err(
+                    |e| anyhow!("Session setup {:?} failed: {}", path, e),
+                )?))
+            }
             Database::Sqlite(params) => {
-                if let Some(path) = &params.path {
-                    let db = {
-                        if path.is_relative() {
-                            rusqlite::Connection::open(info.root.join(path))?
-                        } else {
-                            rusqlite::Connection::open(path)?
-                        }
-                    };
-                    Ok(Self::Sqlite(SqliteBackend::new(db)))
+                let backend = if let Some(path) = &params.path {
+                    let timeout = params.lock_timeout.unwrap_or(0.);
+                    let timeout = Duration::try_from_secs_f32(timeout).map_err(|e| {
+                        anyhow!("Invalid lock_timeout of {} seconds: {}", timeout, e)
+                    })?;
+                    SqliteBackend::open_file(&info.resolve_path(path), timeout)?
                 } else {
-                    let db = rusqlite::Connection::open_in_memory()?;
-                    Ok(Self::Sqlite(SqliteBackend::new(db)))
+                    SqliteBackend::new(rusqlite::Connection::open_in_memory()?)
+                };
+                let mut backend = backend
+                    .with_rename_detection(params.detect_renames)
+                    .with_internal_schema(info.internal_schema());
+                if let Some(mode) = params.journal_mode {
+                    backend.set_journal_mode(mode)?;
                 }
+                if let Some(synchronous) = params.synchronous {
+                    backend.set_synchronous(synchronous)?;
+                }
+                for (name, path) in params.attach.iter() {
+                    backend.attach(name, &info.resolve_path(path))?;
+                }
+                Ok(Self::Sqlite(backend))
             }
         }
     }
+    /// Create the database if it doesn't exist. SQLite databases are created when
+    /// they're opened, so this does nothing for them.
+    pub fn create(info: &ProjectInfo) -> anyhow::Result<()> {
+        match &info.database {
+            Database::Postgres(params) => {
+                PostgresBackend::create_database(params).map_err(ConnectionError)?;
+            }
+            Database::Sqlite(_) => debug!("SQLite databases are created when they're opened"),
+        }
+        Ok(())
+    }
+    /// Connect for checking artifacts in isolation, without touching the configured
+    /// database: Postgres backends connect to the maintenance database to create
+    /// temporary ones, & SQLite databases aren't opened at all.
+    pub fn isolated(info: &ProjectInfo) -> anyhow::Result<Self> {
+        match &info.database {
+            Database::Postgres(_) => Self::open(info, true).map_err(|e| ConnectionError(e).into()),
+            Database::Sqlite(params) => {
+                let mut backend =
+                    SqliteBackend::local()?.with_internal_schema(info.internal_schema());
+                for name in params.attach.keys() {
+                    backend.attach(name, Path::new(":memory:"))?;
+                }
+                Ok(Self::Sqlite(backend))
+            }
+        }
+    }
+    /// Connect to the database, failing if sqigl isn't installed and `auto_install`
+    /// is not set.
+    pub fn connect(info: &ProjectInfo, auto_install: bool) -> anyhow::Result<Self> {
+        let mut backend = Self::get(info)?;
+        if !auto_install {
+            let installed = match &mut backend {
+                Self::Postgres(backend) => backend.installed()?,
+                Self::Sqlite(backend) => backend.installed()?,
+            };
+            if !installed {
+                return Err(anyhow!(
+                    "sqigl is not installed on this database; run `sqigl database install` first"
+                ));
+            }
+        }
+        Ok(backend)
+    }
 }
 
 pub fn run(args: SqiglArguments) -> anyhow::Result<()> {
+    let auto_install = !args.no_install;
     match args.command {
         Cmd::Project(cmd) => match cmd {
             ProjCmd::Init { title, database } => {
@@ -97,112 +207,820 @@ pub fn run(args: SqiglArguments) -> anyhow::Result<()> {
                 let info = open_project(project.canonicalize()?)?;
                 let new_version = new_feature(title, info)?;
             }
+            ProjCmd::Features { project } => {
+                let info = open_project(project.canonicalize()?)?;
+                for feature in list_features(&info)? {
+                    println!("{}", feature);
+                }
+            }
             ProjCmd::Build {
                 project,
                 output,
-                quiet,
+                no_output,
+                split,
+                show_id,
+                expect_id,
+                format,
+                pager,
+                context_file,
+                strict,
+                deny_duplicate_names,
+                only,
+                skip,
             } => {
-                let info = open_project(project.canonicalize()?)?;
-                let artifact = build_project(&info)?;
-                if let Some(output) = output {
+                let info = open_with_context(project, context_file)?;
+                let artifact = build_project(&info)?.filter_modules(&only, &skip)?;
+                if strict {
+                    artifact.check_strict()?;
+                }
+                if deny_duplicate_names {
+                    artifact.check_duplicate_names()?;
+                }
+                if let Some(expected) = expect_id {
+                    let id = artifact.content_id();
+                    if id != expected {
+                        return Err(anyhow!(
+                            "Content ID mismatch:\n    expected {}\n    found    {}",
+                            expected,
+                            id
+                        ));
+                    }
+                }
+
+                let id = if let Some(directory) = split {
+                    artifact.write_split(&directory)?
+                } else if let Some(output) = output {
                     if output.exists() {
                         return Err(anyhow!("Output already exists"));
                     }
                     let f = File::create_new(output)?;
-                    let _id = artifact.write_to(f)?;
-                } else if !quiet {
-                    let _id = artifact.write_to(stdout())?;
+                    match format {
+                        BuildFormat::Raw => artifact.write_to(f)?,
+                        BuildFormat::Psql => artifact.write_psql_to(f)?,
+                        BuildFormat::Bundle => artifact.write_bundle(&info.project.title, f)?,
+                    }
+                } else if !no_output {
+                    let mut out = Output::open(pager);
+                    let id = match format {
+                        BuildFormat::Raw => artifact.write_to(&mut out)?,
+                        BuildFormat::Psql => artifact.write_psql_to(&mut out)?,
+                        BuildFormat::Bundle => {
+                            artifact.write_bundle(&info.project.title, &mut out)?
+                        }
+                    };
+                    out.finish()?;
+                    id
+                } else {
+                    artifact.content_id()
+                };
+                if show_id {
+                    eprintln!("{}", id);
                 }
             }
-            ProjCmd::Check { project } => {
-                let info = open_project(project.canonicalize()?)?;
-                let artifact = build_project(&info)?;
-                match DatabaseBackend::get(&info)? {
-                    DatabaseBackend::Postgres(backend) => check_artifact(artifact, backend)?,
-                    DatabaseBackend::Sqlite(backend) => check_artifact(artifact, backend)?,
+            ProjCmd::Check {
+                project,
+                isolated,
+                all_versions,
+                context_file,
+            } => {
+                let info = open_with_context(project, context_file)?;
+                if all_versions {
+                    let checks = match DatabaseBackend::isolated(&info)? {
+                        DatabaseBackend::Postgres(backend) => check_all_versions(&info, backend)?,
+                        DatabaseBackend::Sqlite(backend) => check_all_versions(&info, backend)?,
+                    };
+                    print!("{}", checks);
+                    if checks.failed() > 0 {
+                        return Err(anyhow!(
+                            "{} saved schema(s) failed to apply",
+                            checks.failed()
+                        ));
+                    }
+                } else {
+                    let artifact = build_project(&info)?;
+                    let backend = if isolated {
+                        DatabaseBackend::isolated(&info)?
+                    } else {
+                        DatabaseBackend::connect(&info, auto_install)?
+                    };
+                    match backend {
+                        DatabaseBackend::Postgres(backend) => {
+                            check_artifact(artifact, backend, isolated)?
+                        }
+                        DatabaseBackend::Sqlite(backend) => {
+                            check_artifact(artifact, backend, isolated)?
+                        }
+                    };
+                }
+            }
+            ProjCmd::Apply {
+                project,
+                force,
+                batch_size,
+                context_file,
+            } => {
+                let info = open_with_context(project, context_file)?;
+                let artifact = build_project(&info)?.with_batch_size(batch_size);
+                match DatabaseBackend::connect(&info, auto_install)? {
+                    DatabaseBackend::Postgres(mut backend) => {
+                        apply_artifact(&mut backend, artifact, force, None)?
+                    }
+                    DatabaseBackend::Sqlite(mut backend) => {
+                        apply_artifact(&mut backend, artifact, force, None)?
+                    }
                 };
             }
-            ProjCmd::Apply { project } => {
-                let info = open_project(project.canonicalize()?)?;
-                let artifact = build_project(&info)?;
-                match DatabaseBackend::get(&info)? {
-                    DatabaseBackend::Postgres(backend) => apply_artifact(backend, artifact)?,
-                    DatabaseBackend::Sqlite(backend) => apply_artifact(backend, artifact)?,
+            ProjCmd::Watch {
+                project,
+                force,
+                isolated,
+                context_file,
+            } => {
+                let info = open_with_context(project, context_file.clone())?;
+                let backend = if isolated {
+                    DatabaseBackend::isolated(&info)?
+                } else {
+                    DatabaseBackend::connect(&info, auto_install)?
+                };
+                match backend {
+                    DatabaseBackend::Postgres(backend) => {
+                        watch_project(&info, backend, force, isolated, context_file.as_deref())?
+                    }
+                    DatabaseBackend::Sqlite(backend) => {
+                        watch_project(&info, backend, force, isolated, context_file.as_deref())?
+                    }
                 };
             }
-            ProjCmd::Save { project } => {
+            ProjCmd::Save {
+                project,
+                compress,
+                context_file,
+            } => {
+                let info = open_with_context(project, context_file)?;
+                save_project(&info, compress)?;
+            }
+            ProjCmd::Graph {
+                project,
+                context_file,
+            } => {
+                let info = open_with_context(project, context_file)?;
+                print!("{}", dependency_graph(&info)?);
+            }
+            ProjCmd::Module {
+                path,
+                project,
+                dependencies,
+            } => {
                 let info = open_project(project.canonicalize()?)?;
-                save_project(&info)?;
+                create_module(&info, &path, dependencies)?;
             }
-            ProjCmd::Release { level, project } => {
+            ProjCmd::Lint { project } => {
                 let info = open_project(project.canonicalize()?)?;
-                let new_version = match DatabaseBackend::get(&info)? {
-                    DatabaseBackend::Postgres(backend) => release(level, &info, backend)?,
-                    DatabaseBackend::Sqlite(backend) => release(level, &info, backend)?,
-                };
+                let lint = lint_project(&info)?;
+                if !lint.findings.is_empty() {
+                    print!("{}", lint);
+                    return Err(anyhow!("Found {} problem(s)", lint.findings.len()));
+                }
+                info!("No problems found");
             }
-        },
-        Cmd::Database(cmd) => match cmd {
-            DbCmd::Install { project } => {
-                let info = open_project(project)?;
-                match DatabaseBackend::get(&info)? {
-                    DatabaseBackend::Postgres(backend) => install_sqigl(backend)?,
-                    DatabaseBackend::Sqlite(backend) => install_sqigl(backend)?,
+            ProjCmd::Fmt { project, check } => {
+                let info = open_project(project.canonicalize()?)?;
+                let changed = format_project(&info, check)?;
+                if check && !changed.is_empty() {
+                    for manifest in changed.iter() {
+                        println!("{}", manifest.display());
+                    }
+                    return Err(anyhow!("{} manifest(s) are not formatted", changed.len()));
+                }
+            }
+            ProjCmd::VerifyBuild {
+                project,
+                context_file,
+            } => {
+                let info = open_with_context(project, context_file)?;
+                let verification = verify_build(&info)?;
+                print!("{}", verification);
+                if !verification.matches() {
+                    return Err(anyhow!(
+                        "The build differs from version {}",
+                        verification.version
+                    ));
+                }
+            }
+            ProjCmd::Clean { project, dry_run } => {
+                let info = open_project(project.canonicalize()?)?;
+                let _removed = clean_project(&info, dry_run)?;
+            }
+            ProjCmd::Release {
+                level,
+                project,
+                compress,
+                offline,
+                dry_run,
+                context_file,
+            } => {
+                let info = open_with_context(project, context_file)?;
+                let plan = if offline {
+                    plan_release(level, &info, None::<SqliteBackend>, compress)?
+                } else {
+                    match DatabaseBackend::connect(&info, auto_install)? {
+                        DatabaseBackend::Postgres(backend) => {
+                            plan_release(level, &info, Some(backend), compress)?
+                        }
+                        DatabaseBackend::Sqlite(backend) => {
+                            plan_release(level, &info, Some(backend), compress)?
+                        }
+                    }
                 };
+                if dry_run {
+                    print!("{}", plan);
+                } else {
+                    release(plan, &info)?;
+                }
             }
-            DbCmd::Apply { version, project } => {
+            ProjCmd::Import { project } => {
                 let info = open_project(project.canonicalize()?)?;
                 match DatabaseBackend::get(&info)? {
-                    DatabaseBackend::Postgres(backend) => apply_version(version, &info, backend)?,
-                    DatabaseBackend::Sqlite(backend) => apply_version(version, &info, backend)?,
+                    DatabaseBackend::Postgres(backend) => import_schema(&info, backend)?,
+                    DatabaseBackend::Sqlite(backend) => import_schema(&info, backend)?,
                 };
             }
         },
+        Cmd::Database(DatabaseArgs { schema, command }) => {
+            // Opens the project, targeting the tenant if one was given
+            let open = |project: PathBuf| -> anyhow::Result<ProjectInfo> {
+                let mut info = open_project(project.canonicalize()?)?;
+                if let Some(tenant) = &schema {
+                    info.set_tenant(tenant)?;
+                }
+                Ok(info)
+            };
+            match command {
+                DbCmd::Install {
+                    project,
+                    create_database,
+                } => {
+                    let info = open(project)?;
+                    if create_database {
+                        DatabaseBackend::create(&info)?;
+                    }
+                    match DatabaseBackend::get(&info)? {
+                        DatabaseBackend::Postgres(backend) => install_sqigl(backend)?,
+                        DatabaseBackend::Sqlite(backend) => install_sqigl(backend)?,
+                    };
+                }
+                DbCmd::Apply {
+                    version,
+                    project,
+                    latest,
+                    plan,
+                    force,
+                    parallel,
+                    create_database,
+                } => {
+                    let info = open(project)?;
+                    if create_database {
+                        DatabaseBackend::create(&info)?;
+                    }
+                    let plan = plan.map(|path| Plan::open(&path)).transpose()?;
+                    let backend = DatabaseBackend::connect(&info, auto_install)?;
+                    match (plan, version, backend) {
+                        (Some(plan), _, DatabaseBackend::Postgres(backend)) => {
+                            apply_plan(&plan, &info, backend, parallel)?
+                        }
+                        (Some(plan), _, DatabaseBackend::Sqlite(backend)) => {
+                            apply_plan(&plan, &info, backend, parallel)?
+                        }
+                        (None, Some(version), DatabaseBackend::Postgres(backend)) => {
+                            apply_version(version, &info, backend, force, parallel)?
+                        }
+                        (None, Some(version), DatabaseBackend::Sqlite(backend)) => {
+                            apply_version(version, &info, backend, force, parallel)?
+                        }
+                        (None, None, DatabaseBackend::Postgres(backend)) => {
+                            apply_latest(&info, backend, force, parallel)?
+                        }
+                        (None, None, DatabaseBackend::Sqlite(backend)) => {
+                            apply_latest(&info, backend, force, parallel)?
+                        }
+                    };
+                }
+                DbCmd::ApplyFile {
+                    artifact,
+                    project,
+                    force,
+                } => {
+                    let info = open(project)?;
+                    let artifact = FileArtifact::open(&artifact)?;
+                    if artifact.title() != info.project.title.trim_ascii() {
+                        warn!(
+                            "This build is of {}, not {}",
+                            artifact.title(),
+                            info.project.title
+                        );
+                    }
+                    match DatabaseBackend::connect(&info, auto_install)? {
+                        DatabaseBackend::Postgres(mut backend) => {
+                            apply_artifact(&mut backend, artifact, force, None)?
+                        }
+                        DatabaseBackend::Sqlite(mut backend) => {
+                            apply_artifact(&mut backend, artifact, force, None)?
+                        }
+                    };
+                }
+                DbCmd::Exec {
+                    script,
+                    project,
+                    record,
+                } => {
+                    let info = open(project)?;
+                    match DatabaseBackend::connect(&info, auto_install)? {
+                        DatabaseBackend::Postgres(backend) => {
+                            exec_script(&script, backend, record)?
+                        }
+                        DatabaseBackend::Sqlite(backend) => exec_script(&script, backend, record)?,
+                    };
+                }
+                DbCmd::Ping { project } => {
+                    let info = open(project)?;
+                    match DatabaseBackend::get(&info)? {
+                        DatabaseBackend::Postgres(backend) => ping_database(backend)?,
+                        DatabaseBackend::Sqlite(backend) => ping_database(backend)?,
+                    };
+                }
+                DbCmd::Plan { project, to } => {
+                    let info = open(project)?;
+                    let plan = match DatabaseBackend::connect(&info, false)? {
+                        DatabaseBackend::Postgres(backend) => plan_version(to, &info, backend)?,
+                        DatabaseBackend::Sqlite(backend) => plan_version(to, &info, backend)?,
+                    };
+                    println!("{}", serde_json::to_string_pretty(&plan)?);
+                }
+                DbCmd::DumpSchema { project, format } => {
+                    let info = open(project)?;
+                    let dump = match DatabaseBackend::get(&info)? {
+                        DatabaseBackend::Postgres(backend) => dump_schema(&info, backend, format)?,
+                        DatabaseBackend::Sqlite(backend) => dump_schema(&info, backend, format)?,
+                    };
+                    println!("{}", dump.trim_end());
+                }
+                DbCmd::History {
+                    project,
+                    since,
+                    until,
+                    limit,
+                } => {
+                    let info = open(project)?;
+                    let filter = HistoryFilter {
+                        since,
+                        until,
+                        limit,
+                    };
+                    match DatabaseBackend::connect(&info, false)? {
+                        DatabaseBackend::Postgres(backend) => {
+                            show_history(backend, &filter, &info)?
+                        }
+                        DatabaseBackend::Sqlite(backend) => show_history(backend, &filter, &info)?,
+                    };
+                }
+                DbCmd::Drift { project } => {
+                    let info = open(project)?;
+                    let drifted = match DatabaseBackend::connect(&info, false)? {
+                        DatabaseBackend::Postgres(backend) => detect_drift(&info, backend)?,
+                        DatabaseBackend::Sqlite(backend) => detect_drift(&info, backend)?,
+                    };
+                    if !drifted.is_empty() {
+                        for id in drifted.iter() {
+                            println!("{}: applied, but matches no artifact in the project", id);
+                        }
+                        return Err(anyhow!("Found {} drifted artifact(s)", drifted.len()));
+                    }
+                    info!("No drift found");
+                }
+                DbCmd::Repair { project, yes } => {
+                    let info = open(project)?;
+                    let repair = match DatabaseBackend::connect(&info, false)? {
+                        DatabaseBackend::Postgres(backend) => repair_history(&info, backend, yes)?,
+                        DatabaseBackend::Sqlite(backend) => repair_history(&info, backend, yes)?,
+                    };
+                    if let Some(repair) = repair {
+                        println!("{} -> {}", repair.recorded, repair.version);
+                    }
+                }
+                DbCmd::Info { project } => {
+                    let info = open(project)?;
+                    print!("{}", database_info(&info)?);
+                }
+            }
+        }
         Cmd::Migration(cmd) => match cmd {
-            MigrationCommands::Create { from, to, project } => {
+            MigrationCommands::Create {
+                from,
+                to,
+                project,
+                exact,
+                with_down,
+            } => {
                 let info = open_project(project.canonicalize()?)?;
                 create_migration(
                     from,
                     to.unwrap_or_else(|| info.project.version.clone()),
                     &info,
+                    exact,
+                    with_down,
                 )?;
             }
-            MigrationCommands::Generate { from, to, project } => {
+            MigrationCommands::Generate {
+                from,
+                to,
+                project,
+                compress,
+                yes,
+                keep_temp,
+                no_verify,
+                exact,
+                if_exists,
+                plan_only,
+                exclude_schemas,
+                format,
+            } => {
                 let info = open_project(project.canonicalize()?)?;
-                match DatabaseBackend::get(&info)? {
+                let to = to.unwrap_or_else(|| info.project.version.clone());
+                let mut options = GenerateOptions::new(&info.migrate);
+                options.exclude_schemas.extend(exclude_schemas);
+                options.keep_temp = keep_temp;
+                options.verify = !no_verify;
+                options.exact = exact;
+                options.if_exists = if_exists;
+                let output = if plan_only {
+                    GenerateOutput::Plan(format)
+                } else {
+                    GenerateOutput::Save { compress, yes }
+                };
+                match DatabaseBackend::connect(&info, auto_install)? {
                     DatabaseBackend::Postgres(mut backend) => {
-                        generate_migration(
-                            from,
-                            to.unwrap_or_else(|| info.project.version.clone()),
-                            &mut backend,
-                            &info,
-                        )?;
+                        generate_migration(from, to, &mut backend, &info, &options, output)?
                     }
                     DatabaseBackend::Sqlite(mut backend) => {
-                        generate_migration(
-                            from,
-                            to.unwrap_or_else(|| info.project.version.clone()),
-                            &mut backend,
-                            &info,
-                        )?;
+                        generate_migration(from, to, &mut backend, &info, &options, output)?
                     }
                 };
             }
-            MigrationCommands::Check { ..  } => todo!(),
-            MigrationCommands::Apply { ..  } => todo!(),
+            MigrationCommands::Squash {
+                from,
+                to,
+                project,
+                archive,
+                compress,
+            } => {
+                let info = open_project(project.canonicalize()?)?;
+                match DatabaseBackend::connect(&info, auto_install)? {
+                    DatabaseBackend::Postgres(mut backend) => {
+                        squash_migrations(from, to, &info, &mut backend, archive, compress)?;
+                    }
+                    DatabaseBackend::Sqlite(mut backend) => {
+                        squash_migrations(from, to, &info, &mut backend, archive, compress)?;
+                    }
+                };
+            }
+            MigrationCommands::Check { .. } => todo!(),
+            MigrationCommands::Apply { .. } => todo!(),
         },
     }
 
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() {
     let args = SqiglArguments::parse();
-    simple_logger::SimpleLogger::new()
-        .with_level(args.log_level.into())
-        .init()
-        .unwrap();
+    let log_level = args.effective_log_level();
+    match args.log_format {
+        LogFormat::Text => simple_logger::SimpleLogger::new()
+            .with_level(log_level)
+            .init()
+            .unwrap(),
+        LogFormat::Json => JsonLogger::new(log_level).init().unwrap(),
+    }
 
     debug!("sqigl Version: {}", SQIGL_VERSION);
-    run(args)
+    let error_format = args.error_format;
+    if let Err(e) = run(args) {
+        match error_format {
+            // As reported when `main` returns an error
+            ErrorFormat::Text => eprintln!("Error: {:?}", e),
+            ErrorFormat::Json => eprintln!("{}", error_json(&e)),
+        }
+        process::exit(ErrorKind::classify(&e).exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_lock_timeout() {
+        // The library's test helpers aren't available to the binary's tests
+        let project = tempfile::tempdir().unwrap();
+        for timeout in ["inf", "-1.0", "1e30"] {
+            fs::write(
+                project.path().join(MANIFEST_FILENAME),
+                format!(
+                    "[project]\ntitle = \"test\"\nversion = \"0.1.0\"\n\n\
+                    [database]\ndb = \"sqlite\"\npath = \"db.sqlite\"\nlock_timeout = {timeout}\n"
+                ),
+            )
+            .unwrap();
+            let info = open_project(project.path().to_path_buf()).unwrap();
+            assert!(
+                DatabaseBackend::get(&info).is_err(),
+                "{timeout} was accepted"
+            );
+        }
+    }
+
+    /// A project with one script, whose `[database]` table is `database`.
+    fn project_with_database(database: &str) -> (tempfile::TempDir, ProjectInfo) {
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join(MANIFEST_FILENAME),
+            format!("[project]\ntitle = \"test\"\nversion = \"0.1.0\"\n\n[database]\n{database}"),
+        )
+        .unwrap();
+        fs::create_dir(project.path().join("src")).unwrap();
+        fs::write(
+            project.path().join("src/a.sql"),
+            "create table a(x integer);",
+        )
+        .unwrap();
+        let info = open_project(project.path().canonicalize().unwrap()).unwrap();
+        (project, info)
+    }
+
+    #[test]
+    fn sqlite_isolated_check_leaves_database_alone() {
+        let (project, info) = project_with_database("db = \"sqlite\"\npath = \"db.sqlite\"\n");
+        let artifact = build_project(&info).unwrap();
+        let DatabaseBackend::Sqlite(backend) = DatabaseBackend::isolated(&info).unwrap() else {
+            panic!("Expected a SQLite backend");
+        };
+        check_artifact(artifact, backend, true).unwrap();
+        assert!(!project.path().join("db.sqlite").exists());
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn postgres_isolated_check_leaves_database_alone() {
+        // The configured database doesn't exist, so connecting to it would fail
+        let database = "sqigl_test_never_created";
+        let password = tempfile::NamedTempFile::new().unwrap();
+        fs::write(password.path(), "password\n").unwrap();
+        let (_project, info) = project_with_database(&format!(
+            "db = \"postgres\"\nhostname = \"localhost\"\nport = 5432\nusername = \"sqigl\"\n\
+            password_file = {:?}\nsslmode = \"disable\"\ndatabase = \"{database}\"\n",
+            password.path()
+        ));
+        let artifact = build_project(&info).unwrap();
+        let DatabaseBackend::Postgres(backend) = DatabaseBackend::isolated(&info).unwrap() else {
+            panic!("Expected a Postgres backend");
+        };
+        check_artifact(artifact, backend, true).unwrap();
+
+        let mut db = postgres::Client::connect(
+            "host=localhost user=sqigl password=password dbname=sqigl",
+            postgres::NoTls,
+        )
+        .unwrap();
+        let exists: bool = db
+            .query_one(
+                "select exists (select from pg_database where datname = $1)",
+                &[&database],
+            )
+            .unwrap()
+            .get(0);
+        assert!(!exists);
+    }
+
+    #[test]
+    fn sqlite_connect_without_install() {
+        let (_project, info) = project_with_database("db = \"sqlite\"\npath = \"db.sqlite\"\n");
+        assert!(DatabaseBackend::connect(&info, false).is_err());
+        let DatabaseBackend::Sqlite(mut backend) = DatabaseBackend::get(&info).unwrap() else {
+            panic!("Expected a SQLite backend");
+        };
+        assert!(!backend.installed().unwrap());
+        backend.install().unwrap();
+        // Release the database's lock
+        drop(backend);
+        assert!(DatabaseBackend::connect(&info, false).is_ok());
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn postgres_connect_without_install() {
+        let database = "sqigl_test_no_install";
+        let mut admin = postgres::Client::connect(
+            "host=localhost user=sqigl password=password dbname=sqigl",
+            postgres::NoTls,
+        )
+        .unwrap();
+        for statement in ["drop database if exists", "create database"] {
+            admin
+                .batch_execute(&format!("{statement} {database}"))
+                .unwrap();
+        }
+
+        let password = tempfile::NamedTempFile::new().unwrap();
+        fs::write(password.path(), "password\n").unwrap();
+        let (_project, info) = project_with_database(&format!(
+            "db = \"postgres\"\nhostname = \"localhost\"\nport = 5432\nusername = \"sqigl\"\n\
+            password_file = {:?}\nsslmode = \"disable\"\ndatabase = \"{database}\"\n",
+            password.path()
+        ));
+        assert!(DatabaseBackend::connect(&info, false).is_err());
+        let DatabaseBackend::Postgres(mut backend) = DatabaseBackend::get(&info).unwrap() else {
+            panic!("Expected a Postgres backend");
+        };
+        assert!(!backend.installed().unwrap());
+        backend.install().unwrap();
+        assert!(DatabaseBackend::connect(&info, false).is_ok());
+
+        drop(backend);
+        admin
+            .batch_execute(&format!("drop database {database} with (force);"))
+            .unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn postgres_install_creates_database() {
+        let database = "sqigl_test_create_database";
+        let mut admin = postgres::Client::connect(
+            "host=localhost user=sqigl password=password dbname=sqigl",
+            postgres::NoTls,
+        )
+        .unwrap();
+        admin
+            .batch_execute(&format!("drop database if exists {database}"))
+            .unwrap();
+
+        let password = tempfile::NamedTempFile::new().unwrap();
+        fs::write(password.path(), "password\n").unwrap();
+        let (project, info) = project_with_database(&format!(
+            "db = \"postgres\"\nhostname = \"localhost\"\nport = 5432\nusername = \"sqigl\"\n\
+            password_file = {:?}\nsslmode = \"disable\"\ndatabase = \"{database}\"\n",
+            password.path()
+        ));
+        assert!(DatabaseBackend::get(&info).is_err());
+        // Installing again finds the database already exists
+        for _ in 0..2 {
+            run(SqiglArguments::parse_from([
+                "sqigl".as_ref(),
+                "database".as_ref(),
+                "install".as_ref(),
+                project.path().as_os_str(),
+                "--create-database".as_ref(),
+            ]))
+            .unwrap();
+        }
+        let DatabaseBackend::Postgres(mut backend) =
+            DatabaseBackend::connect(&info, false).unwrap()
+        else {
+            panic!("Expected a Postgres backend");
+        };
+        assert!(backend.installed().unwrap());
+
+        drop(backend);
+        admin
+            .batch_execute(&format!("drop database {database} with (force);"))
+            .unwrap();
+    }
+
+    #[test]
+    fn build_expect_id() {
+        let (project, info) = project_with_database("db = \"sqlite\"\n");
+        let id = build_project(&info).unwrap().content_id().to_string();
+        let build = |expected: &str| {
+            run(SqiglArguments::parse_from([
+                "sqigl".as_ref(),
+                "project".as_ref(),
+                "build".as_ref(),
+                project.path().as_os_str(),
+                "--no-output".as_ref(),
+                "--expect-id".as_ref(),
+                expected.as_ref(),
+            ]))
+        };
+        build(&id).unwrap();
+
+        let (head, last) = id.split_at(id.len() - 1);
+        let wrong = format!("{head}{}", if last == "0" { '1' } else { '0' });
+        let e = build(&wrong).unwrap_err().to_string();
+        assert!(
+            e.contains(&format!("expected {wrong}")) && e.contains(&format!("found    {id}")),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn applies_built_files() {
+        let (project, info) = project_with_database("db = \"sqlite\"\npath = \"db.sqlite\"\n");
+        let id = build_project(&info).unwrap().content_id();
+        for format in ["raw", "bundle"] {
+            let output = project.path().join(format!("{format}.sql"));
+            let _ = fs::remove_file(project.path().join("db.sqlite"));
+            run(SqiglArguments::parse_from([
+                "sqigl".as_ref(),
+                "project".as_ref(),
+                "build".as_ref(),
+                project.path().as_os_str(),
+                output.as_os_str(),
+                "--format".as_ref(),
+                format.as_ref(),
+            ]))
+            .unwrap();
+            assert_eq!(FileArtifact::open(&output).unwrap().content_id(), id);
+            run(SqiglArguments::parse_from([
+                "sqigl".as_ref(),
+                "database".as_ref(),
+                "apply-file".as_ref(),
+                output.as_os_str(),
+                project.path().as_os_str(),
+            ]))
+            .unwrap();
+
+            let DatabaseBackend::Sqlite(mut backend) = DatabaseBackend::get(&info).unwrap() else {
+                panic!("Expected a SQLite backend");
+            };
+            assert_eq!(
+                backend.open().unwrap().project_version,
+                semver::Version::new(0, 1, 0)
+            );
+            let history = backend.history(&Default::default()).unwrap();
+            assert_eq!(history.len(), 1, "{format}");
+            assert_eq!(history[0].content_id, id, "{format}");
+        }
+    }
+
+    #[test]
+    fn builds_from_the_manifest_path() {
+        let (project, _info) = project_with_database("db = \"sqlite\"\n");
+        run(SqiglArguments::parse_from([
+            "sqigl".as_ref(),
+            "project".as_ref(),
+            "build".as_ref(),
+            project.path().join(MANIFEST_FILENAME).as_os_str(),
+            "--no-output".as_ref(),
+        ]))
+        .unwrap();
+    }
+
+    fn ping(project: &Path) -> anyhow::Result<()> {
+        run(SqiglArguments::parse_from([
+            "sqigl".as_ref(),
+            "database".as_ref(),
+            "ping".as_ref(),
+            project.as_os_str(),
+        ]))
+    }
+
+    #[test]
+    fn sqlite_ping() {
+        let (project, info) = project_with_database("db = \"sqlite\"\npath = \"db.sqlite\"\n");
+        ping(project.path()).unwrap();
+        let DatabaseBackend::Sqlite(mut backend) = DatabaseBackend::get(&info).unwrap() else {
+            panic!("Expected a SQLite backend");
+        };
+        assert!(!backend.installed().unwrap());
+
+        let (project, _info) =
+            project_with_database("db = \"sqlite\"\npath = \"missing/db.sqlite\"\n");
+        assert!(ping(project.path()).is_err());
+    }
+
+    #[test]
+    #[ignore = "requires a local Postgres server"]
+    fn postgres_ping() {
+        let password = tempfile::NamedTempFile::new().unwrap();
+        fs::write(password.path(), "password\n").unwrap();
+        let database = |port: u16| {
+            format!(
+                "db = \"postgres\"\nhostname = \"localhost\"\nport = {port}\n\
+                username = \"sqigl\"\npassword_file = {:?}\nsslmode = \"disable\"\n\
+                database = \"sqigl\"\nconnect_retries = 0\n",
+                password.path()
+            )
+        };
+        let (project, _info) = project_with_database(&database(5432));
+        ping(project.path()).unwrap();
+
+        // Nothing is listening on a port which was just released
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (project, _info) = project_with_database(&database(port));
+        assert!(ping(project.path()).is_err());
+    }
 }
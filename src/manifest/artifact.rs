@@ -1,8 +1,9 @@
 use std::{
     ffi::OsStr,
+    fmt,
     fs::{self, File},
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use log::{error, warn};
@@ -16,7 +17,7 @@ use crate::{
     util::{new_table, new_table_arr, normalize_version, replace_file},
 };
 
-use super::{project::ProjectInfo, ReadTomlError};
+use super::{project::ProjectInfo, ReadTomlError, TomlError};
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ArtifactManifest {
@@ -36,12 +37,61 @@ pub struct Migration {
     pub script: PathBuf,
     pub from: VersionReq,
     pub to: Version,
+    /// The statements of the migration are independent of each other, & may be applied
+    /// concurrently, each in its own transaction.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub parallel: bool,
+    /// Maintenance to run once the migration has been committed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post: Option<Maintenance>,
+    /// What the migration does & why.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// A script which reverses the migration, relative to the artifact directory. It is
+    /// maintained alongside the migration, but sqigl doesn't apply it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub down: Option<PathBuf>,
 }
 impl Migration {
     pub fn insert(&self, table: &mut toml_edit::Table) {
         table["script"] = self.script.to_str().unwrap().into();
         table["from"] = self.from.to_string().into();
         table["to"] = self.to.to_string().into();
+        if self.parallel {
+            table["parallel"] = true.into();
+        }
+        if let Some(post) = self.post {
+            table["post"] = post.as_str().into();
+        }
+        if let Some(description) = &self.description {
+            table["description"] = description.as_str().into();
+        }
+        if let Some(down) = &self.down {
+            table["down"] = down.to_str().unwrap().into();
+        }
+    }
+}
+
+/// A maintenance command run after a migration, outside of its transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Maintenance {
+    /// Refresh the statistics used by the query planner.
+    Analyze,
+    /// Reclaim unused space (& on Postgres, also refresh statistics).
+    Vacuum,
+}
+impl Maintenance {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Maintenance::Analyze => "analyze",
+            Maintenance::Vacuum => "vacuum",
+        }
+    }
+}
+impl fmt::Display for Maintenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
     }
 }
 impl Migration {
@@ -86,9 +136,9 @@ pub enum OpenError {
     #[error("I/O Error: {0}")]
     Io(#[from] io::Error),
     #[error("TOML syntax error: {0}")]
-    SyntaxError(toml::de::Error),
+    SyntaxError(TomlError),
     #[error("Invalid manifest: {0}")]
-    Invalid(toml::de::Error),
+    Invalid(TomlError),
 }
 impl From<ReadTomlError> for OpenError {
     fn from(value: ReadTomlError) -> Self {
@@ -147,6 +197,43 @@ pub fn update_artifact_migration(
     Ok(())
 }
 
+/// Remove the migration using `script` from an artifact manifest, if there is one.
+/// The migration saved as `script` in an artifact directory, if there is one.
+pub fn find_artifact_migration(
+    script: &Path,
+    artifact_directory: PathBuf,
+) -> Result<Option<Migration>, OpenError> {
+    if !artifact_directory.join(MANIFEST_FILENAME).is_file() {
+        return Ok(None);
+    }
+    Ok(open_artifact(artifact_directory)?
+        .migrations
+        .into_iter()
+        .find(|migration| migration.script == script))
+}
+
+pub fn remove_artifact_migration(
+    script: &Path,
+    artifact_directory: PathBuf,
+) -> Result<(), UpdateMigrationError> {
+    let path = artifact_directory.join(MANIFEST_FILENAME);
+    if !path.exists() {
+        return Ok(());
+    }
+    let content = fs::read_to_string(&path)?;
+    let mut document: DocumentMut = content.parse()?;
+    drop(content);
+    document
+        .entry(Migration::KEY)
+        .or_insert_with(new_table_arr)
+        .as_array_of_tables_mut()
+        .ok_or_else(|| UpdateMigrationError::InvalidValue(Migration::KEY.to_string()))?
+        .retain(|m| m.get("script").and_then(|v| v.as_str()) != script.to_str());
+
+    replace_file(&document.to_string(), &path)?;
+    Ok(())
+}
+
 #[derive(Debug, Error)]
 pub enum UpdateMigrationError {
     #[error("I/O error: {0}")]
@@ -163,8 +250,8 @@ pub fn update_migration_versions(
     info: &ProjectInfo,
 ) -> Result<(), UpdateVersionsError> {
     let artifacts_dir = info.artifacts_dir();
-    let old_module = artifacts_dir.join(normalize_version(&old_version).to_string());
-    let new_module = artifacts_dir.join(normalize_version(&new_version).to_string());
+    let old_module = artifacts_dir.join(normalize_version(old_version).to_string());
+    let new_module = artifacts_dir.join(normalize_version(new_version).to_string());
     if new_module.exists() {
         return Err(UpdateVersionsError::AlreadyExists(new_version.clone()));
     }
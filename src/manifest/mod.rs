@@ -7,10 +7,11 @@ use crate::{
     util::{empty_database_version, new_project_version, new_table},
 };
 use semver::{Version, VersionReq};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::serde_as;
 use std::{
     collections::HashMap,
+    fmt::Display,
     fs::{self, File},
     io::{self, Write},
     path::{Path, PathBuf},
@@ -22,25 +23,24 @@ pub const MANIFEST_FILENAME: &str = "sqigl.toml";
 pub const SOURCE_DIRECTORY: &str = "src";
 pub const ARTIFACTS_DIRECTORY: &str = "artifacts";
 
-pub fn read_toml<'de, T: Deserialize<'de>>(path: &Path) -> Result<T, ReadTomlError> {
+pub fn read_toml<T: DeserializeOwned>(path: &Path) -> Result<T, ReadTomlError> {
     let content = fs::read_to_string(path)?;
-    let table: toml::Table = toml::from_str(&content)?;
-    match table.try_into() {
-        Ok(x) => Ok(x),
-        Err(e) => Err(ReadTomlError::Invalid(e)),
-    }
+    let _: toml::Table = toml::from_str(&content)
+        .map_err(|e| ReadTomlError::SyntaxError(TomlError::new(path, &content, e)))?;
+    toml::from_str(&content).map_err(|e| ReadTomlError::Invalid(TomlError::new(path, &content, e)))
 }
 
-pub fn maybe_read_toml<'de, T: Deserialize<'de>>(
+pub fn maybe_read_toml<T: DeserializeOwned>(
     path: &Path,
     key: &str,
 ) -> Result<Option<T>, ReadTomlError> {
     let content = fs::read_to_string(path)?;
-    let table: toml::Table = toml::from_str(&content)?;
+    let table: toml::Table = toml::from_str(&content)
+        .map_err(|e| ReadTomlError::SyntaxError(TomlError::new(path, &content, e)))?;
     if table.contains_key(key) {
-        match table.try_into() {
-            Ok(x) => Ok(x),
-            Err(e) => Err(ReadTomlError::Invalid(e)),
+        match toml::from_str(&content) {
+            Ok(x) => Ok(Some(x)),
+            Err(e) => Err(ReadTomlError::Invalid(TomlError::new(path, &content, e))),
         }
     } else {
         Ok(None)
@@ -52,7 +52,46 @@ pub enum ReadTomlError {
     #[error("{0}")]
     Io(#[from] io::Error),
     #[error("{0}")]
-    SyntaxError(#[from] toml::de::Error),
+    SyntaxError(TomlError),
     #[error("{0}")]
-    Invalid(toml::de::Error),
+    Invalid(TomlError),
+}
+
+/// An error in a TOML file, along with the file & the position (if known)
+/// at which it occurred.
+#[derive(Debug, Error)]
+pub struct TomlError {
+    pub path: PathBuf,
+    /// 1-indexed line & column
+    pub position: Option<(usize, usize)>,
+    pub error: toml::de::Error,
+}
+impl TomlError {
+    pub fn new(path: &Path, content: &str, error: toml::de::Error) -> Self {
+        let position = error.span().map(|span| {
+            let preceding = &content[..span.start.min(content.len())];
+            let line = preceding.matches('\n').count() + 1;
+            let column = match preceding.rfind('\n') {
+                Some(idx) => preceding[idx + 1..].chars().count() + 1,
+                None => preceding.chars().count() + 1,
+            };
+            (line, column)
+        });
+        Self {
+            path: path.to_path_buf(),
+            position,
+            error,
+        }
+    }
+}
+impl Display for TomlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = self.path.to_string_lossy();
+        let message = self.error.message().trim();
+        if let Some((line, column)) = self.position {
+            f.write_fmt(format_args!("{}:{}:{}: {}", path, line, column, message))
+        } else {
+            f.write_fmt(format_args!("{}: {}", path, message))
+        }
+    }
 }
@@ -5,7 +5,7 @@ use thiserror::Error;
 
 use crate::manifest::{read_toml, MANIFEST_FILENAME};
 
-use super::ReadTomlError;
+use super::{ReadTomlError, TomlError};
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct ModuleManifest {
@@ -22,6 +22,14 @@ impl ModuleManifest {
 pub struct Module {
     #[serde(default)]
     pub dependencies: Vec<PathBuf>,
+    /// Depend on the dependencies exported by the nearest ancestor module which exports
+    /// any.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inherit_dependencies: bool,
+    /// Dependencies of descendant modules which set `inherit_dependencies`. They are
+    /// relative to this module.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub export_dependencies: Vec<PathBuf>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -29,6 +37,16 @@ pub struct Script {
     pub script: PathBuf,
     #[serde(default)]
     pub dependencies: Vec<PathBuf>,
+    /// Orders the script among siblings it has no dependencies with, lowest first.
+    /// Scripts without an order follow those with one. Ties are broken by name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<i32>,
+}
+impl Script {
+    /// The script's order, or a large value for scripts without one.
+    pub fn priority(&self) -> i32 {
+        self.order.unwrap_or(i32::MAX)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -90,9 +108,9 @@ pub enum OpenError {
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
     #[error("TOML syntax error: {0}")]
-    SyntaxError(toml::de::Error),
+    SyntaxError(TomlError),
     #[error("Could not read manifest: {0}")]
-    Invalid(#[from] toml::de::Error),
+    Invalid(TomlError),
 }
 impl From<ReadTomlError> for OpenError {
     fn from(value: ReadTomlError) -> Self {
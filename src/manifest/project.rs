@@ -1,4 +1,10 @@
-use std::{fs, io, num::NonZeroU16, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    num::NonZeroU16,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
 
 use semver::Version;
 use serde::{Deserialize, Serialize};
@@ -6,16 +12,27 @@ use thiserror::Error;
 use toml_edit::DocumentMut;
 
 use crate::{
-    manifest::{maybe_read_toml, ARTIFACTS_DIRECTORY, MANIFEST_FILENAME, SOURCE_DIRECTORY},
+    backend::DEFAULT_INTERNAL_SCHEMA,
+    manifest::{
+        maybe_read_toml, read_toml, ARTIFACTS_DIRECTORY, MANIFEST_FILENAME, SOURCE_DIRECTORY,
+    },
     util::{empty_database_version, new_project_version, new_table, replace_file},
 };
 
-use super::ReadTomlError;
+use super::{ReadTomlError, TomlError};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProjectManifest {
     pub project: Project,
     pub database: Database,
+    #[serde(default, skip_serializing_if = "Migrate::is_empty")]
+    pub migrate: Migrate,
+    #[serde(default, skip_serializing_if = "BuildOptions::is_empty")]
+    pub build: BuildOptions,
+    /// Fragments of SQL which scripts may inline by name, mapped to their paths relative
+    /// to the project root.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fragments: BTreeMap<String, PathBuf>,
 }
 impl ProjectManifest {
     pub const KEY: &'static str = "project";
@@ -25,8 +42,14 @@ impl ProjectManifest {
             project: Project {
                 version: new_project_version(),
                 title,
+                source_dir: None,
+                artifacts_dir: None,
+                internal_schema: None,
             },
             database,
+            migrate: Default::default(),
+            build: Default::default(),
+            fragments: Default::default(),
         }
     }
 }
@@ -35,23 +58,186 @@ impl ProjectManifest {
 pub struct Project {
     pub version: Version,
     pub title: String,
+    /// The source directory, relative to the project root, if it isn't `src`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_dir: Option<PathBuf>,
+    /// The artifacts directory, relative to the project root, if it isn't `artifacts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifacts_dir: Option<PathBuf>,
+    /// The schema (Postgres) or table prefix (SQLite) of sqigl's own tables, if it isn't
+    /// `sqigl_internal`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub internal_schema: Option<String>,
+}
+impl Project {
+    /// Check that the source & artifacts directories are within the project root, &
+    /// don't overlap.
+    fn check_directories(&self) -> Result<(), OpenError> {
+        for directory in [&self.source_dir, &self.artifacts_dir]
+            .into_iter()
+            .flatten()
+        {
+            if directory.as_os_str().is_empty()
+                || !directory
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)))
+            {
+                return Err(OpenError::InvalidDirectory(directory.clone()));
+            }
+        }
+        let source_dir = self
+            .source_dir
+            .as_deref()
+            .unwrap_or(Path::new(SOURCE_DIRECTORY));
+        let artifacts_dir = self
+            .artifacts_dir
+            .as_deref()
+            .unwrap_or(Path::new(ARTIFACTS_DIRECTORY));
+        if source_dir.starts_with(artifacts_dir) || artifacts_dir.starts_with(source_dir) {
+            return Err(OpenError::OverlappingDirectories);
+        }
+        Ok(())
+    }
+    /// Check that the internal schema is a lowercase identifier, since it is substituted
+    /// into the quoted names of sqigl's queries without escaping.
+    fn check_internal_schema(&self) -> Result<(), OpenError> {
+        let Some(name) = &self.internal_schema else {
+            return Ok(());
+        };
+        if !is_plain_identifier(name) {
+            return Err(OpenError::InvalidInternalSchema(name.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// The longest identifier Postgres allows, in bytes; longer names are truncated.
+const MAX_IDENTIFIER_BYTES: usize = 63;
+
+/// Whether a name matches `[a-z_][a-z0-9_]*`, & so is an identifier which needs no
+/// quoting.
+fn is_plain_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Options for generating migrations.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Migrate {
+    /// Objects which generated migrations will never drop, such as those managed outside
+    /// of sqigl, along with the indexes & triggers on them and the deleted objects they
+    /// depend on. `*` matches any sequence of characters; see `util::is_protected`.
+    #[serde(default)]
+    pub protect: Vec<String>,
+    /// Schemas which generated migrations ignore entirely, such as those managed by
+    /// extensions. sqigl's internal schema is always ignored.
+    #[serde(default)]
+    pub exclude_schemas: Vec<String>,
+}
+impl Migrate {
+    fn is_empty(&self) -> bool {
+        self.protect.is_empty() && self.exclude_schemas.is_empty()
+    }
+}
+
+/// The largest script read by default, in bytes.
+pub const DEFAULT_MAX_SCRIPT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Options for building the project.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct BuildOptions {
+    /// Variables available to template scripts.
+    #[serde(default)]
+    pub context: toml::Table,
+    /// The largest script, fragment or saved migration which is read, in bytes (after
+    /// decompression), if it isn't `DEFAULT_MAX_SCRIPT_BYTES`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_script_bytes: Option<u64>,
+}
+impl BuildOptions {
+    fn is_empty(&self) -> bool {
+        self.context.is_empty() && self.max_script_bytes.is_none()
+    }
+    pub fn max_script_bytes(&self) -> u64 {
+        self.max_script_bytes.unwrap_or(DEFAULT_MAX_SCRIPT_BYTES)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ProjectInfo {
     pub project: Project,
     pub database: Database,
+    pub migrate: Migrate,
+    pub build: BuildOptions,
+    pub fragments: BTreeMap<String, PathBuf>,
     pub root: PathBuf,
+    /// The tenant targeted with `set_tenant`, if any.
+    pub tenant: Option<String>,
 }
 impl ProjectInfo {
     pub fn project_manifest(&self) -> PathBuf {
         self.root.join(MANIFEST_FILENAME)
     }
     pub fn source_dir(&self) -> PathBuf {
-        self.root.join(SOURCE_DIRECTORY)
+        match &self.project.source_dir {
+            Some(directory) => self.root.join(directory),
+            None => self.root.join(SOURCE_DIRECTORY),
+        }
     }
     pub fn artifacts_dir(&self) -> PathBuf {
-        self.root.join(ARTIFACTS_DIRECTORY)
+        match &self.project.artifacts_dir {
+            Some(directory) => self.root.join(directory),
+            None => self.root.join(ARTIFACTS_DIRECTORY),
+        }
+    }
+    /// The schema (Postgres) or table prefix (SQLite) of sqigl's own tables.
+    pub fn internal_schema(&self) -> &str {
+        self.project
+            .internal_schema
+            .as_deref()
+            .unwrap_or(DEFAULT_INTERNAL_SCHEMA)
+    }
+    /// Target one tenant of a database in which each tenant has its own copy of the
+    /// project's schema, in the Postgres schema named `tenant`. Scripts are run with it
+    /// as the search path, & sqigl's tables are kept in an internal schema of its own
+    /// (the internal schema suffixed with `_<tenant>`), so that each tenant's version is
+    /// tracked independently.
+    pub fn set_tenant(&mut self, tenant: &str) -> Result<(), OpenError> {
+        if !is_plain_identifier(tenant) {
+            return Err(OpenError::InvalidTenant(tenant.to_string()));
+        }
+        let internal = format!("{}_{}", self.internal_schema(), tenant);
+        let Database::Postgres(params) = &mut self.database else {
+            return Err(OpenError::TenantsUnsupported);
+        };
+        if internal.len() > MAX_IDENTIFIER_BYTES {
+            return Err(OpenError::TenantTooLong {
+                tenant: tenant.to_string(),
+                internal,
+            });
+        }
+        params.search_path = Some(vec![tenant.to_string()]);
+        self.project.internal_schema = Some(internal);
+        self.tenant = Some(tenant.to_string());
+        Ok(())
+    }
+    /// Add the variables of a TOML file to the build context, taking precedence over the
+    /// `[build.context]` of the manifest.
+    pub fn add_context_file(&mut self, path: &Path) -> Result<(), ReadTomlError> {
+        let context: toml::Table = read_toml(path)?;
+        self.build.context.extend(context);
+        Ok(())
+    }
+    /// Resolve a path from the manifest, which is relative to the project root.
+    pub fn resolve_path(&self, path: &Path) -> PathBuf {
+        if path.is_relative() {
+            self.root.join(path)
+        } else {
+            path.to_path_buf()
+        }
     }
 }
 
@@ -64,26 +250,178 @@ pub enum Database {
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct PostgresDatabase {
+    /// The server's hostname, or the directory containing its Unix socket if it starts
+    /// with `/`.
     pub hostname: Option<String>,
+    /// The directory containing the server's Unix socket, such as `/var/run/postgresql`,
+    /// to connect through rather than TCP. Takes priority over `hostname`.
+    pub socket: Option<PathBuf>,
     pub port: Option<NonZeroU16>,
     pub username: Option<String>,
     pub database: Option<String>,
     pub certificate: Option<PathBuf>,
     pub statement_timeout: Option<f32>,
-    pub transaction_timeout: Option<f32>
+    pub transaction_timeout: Option<f32>,
+    pub connect_retries: Option<u32>,
+    pub connect_backoff: Option<f32>,
+    /// A shell command which prints the password, such as a secret manager's client.
+    pub password_command: Option<String>,
+    /// A file whose first line is the password.
+    pub password_file: Option<PathBuf>,
+    pub sslmode: Option<SslMode>,
+    /// A client certificate to present to the server, in PEM format.
+    pub sslcert: Option<PathBuf>,
+    /// The private key of the client certificate, in PKCS #8 PEM format.
+    pub sslkey: Option<PathBuf>,
+    /// A role to run scripts as, with `set local role`. Objects the scripts create are
+    /// owned by it. sqigl's own bookkeeping runs as the connecting user.
+    pub role: Option<String>,
+    /// Schemas to search for unqualified names while running scripts, with
+    /// `set local search_path`.
+    pub search_path: Option<Vec<String>>,
+    /// A script run on each connection immediately after connecting, outside of any
+    /// transaction, such as to `set jit = off`. Relative to the project root.
+    pub session_setup: Option<PathBuf>,
+}
+
+/// Whether & how TLS is used when connecting to Postgres, after libpq's `sslmode`.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    /// Never use TLS.
+    Disable,
+    /// Use TLS if the server supports it, verifying the certificate & hostname.
+    #[default]
+    Prefer,
+    /// Require TLS, but don't verify the certificate unless a root certificate was
+    /// supplied.
+    Require,
+    /// Require TLS & verify the certificate, but not the hostname.
+    VerifyCa,
+    /// Require TLS & verify both the certificate & the hostname.
+    VerifyFull,
+}
+impl SslMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SslMode::Disable => "disable",
+            SslMode::Prefer => "prefer",
+            SslMode::Require => "require",
+            SslMode::VerifyCa => "verify-ca",
+            SslMode::VerifyFull => "verify-full",
+        }
+    }
+}
+impl fmt::Display for SslMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl FromStr for SslMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(SslMode::Disable),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(format!(
+                "Unknown sslmode {} (expected disable, prefer, require, verify-ca or \
+                verify-full)",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct SqliteDatabase {
     pub path: Option<PathBuf>,
+    /// Generate `ALTER TABLE .. RENAME` for tables which appear to have been renamed.
+    #[serde(default)]
+    pub detect_renames: bool,
+    /// Additional databases to attach, by schema name.
+    #[serde(default)]
+    pub attach: BTreeMap<String, PathBuf>,
+    /// In seconds, how long to wait for another sqigl process using the database to
+    /// finish. By default, fail immediately.
+    pub lock_timeout: Option<f32>,
+    /// Set with `PRAGMA journal_mode` when the database is opened. `wal` persists in the
+    /// database file.
+    pub journal_mode: Option<JournalMode>,
+    /// Set with `PRAGMA synchronous` when the database is opened.
+    pub synchronous: Option<Synchronous>,
+}
+
+/// SQLite's journal mode, after `PRAGMA journal_mode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+impl JournalMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+            JournalMode::Off => "off",
+        }
+    }
+}
+impl fmt::Display for JournalMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// How often SQLite syncs to disk, after `PRAGMA synchronous`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+impl Synchronous {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
+    }
+}
+impl fmt::Display for Synchronous {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
-pub fn open_project(directory: PathBuf) -> Result<ProjectInfo, OpenError> {
-    debug_assert!(directory.is_dir(), "Directory does not exist or is a file.");
+/// Open the project containing `path`, which may be the project root, one of its
+/// subdirectories, or a file within them (such as the project manifest itself).
+pub fn open_project(path: PathBuf) -> Result<ProjectInfo, OpenError> {
     debug_assert!(
-        directory == directory.canonicalize().unwrap(),
-        "The directory must be canonical to ensure that all returned paths are canonical."
+        path == path.canonicalize().unwrap(),
+        "The path must be canonical to ensure that all returned paths are canonical."
     );
+    // Files are searched for from the directory containing them
+    let directory = if path.is_dir() {
+        path.as_path()
+    } else {
+        path.parent().unwrap_or(&path)
+    };
     for d in directory.ancestors() {
         let manifest_path = d.join(MANIFEST_FILENAME);
         if manifest_path.is_file() {
@@ -93,18 +431,24 @@ pub fn open_project(directory: PathBuf) -> Result<ProjectInfo, OpenError> {
                 if project_manifest.project.version <= empty_database_version() {
                     return Err(OpenError::InvalidVersion);
                 }
+                project_manifest.project.check_directories()?;
+                project_manifest.project.check_internal_schema()?;
 
                 let root = manifest_path.parent().unwrap();
                 return Ok(ProjectInfo {
                     root: root.to_path_buf(),
                     project: project_manifest.project,
                     database: project_manifest.database,
+                    migrate: project_manifest.migrate,
+                    build: project_manifest.build,
+                    fragments: project_manifest.fragments,
+                    tenant: None,
                 });
             }
         }
     }
 
-    Err(OpenError::NotFound(directory))
+    Err(OpenError::NotFound(directory.to_path_buf()))
 }
 
 #[derive(Debug, Error)]
@@ -113,12 +457,27 @@ pub enum OpenError {
     InvalidVersion,
     #[error("No project manifest was found in {0} or any of it's ancestors.")]
     NotFound(PathBuf),
+    #[error("Invalid directory {0}: Must be relative to the project root, without `.` or `..`")]
+    InvalidDirectory(PathBuf),
+    #[error("The source & artifacts directories must not overlap.")]
+    OverlappingDirectories,
+    #[error("Invalid internal schema {0:?}: Must match `[a-z_][a-z0-9_]*`")]
+    InvalidInternalSchema(String),
+    #[error("Invalid tenant schema {0:?}: Must match `[a-z_][a-z0-9_]*`")]
+    InvalidTenant(String),
+    #[error("Tenant schemas are only supported for Postgres.")]
+    TenantsUnsupported,
+    #[error(
+        "Invalid tenant schema {tenant:?}: Its internal schema {internal:?} is longer than \
+        Postgres' limit of 63 bytes"
+    )]
+    TenantTooLong { tenant: String, internal: String },
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
     #[error("TOML syntax error: {0}")]
-    SyntaxError(toml::de::Error),
+    SyntaxError(TomlError),
     #[error("Invalid manifest: {0}")]
-    Invalid(toml::de::Error),
+    Invalid(TomlError),
 }
 impl From<ReadTomlError> for OpenError {
     fn from(value: ReadTomlError) -> Self {
@@ -165,3 +524,118 @@ pub enum UpdateVersionError {
     #[error("Invalid value in key {0}")]
     InvalidValue(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestProject;
+
+    fn postgres_project() -> TestProject {
+        let project = TestProject::new("0.1.0");
+        project.write(
+            "sqigl.toml",
+            "[project]\ntitle = \"test\"\nversion = \"0.1.0\"\n\n[database]\ndb = \"postgres\"\n",
+        );
+        project
+    }
+
+    #[test]
+    fn context_file_takes_precedence() {
+        let project = TestProject::new("0.1.0");
+        project.write("context.toml", "env = \"prod\"\nreplicas = 3\n");
+        let mut info = project.info();
+        info.build
+            .context
+            .insert("env".to_string(), toml::Value::from("dev"));
+        info.build
+            .context
+            .insert("owner".to_string(), toml::Value::from("app"));
+        info.add_context_file(&project.path().join("context.toml"))
+            .unwrap();
+
+        assert_eq!(info.build.context["env"].as_str(), Some("prod"));
+        assert_eq!(info.build.context["replicas"].as_integer(), Some(3));
+        assert_eq!(info.build.context["owner"].as_str(), Some("app"));
+        assert!(info
+            .add_context_file(&project.path().join("missing.toml"))
+            .is_err());
+    }
+
+    #[test]
+    fn tenant_internal_schema() {
+        let project = postgres_project();
+        let mut info = project.info();
+        info.set_tenant("acme").unwrap();
+        assert_eq!(info.internal_schema(), "sqigl_internal_acme");
+        assert_eq!(info.tenant.as_deref(), Some("acme"));
+        let Database::Postgres(params) = &info.database else {
+            unreachable!()
+        };
+        assert_eq!(params.search_path, Some(vec!["acme".to_string()]));
+    }
+
+    #[test]
+    fn tenant_too_long() {
+        let project = postgres_project();
+        // `sqigl_internal_` is 15 bytes, leaving 48 for the tenant
+        project.info().set_tenant(&"a".repeat(48)).unwrap();
+        assert!(matches!(
+            project.info().set_tenant(&"a".repeat(49)),
+            Err(OpenError::TenantTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_tenants() {
+        let project = postgres_project();
+        assert!(matches!(
+            project.info().set_tenant("Acme"),
+            Err(OpenError::InvalidTenant(_))
+        ));
+        assert!(matches!(
+            TestProject::new("0.1.0").info().set_tenant("acme"),
+            Err(OpenError::TenantsUnsupported)
+        ));
+    }
+
+    #[test]
+    fn malformed_manifest_location() {
+        let project = TestProject::new("0.1.0");
+        let open = || open_project(project.path().canonicalize().unwrap()).unwrap_err();
+
+        project.write(
+            "sqigl.toml",
+            "[project]\ntitle = \"test\"\nversion = = \"0.1.0\"\n",
+        );
+        let e = open();
+        let OpenError::SyntaxError(error) = &e else {
+            panic!("Expected a syntax error, got {e}");
+        };
+        assert_eq!(error.position, Some((3, 11)));
+        let message = e.to_string();
+        assert!(message.contains("sqigl.toml:3:11: "), "{message}");
+
+        project.write(
+            "sqigl.toml",
+            "[project]\ntitle = \"test\"\nversion = 1\n\n[database]\ndb = \"sqlite\"\n",
+        );
+        let e = open();
+        let OpenError::Invalid(error) = &e else {
+            panic!("Expected an invalid manifest, got {e}");
+        };
+        assert_eq!(error.position.map(|(line, _)| line), Some(3));
+        assert!(e.to_string().contains("sqigl.toml:3:"), "{e}");
+    }
+
+    #[test]
+    fn opens_from_files() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/module/a.sql", "create table a(x integer);");
+        let root = project.path().canonicalize().unwrap();
+        for path in ["sqigl.toml", "src/module", "src/module/a.sql"] {
+            let info = open_project(root.join(path)).unwrap();
+            assert_eq!(info.root, root, "{path}");
+            assert_eq!(info.project.version, Version::new(0, 1, 0), "{path}");
+        }
+    }
+}
@@ -1,53 +1,177 @@
 use std::{
-    collections::BTreeMap,
+    cmp::Reverse,
+    collections::{BTreeMap, VecDeque},
     fs::{self, File},
     io,
+    ops::Bound,
     path::{Path, PathBuf},
 };
 
+use anyhow::anyhow;
 use log::{debug, error, info, trace, warn};
 use semver::{Version, VersionReq};
 use sha2::{Digest, Sha256};
 
 use crate::{
     actions::build::SQL_EXTENSION,
-    artifact::{Artifact, ContentId},
+    artifact::{Artifact, ContentId, ScriptConsumer, ScriptName},
     manifest::{
         self,
-        artifact::{self, open_artifact, update_artifact_migration, ArtifactInfo},
+        artifact::{
+            self, find_artifact_migration, open_artifact, remove_artifact_migration,
+            update_artifact_migration, ArtifactInfo, Maintenance, UpdateMigrationError,
+        },
         project::ProjectInfo,
         MANIFEST_FILENAME,
     },
-    util::{empty_database_version, normalize_version, replace_artifact},
+    util::{
+        empty_database_version, normalize_version, read_artifact, read_script, replace_artifact,
+        specificity, ReplaceArtifactError, COMPRESSED_EXTENSION,
+    },
 };
 
 pub fn save_migration<A: Artifact>(
     title: &str,
     artifact: A,
     info: &ProjectInfo,
-) -> anyhow::Result<PathBuf> {
+    compress: bool,
+) -> Result<PathBuf, SaveMigrationError> {
     let (from, to) = artifact.spec();
     trace!("Saving migration {from} -> {to}");
 
     let version_dir = info
         .artifacts_dir()
         .join(normalize_version(&to).to_string());
-    let script = Path::new(title).with_extension(SQL_EXTENSION);
+    let uncompressed = Path::new(title).with_extension(SQL_EXTENSION);
+    let compressed =
+        uncompressed.with_extension(format!("{}.{}", SQL_EXTENSION, COMPRESSED_EXTENSION));
+    let (script, replaced) = if compress {
+        (compressed, uncompressed)
+    } else {
+        (uncompressed, compressed)
+    };
     let script_path = version_dir.join(&script);
-    replace_artifact(&artifact, &script_path)?;
+    check_collision(&artifact, &version_dir, &[&script, &replaced])?;
+    // Settings of the migration being replaced, under either compression setting, are kept
+    let existing = match find_artifact_migration(&script, version_dir.clone())? {
+        Some(existing) => Some(existing),
+        None => find_artifact_migration(&replaced, version_dir.clone())?,
+    };
+    replace_artifact(&artifact, &script_path, compress)?;
+
+    // Don't leave behind a copy of this migration saved with the other compression setting
+    let replaced_path = version_dir.join(&replaced);
+    if replaced_path.exists() {
+        debug!("Removing {:?}", replaced_path);
+        fs::remove_file(&replaced_path)?;
+        remove_artifact_migration(&replaced, version_dir.clone())?;
+    }
 
-    let migration = artifact::Migration { script, from, to };
+    let (parallel, post, description, down) = match existing {
+        Some(existing) => (
+            existing.parallel,
+            existing.post,
+            existing.description,
+            existing.down,
+        ),
+        None => Default::default(),
+    };
+    let migration = artifact::Migration {
+        script,
+        from,
+        to,
+        parallel,
+        post,
+        description,
+        down,
+    };
     update_artifact_migration(migration, version_dir)?;
 
     Ok(script_path)
 }
 
+/// Versions which differ only by patch share an artifact directory, so migrations to
+/// them may be saved under the same name. Fail rather than overwrite a migration to a
+/// different version, unless its content is identical.
+fn check_collision<A: Artifact>(
+    artifact: &A,
+    version_dir: &Path,
+    scripts: &[&Path],
+) -> Result<(), SaveMigrationError> {
+    if !version_dir.join(MANIFEST_FILENAME).is_file() {
+        return Ok(());
+    }
+    let to = artifact.version();
+    for existing in open_artifact(version_dir.to_path_buf())?.migrations {
+        if existing.to == *to || !scripts.contains(&existing.script.as_path()) {
+            continue;
+        }
+        let path = version_dir.join(&existing.script);
+        if path.exists()
+            && Sha256::digest(read_artifact(&path)?) != artifact.content_id().unwrap().into()
+        {
+            return Err(SaveMigrationError::Collision {
+                to: to.clone(),
+                path,
+                existing: existing.to,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SaveMigrationError {
+    #[error("I/O Error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Could not read artifact manifest: {0}")]
+    OpenArtifact(#[from] artifact::OpenError),
+    #[error("Could not update artifact manifest: {0}")]
+    UpdateArtifact(#[from] UpdateMigrationError),
+    #[error("{0}")]
+    Replace(#[from] ReplaceArtifactError),
+    #[error(
+        "Cannot save migration to {to}: {path:?} already contains a different migration to \
+        {existing}"
+    )]
+    Collision {
+        to: Version,
+        path: PathBuf,
+        existing: Version,
+    },
+}
+
+#[derive(Clone)]
 pub struct MigrationArtifact {
     from: VersionReq,
     to: Version,
     script: PathBuf,
+    parallel: bool,
+    post: Option<Maintenance>,
+    description: Option<String>,
+    max_bytes: u64,
 }
 impl MigrationArtifact {
+    fn new(directory: &Path, migration: &artifact::Migration, max_bytes: u64) -> Self {
+        let artifact::Migration {
+            script,
+            from,
+            to,
+            parallel,
+            post,
+            description,
+            ..
+        } = migration.clone();
+        Self {
+            from,
+            to,
+            script: directory.join(script),
+            parallel,
+            post,
+            description,
+            max_bytes,
+        }
+    }
     pub fn script(&self) -> &Path {
         &self.script
     }
@@ -62,12 +186,21 @@ impl Artifact for MigrationArtifact {
     fn spec(&self) -> (VersionReq, Version) {
         (self.from.clone(), self.to.clone())
     }
+    fn parallel(&self) -> bool {
+        self.parallel
+    }
+    fn post(&self) -> Option<Maintenance> {
+        self.post
+    }
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
 
-    fn scripts<C: crate::artifact::ScriptConsumer>(
+    fn scripts<C: ScriptConsumer>(
         &self,
         mut consumer: C,
     ) -> Result<crate::artifact::ContentId, crate::artifact::ScriptProcessingError<C::Error>> {
-        let code = fs::read_to_string(&self.script)?;
+        let code = read_script(&self.script, self.max_bytes)?;
         let mut hasher = Sha256::new();
         hasher.update(&code);
         let id = hasher.finalize().into();
@@ -79,8 +212,68 @@ impl Artifact for MigrationArtifact {
     }
 }
 
+/// A chain of migrations, applied in sequence as a single migration.
+pub struct SquashedMigration {
+    from: VersionReq,
+    to: Version,
+    steps: Vec<MigrationArtifact>,
+}
+impl SquashedMigration {
+    /// Squash `steps`, which must form a chain. The result is compatible with the same
+    /// versions as the first step.
+    pub fn new(steps: Vec<MigrationArtifact>) -> Self {
+        debug_assert!(!steps.is_empty());
+        debug_assert!(steps.windows(2).all(|w| w[1].compatible(w[0].version())));
+        Self {
+            from: steps.first().unwrap().from.clone(),
+            to: steps.last().unwrap().to.clone(),
+            steps,
+        }
+    }
+    pub fn steps(&self) -> &[MigrationArtifact] {
+        &self.steps
+    }
+}
+impl Artifact for SquashedMigration {
+    fn compatible(&self, version: &Version) -> bool {
+        self.from.matches(version)
+    }
+    fn version(&self) -> &Version {
+        &self.to
+    }
+    fn spec(&self) -> (VersionReq, Version) {
+        (self.from.clone(), self.to.clone())
+    }
+
+    fn scripts<C: ScriptConsumer>(
+        &self,
+        mut consumer: C,
+    ) -> Result<crate::artifact::ContentId, crate::artifact::ScriptProcessingError<C::Error>> {
+        let mut hasher = Sha256::new();
+        let last_idx = self.steps.len() - 1;
+        for (idx, step) in self.steps.iter().enumerate() {
+            let code = read_script(&step.script, step.max_bytes)?;
+            let mut batch = format!("-- [ {} ]\n\n{}", step.print(), code.trim_ascii());
+            batch.push_str(if idx != last_idx { "\n\n" } else { "\n" });
+            hasher.update(&batch);
+            let name = step.script.file_name().unwrap().to_str().unwrap();
+            let name = ScriptName {
+                name,
+                index: idx + 1,
+                total: self.steps.len(),
+            };
+            consumer.accept_named(name, &batch)?;
+        }
+        let id = hasher.finalize().into();
+        consumer.commit(id)?;
+
+        Ok(id)
+    }
+}
+
 pub struct MigrationSet {
     entries: BTreeMap<Version, (PathBuf, Vec<artifact::Migration>)>,
+    max_script_bytes: u64,
 }
 impl MigrationSet {
     pub fn open(info: &ProjectInfo) -> Result<Self, MigrationSetError> {
@@ -88,15 +281,23 @@ impl MigrationSet {
 
         let mut migrations: BTreeMap<Version, (PathBuf, Vec<_>)> = BTreeMap::default();
         let artifacts_dir = info.artifacts_dir();
+        if artifacts_dir.exists() && !artifacts_dir.is_dir() {
+            return Err(MigrationSetError::ArtifactsNotDirectory(artifacts_dir));
+        }
+        // The order of `read_dir` is platform-dependent, so directories are visited in
+        // order of their names
+        let mut children = Vec::new();
         for child_res in artifacts_dir
             .read_dir()
             .map_err(|e| MigrationSetError::Io(artifacts_dir.clone(), e))?
         {
             let child = child_res.map_err(|e| MigrationSetError::Io(artifacts_dir.clone(), e))?;
-            let path = child.path();
-            let md = child
-                .metadata()
-                .map_err(|e| MigrationSetError::Io(path.clone(), e))?;
+            children.push(child.path());
+        }
+        children.sort();
+        for path in children {
+            let md =
+                fs::symlink_metadata(&path).map_err(|e| MigrationSetError::Io(path.clone(), e))?;
             if md.is_dir() {
                 let manifest = open_artifact(path.clone())
                     .map_err(|e| MigrationSetError::OpenArtifact(path.clone(), e))?;
@@ -112,8 +313,15 @@ impl MigrationSet {
             }
         }
 
+        // Migrations to the same version are tried from the most specific `from` to the
+        // least, and otherwise in the order they are listed in the manifest
+        for (_, candidates) in migrations.values_mut() {
+            candidates.sort_by_key(|m| Reverse(specificity(&m.from)));
+        }
+
         Ok(Self {
             entries: migrations,
+            max_script_bytes: info.build.max_script_bytes(),
         })
     }
     pub fn is_empty(&self) -> bool {
@@ -123,39 +331,106 @@ impl MigrationSet {
     pub fn latest_released_version(&self) -> Option<&Version> {
         self.entries.keys().rev().find(|k| k.pre.is_empty())
     }
+    /// Iterates over the versions with prerelease information, along with the
+    /// artifact directories containing them
+    pub fn prerelease_entries(&self) -> impl Iterator<Item = (&Version, &Path)> {
+        self.entries
+            .iter()
+            .filter(|(version, _)| !version.pre.is_empty())
+            .map(|(version, (path, _))| (version, path.as_path()))
+    }
+    /// Iterates over the versions without prerelease information, along with the
+    /// artifact directories containing them
+    pub fn released_entries(&self) -> impl Iterator<Item = (&Version, &Path)> {
+        self.entries
+            .iter()
+            .filter(|(version, _)| version.pre.is_empty())
+            .map(|(version, (path, _))| (version, path.as_path()))
+    }
+    /// Whether any migration to a released version can be applied to `version`
+    pub fn released_from(&self, version: &Version) -> bool {
+        self.entries
+            .iter()
+            .filter(|(to, _)| to.pre.is_empty())
+            .flat_map(|(_, (_, migrations))| migrations.iter())
+            .any(|m| m.from.matches(version))
+    }
+    /// Finds the migration which can be applied to `version` & leads to the highest
+    /// version above it. This is a single step; use `path` to find a chain of
+    /// migrations. Of the compatible migrations to that version, the one with the most
+    /// specific `from` is chosen, or else the first in its artifact manifest.
     pub fn latest_compatible(&self, version: &Version) -> Option<MigrationArtifact> {
-        for (path, migration_list) in self.entries.values().rev() {
-            for migration in migration_list.iter() {
-                if migration.from.matches(version) {
-                    let artifact::Migration { script, from, to } = migration.clone();
-                    return Some(MigrationArtifact {
-                        from,
-                        to,
-                        script: path.join(script),
-                    });
-                }
-            }
-        }
-
-        None
+        self.entries
+            .range((Bound::Excluded(version), Bound::Unbounded))
+            .rev()
+            .find_map(|(_, (path, migrations))| {
+                migrations
+                    .iter()
+                    .find(|m| m.from.matches(version))
+                    .map(|m| MigrationArtifact::new(path, m, self.max_script_bytes))
+            })
     }
+    /// Finds the migration from `from` to `to`. Of the compatible migrations, the one
+    /// with the most specific `from` is chosen, or else the first in its artifact
+    /// manifest.
     pub fn get(&self, from: &Version, to: &Version) -> Option<MigrationArtifact> {
         if let Some((path, candidates)) = self.entries.get(to) {
-            candidates.iter().find(|m| m.from.matches(&from)).map(|m| {
-                let artifact::Migration { script, from, to } = m.clone();
-                MigrationArtifact {
-                    from,
-                    to,
-                    script: path.join(script),
-                }
-            })
+            candidates
+                .iter()
+                .find(|m| m.from.matches(from))
+                .map(|m| MigrationArtifact::new(path, m, self.max_script_bytes))
         } else {
             None
         }
     }
+    /// Iterates over every migration in the set
+    pub fn migrations(&self) -> impl Iterator<Item = MigrationArtifact> + '_ {
+        let max_bytes = self.max_script_bytes;
+        self.entries.values().flat_map(move |(path, migrations)| {
+            migrations
+                .iter()
+                .map(move |m| MigrationArtifact::new(path, m, max_bytes))
+        })
+    }
+    /// Iterates over every version which a migration leads to, in ascending order
+    pub fn versions(&self) -> impl Iterator<Item = &Version> {
+        self.entries.keys()
+    }
     pub fn get_schema(&self, version: &Version) -> Option<MigrationArtifact> {
         self.get(&empty_database_version(), version)
     }
+    /// Finds the shortest chain of migrations leading from `from` to `to`. Of chains
+    /// which are equally short, the one whose earliest steps move the furthest is chosen.
+    pub fn path(&self, from: &Version, to: &Version) -> Option<Vec<MigrationArtifact>> {
+        // A breadth-first search, with each version visited recording the step which
+        // first reached it
+        let mut reached: BTreeMap<Version, (Version, MigrationArtifact)> = BTreeMap::new();
+        let mut queue = VecDeque::from([from.clone()]);
+        while let Some(current) = queue.pop_front() {
+            if current == *to {
+                let mut steps = Vec::new();
+                let mut version = current;
+                while version != *from {
+                    let (prev, step) = reached.remove(&version).unwrap();
+                    version = prev;
+                    steps.push(step);
+                }
+                steps.reverse();
+                return Some(steps);
+            }
+            for version in self.entries.keys().rev() {
+                if *version == *from || reached.contains_key(version) {
+                    continue;
+                }
+                if let Some(step) = self.get(&current, version) {
+                    reached.insert(version.clone(), (current.clone(), step));
+                    queue.push_back(version.clone());
+                }
+            }
+        }
+
+        None
+    }
 }
 
 struct MigrationSetEntry {
@@ -169,4 +444,212 @@ pub enum MigrationSetError {
     Io(PathBuf, io::Error),
     #[error("{0} {1}")]
     OpenArtifact(PathBuf, artifact::OpenError),
+    #[error("The artifacts directory {0} is not a directory")]
+    ArtifactsNotDirectory(PathBuf),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actions::build::build_project, testing::TestProject};
+
+    /// A project with a migration between each pair of versions in `migrations`.
+    fn migrations(migrations: &[(&str, &str)]) -> TestProject {
+        let project = TestProject::new("1.0.0");
+        let mut manifests: BTreeMap<&str, String> = BTreeMap::new();
+        for (from, to) in migrations {
+            let script = format!("from_{}.sql", from);
+            project.write(&format!("artifacts/{to}/{script}"), "select 1;");
+            manifests.entry(to).or_default().push_str(&format!(
+                "[[migrations]]\nscript = \"{script}\"\nfrom = \"={from}\"\nto = \"{to}\"\n\n"
+            ));
+        }
+        for (to, manifest) in manifests {
+            project.write(&format!("artifacts/{to}/{MANIFEST_FILENAME}"), &manifest);
+        }
+        project
+    }
+
+    fn path(project: &TestProject, from: &str, to: &str) -> Option<Vec<String>> {
+        let set = MigrationSet::open(&project.info()).unwrap();
+        let steps = set.path(&from.parse().unwrap(), &to.parse().unwrap())?;
+        Some(steps.iter().map(|step| step.to.to_string()).collect())
+    }
+
+    #[test]
+    fn save_keeps_migration_settings() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let info = project.info();
+        let version_dir = info.artifacts_dir().join("0.1.0");
+        fs::create_dir(&version_dir).unwrap();
+        save_migration("schema", build_project(&info).unwrap(), &info, false).unwrap();
+        let manifest = fs::read_to_string(version_dir.join(MANIFEST_FILENAME)).unwrap();
+        project.write(
+            "artifacts/0.1.0/sqigl.toml",
+            &manifest.replace(
+                "to = \"0.1.0\"",
+                "to = \"0.1.0\"\nparallel = true\npost = \"analyze\"\n\
+                description = \"Adds a\"\ndown = \"schema.down.sql\"",
+            ),
+        );
+
+        // Saved again, compressed
+        project.write("src/a.sql", "create table a(x integer, y integer);");
+        save_migration("schema", build_project(&info).unwrap(), &info, true).unwrap();
+        let migrations = open_artifact(version_dir).unwrap().migrations;
+        assert_eq!(migrations.len(), 1);
+        let migration = &migrations[0];
+        assert_eq!(migration.script, Path::new("schema.sql.gz"));
+        assert!(migration.parallel);
+        assert_eq!(migration.post, Some(Maintenance::Analyze));
+        assert_eq!(migration.description.as_deref(), Some("Adds a"));
+        assert_eq!(
+            migration.down.as_deref(),
+            Some(Path::new("schema.down.sql"))
+        );
+    }
+
+    #[test]
+    fn rejects_colliding_patch_releases() {
+        let project = TestProject::new("0.2.1");
+        project.write("src/a.sql", "create table a(x integer);");
+        let info = project.info();
+        fs::create_dir(info.artifacts_dir().join("0.2.0")).unwrap();
+        let mut artifact = build_project(&info).unwrap();
+        save_migration("schema", artifact.clone(), &info, false).unwrap();
+        let script = info.artifacts_dir().join("0.2.0/schema.sql");
+        let saved = fs::read_to_string(&script).unwrap();
+
+        // 0.2.1 & 0.2.3 share the 0.2.0 directory
+        artifact.set_version(&Version::new(0, 2, 3));
+        assert!(save_migration("schema", artifact.clone(), &info, false).is_err());
+        assert!(save_migration("schema", artifact.clone(), &info, true).is_err());
+        assert_eq!(fs::read_to_string(&script).unwrap(), saved);
+        save_migration("schema_0.2.3", artifact, &info, false).unwrap();
+
+        // A migration to the same version may be replaced
+        project.write("src/a.sql", "create table a(x integer, y integer);");
+        save_migration("schema", build_project(&info).unwrap(), &info, false).unwrap();
+        assert_ne!(fs::read_to_string(&script).unwrap(), saved);
+    }
+
+    #[test]
+    fn prefers_most_specific_from() {
+        let project = TestProject::new("0.2.0");
+        let mut manifest = String::new();
+        for (script, from) in [("wide", "^0.1"), ("exact", "=0.1.3"), ("minor", "=0.1")] {
+            project.write(&format!("artifacts/0.2.0/{script}.sql"), "select 1;");
+            manifest.push_str(&format!(
+                "[[migrations]]\nscript = \"{script}.sql\"\nfrom = \"{from}\"\nto = \"0.2.0\"\n\n"
+            ));
+        }
+        project.write(&format!("artifacts/0.2.0/{MANIFEST_FILENAME}"), &manifest);
+        let set = MigrationSet::open(&project.info()).unwrap();
+        let script = |from: &str| {
+            let migration = set
+                .get(&from.parse().unwrap(), &Version::new(0, 2, 0))
+                .unwrap();
+            migration.script().file_name().unwrap().to_owned()
+        };
+
+        assert_eq!(script("0.1.3"), "exact.sql");
+        assert_eq!(script("0.1.4"), "minor.sql");
+        assert_eq!(
+            set.latest_compatible(&Version::new(0, 1, 3))
+                .unwrap()
+                .script()
+                .file_name()
+                .unwrap(),
+            "exact.sql"
+        );
+    }
+
+    #[test]
+    fn path_avoids_dead_ends() {
+        // Moving furthest first reaches 0.3.0, which leads nowhere
+        let project = migrations(&[("0.1.0", "0.2.0"), ("0.1.0", "0.3.0"), ("0.2.0", "0.4.0")]);
+        assert_eq!(
+            path(&project, "0.1.0", "0.4.0"),
+            Some(vec!["0.2.0".to_string(), "0.4.0".to_string()])
+        );
+        assert_eq!(path(&project, "0.3.0", "0.4.0"), None);
+    }
+
+    #[test]
+    fn path_is_shortest() {
+        let project = migrations(&[
+            ("0.1.0", "0.2.0"),
+            ("0.2.0", "0.3.0"),
+            ("0.3.0", "0.4.0"),
+            ("0.1.0", "0.3.0"),
+            ("0.2.0", "0.4.0"),
+        ]);
+        // Both two-step paths are equally short; the first step moves furthest
+        assert_eq!(
+            path(&project, "0.1.0", "0.4.0"),
+            Some(vec!["0.3.0".to_string(), "0.4.0".to_string()])
+        );
+        assert_eq!(path(&project, "0.1.0", "0.1.0"), Some(vec![]));
+    }
+
+    #[test]
+    fn latest_compatible_with_overlapping_requirements() {
+        let project = TestProject::new("0.4.0");
+        for (to, migrations) in [
+            ("0.2.0", &[("any", "^0.1")][..]),
+            (
+                "0.3.0",
+                &[
+                    ("wide", ">=0.1.1, <0.3"),
+                    ("minor", "=0.1"),
+                    ("also", "=0.1"),
+                ],
+            ),
+            ("0.4.0", &[("from_0.2", "=0.2")]),
+        ] {
+            let mut manifest = String::new();
+            for (script, from) in migrations {
+                project.write(&format!("artifacts/{to}/{script}.sql"), "select 1;");
+                manifest.push_str(&format!(
+                    "[[migrations]]\nscript = \"{script}.sql\"\nfrom = \"{from}\"\n\
+                    to = \"{to}\"\n\n"
+                ));
+            }
+            project.write(&format!("artifacts/{to}/{MANIFEST_FILENAME}"), &manifest);
+        }
+        let set = MigrationSet::open(&project.info()).unwrap();
+        let latest = |version: &str| {
+            let migration = set.latest_compatible(&version.parse().unwrap())?;
+            let script = migration.script().file_stem().unwrap().to_owned();
+            Some((
+                migration.version().to_string(),
+                script.into_string().unwrap(),
+            ))
+        };
+        let step = |to: &str, script: &str| Some((to.to_string(), script.to_string()));
+
+        // The highest version reachable in one step, although 0.2.0 is also reachable
+        assert_eq!(latest("0.1.0"), step("0.3.0", "minor"));
+        // The most specific requirement, & then the first in the manifest, is chosen
+        assert_eq!(latest("0.1.5"), step("0.3.0", "minor"));
+        // `wide` also matches, but leads to a lower version
+        assert_eq!(latest("0.2.0"), step("0.4.0", "from_0.2"));
+        assert_eq!(latest("0.2.7"), step("0.4.0", "from_0.2"));
+        assert_eq!(latest("0.4.0"), None);
+    }
+
+    #[test]
+    fn artifacts_must_be_a_directory() {
+        let project = TestProject::new("0.1.0");
+        fs::remove_dir(project.path().join("artifacts")).unwrap();
+        project.write("artifacts", "");
+        let info = project.info();
+        match MigrationSet::open(&info) {
+            Err(MigrationSetError::ArtifactsNotDirectory(path)) => {
+                assert_eq!(path, info.artifacts_dir())
+            }
+            result => panic!("Expected ArtifactsNotDirectory, got {:?}", result.err()),
+        }
+    }
 }
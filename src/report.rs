@@ -0,0 +1,242 @@
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    actions::{apply::IncompatibleError, build::BuildError},
+    artifact::ScriptProcessingError,
+    manifest::project::OpenError,
+    ConnectionError,
+};
+
+// Exit codes, which are documented in the help text of `SqiglArguments`. Invalid
+// arguments exit with 2, as reported by clap.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_NO_PROJECT: i32 = 3;
+const EXIT_BUILD: i32 = 4;
+const EXIT_CONNECTION: i32 = 5;
+const EXIT_INCOMPATIBLE: i32 = 6;
+
+/// The category of an error which caused sqigl to fail.
+pub enum ErrorKind<'a> {
+    /// The database's version is not compatible with the artifact. The versions are
+    /// known if the incompatibility was detected before applying the artifact.
+    Incompatible(Option<&'a IncompatibleError>),
+    ManifestNotFound(&'a Path),
+    Build(&'a BuildError),
+    Connection,
+    Other,
+}
+impl<'a> ErrorKind<'a> {
+    pub fn classify(e: &'a anyhow::Error) -> Self {
+        let incompatible = matches!(
+            e.downcast_ref::<ScriptProcessingError<postgres::Error>>(),
+            Some(ScriptProcessingError::Incompatible)
+        ) || matches!(
+            e.downcast_ref::<ScriptProcessingError<rusqlite::Error>>(),
+            Some(ScriptProcessingError::Incompatible)
+        );
+        if let Some(e) = e.downcast_ref::<IncompatibleError>() {
+            Self::Incompatible(Some(e))
+        } else if incompatible {
+            Self::Incompatible(None)
+        } else if let Some(OpenError::NotFound(path)) = e.downcast_ref() {
+            Self::ManifestNotFound(path)
+        } else if let Some(e) = e.downcast_ref() {
+            Self::Build(e)
+        } else if e.is::<ConnectionError>() {
+            Self::Connection
+        } else {
+            Self::Other
+        }
+    }
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Incompatible(_) => EXIT_INCOMPATIBLE,
+            Self::ManifestNotFound(_) => EXIT_NO_PROJECT,
+            Self::Build(_) => EXIT_BUILD,
+            Self::Connection => EXIT_CONNECTION,
+            Self::Other => EXIT_FAILURE,
+        }
+    }
+    /// A stable identifier of the category.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Incompatible(_) => "incompatible",
+            Self::ManifestNotFound(_) => "manifest_not_found",
+            Self::Build(e) => match e {
+                BuildError::DependencyOutsideRoot { .. } => "dependency_outside_root",
+                BuildError::DependencyDoesNotExist { .. } => "dependency_does_not_exist",
+                BuildError::DependencyIllegal { .. } => "dependency_illegal",
+                BuildError::DependencyCycle(_) => "dependency_cycle",
+                BuildError::ScriptDoesNotExist(_) => "script_does_not_exist",
+                BuildError::Template { .. } => "template",
+                BuildError::BlankScript(_) => "blank_script",
+                BuildError::DuplicateScriptName { .. } => "duplicate_script_name",
+                BuildError::NoSuchModule(_) => "no_such_module",
+                BuildError::SourceNotDirectory(_) => "source_not_directory",
+                BuildError::DependsOnSkipped { .. } => "depends_on_skipped",
+                BuildError::UndefinedFragment { .. } => "undefined_fragment",
+                BuildError::FragmentUnreadable { .. } => "fragment_unreadable",
+                BuildError::Io(_) | BuildError::ModuleManifest(_) => "build",
+            },
+            Self::Connection => "connection",
+            Self::Other => "error",
+        }
+    }
+    /// Fields describing the error, to be reported along with its code.
+    fn fields(&self) -> Map<String, Value> {
+        let path = |path: &Path| Value::from(path.to_string_lossy());
+        let fields = match self {
+            Self::Incompatible(Some(e)) => json!({
+                "version": e.version.to_string(),
+                "from": e.from.to_string(),
+            }),
+            Self::ManifestNotFound(directory) => json!({ "directory": path(directory) }),
+            Self::Build(e) => match e {
+                BuildError::DependencyOutsideRoot { module, dep }
+                | BuildError::DependencyDoesNotExist { module, dep }
+                | BuildError::DependencyIllegal { module, dep }
+                | BuildError::DependsOnSkipped { module, dep } => json!({
+                    "module": path(module),
+                    "dependency": path(dep),
+                }),
+                BuildError::DependencyCycle(cycle) => json!({
+                    "cycle": cycle
+                        .cycle_path
+                        .iter()
+                        .map(|p| path(p.strip_prefix(&cycle.root).unwrap_or(p)))
+                        .collect::<Vec<_>>(),
+                }),
+                BuildError::ScriptDoesNotExist(script)
+                | BuildError::Template { script, .. }
+                | BuildError::BlankScript(script) => json!({ "script": path(script) }),
+                BuildError::DuplicateScriptName { name, scripts } => json!({
+                    "name": name,
+                    "scripts": scripts.iter().map(|p| path(p)).collect::<Vec<_>>(),
+                }),
+                BuildError::NoSuchModule(module) => json!({ "module": path(module) }),
+                BuildError::SourceNotDirectory(directory) => {
+                    json!({ "directory": path(directory) })
+                }
+                BuildError::UndefinedFragment { script, fragment } => json!({
+                    "script": path(script),
+                    "fragment": fragment,
+                }),
+                BuildError::FragmentUnreadable {
+                    fragment, path: p, ..
+                } => json!({
+                    "fragment": fragment,
+                    "path": path(p),
+                }),
+                BuildError::Io(_) | BuildError::ModuleManifest(_) => json!({}),
+            },
+            _ => json!({}),
+        };
+        match fields {
+            Value::Object(fields) => fields,
+            _ => unreachable!("Fields are always an object"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    code: &'static str,
+    message: String,
+    #[serde(flatten)]
+    fields: Map<String, Value>,
+}
+
+/// Describe an error as a JSON object, with a stable `code`, a human-readable
+/// `message`, & fields specific to the error.
+pub fn error_json(e: &anyhow::Error) -> String {
+    let kind = ErrorKind::classify(e);
+    let error = JsonError {
+        code: kind.code(),
+        message: format!("{:#}", e),
+        fields: kind.fields(),
+    };
+    serde_json::to_string(&error).expect("Errors can always be serialized")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::{apply::apply_artifact, build::build_project},
+        backend::{sqlite::SqliteBackend, Backend},
+        manifest::project::open_project,
+        testing::TestProject,
+    };
+
+    fn parse(e: anyhow::Error) -> Value {
+        serde_json::from_str(&error_json(&e)).unwrap()
+    }
+
+    #[test]
+    fn cycle_json() {
+        let project = TestProject::new("0.1.0");
+        project
+            .write("src/a.sql", "-- sqigl: include b.sql\n")
+            .write("src/b.sql", "-- sqigl: include a.sql\n");
+        let e = anyhow::Error::from(build_project(&project.info()).unwrap_err());
+        assert_eq!(ErrorKind::classify(&e).exit_code(), EXIT_BUILD);
+        let json = parse(e);
+        assert_eq!(json["code"], "dependency_cycle");
+        // Paths are relative to the source directory
+        assert_eq!(json["cycle"], json!(["a.sql", "b.sql"]));
+        assert!(json["message"].as_str().unwrap().contains("a.sql"));
+    }
+
+    #[test]
+    fn incompatible_json() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.apply(&artifact, false).unwrap();
+
+        let e = apply_artifact(&mut db, artifact, false, None).unwrap_err();
+        assert_eq!(ErrorKind::classify(&e).exit_code(), EXIT_INCOMPATIBLE);
+        assert_eq!(
+            parse(e),
+            json!({
+                "code": "incompatible",
+                "message": "Cannot apply: The database is not compatible with this artifact.",
+                "version": "0.1.0",
+                "from": "=0.0.0",
+            })
+        );
+    }
+
+    #[test]
+    fn incompatible_apply_exit_code() {
+        let project = TestProject::new("0.1.0");
+        project.write("src/a.sql", "create table a(x integer);");
+        let artifact = build_project(&project.info()).unwrap();
+        let mut db = SqliteBackend::local().unwrap();
+        db.install().unwrap();
+        db.apply(&artifact, false).unwrap();
+
+        // Detected by the backend, once the artifact's transaction has begun
+        let e = anyhow::Error::from(db.apply(&artifact, false).unwrap_err());
+        let kind = ErrorKind::classify(&e);
+        assert!(matches!(kind, ErrorKind::Incompatible(None)));
+        assert_eq!(kind.exit_code(), EXIT_INCOMPATIBLE);
+        assert_eq!(parse(e)["code"], "incompatible");
+    }
+
+    #[test]
+    fn missing_project_exit_code() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().canonicalize().unwrap();
+        let e = anyhow::Error::from(open_project(path.clone()).unwrap_err());
+        assert_eq!(ErrorKind::classify(&e).exit_code(), EXIT_NO_PROJECT);
+        let json = parse(e);
+        assert_eq!(json["code"], "manifest_not_found");
+        assert_eq!(json["directory"], path.to_str().unwrap());
+    }
+}
@@ -0,0 +1,45 @@
+//! Helpers shared by unit tests.
+use crate::manifest::{
+    project::{open_project, ProjectInfo},
+    ARTIFACTS_DIRECTORY, SOURCE_DIRECTORY,
+};
+use std::{fs, path::Path};
+use tempfile::TempDir;
+
+/// A project in a temporary directory, deleted when dropped.
+pub struct TestProject {
+    dir: TempDir,
+}
+impl TestProject {
+    /// A SQLite project at version `version`, with empty source & artifacts
+    /// directories, as created by `project create`.
+    pub fn new(version: &str) -> Self {
+        let project = Self {
+            dir: tempfile::tempdir().unwrap(),
+        };
+        fs::create_dir(project.path().join(SOURCE_DIRECTORY)).unwrap();
+        fs::create_dir(project.path().join(ARTIFACTS_DIRECTORY)).unwrap();
+        project.write(
+            "sqigl.toml",
+            &format!(
+                "[project]\ntitle = \"test\"\nversion = \"{version}\"\n\n\
+                [database]\ndb = \"sqlite\"\n"
+            ),
+        );
+        project
+    }
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+    /// Write `content` to `path`, relative to the project root, creating directories as
+    /// needed.
+    pub fn write(&self, path: &str, content: &str) -> &Self {
+        let path = self.dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        self
+    }
+    pub fn info(&self) -> ProjectInfo {
+        open_project(self.dir.path().canonicalize().unwrap()).unwrap()
+    }
+}
@@ -1,13 +1,22 @@
 use std::{
+    env,
+    ffi::OsStr,
     fs::{self, File},
-    io::{self, Write},
+    io::{self, IsTerminal, Read, Stdout, Write},
+    ops::Range,
     path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    time::Duration,
 };
 
+use anyhow::anyhow;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use log::{debug, warn};
 use semver::{BuildMetadata, Comparator, Op, Prerelease, Version, VersionReq};
 use tempfile::tempdir;
 
 use crate::{
+    arguments::PagerMode,
     artifact::{Artifact, ContentId, NullConsumerError, ScriptProcessingError},
     backend::SqiglState,
 };
@@ -73,6 +82,32 @@ pub fn from_patch_version(version: &Version) -> VersionReq {
     }
 }
 
+/// The requirement a migration from `version` is compatible with: `=a.b.c` if `exact`
+/// is set, or `=a.b` otherwise.
+pub fn from_version(version: &Version, exact: bool) -> VersionReq {
+    if exact {
+        from_patch_version(version)
+    } else {
+        from_minor_version(version)
+    }
+}
+
+/// How narrowly a requirement matches versions, for choosing between migrations. Higher
+/// is more specific. Exact requirements are the most specific, those naming a patch
+/// version more so than those naming a minor or major version. Tilde & caret ranges
+/// follow, and then comparisons such as `>=0.2`. A requirement with several comparators
+/// is as specific as the most specific of them, & more specific than one with fewer.
+pub fn specificity(req: &VersionReq) -> (u8, usize) {
+    let rank = |comparator: &Comparator| match comparator.op {
+        Op::Exact => 4 + comparator.minor.is_some() as u8 + comparator.patch.is_some() as u8,
+        Op::Tilde => 3,
+        Op::Caret => 2,
+        _ => 1,
+    };
+    let narrowest = req.comparators.iter().map(rank).max().unwrap_or(0);
+    (narrowest, req.comparators.len())
+}
+
 /// Normalize version for use in artifact directories
 pub fn normalize_version(version: &Version) -> Version {
     Version {
@@ -83,6 +118,197 @@ pub fn normalize_version(version: &Version) -> Version {
     }
 }
 
+/// Match `name` against a pattern in which `*` matches any sequence of characters.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|idx| name.is_char_boundary(*idx))
+                .any(|idx| wildcard_match(rest, &name[idx..]))
+        }
+    }
+}
+
+/// Whether an object is protected from being dropped by migrations. Patterns containing
+/// a `.` are matched against the schema-qualified name, others against the bare name.
+/// Objects which aren't in a schema (such as schemas themselves) are only matched by the
+/// latter.
+pub fn is_protected(protect: &[String], schema: Option<&str>, name: &str) -> bool {
+    protect.iter().any(|pattern| match schema {
+        Some(schema) if pattern.contains('.') => {
+            wildcard_match(pattern, &format!("{}.{}", schema, name))
+        }
+        _ => !pattern.contains('.') && wildcard_match(pattern, name),
+    })
+}
+
+/// The length of the dollar quote tag (such as `$$` or `$body$`) at the start of `sql`,
+/// if there is one.
+fn dollar_tag_len(sql: &[u8]) -> Option<usize> {
+    let rest = &sql[1..];
+    let len = rest
+        .iter()
+        .position(|c| !(c.is_ascii_alphanumeric() || *c == b'_' || *c >= 0x80))?;
+    // Tags can't start with a digit, so that positional parameters aren't mistaken for
+    // them
+    if rest[len] != b'$' || rest.first().is_some_and(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(len + 2)
+}
+
+/// The kinds of lexemes which `SqlLexer` splits SQL code into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lexeme {
+    Whitespace,
+    Comment,
+    /// A string, quoted identifier, or dollar-quoted string
+    Quoted,
+    Semicolon,
+    /// Any other byte of code
+    Code,
+}
+
+/// Splits SQL code into comments, quotes, semicolons & everything else, so that
+/// semicolons & comments within quotes aren't mistaken for the real thing. Quotes &
+/// comments which aren't terminated extend to the end of the code.
+struct SqlLexer<'a> {
+    sql: &'a str,
+    idx: usize,
+}
+impl<'a> SqlLexer<'a> {
+    fn new(sql: &'a str) -> Self {
+        Self { sql, idx: 0 }
+    }
+    /// Whether the byte before `idx` is part of an identifier, such that a `$` or `E` at
+    /// `idx` is too.
+    fn in_identifier(&self, idx: usize) -> bool {
+        idx > 0 && {
+            let c = self.sql.as_bytes()[idx - 1];
+            c.is_ascii_alphanumeric() || c == b'_'
+        }
+    }
+    /// The end of `terminator`'s first occurrence from `from`.
+    fn skip_to(&self, from: usize, terminator: &str) -> usize {
+        self.sql[from..]
+            .find(terminator)
+            .map(|end| from + end + terminator.len())
+            .unwrap_or(self.sql.len())
+    }
+    /// The end of a string with C-style escapes (`E'...'`), from just after its opening
+    /// quote.
+    fn skip_escaped(&self, from: usize) -> usize {
+        let bytes = self.sql.as_bytes();
+        let mut idx = from;
+        while idx < bytes.len() {
+            match bytes[idx] {
+                b'\\' => idx += 2,
+                b'\'' if bytes.get(idx + 1) == Some(&b'\'') => idx += 2,
+                b'\'' => return idx + 1,
+                _ => idx += 1,
+            }
+        }
+        bytes.len()
+    }
+    /// The end of a block comment starting at `from`. Block comments nest.
+    fn skip_block_comment(&self, from: usize) -> usize {
+        let bytes = self.sql.as_bytes();
+        let mut depth = 0;
+        let mut idx = from;
+        while idx < bytes.len() {
+            if bytes[idx..].starts_with(b"/*") {
+                depth += 1;
+                idx += 2;
+            } else if bytes[idx..].starts_with(b"*/") {
+                depth -= 1;
+                idx += 2;
+                if depth == 0 {
+                    break;
+                }
+            } else {
+                idx += 1;
+            }
+        }
+        idx
+    }
+}
+impl Iterator for SqlLexer<'_> {
+    type Item = (Lexeme, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.sql.as_bytes();
+        let start = self.idx;
+        let next = bytes.get(start + 1);
+        let (lexeme, end) = match *bytes.get(start)? {
+            b'\'' => (Lexeme::Quoted, self.skip_to(start + 1, "'")),
+            b'"' => (Lexeme::Quoted, self.skip_to(start + 1, "\"")),
+            b'e' | b'E' if next == Some(&b'\'') && !self.in_identifier(start) => {
+                (Lexeme::Quoted, self.skip_escaped(start + 2))
+            }
+            b'-' if next == Some(&b'-') => (Lexeme::Comment, self.skip_to(start + 2, "\n")),
+            b'/' if next == Some(&b'*') => (Lexeme::Comment, self.skip_block_comment(start)),
+            b'$' if !self.in_identifier(start) => match dollar_tag_len(&bytes[start..]) {
+                Some(len) => (
+                    Lexeme::Quoted,
+                    self.skip_to(start + len, &self.sql[start..start + len]),
+                ),
+                None => (Lexeme::Code, start + 1),
+            },
+            b';' => (Lexeme::Semicolon, start + 1),
+            c if c.is_ascii_whitespace() => (Lexeme::Whitespace, start + 1),
+            _ => (Lexeme::Code, start + 1),
+        };
+        // An escape at the very end of the code may point past it
+        let end = end.min(bytes.len());
+        self.idx = end;
+        Some((lexeme, start..end))
+    }
+}
+
+/// Split SQL code into its statements, on semicolons which aren't within quotes,
+/// dollar quotes or comments. Each statement includes its semicolon; statements which
+/// are empty are skipped.
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for (lexeme, range) in SqlLexer::new(sql) {
+        if lexeme == Lexeme::Semicolon {
+            let statement = sql[start..range.end].trim();
+            if statement != ";" {
+                statements.push(statement);
+            }
+            start = range.end;
+        }
+    }
+    let statement = sql[start..].trim();
+    if !statement.is_empty() {
+        statements.push(statement);
+    }
+
+    statements
+}
+
+/// Format a duration for humans, such as `450ms`, `2.3s` or `4m 12s`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else if secs >= 1 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+/// Whether SQL code contains only whitespace & comments.
+pub fn is_blank_sql(sql: &str) -> bool {
+    SqlLexer::new(sql).all(|(lexeme, _)| matches!(lexeme, Lexeme::Whitespace | Lexeme::Comment))
+}
+
 pub fn new_table() -> toml_edit::Item {
     toml_edit::Item::Table(Default::default())
 }
@@ -91,6 +317,28 @@ pub fn new_table_arr() -> toml_edit::Item {
     toml_edit::Item::ArrayOfTables(Default::default())
 }
 
+/// Ask the user to confirm an action on stdin. Errors rather than prompting when stdin
+/// is not a terminal, so that automated use never proceeds without explicit consent.
+pub fn confirm(prompt: &str) -> Result<bool, ConfirmError> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Err(ConfirmError::NotTerminal);
+    }
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfirmError {
+    #[error("Confirmation required, but stdin is not a terminal (pass --yes to proceed)")]
+    NotTerminal,
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
 /// Write to a file atomically.
 pub fn replace_file(content: &str, path: &Path) -> Result<(), io::Error> {
     let tmp_dir = tempdir()?;
@@ -105,15 +353,72 @@ pub fn replace_file(content: &str, path: &Path) -> Result<(), io::Error> {
     Ok(())
 }
 
-/// Write the contents of an artifact to a file atomically.
+/// Extension of gzip-compressed artifacts (eg, `schema.sql.gz`)
+pub const COMPRESSED_EXTENSION: &str = "gz";
+
+pub fn is_compressed(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new(COMPRESSED_EXTENSION))
+}
+
+/// Read an artifact's code, decompressing it if it is compressed.
+pub fn read_artifact(path: &Path) -> Result<String, io::Error> {
+    if is_compressed(path) {
+        let mut code = String::new();
+        GzDecoder::new(File::open(path)?).read_to_string(&mut code)?;
+        Ok(code)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+/// Read a script or an artifact's code like `read_artifact`, but fail with
+/// `FileTooLarge` rather than read more than `max_bytes` of it into memory.
+pub fn read_script(path: &Path, max_bytes: u64) -> Result<String, io::Error> {
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            format!(
+                "{:?} is larger than the maximum script size of {} bytes",
+                path, max_bytes
+            ),
+        )
+    };
+    let f = File::open(path)?;
+    let mut code = Vec::new();
+    // The limit is one byte more than the maximum, so that reaching it means the file
+    // is too large
+    let limit = max_bytes.saturating_add(1);
+    if is_compressed(path) {
+        GzDecoder::new(f).take(limit).read_to_end(&mut code)?;
+    } else {
+        if f.metadata()?.len() > max_bytes {
+            return Err(too_large());
+        }
+        f.take(limit).read_to_end(&mut code)?;
+    }
+    if code.len() as u64 > max_bytes {
+        return Err(too_large());
+    }
+    String::from_utf8(code).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write the contents of an artifact to a file atomically, optionally gzip-compressing
+/// it. The content id is always that of the uncompressed code.
 pub fn replace_artifact<A: Artifact>(
     artifact: &A,
     path: &Path,
+    compress: bool,
 ) -> Result<ContentId, ReplaceArtifactError> {
     let tmp_dir = tempdir()?;
     let tmp_path = tmp_dir.path().join("tmp");
     let mut f = File::create_new(&tmp_path)?;
-    let id = artifact.write_to(&mut f)?;
+    let (id, f) = if compress {
+        let mut encoder = GzEncoder::new(f, Compression::default());
+        let id = artifact.write_to(&mut encoder)?;
+        (id, encoder.finish()?)
+    } else {
+        (artifact.write_to(&mut f)?, f)
+    };
     f.sync_data()?;
     drop(f);
 
@@ -129,3 +434,177 @@ pub enum ReplaceArtifactError {
     #[error("{0}")]
     Artiface(#[from] ScriptProcessingError<NullConsumerError>),
 }
+
+/// The pager used when `$PAGER` isn't set.
+pub const DEFAULT_PAGER: &str = "less -R";
+
+/// Whether output is paged in `mode`, given whether stdout is a terminal.
+fn pages(mode: PagerMode, terminal: bool) -> bool {
+    mode == PagerMode::Auto && terminal
+}
+
+/// Where long output is printed: stdout, or a pager reading from its stdin.
+pub enum Output {
+    Stdout(Stdout),
+    Pager { child: Child, stdin: ChildStdin },
+}
+impl Output {
+    /// Stdout, through a pager if `mode` is `Auto` & stdout is a terminal. Output which is
+    /// redirected or piped is never paged, so that sqigl can be scripted. If the pager
+    /// can't be started, output is printed to stdout instead.
+    pub fn open(mode: PagerMode) -> Self {
+        let stdout = io::stdout();
+        if !pages(mode, stdout.is_terminal()) {
+            return Self::Stdout(stdout);
+        }
+        let command = env::var("PAGER")
+            .ok()
+            .filter(|pager| !pager.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+        debug!("Paging output through {}", command);
+        let spawned = if cfg!(windows) {
+            Command::new("cmd")
+                .arg("/C")
+                .arg(&command)
+                .stdin(Stdio::piped())
+                .spawn()
+        } else {
+            Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .stdin(Stdio::piped())
+                .spawn()
+        };
+        match spawned {
+            Ok(mut child) => {
+                let stdin = child.stdin.take().expect("Pager's stdin is piped");
+                Self::Pager { child, stdin }
+            }
+            Err(e) => {
+                warn!("Failed to start pager {}: {}", command, e);
+                Self::Stdout(stdout)
+            }
+        }
+    }
+    /// Flush the output, & wait for the pager to exit if there is one.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Stdout(mut stdout) => stdout.flush(),
+            Self::Pager { mut child, stdin } => {
+                // Closing the pager's stdin signals the end of the output
+                drop(stdin);
+                child.wait()?;
+                Ok(())
+            }
+        }
+    }
+}
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(stdout) => stdout.write(buf),
+            // The user may quit the pager before reading all of the output
+            Self::Pager { stdin, .. } => match stdin.write(buf) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(buf.len()),
+                res => res,
+            },
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::Pager { stdin, .. } => match stdin.flush() {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                res => res,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specificity_ranks_requirements() {
+        let ranked = [
+            ">=0.2",
+            ">=0.2, <0.4",
+            "^0.2",
+            "~0.2",
+            "=0",
+            "=0.2",
+            "=0.2.3",
+            ">=0.2.0, =0.2.3",
+        ];
+        let specificities: Vec<_> = ranked
+            .iter()
+            .map(|req| specificity(&req.parse().unwrap()))
+            .collect();
+        for (pair, reqs) in specificities.windows(2).zip(ranked.windows(2)) {
+            assert!(
+                pair[0] < pair[1],
+                "{} should be less specific than {}",
+                reqs[0],
+                reqs[1]
+            );
+        }
+        assert_eq!(specificity(&VersionReq::STAR), (0, 0));
+    }
+
+    #[test]
+    fn splits_statements() {
+        assert_eq!(
+            split_statements("select 1; select ';' ;\n; select 2"),
+            vec!["select 1;", "select ';' ;", "select 2"]
+        );
+        assert_eq!(
+            split_statements("select 'it''s; here'; select \"a;b\";"),
+            vec!["select 'it''s; here';", "select \"a;b\";"]
+        );
+        assert_eq!(
+            split_statements("-- a; b\nselect 1; /* c; /* d; */ e; */ select 2;"),
+            vec!["-- a; b\nselect 1;", "/* c; /* d; */ e; */ select 2;"]
+        );
+        assert_eq!(
+            split_statements("create function f() as $body$ select 1; $body$; select $1;"),
+            vec![
+                "create function f() as $body$ select 1; $body$;",
+                "select $1;"
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_escaped_strings() {
+        assert_eq!(
+            split_statements(r"select E'it\'s; here'; select e'\\'; select 2;"),
+            vec![r"select E'it\'s; here';", r"select e'\\';", "select 2;"]
+        );
+        // Not an escape string, since the `e` ends an identifier
+        assert_eq!(
+            split_statements(r"select name'\'; select 2;"),
+            vec![r"select name'\';", "select 2;"]
+        );
+    }
+
+    #[test]
+    fn blank_sql() {
+        assert!(is_blank_sql(""));
+        assert!(is_blank_sql(" -- a\n/* b /* c */ d */\n"));
+        assert!(!is_blank_sql("-- a\nselect 1;"));
+        assert!(!is_blank_sql("/* a */ ;"));
+        assert!(!is_blank_sql("E'-- a'"));
+    }
+
+    #[test]
+    fn pages_only_terminals() {
+        assert!(pages(PagerMode::Auto, true));
+        assert!(!pages(PagerMode::Auto, false));
+        assert!(!pages(PagerMode::Never, true));
+        assert!(!pages(PagerMode::Never, false));
+        if !io::stdout().is_terminal() {
+            assert!(matches!(Output::open(PagerMode::Auto), Output::Stdout(_)));
+        }
+    }
+}